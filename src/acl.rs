@@ -0,0 +1,163 @@
+//! POSIX access control lists
+//!
+//! ACLs are read and written through the `system.posix_acl_access`
+//! extended attribute which uses the kernel's binary representation:
+//! a little endian `u32` version header followed by `(u16 tag, u16
+//! perm, u32 id)` records.
+extern crate libc;
+
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::fd::FdRaw;
+use crate::errors::*;
+
+const XATTR_ACL_ACCESS: &str = "system.posix_acl_access";
+const ACL_EA_VERSION: u32 = 2;
+const ACL_UNDEFINED_ID: u32 = !0;
+
+pub const ACL_READ: u16 = 0x04;
+pub const ACL_WRITE: u16 = 0x02;
+pub const ACL_EXECUTE: u16 = 0x01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AclTag {
+    UserObj,
+    User(libc::uid_t),
+    GroupObj,
+    Group(libc::gid_t),
+    Mask,
+    Other,
+}
+
+impl AclTag {
+    fn encode(&self) -> (u16, u32) {
+        match *self {
+            AclTag::UserObj	=> (0x01, ACL_UNDEFINED_ID),
+            AclTag::User(uid)	=> (0x02, uid),
+            AclTag::GroupObj	=> (0x04, ACL_UNDEFINED_ID),
+            AclTag::Group(gid)	=> (0x08, gid),
+            AclTag::Mask	=> (0x10, ACL_UNDEFINED_ID),
+            AclTag::Other	=> (0x20, ACL_UNDEFINED_ID),
+        }
+    }
+
+    fn decode(tag: u16, id: u32) -> Result<Self> {
+        Ok(match tag {
+            0x01 => AclTag::UserObj,
+            0x02 => AclTag::User(id),
+            0x04 => AclTag::GroupObj,
+            0x08 => AclTag::Group(id),
+            0x10 => AclTag::Mask,
+            0x20 => AclTag::Other,
+            _ => bail!("unknown ACL tag {:#x}", tag),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag:	AclTag,
+    /// combination of `ACL_READ`, `ACL_WRITE` and `ACL_EXECUTE`
+    pub perm:	u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub entries:	Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Decodes the value of a `system.posix_acl_access` attribute
+    pub fn from_xattr(data: &[u8]) -> Result<Self> {
+        ensure!(data.len() >= 4 && (data.len() - 4) % 8 == 0,
+                "bad ACL size {}", data.len());
+
+        let version = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+
+        ensure!(version == ACL_EA_VERSION, "unsupported ACL version {}", version);
+
+        let mut entries = Vec::with_capacity((data.len() - 4) / 8);
+
+        for e in data[4..].chunks(8) {
+            let tag = u16::from_le_bytes([e[0], e[1]]);
+            let perm = u16::from_le_bytes([e[2], e[3]]);
+            let id = u32::from_le_bytes([e[4], e[5], e[6], e[7]]);
+
+            entries.push(AclEntry {
+                tag:	AclTag::decode(tag, id)?,
+                perm:	perm,
+            });
+        }
+
+        Ok(Acl {
+            entries: entries,
+        })
+    }
+
+    /// Encodes the ACL for the `system.posix_acl_access` attribute.
+    ///
+    /// Entries are sorted by tag and id as required by the kernel.
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut entries: Vec<(u16, u32, u16)> = self.entries.iter()
+            .map(|e| {
+                let (tag, id) = e.tag.encode();
+                (tag, id, e.perm)
+            })
+            .collect();
+
+        entries.sort();
+
+        let mut res = Vec::with_capacity(4 + entries.len() * 8);
+
+        res.extend_from_slice(&ACL_EA_VERSION.to_le_bytes());
+
+        for (tag, id, perm) in entries {
+            res.extend_from_slice(&tag.to_le_bytes());
+            res.extend_from_slice(&perm.to_le_bytes());
+            res.extend_from_slice(&id.to_le_bytes());
+        }
+
+        res
+    }
+}
+
+impl FdRaw {
+    /// Returns the access ACL or `None` when the file has only the
+    /// permissions from its mode.
+    pub fn get_acl(&self) -> Result<Option<Acl>> {
+        match self.getxattr(XATTR_ACL_ACCESS)? {
+            None => Ok(None),
+            Some(data) => Acl::from_xattr(&data).map(Some),
+        }
+    }
+
+    /// Sets the access ACL
+    pub fn set_acl(&self, acl: &Acl) -> Result<()> {
+        self.setxattr(XATTR_ACL_ACCESS, &acl.to_xattr(), 0)
+    }
+}
+
+impl Chroot {
+    /// Returns the access ACL of `path`; a symlink in the last
+    /// component is resolved within the chroot.
+    pub fn get_acl<T>(&self, path: &T) -> Result<Option<Acl>>
+    where
+        T: AsRef<Path>,
+    {
+        self.open(path, libc::O_PATH | libc::O_CLOEXEC)?.get_acl()
+    }
+
+    /// Sets the access ACL of `path`; a symlink in the last component
+    /// is resolved within the chroot.
+    pub fn set_acl<T>(&self, path: &T, acl: &Acl) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        self.open(path, libc::O_PATH | libc::O_CLOEXEC)?.set_acl(acl)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/acl.inc.rs"]
+mod test;
@@ -0,0 +1,340 @@
+//! Tar archives of directory trees within a chroot
+//!
+//! `Chroot::archive()` writes a POSIX (pax) tar stream.  Fields which
+//! do not fit into the ustar header (long names, large sizes or ids)
+//! and extended attributes are stored in pax extended headers like
+//! GNU tar and bsdtar do.
+extern crate libc;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::fd::Fd;
+use crate::metadata::{Metadata, Xattrs};
+use crate::control::Progress;
+use crate::visit::{VisitAction, VisitOptions};
+use crate::errors::*;
+
+const BLOCK_SIZE: usize = 512;
+
+/// Options for `Chroot::archive()`
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveOptions {
+    /// record extended attributes (`SCHILY.xattr.*` records)
+    pub xattrs:		bool,
+}
+
+/// Writes `value` as NUL terminated octal number into `field`;
+/// returns `false` when it does not fit.
+fn put_octal(field: &mut [u8], value: u64) -> bool {
+    let digits = field.len() - 1;
+    let s = format!("{:0width$o}", value, width = digits);
+
+    if s.len() > digits {
+        return false;
+    }
+
+    field[..digits].copy_from_slice(s.as_bytes());
+    field[digits] = 0;
+
+    true
+}
+
+/// Appends a pax record; its length field counts itself too.
+fn push_pax_record(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+    // ' ', '=' and '\n'
+    let payload = key.len() + value.len() + 3;
+    let mut len = payload + 1;
+
+    while len != payload + len.to_string().len() {
+        len = payload + len.to_string().len();
+    }
+
+    buf.extend_from_slice(len.to_string().as_bytes());
+    buf.push(b' ');
+    buf.extend_from_slice(key.as_bytes());
+    buf.push(b'=');
+    buf.extend_from_slice(value);
+    buf.push(b'\n');
+}
+
+/// ustar header of an entry with the pax records for the fields
+/// which do not fit
+struct Header {
+    block:	[u8; BLOCK_SIZE],
+    pax:	Vec<u8>,
+}
+
+impl Header {
+    fn new(name: &[u8], typeflag: u8) -> Self {
+        let mut h = Header {
+            block:	[0; BLOCK_SIZE],
+            pax:	Vec::new(),
+        };
+
+        h.set_bytes(0, 100, "path", name);
+        h.block[156] = typeflag;
+        h.block[257..263].copy_from_slice(b"ustar\0");
+        h.block[263..265].copy_from_slice(b"00");
+
+        h
+    }
+
+    fn set_bytes(&mut self, offset: usize, len: usize, key: &str, value: &[u8]) {
+        let cnt = value.len().min(len);
+
+        self.block[offset..offset + cnt].copy_from_slice(&value[..cnt]);
+
+        if value.len() > len {
+            push_pax_record(&mut self.pax, key, value);
+        }
+    }
+
+    fn set_octal(&mut self, offset: usize, len: usize, key: &str, value: u64) {
+        if !put_octal(&mut self.block[offset..offset + len], value) {
+            push_pax_record(&mut self.pax, key, value.to_string().as_bytes());
+        }
+    }
+
+    fn set_metadata(&mut self, md: &Metadata, size: u64) {
+        put_octal(&mut self.block[100..108], md.mode() as u64);
+        self.set_octal(108, 8, "uid", md.uid() as u64);
+        self.set_octal(116, 8, "gid", md.gid() as u64);
+        self.set_octal(124, 12, "size", size);
+        self.set_octal(136, 12, "mtime", md.mtime().tv_sec.max(0) as u64);
+
+        if md.is_char_device() || md.is_block_device() {
+            put_octal(&mut self.block[329..337], libc::major(md.rdev()) as u64);
+            put_octal(&mut self.block[337..345], libc::minor(md.rdev()) as u64);
+        }
+    }
+
+    fn finish_block(&mut self) {
+        self.block[148..156].copy_from_slice(b"        ");
+
+        let sum: u32 = self.block.iter().map(|c| *c as u32).sum();
+
+        self.block[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    }
+
+    /// Writes the header and the preceding pax header when it is
+    /// needed.
+    fn write<W: Write>(mut self, writer: &mut W) -> Result<()> {
+        if !self.pax.is_empty() {
+            let mut pax = Header::new(b"././@PaxHeader", b'x');
+
+            put_octal(&mut pax.block[100..108], 0o644);
+            pax.set_octal(124, 12, "size", self.pax.len() as u64);
+            pax.finish_block();
+
+            writer.write_all(&pax.block)?;
+            write_padded(writer, &self.pax)?;
+        }
+
+        self.finish_block();
+        writer.write_all(&self.block)?;
+
+        Ok(())
+    }
+}
+
+fn write_padded<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    writer.write_all(data)?;
+    write_padding(writer, data.len() as u64)
+}
+
+fn write_padding<W: Write>(writer: &mut W, len: u64) -> Result<()> {
+    let rem = (len % BLOCK_SIZE as u64) as usize;
+
+    if rem != 0 {
+        writer.write_all(&[0; BLOCK_SIZE][rem..])?;
+    }
+
+    Ok(())
+}
+
+/// Content of an entry which is read before its header is written
+enum Content {
+    None,
+    File(Fd),
+    Link(std::ffi::OsString),
+    HardLink(Vec<u8>),
+}
+
+/// State of `Chroot::archive()`
+struct ArchiveWriter<'a, W: Write> {
+    chroot:	&'a Chroot,
+    writer:	&'a mut W,
+    opts:	&'a ArchiveOptions,
+    progress:	&'a Progress<'a>,
+    /// archive names of files with more than one link
+    links:	HashMap<(libc::dev_t, libc::ino_t), Vec<u8>>,
+}
+
+impl<W: Write> ArchiveWriter<'_, W> {
+    /// Collects everything which is read before the header of an
+    /// entry is written.
+    fn prepare(&mut self, dir_fd: &Fd, name: &Path, md: &Metadata, arch_name: &[u8])
+               -> Result<(Content, Xattrs)>
+    {
+        let xattrs = if self.opts.xattrs {
+            let fd = dir_fd.open_path_at(&name)?;
+
+            crate::manifest::capture_xattrs(&fd, md)?
+        } else {
+            Vec::new()
+        };
+
+        let link_key = Some((md.dev(), md.ino())).filter(|_| !md.is_dir() && md.nlink() > 1);
+
+        if let Some(first) = link_key.and_then(|k| self.links.get(&k)) {
+            return Ok((Content::HardLink(first.clone()), xattrs));
+        }
+
+        let content = if md.is_file() {
+            let fd = dir_fd.openat(&name, libc::O_RDONLY | libc::O_CLOEXEC |
+                                   libc::O_NOFOLLOW | libc::O_NOCTTY |
+                                   libc::O_NONBLOCK)?;
+
+            ensure!(fd.metadata()?.is_same_file(md),
+                    "{:?} was replaced while archiving", name);
+
+            Content::File(fd)
+        } else if md.is_symlink() {
+            Content::Link(dir_fd.readlinkat(&name)?)
+        } else {
+            Content::None
+        };
+
+        if let Some(key) = link_key {
+            self.links.insert(key, arch_name.to_vec());
+        }
+
+        Ok((content, xattrs))
+    }
+
+    /// Writes the records of the entry `name` of `dir_fd` whose path
+    /// relative to the top is `rel`.  Entries which vanish before
+    /// anything was written are skipped.
+    fn add(&mut self, dir_fd: &Fd, name: &Path, rel: &Path, md: &Metadata) -> Result<()> {
+        let mut arch_name = b".".to_vec();
+
+        if !rel.as_os_str().is_empty() {
+            arch_name.push(b'/');
+            arch_name.extend_from_slice(rel.as_os_str().as_bytes());
+        }
+
+        let typeflag = match md.file_type() {
+            libc::S_IFREG	=> b'0',
+            libc::S_IFLNK	=> b'2',
+            libc::S_IFCHR	=> b'3',
+            libc::S_IFBLK	=> b'4',
+            libc::S_IFDIR	=> {
+                arch_name.push(b'/');
+                b'5'
+            },
+            libc::S_IFIFO	=> b'6',
+            // sockets can not be archived
+            _			=> return Ok(()),
+        };
+
+        let (content, xattrs) = match self.prepare(dir_fd, name, md, &arch_name) {
+            // entry was removed meanwhile
+            Err(ref e) if e.errno() == Some(libc::ENOENT) => return Ok(()),
+            r => r?,
+        };
+
+        let (typeflag, size) = match content {
+            Content::HardLink(_)	=> (b'1', 0),
+            Content::File(_)		=> (typeflag, md.len()),
+            _				=> (typeflag, 0),
+        };
+
+        let mut header = Header::new(&arch_name, typeflag);
+
+        header.set_metadata(md, size);
+
+        match content {
+            Content::HardLink(ref target)	=> header.set_bytes(157, 100, "linkpath", target),
+            Content::Link(ref target)	=>
+                header.set_bytes(157, 100, "linkpath", target.as_bytes()),
+            _				=> {},
+        }
+
+        for (key, value) in &xattrs {
+            push_pax_record(&mut header.pax, &format!("SCHILY.xattr.{}", key), value);
+        }
+
+        header.write(self.writer)?;
+
+        if let Content::File(fd) = content {
+            let copied = std::io::copy(&mut fd.reader().take(size), self.writer)?;
+
+            // keep the stream consistent when the file was truncated
+            // meanwhile
+            std::io::copy(&mut std::io::repeat(0).take(size - copied), self.writer)?;
+            write_padding(self.writer, size)?;
+
+            self.chroot.advise_copied(&[&fd]);
+            self.progress.add(0, size);
+        }
+
+        Ok(())
+    }
+}
+
+impl Chroot {
+    /// Writes the tree at `path` as tar stream into `writer`.
+    ///
+    /// Entries are named relative to `path` (`./`, `./a`, ...) and
+    /// keep mode, ownership, modification times, device numbers and
+    /// hard links (within the tree); extended attributes are stored
+    /// with `ArchiveOptions::xattrs`.  `path` is resolved like by
+    /// `Self::chdir()`, symlinks below it are stored but never
+    /// followed.  Sockets are skipped; see `Self::visit()` for the
+    /// handling of entries which disappear meanwhile.
+    pub fn archive<T, W>(&self, path: &T, writer: &mut W, opts: &ArchiveOptions) -> Result<()>
+    where
+        T: AsRef<Path>,
+        W: Write,
+    {
+        let top = self.canonicalize(path)?;
+        let top_fd = self.chdir(&top)?;
+        let top_md = top_fd.metadata()?;
+        let progress = self.progress();
+
+        let mut archive = ArchiveWriter {
+            chroot:	self,
+            writer:	writer,
+            opts:	opts,
+            progress:	&progress,
+            links:	HashMap::new(),
+        };
+
+        archive.add(&top_fd, Path::new("."), Path::new(""), &top_md)?;
+
+        self.visit_internal(top.as_path(), &VisitOptions::default(), &progress, |e| {
+            let rel = match e.path.strip_prefix(&top) {
+                Ok(p) => p,
+                Err(_) => bail!("{:?} is not below {:?}", e.path, top.as_path()),
+            };
+
+            archive.add(e.parent, Path::new(&e.entry.d_name), rel, e.metadata)?;
+
+            Ok(VisitAction::Descend)
+        })?;
+
+        // end of archive
+        archive.writer.write_all(&[0; 2 * BLOCK_SIZE])?;
+        archive.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path="tests/archive.inc.rs"]
+mod test;
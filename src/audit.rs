@@ -0,0 +1,159 @@
+//! Hardening checks of a chroot directory tree
+extern crate libc;
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use crate::chroot::Chroot;
+use crate::fd::Fd;
+use crate::metadata::Metadata;
+use crate::errors::*;
+
+/// Problem reported by `Chroot::audit()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuditIssue {
+    /// world writable directory without sticky bit
+    WorldWritableDir,
+    /// file with setuid bit
+    SetUid,
+    /// file with setgid bit
+    SetGid,
+    /// symlink whose target leaves the root when it is resolved by
+    /// the kernel instead of the chroot
+    EscapingSymlink(OsString),
+    /// character or block device
+    DeviceNode,
+    /// file or directory on a different mount than the root, e.g. a
+    /// bind mount; directories are not descended into
+    ForeignMount,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditFinding {
+    /// path relative to the chroot
+    pub path:	PathBuf,
+    pub issue:	AuditIssue,
+}
+
+/// Checks whether `target` of a symlink in a directory `depth` levels
+/// below the root leaves the root when resolved lexically.
+fn is_escaping_link(target: &Path, depth: usize) -> bool {
+    let mut depth = depth as isize;
+
+    for c in target.components() {
+        match c {
+            Component::Prefix(_) |
+            Component::RootDir => return true,
+            Component::CurDir => {},
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+        }
+
+        if depth < 0 {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn check_entry(dir_fd: &Fd, path: &Path, name: &OsString, md: &Metadata,
+               mnt_id: u64, root_mnt_id: u64, depth: usize) -> Result<Vec<AuditIssue>>
+{
+    let mode = md.mode();
+    let mut res = Vec::new();
+
+    if md.is_dir() && mode & 0o002 != 0 && mode & libc::S_ISVTX == 0 {
+        res.push(AuditIssue::WorldWritableDir);
+    }
+
+    if !md.is_dir() && mode & libc::S_ISUID != 0 {
+        res.push(AuditIssue::SetUid);
+    }
+
+    // setgid on directories only controls group inheritance
+    if !md.is_dir() && mode & libc::S_ISGID != 0 {
+        res.push(AuditIssue::SetGid);
+    }
+
+    if md.is_char_device() || md.is_block_device() {
+        res.push(AuditIssue::DeviceNode);
+    }
+
+    if md.is_symlink() {
+        let target = dir_fd.readlinkat(name)
+            .chain_err(|| format!("failed to read link {:?}", path))?;
+
+        if is_escaping_link(Path::new(&target), depth) {
+            res.push(AuditIssue::EscapingSymlink(target));
+        }
+    }
+
+    if mnt_id != root_mnt_id {
+        res.push(AuditIssue::ForeignMount);
+    }
+
+    Ok(res)
+}
+
+impl Chroot {
+    /// Walks the whole chroot and reports security relevant findings.
+    ///
+    /// Reported are world writable directories without sticky bit,
+    /// setuid/setgid files, symlinks pointing outside the root when
+    /// resolved by the kernel, device nodes and mount points
+    /// including bind mounts of the root filesystem.  Symlinks are
+    /// not followed.
+    pub fn audit(&self) -> Result<Vec<AuditFinding>> {
+        let root_fd = self.root_fd()?;
+        let root_mnt_id = root_fd.mount_id_or_dev()?;
+        let mut res = Vec::new();
+        let mut todo = vec![(root_fd, PathBuf::from("/"), 0)];
+        let mut deadline = None;
+        let progress = self.progress();
+
+        while let Some((dir_fd, dir_path, depth)) = todo.pop() {
+            for e in dir_fd.read_dir()? {
+                self.check_interrupt(&mut deadline)?;
+
+                let name = e?.d_name;
+                let path = dir_path.join(&name);
+
+                // the mount id is only reported for an opened entry
+                let entry = dir_fd.openat(&name, libc::O_PATH | libc::O_NOFOLLOW |
+                                          libc::O_CLOEXEC)
+                    .and_then(|fd| Ok((fd.metadata()?, fd.mount_id_or_dev()?)));
+
+                let (md, mnt_id) = match entry {
+                    Ok(r) => r,
+                    // entry was removed after readdir()
+                    Err(ref e) if e.errno() == Some(libc::ENOENT) => continue,
+                    Err(e) => return Err(e),
+                };
+
+                let issues = check_entry(&dir_fd, &path, &name, &md, mnt_id,
+                                         root_mnt_id, depth)?;
+
+                progress.add(1, 0);
+                let is_foreign = issues.contains(&AuditIssue::ForeignMount);
+
+                for issue in issues {
+                    res.push(AuditFinding {
+                        path:	path.clone(),
+                        issue:	issue,
+                    });
+                }
+
+                if md.is_dir() && !is_foreign {
+                    let sub_fd = dir_fd.openat(&name, libc::O_DIRECTORY |
+                                               libc::O_RDONLY | libc::O_CLOEXEC |
+                                               libc::O_NOFOLLOW)?;
+
+                    todo.push((sub_fd, path, depth + 1));
+                }
+            }
+        }
+
+        Ok(res)
+    }
+}
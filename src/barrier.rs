@@ -0,0 +1,101 @@
+//! Durability of written data and directory entries
+extern crate libc;
+
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::fd::FdRaw;
+use crate::errors::*;
+
+impl FdRaw {
+    /// Writes back the dirty pages in `len` bytes at `offset` and
+    /// waits until this is complete; a `len` of 0 covers everything
+    /// up to the end of the file.
+    ///
+    /// This uses `sync_file_range()` which neither flushes the
+    /// metadata required to read the data back nor the write cache
+    /// of the disk.  It is meant for writing out large files in
+    /// portions; a final `Barrier::commit()` makes them durable.
+    pub fn sync_data_range(&self, offset: u64, len: u64) -> Result<()> {
+        let offset = libc::off64_t::try_from(offset)
+            .map_err(|_| std::io::Error::from_raw_os_error(libc::EINVAL))?;
+        let len = libc::off64_t::try_from(len)
+            .map_err(|_| std::io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        let rc = unsafe {
+            libc::sync_file_range(self.fd, offset, len,
+                                  libc::SYNC_FILE_RANGE_WAIT_BEFORE |
+                                  libc::SYNC_FILE_RANGE_WRITE |
+                                  libc::SYNC_FILE_RANGE_WAIT_AFTER)
+        };
+
+        ensure!(rc >= 0, std::io::Error::last_os_error());
+
+        Ok(())
+    }
+}
+
+/// Makes a set of files and directories durable
+///
+/// `commit()` flushes the data of all files (`fdatasync()`) before
+/// the directories (`fsync()`), so that directory entries never
+/// become durable before the content they refer to.  A typical
+/// commit protocol writes a temporary file, commits a barrier with
+/// this file, renames it and commits a barrier with the parent
+/// directory.
+///
+/// ```no_run
+/// # use unix_fd::{barrier::Barrier, fd::Fd};
+/// # fn f(file: &Fd, dir: &Fd) -> unix_fd::errors::Result<()> {
+/// Barrier::new()
+///     .file(file)
+///     .dir(dir)
+///     .commit()
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Barrier<'a> {
+    files:	Vec<RawFd>,
+    dirs:	Vec<RawFd>,
+    fds:	PhantomData<&'a ()>,
+}
+
+impl<'a> Barrier<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file whose data and size must be durable
+    pub fn file<T: AsRawFd>(mut self, fd: &'a T) -> Self {
+        self.files.push(fd.as_raw_fd());
+        self
+    }
+
+    /// Adds a directory whose entries must be durable; it must not be
+    /// opened with `O_PATH`.
+    pub fn dir<T: AsRawFd>(mut self, fd: &'a T) -> Self {
+        self.dirs.push(fd.as_raw_fd());
+        self
+    }
+
+    /// Flushes the files and then the directories; stops at the
+    /// first error.
+    pub fn commit(self) -> Result<()> {
+        for fd in self.files {
+            let rc = unsafe { libc::fdatasync(fd) };
+            ensure!(rc >= 0, std::io::Error::last_os_error());
+        }
+
+        for fd in self.dirs {
+            let rc = unsafe { libc::fsync(fd) };
+            ensure!(rc >= 0, std::io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path="tests/barrier.inc.rs"]
+mod test;
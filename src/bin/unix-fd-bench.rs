@@ -0,0 +1,199 @@
+//! Measures resolve, open and walk throughput of `Chroot` on a
+//! synthetic tree
+//!
+//! Usage: `unix-fd-bench [--depth N] [--width N] [--iterations N]
+//! [--link-cache] [--negative-cache] <dir>`
+//!
+//! The tree is created in a new subdirectory of `<dir>` and removed
+//! afterwards, so the filesystem under test is chosen by the caller.
+
+#[macro_use]
+extern crate error_chain;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use unix_fd::chroot::Chroot;
+use unix_fd::fd::Fd;
+use unix_fd::visit::VisitAction;
+use unix_fd::errors::*;
+
+/// owned counterpart of the `FsItem` test fixtures
+enum FsItem {
+    Dir(String, Vec<FsItem>),
+    File(String),
+    Link(String, String),
+}
+
+struct Options {
+    dir:		PathBuf,
+    depth:		usize,
+    width:		usize,
+    iterations:		usize,
+    link_cache:		bool,
+    negative_cache:	bool,
+}
+
+fn parse_args() -> Result<Options> {
+    let mut opts = Options {
+        dir:		PathBuf::new(),
+        depth:		32,
+        width:		64,
+        iterations:	10000,
+        link_cache:	false,
+        negative_cache:	false,
+    };
+    let mut dir = None;
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut num = |name: &str| -> Result<usize> {
+            let v = args.next().ok_or_else(|| format!("missing value for {}", name))?;
+
+            v.parse().chain_err(|| format!("bad value {:?} for {}", v, name))
+        };
+
+        match arg.as_str() {
+            "--depth"		=> opts.depth = num(&arg)?,
+            "--width"		=> opts.width = num(&arg)?,
+            "--iterations"	=> opts.iterations = num(&arg)?,
+            "--link-cache"	=> opts.link_cache = true,
+            "--negative-cache"	=> opts.negative_cache = true,
+            a if a.starts_with("--") => bail!("unknown option {:?}", a),
+            a if dir.is_none()	=> dir = Some(PathBuf::from(a)),
+            a			=> bail!("unexpected argument {:?}", a),
+        }
+    }
+
+    opts.dir = dir.ok_or("missing directory")?;
+    ensure!(opts.depth > 0 && opts.iterations > 0, "depth and iterations must not be 0");
+
+    Ok(opts)
+}
+
+/// `width` directories with `width` files each
+fn wide_tree(width: usize) -> FsItem {
+    FsItem::Dir("wide".into(), (0..width)
+                .map(|i| FsItem::Dir(format!("d{}", i), (0..width)
+                                     .map(|j| FsItem::File(format!("f{}", j)))
+                                     .collect()))
+                .collect())
+}
+
+/// chain of `depth` directories; every level has a file and a
+/// symlink to the parent level
+fn deep_tree(depth: usize) -> FsItem {
+    let mut item = FsItem::Dir("d".into(), vec![
+        FsItem::File("f".into()),
+        FsItem::Link("up".into(), "..".into()),
+    ]);
+
+    for _ in 1..depth {
+        item = FsItem::Dir("d".into(), vec![
+            item,
+            FsItem::File("f".into()),
+            FsItem::Link("up".into(), "..".into()),
+        ]);
+    }
+
+    FsItem::Dir("deep".into(), vec![item])
+}
+
+fn create_fsitem(dir_fd: &Fd, item: &FsItem) -> Result<()> {
+    match item {
+        FsItem::Dir(name, content) => {
+            dir_fd.mkdirat(name, 0o755)?;
+
+            let sub_fd = dir_fd.openat(name, libc::O_RDONLY | libc::O_DIRECTORY |
+                                       libc::O_CLOEXEC)?;
+
+            for i in content {
+                create_fsitem(&sub_fd, i)?;
+            }
+        },
+
+        FsItem::File(name) => {
+            dir_fd.createat(name, libc::O_WRONLY | libc::O_CLOEXEC | libc::O_EXCL,
+                            0o644)?;
+        },
+
+        FsItem::Link(name, target) => dir_fd.symlinkat(target, name)?,
+    }
+
+    Ok(())
+}
+
+fn report(name: &str, ops: usize, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+
+    println!("{:<10} {:>10} ops {:>10.3} s {:>12.0} ops/s", name, ops, secs,
+             ops as f64 / secs);
+}
+
+fn measure<F: FnMut() -> Result<()>>(name: &str, ops: usize, mut f: F) -> Result<()> {
+    let start = Instant::now();
+
+    for _ in 0..ops {
+        f()?;
+    }
+
+    report(name, ops, start.elapsed());
+
+    Ok(())
+}
+
+fn run_bench(opts: &Options, top: &Path) -> Result<()> {
+    let top_fd = Fd::open(&top, libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC)?;
+    let deep_dir = "/deep/".to_string() + &vec!["d"; opts.depth].join("/");
+    let deep_file = format!("{}/f", deep_dir);
+    // walks down and up the chain through the symlinks
+    let link_path = format!("{}/{}f", deep_dir, "up/".repeat(opts.depth - 1));
+
+    create_fsitem(&top_fd, &wide_tree(opts.width))?;
+    create_fsitem(&top_fd, &deep_tree(opts.depth))?;
+
+    let mut chroot = Chroot::new(&top)
+        .with_link_cache(opts.link_cache);
+
+    if opts.negative_cache {
+        chroot = chroot.with_negative_cache(Some(Duration::from_secs(60)));
+    }
+
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+    let walk_ops = (opts.iterations / (opts.width * opts.width).max(1)).max(1);
+
+    measure("resolve", opts.iterations, || chroot.canonicalize(&deep_file).map(|_| ()))?;
+    measure("open", opts.iterations, || chroot.open(&deep_file, flags).map(|_| ()))?;
+    measure("symlinks", opts.iterations, || chroot.open(&link_path, flags).map(|_| ()))?;
+    measure("missing", opts.iterations, || match chroot.open(&"/wide/d0/missing", flags) {
+        Err(ref e) if e.errno() == Some(libc::ENOENT) => Ok(()),
+        Err(e)	=> Err(e),
+        Ok(_)	=> bail!("unexpected file"),
+    })?;
+    measure("walk", walk_ops, || chroot.visit(&"/", |_| Ok(VisitAction::Descend)))?;
+
+    println!("{:?}", chroot.stats());
+
+    Ok(())
+}
+
+fn run() -> Result<()> {
+    let opts = parse_args()?;
+    let name = format!("unix-fd-bench.{}", std::process::id());
+    let top = opts.dir.join(&name);
+
+    std::fs::create_dir(&top)?;
+
+    let res = run_bench(&opts, &top);
+
+    Chroot::new(&opts.dir).remove_dir_all(&Path::new("/").join(&name))?;
+
+    res
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("unix-fd-bench: {}", e);
+        std::process::exit(1);
+    }
+}
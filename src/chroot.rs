@@ -5,35 +5,501 @@ extern crate error_chain;
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::ffi::OsString;
-
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::barrier::Barrier;
+use crate::control::{ControlRef, Progress};
+use crate::watch::DirWatch;
 use crate::fd::*;
 use crate::dir::*;
 
 use crate::errors::*;
 
+/// default limit of nested symlinks; see `Chroot::with_symlink_limit()`
 const MAX_LOOP_CNT: u32 = 256;
 
+/// default number of path components which are resolved by a single
+/// lookup; see `Chroot::with_component_budget()`
+const COMPONENT_BUDGET: u64 = 1 << 16;
+
+const DIR_OPEN_FLAGS: libc::c_int =
+    libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY | libc::O_NOFOLLOW;
+
+const PATH_OPEN_FLAGS: libc::c_int =
+    libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_PATH | libc::O_NOFOLLOW;
+
+/// Identifies a symlink by the directory containing it and by its
+/// inode.  Hardlinked symlinks in different directories resolve
+/// differently and must not be treated as the same link.
+type LinkId = (libc::dev_t, libc::ino_t, libc::dev_t, libc::ino_t);
+
+fn link_id(dir_fd: &Fd, name: &Path) -> Result<LinkId> {
+    let dir_stat = dir_fd.fstat()?;
+    let stat = dir_fd.fstatat(&name, false)?;
+
+    Ok((dir_stat.st_dev, dir_stat.st_ino, stat.st_dev, stat.st_ino))
+}
+
+/// Pending work of `Chroot::chdir_internal()`
+enum Step {
+    Root,
+    Parent,
+    Name(OsString),
+    /// end of the target of the innermost expanded symlink
+    LinkEnd,
+}
+
+/// Pushes the components of `path` onto `steps` so that the first
+/// one is popped first.
+fn push_steps(steps: &mut Vec<Step>, path: &Path) {
+    use std::path::Component;
+
+    let start = steps.len();
+
+    for c in path.components() {
+        steps.push(match c {
+            Component::Prefix(_)	=> unreachable!(),
+            Component::RootDir		=> Step::Root,
+            Component::ParentDir	=> Step::Parent,
+            Component::CurDir		=> continue,
+            Component::Normal(name)	=> Step::Name(name.to_owned()),
+        });
+    }
+
+    steps[start..].reverse();
+}
+
 struct ChdirLoopEnv {
-    counter: u32,
+    /// symlinks which are currently expanded by `chdir_internal()`
+    links: Vec<LinkId>,
+    /// path components resolved by this lookup; see
+    /// `Chroot::with_component_budget()`
+    components: u64,
     root_stat: Option<libc::stat>,
+    /// mount id of the root; set when foreign mounts are masked
+    root_mnt_id: Option<u64>,
+    /// followed symlinks; recorded for `Chroot::link_chain()` only
+    trace: Option<Vec<LinkHop>>,
+    /// set by the first `Chroot::check_interrupt()`
+    deadline: Option<Instant>,
+    /// targets of symlinks read in this operation; filled with
+    /// `Chroot::with_link_cache()` only
+    link_targets: HashMap<LinkId, OsString>,
 }
 
 impl ChdirLoopEnv {
     fn new() -> ChdirLoopEnv {
         ChdirLoopEnv {
-            counter: MAX_LOOP_CNT,
+            links: Vec::new(),
+            components: 0,
             root_stat: None,
+            root_mnt_id: None,
+            trace: None,
+            deadline: None,
+            link_targets: HashMap::new(),
         }
     }
 }
 
 impl fmt::Debug for ChdirLoopEnv {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "counter={:?}, root_stat={:?}",
-               self.counter, self.root_stat.map(|_| "..."))
+        write!(f, "depth={:?}, root_stat={:?}",
+               self.links.len(), self.root_stat.map(|_| "..."))
+    }
+}
+
+/// Keeps the chain of directories resolved by the last lookup of a
+/// batch operation.  When inputs are sorted, consecutive lookups can
+/// continue from the longest common prefix instead of walking the
+/// whole path again.
+struct DirCache {
+    base_fd: Fd,
+    chain: Vec<(PathBuf, Fd)>,
+}
+
+impl DirCache {
+    fn new(base_fd: Fd) -> Self {
+        DirCache {
+            base_fd: base_fd,
+            chain: Vec::new(),
+        }
+    }
+
+    fn resolve(&mut self, chroot: &Chroot, dir: &Path,
+               env: &mut ChdirLoopEnv) -> Result<Fd>
+    {
+        while let Some((p, _)) = self.chain.last() {
+            if dir.starts_with(p) {
+                break;
+            }
+
+            self.chain.pop();
+        }
+
+        let (mut fd, mut cur) = match self.chain.last() {
+            Some((p, fd)) => (fd.clone(), p.clone()),
+            None => (self.base_fd.clone(), PathBuf::new()),
+        };
+
+        let rest = dir.strip_prefix(&cur).unwrap().to_owned();
+
+        // resolving component by component is equivalent to resolving
+        // the whole path because symlinks are expanded relative to
+        // the directory containing them
+        for c in rest.components() {
+            fd = chroot.chdir_internal(fd, Path::new(c.as_os_str()), env)?;
+            cur.push(c);
+
+            self.chain.push((cur.clone(), fd.clone()));
+        }
+
+        Ok(fd)
+    }
+}
+
+/// Options for `Chroot::create_with()`
+#[derive(Clone, Debug)]
+pub struct CreateOptions {
+    /// flags passed to `openat()`; `O_CREAT` is added automatically
+    pub flags:		libc::c_int,
+    /// mode of the created file
+    pub mode:		u32,
+    /// create missing parent directories
+    pub create_parents:	bool,
+    /// mode of created parent directories
+    pub parent_mode:	u32,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            flags:		libc::O_WRONLY | libc::O_CLOEXEC,
+            mode:		0o666,
+            create_parents:	false,
+            parent_mode:	0o777,
+        }
+    }
+}
+
+/// Attributes of directories created by `Chroot::create_dir_all_with()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirAttrs {
+    /// permissions; not modified by the umask
+    pub mode:	u32,
+    pub uid:	Option<libc::uid_t>,
+    pub gid:	Option<libc::gid_t>,
+}
+
+/// Source of the unique part of temporary names; see `TempNaming`
+pub type RandomSource = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+/// Names of the temporary files created by `Chroot::write_atomic()`
+/// and `Chroot::rename_or_copy()` before they are renamed to `comp`
+///
+/// Names are built as `<prefix><comp><suffix><unique>`.  Without a
+/// `random` source, the unique part consists of the process id and
+/// a counter; else it is the hex encoded result of `random`.
+/// Services which share a chroot can use distinct prefixes to keep
+/// out of each other's way; tests can use a deterministic source.
+#[derive(Clone)]
+pub struct TempNaming {
+    pub prefix:	OsString,
+    pub suffix:	OsString,
+    pub random:	Option<RandomSource>,
+}
+
+impl Default for TempNaming {
+    fn default() -> Self {
+        TempNaming {
+            prefix:	".".into(),
+            suffix:	".tmp".into(),
+            random:	None,
+        }
+    }
+}
+
+impl fmt::Debug for TempNaming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TempNaming")
+            .field("prefix", &self.prefix)
+            .field("suffix", &self.suffix)
+            .field("random", &self.random.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+impl TempNaming {
+    /// Returns a name for a temporary file which is renamed to `comp`
+    /// later
+    pub fn name(&self, comp: &std::ffi::OsStr) -> OsString {
+        use std::sync::atomic::AtomicUsize;
+
+        static TMP_CNT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut res = self.prefix.clone();
+
+        res.push(comp);
+        res.push(&self.suffix);
+
+        match self.random {
+            Some(ref r) => res.push(format!("{:016x}", r())),
+            None => res.push(format!("{}-{}", std::process::id(),
+                                     TMP_CNT.fetch_add(1, Ordering::Relaxed))),
+        }
+
+        res
+    }
+}
+
+/// number of names tried by `Chroot::create_tmp()`
+const TMP_ATTEMPTS: u32 = 16;
+
+/// Converts a freshly opened `Fd` into a `std::fs::File`
+fn fd_into_file(fd: Fd) -> Result<std::fs::File> {
+    fd.into_rawfd()
+        .map_err(|_| Error::from("filedescriptor is shared"))?
+        .into_file()
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    absolute_links:	AtomicU64,
+    root_clamps:	AtomicU64,
+    loop_limits:	AtomicU64,
+    link_cycles:	AtomicU64,
+    masked_mounts:	AtomicU64,
+    fallback_reads:	AtomicU64,
+    negative_hits:	AtomicU64,
+    policy_rejections:	AtomicU64,
+    stale_retries:	AtomicU64,
+    link_cache_hits:	AtomicU64,
+}
+
+impl Stats {
+    fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters of security relevant events while resolving paths
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChrootStats {
+    /// absolute symlinks which were resolved relative to the chroot
+    /// instead of the host root
+    pub absolute_links:	u64,
+    /// `..` components at the top directory which were clamped to it
+    pub root_clamps:	u64,
+    /// lookups aborted because of too many symlinks or path
+    /// components
+    pub loop_limits:	u64,
+    /// lookups aborted because of a symlink cycle
+    pub link_cycles:	u64,
+    /// lookups rejected by `Chroot::with_mask_foreign_mounts()`
+    pub masked_mounts:	u64,
+    /// lookups retried on the fallback root
+    pub fallback_reads:	u64,
+    /// lookups answered by `Chroot::with_negative_cache()`
+    pub negative_hits:	u64,
+    /// opens refused by `Chroot::with_type_guard()`
+    pub policy_rejections:	u64,
+    /// resolutions repeated because of `ESTALE`
+    pub stale_retries:	u64,
+    /// symlink targets taken from `Chroot::with_link_cache()`
+    pub link_cache_hits:	u64,
+}
+
+/// maximum number of entries in a `NegativeCache`
+const NEGATIVE_CACHE_SIZE: usize = 4096;
+
+/// Absolute paths whose lookup failed with `ENOENT`, together with
+/// the time when they were added
+///
+/// The generation is increased by `clear()`; misses of lookups which
+/// overlapped with it are not recorded because they might be stale.
+///
+/// With a `DirWatch`, misses are recorded only when the directory
+/// which lacks the entry could be watched; every event clears the
+/// cache.  The watch is recreated by `clear()` so that watches do not
+/// accumulate.
+#[derive(Debug)]
+struct NegativeCache {
+    ttl:	Duration,
+    generation:	AtomicU64,
+    entries:	Mutex<HashMap<PathBuf, Instant>>,
+    watch:	Option<Mutex<Option<DirWatch>>>,
+}
+
+impl NegativeCache {
+    fn new(ttl: Duration, watch: bool) -> Self {
+        NegativeCache {
+            ttl:	ttl,
+            generation:	AtomicU64::new(0),
+            entries:	Mutex::new(HashMap::new()),
+            watch:	match watch {
+                true	=> Some(Mutex::new(DirWatch::new().ok())),
+                false	=> None,
+            },
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        let changed = self.watch.as_ref()
+            .map(|w| w.lock().unwrap().as_ref().map(DirWatch::has_events).unwrap_or(true))
+            .unwrap_or(false);
+
+        if changed {
+            self.clear();
+            return false;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(path) {
+            Some(t) if t.elapsed() < self.ttl	=> true,
+            Some(_)				=> {
+                entries.remove(path);
+                false
+            },
+            None				=> false,
+        }
+    }
+
+    /// Records the miss of `path`.  With a watch, `watch_missing`
+    /// must watch the directory lacking the entry and return it
+    /// together with the missing name.
+    fn insert<F>(&self, path: &Path, generation: u64, watch_missing: F)
+    where
+        F: FnOnce(&DirWatch) -> Option<(Fd, OsString)>,
+    {
+        if let Some(ref watch) = self.watch {
+            let watch = watch.lock().unwrap();
+            let missing = watch.as_ref().and_then(watch_missing);
+
+            // the entry might have been created before the watch was
+            // registered
+            match missing.map(|(dir, name)| dir.fstatat(&name, false)) {
+                Some(Err(ref e)) if e.errno() == Some(libc::ENOENT) => {},
+                _ => return,
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if generation != self.generation() {
+            return;
+        }
+
+        if entries.len() >= NEGATIVE_CACHE_SIZE {
+            let ttl = self.ttl;
+
+            entries.retain(|_, t| t.elapsed() < ttl);
+        }
+
+        if entries.len() >= NEGATIVE_CACHE_SIZE {
+            entries.clear();
+        }
+
+        entries.insert(path.to_path_buf(), Instant::now());
+    }
+
+    fn clear(&self) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+
+            self.generation.fetch_add(1, Ordering::SeqCst);
+            entries.clear();
+        }
+
+        if let Some(ref watch) = self.watch {
+            *watch.lock().unwrap() = DirWatch::new().ok();
+        }
+    }
+}
+
+/// Operation reported to the hook of `Chroot::with_audit_hook()`
+#[derive(Debug)]
+pub struct AuditEvent<'a> {
+    /// name of the `Chroot` method
+    pub op:		&'static str,
+    /// path as passed to the method
+    pub path:		&'a Path,
+    /// absolute path within the chroot without symlinks; `None` when
+    /// the operation failed or the path of its result could not be
+    /// determined
+    pub canonical:	Option<&'a Path>,
+    /// `None` when the operation succeeded
+    pub error:		Option<&'a Error>,
+    /// time spent in the operation; determining `canonical` is not
+    /// included
+    pub latency:	Duration,
+}
+
+/// Callback registered by `Chroot::with_audit_hook()`
+pub type AuditHook = Arc<dyn Fn(&AuditEvent) + Send + Sync>;
+
+#[derive(Clone)]
+struct AuditHookFn(AuditHook);
+
+impl fmt::Debug for AuditHookFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuditHook")
+    }
+}
+
+#[derive(Clone)]
+struct ControlFn(ControlRef);
+
+impl fmt::Debug for ControlFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Control")
     }
 }
 
+/// Result of `Chroot::resolve_component()`
+#[cfg(feature = "unstable-resolver")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ComponentStep {
+    /// `.` or an entry which is not a symlink
+    Normal,
+    /// `/`; the top directory was opened
+    Root,
+    /// `..` below the top directory
+    Parent,
+    /// `..` at the top directory which stayed there
+    RootClamp,
+    /// symlink with the given content which was resolved completely
+    Symlink(PathBuf),
+}
+
+/// Symlink followed while resolving a path; see `Chroot::link_chain()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkHop {
+    /// absolute path of the symlink within the chroot
+    pub link:	PathBuf,
+    /// content of the symlink
+    pub target:	PathBuf,
+}
+
+/// Identity of a chroot top directory
+///
+/// Can be stored by long running programs to detect whether the
+/// directory was replaced by `Chroot::reattach()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChrootIdentity {
+    pub path:	PathBuf,
+    pub dev:	u64,
+    pub ino:	u64,
+    pub mnt_id:	u64,
+}
+
 struct DirInfo {
     is_root: bool,
     stat: libc::stat,
@@ -79,293 +545,2094 @@ struct DirInfo {
 /// will access `/srv/www/etc/passwd` instead of `/etc/passwd`.
 #[derive(Debug)]
 pub struct Chroot {
-    root: PathBuf
+    root: PathBuf,
+    /// `O_PATH` descriptor of the root kept by `Chroot::open_root()`
+    root_dir: Option<Arc<FdRaw>>,
+    sync_dirs: bool,
+    symlink_limit: u32,
+    component_budget: u64,
+    mask_foreign_mounts: bool,
+    type_guard: bool,
+    max_file_size: Option<u64>,
+    stale_retries: u32,
+    link_cache: bool,
+    timeout: Option<Duration>,
+    cancel: Option<Arc<AtomicBool>>,
+    control: Option<ControlFn>,
+    fallback: Option<PathBuf>,
+    negative_cache: Option<NegativeCache>,
+    cache_watch: bool,
+    audit_hook: Option<AuditHookFn>,
+    temp_naming: TempNaming,
+    file_mode: u32,
+    dir_mode: u32,
+    umask: Option<u32>,
+    drop_page_cache: bool,
+    preserve_dir_times: bool,
+    stats: Stats,
 }
 
 impl Chroot {
     pub fn new<T: AsRef<Path>>(root: &T) -> Self {
         Chroot {
             root: root.as_ref().to_path_buf(),
+            root_dir: None,
+            sync_dirs: false,
+            symlink_limit: MAX_LOOP_CNT,
+            component_budget: COMPONENT_BUDGET,
+            mask_foreign_mounts: false,
+            type_guard: true,
+            max_file_size: None,
+            stale_retries: 0,
+            link_cache: false,
+            timeout: None,
+            cancel: None,
+            control: None,
+            fallback: None,
+            negative_cache: None,
+            cache_watch: false,
+            audit_hook: None,
+            temp_naming: TempNaming::default(),
+            file_mode: 0o666,
+            dir_mode: 0o777,
+            umask: None,
+            drop_page_cache: false,
+            preserve_dir_times: false,
+            stats: Stats::default(),
         }
     }
 
-    /// Opens the top level directory of the chroot directory and
-    /// returns the filedescriptor.
+    /// Opens `root` and returns a chroot on it.
     ///
-    /// The directory will be opened with `O_CLOEXEC` flag being set.
-    pub fn root_fdraw(&self) -> Result<FdRaw> {
-        let open_flags = libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY;
+    /// Unlike `Self::new()`, this fails immediately with
+    /// `ErrorKind::InvalidRoot` when `root` does not exist, is not a
+    /// directory or can not be searched; it carries `ENOENT`,
+    /// `ENOTDIR` or `EACCES` then.  The directory is kept open
+    /// and used by all operations; it is not looked up again when
+    /// `root` is renamed or replaced.
+    pub fn open_root<T: AsRef<Path>>(root: &T) -> Result<Self> {
+        let root = root.as_ref();
+        let invalid = |e: Error, errno| {
+            Error::with_chain(e, ErrorKind::InvalidRoot(root.to_path_buf(), errno))
+        };
+
+        let fd = FdRaw::open(&root, libc::O_PATH | libc::O_CLOEXEC)
+            .map_err(|e| match e.errno() {
+                Some(errno @ libc::ENOENT) |
+                Some(errno @ libc::ENOTDIR) |
+                Some(errno @ libc::EACCES)	=> invalid(e, errno),
+                _				=> e,
+            })?;
+
+        if !fd.metadata()?.is_dir() {
+            bail!(ErrorKind::InvalidRoot(root.to_path_buf(), libc::ENOTDIR));
+        }
+
+        // lookups below the root require search permission
+        fd.openat(&".", PATH_OPEN_FLAGS)
+            .map_err(|e| match e.errno() {
+                Some(libc::EACCES)	=> invalid(e, libc::EACCES),
+                _			=> e,
+            })?;
+
+        Ok(Chroot {
+            root_dir: Some(Arc::new(fd)),
+            .. Chroot::new(&root)
+        })
+    }
+
+    /// Returns the counters of escape attempts and similar events of
+    /// all operations on this object so far.
+    pub fn stats(&self) -> ChrootStats {
+        ChrootStats {
+            absolute_links:	self.stats.absolute_links.load(Ordering::Relaxed),
+            root_clamps:	self.stats.root_clamps.load(Ordering::Relaxed),
+            loop_limits:	self.stats.loop_limits.load(Ordering::Relaxed),
+            link_cycles:	self.stats.link_cycles.load(Ordering::Relaxed),
+            masked_mounts:	self.stats.masked_mounts.load(Ordering::Relaxed),
+            fallback_reads:	self.stats.fallback_reads.load(Ordering::Relaxed),
+            negative_hits:	self.stats.negative_hits.load(Ordering::Relaxed),
+            policy_rejections:	self.stats.policy_rejections.load(Ordering::Relaxed),
+            stale_retries:	self.stats.stale_retries.load(Ordering::Relaxed),
+            link_cache_hits:	self.stats.link_cache_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Enables `fsync()` of the parent directories after
+    /// `Self::swap()` and `Self::write_atomic()`.
+    pub fn with_sync_dirs(mut self, enable: bool) -> Self {
+        self.sync_dirs = enable;
+        self
+    }
+
+    /// Sets the maximum number of symlinks which are followed while
+    /// resolving a single path (default: 256).
+    ///
+    /// Cycles are detected independently of this limit, so it only
+    /// needs to be raised for deep but acyclic link chains.
+    pub fn with_symlink_limit(mut self, limit: u32) -> Self {
+        self.symlink_limit = limit;
+        self
+    }
+
+    /// Sets the maximum number of path components which are resolved
+    /// by a single lookup (default: 65536).  Components of followed
+    /// symlink targets are counted too, so this bounds the work for
+    /// trees with many links which expand to each other.  Lookups
+    /// exceeding it fail with `ErrorKind::BudgetExceeded`.
+    ///
+    /// Batch operations like `Self::metadata_many()` apply it to every
+    /// path separately.
+    pub fn with_component_budget(mut self, budget: u64) -> Self {
+        self.component_budget = budget;
+        self
+    }
+
+    /// Refuses opening character and block devices and FIFOs with
+    /// `ErrorKind::SpecialFile`.  This is enabled by default; it
+    /// prevents untrusted trees from blocking the caller on a FIFO or
+    /// giving access to devices.
+    ///
+    /// The type is checked before and after the file is opened; the
+    /// latter with `O_NONBLOCK` which is cleared again when it was not
+    /// requested.  `O_PATH` opens are not restricted.
+    pub fn with_type_guard(mut self, enable: bool) -> Self {
+        self.type_guard = enable;
+        self
+    }
+
+    /// Refuses opening regular files larger than `limit` bytes for
+    /// reading with `ErrorKind::FileTooLarge`; other file types are
+    /// not checked.  `Self::read()` and
+    /// `Self::read_to_string()` fail too when the file grows beyond
+    /// the limit while it is read.
+    ///
+    /// Opens with `O_PATH`, `O_WRONLY` or `O_TRUNC` are not
+    /// restricted.
+    pub fn with_max_file_size(mut self, limit: Option<u64>) -> Self {
+        self.max_file_size = limit;
+        self
+    }
+
+    /// Repeats the whole resolution up to `retries` times when it
+    /// failed with `ESTALE`.
+    ///
+    /// NFS file handles of the directories held open while walking
+    /// can become stale when the server side tree is modified.
+    /// Applies to the methods listed at `Self::with_fallback()`, to
+    /// `Self::openat()` with write flags and to `Self::create_with()`.
+    pub fn with_stale_retries(mut self, retries: u32) -> Self {
+        self.stale_retries = retries;
+        self
+    }
+
+    /// Remembers the targets of symlinks during a single operation so
+    /// that paths which pass the same links repeatedly (e.g.
+    /// `/ld0/../ld0/../ld0`) read them only once.
+    ///
+    /// Links are identified by the inodes of the link and its
+    /// directory.  The cache is discarded when the operation returns.
+    pub fn with_link_cache(mut self, enable: bool) -> Self {
+        self.link_cache = enable;
+        self
+    }
+
+    /// Drops the page cache of files after they were copied by
+    /// `Self::snapshot()` or `Self::rename_or_copy()` so that copying
+    /// large trees does not evict the working set of other
+    /// processes.  See `FdRaw::drop_page_cache()`.
+    pub fn with_drop_page_cache(mut self, enable: bool) -> Self {
+        self.drop_page_cache = enable;
+        self
+    }
+
+    /// Calls `FdRaw::drop_page_cache()` for `fds` when enabled by
+    /// `Self::with_drop_page_cache()`; errors are ignored because the
+    /// advice is optional.
+    pub(crate) fn advise_copied(&self, fds: &[&FdRaw]) {
+        if self.drop_page_cache {
+            for fd in fds {
+                let _ = fd.drop_page_cache();
+            }
+        }
+    }
+
+    /// Returns the target of the symlink `name` in `dir_fd` with the
+    /// id `id`; see `Self::with_link_cache()`.
+    fn read_link(&self, dir_fd: &Fd, name: &Path, id: &LinkId,
+                 env: &mut ChdirLoopEnv) -> Result<OsString>
+    {
+        if !self.link_cache {
+            return dir_fd.readlinkat(&name);
+        }
+
+        if let Some(target) = env.link_targets.get(id) {
+            Stats::inc(&self.stats.link_cache_hits);
+            return Ok(target.clone());
+        }
+
+        let target = dir_fd.readlinkat(&name)?;
+
+        env.link_targets.insert(*id, target.clone());
+
+        Ok(target)
+    }
+
+    /// Runs `f` again while it fails with `ESTALE` and
+    /// `Self::with_stale_retries()` permits it.
+    fn retry_stale<R, F>(&self, f: F) -> Result<R>
+    where
+        F: Fn() -> Result<R>,
+    {
+        let mut retries = self.stale_retries;
+
+        loop {
+            match f() {
+                Err(ref e) if e.errno() == Some(libc::ESTALE) && retries > 0 => {
+                    Stats::inc(&self.stats.stale_retries);
+                    retries -= 1;
+                },
+                r => break r,
+            }
+        }
+    }
+
+    /// Hides mount points below the root.  Lookups which enter a
+    /// directory or open a file on another mount than the root fail
+    /// with `ENOENT`; see `Self::foreign_mounts()` for listing them.
+    ///
+    /// Requires mount ids (Linux 5.8).  Only opened components are
+    /// checked, so e.g. `Self::fstatat()` on a mount point itself
+    /// still succeeds.
+    pub fn with_mask_foreign_mounts(mut self, enable: bool) -> Self {
+        self.mask_foreign_mounts = enable;
+        self
+    }
+
+    /// Limits the duration of every single operation.  Lookups fail
+    /// with `ErrorKind::TimedOut` when the limit is exceeded.
+    ///
+    /// The deadline is checked between path components; a blocking
+    /// syscall (e.g. an `openat()` on a hanging network filesystem)
+    /// is not interrupted.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Registers a flag which aborts running and later operations
+    /// with `ErrorKind::Cancelled` once it is set.  Like the timeout,
+    /// it is checked between path components.
+    pub fn with_cancel_flag(mut self, flag: Option<Arc<AtomicBool>>) -> Self {
+        self.cancel = flag;
+        self
+    }
+
+    /// Registers a `Control` which can abort operations like the
+    /// cancel flag and which gets the progress of recursive
+    /// operations; see `crate::control`.
+    pub fn with_control(mut self, control: Option<ControlRef>) -> Self {
+        self.control = control.map(ControlFn);
+        self
+    }
+
+    /// Returns the progress counters for a new operation
+    pub(crate) fn progress(&self) -> Progress<'_> {
+        Progress::new(self.control.as_ref().map(|c| &*c.0))
+    }
+
+    /// Registers a callback which is invoked after `Self::chdir()`,
+    /// `Self::chdirat()`, `Self::open()`, `Self::openat()`,
+    /// `Self::create_with()` and `Self::apply_metadata()`; this
+    /// includes calls made by other methods of this crate.
+    ///
+    /// See `crate::eventlog` for a ready-made hook.
+    pub fn with_audit_hook(mut self, hook: Option<AuditHook>) -> Self {
+        self.audit_hook = hook.map(AuditHookFn);
+        self
+    }
+
+    /// Sets the names of temporary files
+    pub fn with_temp_naming(mut self, naming: TempNaming) -> Self {
+        self.temp_naming = naming;
+        self
+    }
+
+    /// Sets the modes of files and directories which are created
+    /// without explicit mode; e.g. by `Self::open()` with `O_CREAT`,
+    /// `Self::write_atomic()` or with the `CreateOptions` of
+    /// `Self::create_options()`.  Defaults are `0o666` and `0o777`.
+    pub fn with_create_modes(mut self, file_mode: u32, dir_mode: u32) -> Self {
+        self.file_mode = file_mode;
+        self.dir_mode = dir_mode;
+        self
+    }
+
+    /// Returns the default `CreateOptions` with the modes set by
+    /// `Self::with_create_modes()`.
+    pub fn create_options(&self) -> CreateOptions {
+        CreateOptions {
+            mode:		self.file_mode,
+            parent_mode:	self.dir_mode,
+            .. CreateOptions::default()
+        }
+    }
+
+    /// Replaces the process umask for files and directories created
+    /// within the chroot.  With `Some(mask)`, they get their mode
+    /// minus `mask` exactly, no matter what the umask of the process
+    /// is; e.g. `Some(0o022)` prevents group or other writable files.
+    /// With `None` (the default), the process umask applies.
+    pub fn with_umask(mut self, umask: Option<u32>) -> Self {
+        self.umask = umask;
+        self
+    }
+
+    /// Keeps the access and modification times of directories in
+    /// which entries are created, removed or renamed by this object;
+    /// e.g. for reproducible images.  The times are captured before
+    /// and restored after every such operation, also when it failed.
+    ///
+    /// Restoring requires ownership of the directories (or
+    /// `CAP_FOWNER`) and changes their ctime.  Directories which are
+    /// created or emptied by the operation itself are not covered.
+    pub fn with_preserve_dir_times(mut self, enable: bool) -> Self {
+        self.preserve_dir_times = enable;
+        self
+    }
+
+    /// Runs `f` which changes the entries of `dirs` and restores the
+    /// times of `dirs` when enabled by `Self::with_preserve_dir_times()`.
+    pub(crate) fn keeping_dir_times<R, F>(&self, dirs: &[&Fd], f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        if !self.preserve_dir_times {
+            return f();
+        }
+
+        let times = |st: &libc::stat| (st.st_atime, st.st_atime_nsec,
+                                       st.st_mtime, st.st_mtime_nsec);
+        let orig = dirs.iter().map(|d| d.fstat()).collect::<Result<Vec<_>>>()?;
+        let res = f();
+
+        for (dir, st) in dirs.iter().zip(&orig) {
+            if dir.fstat().map(|now| times(&now) == times(st)).unwrap_or(false) {
+                continue;
+            }
+
+            let restored = dir.futimens(
+                libc::timespec { tv_sec: st.st_atime, tv_nsec: st.st_atime_nsec },
+                libc::timespec { tv_sec: st.st_mtime, tv_nsec: st.st_mtime_nsec });
+
+            if let (Err(e), true) = (restored, res.is_ok()) {
+                return Err(e);
+            }
+        }
+
+        res
+    }
+
+    /// Sets the mode of the just created `fd` when the process umask
+    /// is replaced by `Self::with_umask()`.
+    fn apply_umask(&self, fd: &FdRaw, mode: u32) -> Result<()> {
+        match self.umask {
+            Some(mask) => fd.fchmod(mode & !mask),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates `comp` in `dir_fd` with `flags | O_CREAT` and `mode`.
+    /// When the process umask is replaced, files which already exist
+    /// are opened without modifying their mode.
+    pub(crate) fn create_entry(&self, dir_fd: &Fd, comp: &std::ffi::OsStr,
+                               flags: libc::c_int, mode: u32) -> Result<Fd>
+    {
+        let comp = Path::new(comp);
+        let mask = match self.umask {
+            Some(mask) => mask,
+            None => return self.keeping_dir_times(&[dir_fd], || {
+                dir_fd.createat(&comp, flags, mode)
+            }),
+        };
+
+        self.keeping_dir_times(&[dir_fd], || loop {
+            match dir_fd.createat(&comp, flags | libc::O_EXCL, mode & !mask) {
+                Ok(fd) => {
+                    self.apply_umask(&fd, mode)?;
+                    break Ok(fd);
+                },
+                Err(ref e) if e.errno() == Some(libc::EEXIST) &&
+                    flags & libc::O_EXCL == 0 => {},
+                Err(e) => break Err(e),
+            }
+
+            match dir_fd.openat(&comp, flags & !libc::O_CREAT) {
+                // removed meanwhile; create it again
+                Err(ref e) if e.errno() == Some(libc::ENOENT) => continue,
+                r => break r,
+            }
+        })
+    }
+
+    /// Fails with `ErrorKind::ReadOnlyMount` for `path` when `dir_fd`
+    /// is on a read-only mount.  Operations which consist of several
+    /// steps check this upfront instead of failing with `EROFS` in
+    /// the middle.
+    pub(crate) fn ensure_writable(&self, dir_fd: &Fd, path: &Path) -> Result<()> {
+        if dir_fd.is_on_readonly_mount()? {
+            bail!(ErrorKind::ReadOnlyMount(path.to_path_buf()));
+        }
+
+        Ok(())
+    }
+
+    /// Creates a temporary file for `comp` in `dir_fd` and returns
+    /// its name.  Other names are tried when it exists already.
+    pub(crate) fn create_tmp(&self, dir_fd: &Fd, comp: &std::ffi::OsStr, mode: u32)
+                             -> Result<(OsString, Fd)>
+    {
+        let mut attempts = TMP_ATTEMPTS;
+
+        loop {
+            let name = self.temp_naming.name(comp);
+
+            crate::pathutil::validate_component(&name)?;
+
+            match self.create_entry(dir_fd, &name, libc::O_WRONLY | libc::O_CLOEXEC |
+                                    libc::O_EXCL | libc::O_NOFOLLOW, mode) {
+                Err(ref e) if e.errno() == Some(libc::EEXIST) && attempts > 1 =>
+                    attempts -= 1,
+                r => break r.map(|fd| (name, fd)),
+            }
+        }
+    }
+
+    /// Runs `f` and reports it as `op` on `path` to the audit hook
+    pub(crate) fn audited<R, F>(&self, op: &'static str, path: &Path,
+                                canonical: Option<&Path>, f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        self.audited_with(op, path, f, |_| canonical.map(Path::to_path_buf))
+    }
+
+    /// Like `Self::audited()` but determines the canonical path from
+    /// the result of a successful operation; `canonical` is called
+    /// only when a hook is registered.
+    pub(crate) fn audited_with<R, F, C>(&self, op: &'static str, path: &Path,
+                                        f: F, canonical: C) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+        C: FnOnce(&R) -> Option<PathBuf>,
+    {
+        let hook = match self.audit_hook {
+            Some(AuditHookFn(ref hook)) => hook,
+            None => return f(),
+        };
+
+        let start = Instant::now();
+        let res = f();
+        let latency = start.elapsed();
+        let canonical = res.as_ref().ok().and_then(canonical);
+
+        hook(&AuditEvent {
+            op:		op,
+            path:	path,
+            canonical:	canonical.as_deref(),
+            error:	res.as_ref().err(),
+            latency:	latency,
+        });
+
+        res
+    }
+
+    /// Like `Self::audited()` for operations which return a
+    /// descriptor; the canonical path is the one of `Self::fd_path()`.
+    fn audited_fd<F>(&self, op: &'static str, path: &Path, f: F) -> Result<Fd>
+    where
+        F: FnOnce() -> Result<Fd>,
+    {
+        self.audited_with(op, path, f, |fd| self.fd_path(fd).ok())
+    }
+
+    /// Fails when the operation was cancelled or exceeded its
+    /// timeout.  `deadline` must be `None` at the start of the
+    /// operation.
+    pub(crate) fn check_interrupt(&self, deadline: &mut Option<Instant>)
+                                  -> Result<()>
+    {
+        if let Some(ref flag) = self.cancel {
+            if flag.load(Ordering::Relaxed) {
+                bail!(ErrorKind::Cancelled);
+            }
+        }
+
+        if let Some(ControlFn(ref control)) = self.control {
+            if control.should_stop() {
+                bail!(ErrorKind::Cancelled);
+            }
+        }
+
+        if let Some(timeout) = self.timeout {
+            let now = Instant::now();
+
+            match *deadline {
+                None => *deadline = Some(now + timeout),
+                Some(d) if now > d => bail!(ErrorKind::TimedOut),
+                Some(_) => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `path` like `Self::open()` and returns the directory
+    /// and name of the final entry.  When `path` does not end with a
+    /// normal component (e.g. `/` or `a/..`), the directory itself
+    /// and `.` are returned.
+    pub(crate) fn resolve_entry(&self, path: &Path) -> Result<(Fd, OsString)> {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
+
+        match path.components().next_back() {
+            Some(std::path::Component::Normal(_)) =>
+                self.resolve_final(&root_fd, path, &mut env),
+            _ =>
+                Ok((self.chdir_internal(root_fd, path, &mut env)?, ".".into())),
+        }
+    }
+
+    /// Applies `Self::with_mask_foreign_mounts()` to a descriptor
+    /// which was opened outside of the resolver.
+    pub(crate) fn check_opened(&self, fd: Fd) -> Result<Fd> {
+        self.check_mount(fd, &mut ChdirLoopEnv::new())
+    }
+
+    /// Returns a chroot on `root` with the same settings but without
+    /// fallback and negative cache.  Statistics are not shared.
+    fn derive(&self, root: &Path) -> Chroot {
+        Chroot {
+            root: root.to_path_buf(),
+            root_dir: None,
+            sync_dirs: self.sync_dirs,
+            symlink_limit: self.symlink_limit,
+            component_budget: self.component_budget,
+            mask_foreign_mounts: self.mask_foreign_mounts,
+            type_guard: self.type_guard,
+            max_file_size: self.max_file_size,
+            stale_retries: self.stale_retries,
+            link_cache: self.link_cache,
+            timeout: self.timeout,
+            cancel: self.cancel.clone(),
+            control: self.control.clone(),
+            fallback: None,
+            negative_cache: None,
+            cache_watch: self.cache_watch,
+            audit_hook: self.audit_hook.clone(),
+            temp_naming: self.temp_naming.clone(),
+            file_mode: self.file_mode,
+            dir_mode: self.dir_mode,
+            umask: self.umask,
+            drop_page_cache: self.drop_page_cache,
+            preserve_dir_times: self.preserve_dir_times,
+            stats: Stats::default(),
+        }
+    }
+
+    /// Returns a chroot with the same settings but without masking
+    /// foreign mounts.  Statistics are not shared.
+    pub(crate) fn unmasked(&self) -> Chroot {
+        Chroot {
+            mask_foreign_mounts: false,
+            fallback: self.fallback.clone(),
+            root_dir: self.root_dir.clone(),
+            .. self.derive(&self.root)
+        }
+    }
+
+    /// Sets a read-only fallback root (e.g. a pristine image).
+    ///
+    /// Read-only lookups of absolute paths which fail with `ENOENT`
+    /// are retried below `root`: `Self::chdir()`, `Self::open()` and
+    /// `Self::openat()` without write flags, `Self::fstatat()`,
+    /// `Self::readlinkat()`, `Self::existsat()`, the `is_*at()`
+    /// methods and `Self::metadata_many()`.  All other operations and
+    /// lookups relative to a directory descriptor use the primary
+    /// root only.
+    pub fn with_fallback<T: AsRef<Path>>(mut self, root: Option<&T>) -> Self {
+        self.fallback = root.map(|r| r.as_ref().to_path_buf());
+        self
+    }
+
+    /// Remembers absolute paths whose read-only lookup failed with
+    /// `ENOENT` for `ttl` and lets further lookups of them fail
+    /// without accessing the filesystem.
+    ///
+    /// This helps workloads which probe many non-existing paths
+    /// (e.g. module searches of interpreters).  The cache is cleared
+    /// by every operation of this object which creates, renames or
+    /// removes entries.  Changes done by other means become visible
+    /// only after `ttl` or `Self::clear_negative_cache()`.  It covers
+    /// the lookups which are retried by `Self::with_fallback()`
+    /// except `Self::metadata_many()` and is used by
+    /// `Self::exists_many()`; paths are compared literally.
+    pub fn with_negative_cache(mut self, ttl: Option<Duration>) -> Self {
+        let watch = self.cache_watch;
+
+        self.negative_cache = ttl.map(|ttl| NegativeCache::new(ttl, watch));
+        self
+    }
+
+    /// Watches the directories which lack the entries recorded by
+    /// `Self::with_negative_cache()` with inotify and clears the cache
+    /// when entries are created, renamed or removed in them.  This
+    /// makes changes done by other processes visible before the `ttl`
+    /// expired.
+    ///
+    /// Misses are not recorded when the directory can not be watched
+    /// (e.g. because of the limit of inotify watches).  Changes of
+    /// parent directories or of the fallback root are not noticed.
+    pub fn with_cache_watch(mut self, enable: bool) -> Self {
+        self.cache_watch = enable;
+
+        let ttl = self.negative_cache.as_ref().map(|c| c.ttl);
+
+        self.with_negative_cache(ttl)
+    }
+
+    /// Watches the deepest existing directory of `path` and returns
+    /// it together with the name of the missing entry in it.
+    fn watch_missing(&self, watch: &DirWatch, path: &Path) -> Option<(Fd, OsString)> {
+        for dir in path.ancestors().skip(1) {
+            let name = match path.strip_prefix(dir).ok()?.components().next()? {
+                std::path::Component::Normal(n) => n.to_os_string(),
+                _ => return None,
+            };
+
+            let dir_fd = self.open_root_dir()
+                .and_then(|root| self.chdir_internal(root, dir, &mut ChdirLoopEnv::new()));
+
+            match dir_fd {
+                Ok(fd) => {
+                    watch.add(&fd).ok()?;
+                    return Some((fd, name));
+                },
+                Err(ref e) if e.errno() == Some(libc::ENOENT) ||
+                    e.errno() == Some(libc::ENOTDIR) => continue,
+                Err(_) => return None,
+            }
+        }
+
+        None
+    }
+
+    /// Forgets all entries of `Self::with_negative_cache()`
+    pub fn clear_negative_cache(&self) {
+        if let Some(ref cache) = self.negative_cache {
+            cache.clear();
+        }
+    }
+
+    /// Runs the read-only lookup `f` on this chroot and repeats it on
+    /// the fallback root when it failed with `ENOENT` for an absolute
+    /// `path`.  Such misses are recorded in the negative cache.
+    fn read_lookup<R, F>(&self, path: &Path, f: F) -> Result<R>
+    where
+        F: Fn(&Chroot) -> Result<R>,
+    {
+        let cache = self.negative_cache.as_ref()
+            .filter(|_| path.is_absolute());
+
+        if cache.map(|c| c.contains(path)).unwrap_or(false) {
+            Stats::inc(&self.stats.negative_hits);
+            return Err(std::io::Error::from_raw_os_error(libc::ENOENT).into());
+        }
+
+        let generation = cache.map(NegativeCache::generation);
+
+        let res = match (self.retry_stale(|| f(self)), &self.fallback) {
+            (Err(ref e), Some(root)) if e.errno() == Some(libc::ENOENT) &&
+                path.is_absolute() => {
+                let fallback = self.derive(root);
+
+                Stats::inc(&self.stats.fallback_reads);
+                self.retry_stale(|| f(&fallback))
+            },
+            (r, _) => r,
+        };
+
+        match (&res, cache, generation) {
+            (Err(e), Some(c), Some(g)) if e.errno() == Some(libc::ENOENT) =>
+                c.insert(path, g, |w| self.watch_missing(w, path)),
+            _ => {},
+        }
+
+        res
+    }
+
+    /// Fails with `ENOENT` when foreign mounts are masked and `fd` is
+    /// not on the mount of the root.
+    fn check_mount(&self, fd: Fd, env: &mut ChdirLoopEnv) -> Result<Fd> {
+        if !self.mask_foreign_mounts {
+            return Ok(fd);
+        }
+
+        if env.root_mnt_id.is_none() {
+            env.root_mnt_id = Some(self.open_root_dir()?.mount_id()?);
+        }
+
+        if Some(fd.mount_id()?) != env.root_mnt_id {
+            Stats::inc(&self.stats.masked_mounts);
+            return Err(std::io::Error::from_raw_os_error(libc::ENOENT).into());
+        }
+
+        Ok(fd)
+    }
+
+    /// Records the hop from `dir_fd`/`name` to `target` when the
+    /// lookup is traced.
+    fn trace_link(&self, dir_fd: &Fd, name: &Path, target: &Path,
+                  env: &mut ChdirLoopEnv) -> Result<()>
+    {
+        if env.trace.is_none() {
+            return Ok(());
+        }
+
+        let link = self.full_path(dir_fd, Some(&name))?;
+
+        if let Some(trace) = env.trace.as_mut() {
+            trace.push(LinkHop {
+                link:	link.into(),
+                target:	target.to_path_buf(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether following the link `id` would exceed the limit
+    /// or close a cycle with the links in `active`.
+    fn check_link(&self, id: &LinkId, active: &[LinkId], path: &Path)
+                  -> Result<()>
+    {
+        if active.contains(id) {
+            Stats::inc(&self.stats.link_cycles);
+            bail!("symbolic link cycle while resolving '{:?}'", path);
+        }
+
+        if active.len() >= self.symlink_limit as usize {
+            Stats::inc(&self.stats.loop_limits);
+            bail!("too much loops while resolving symbolic link '{:?}'",
+                  path);
+        }
+
+        Ok(())
+    }
+
+    /// Flushes `dir_fd` when `sync_dirs` is enabled.  `dir_fd` may be
+    /// an `O_PATH` descriptor and is reopened for this purpose.
+    fn sync_dir(&self, dir_fd: &Fd) -> Result<()> {
+        if !self.sync_dirs {
+            return Ok(());
+        }
+
+        let dir = dir_fd.openat(&".", libc::O_DIRECTORY | libc::O_CLOEXEC |
+                                libc::O_RDONLY)?;
+
+        Barrier::new().dir(&dir).commit()
+    }
+
+    /// Returns path, device, inode and mount id of the top directory
+    pub fn identity(&self) -> Result<ChrootIdentity> {
+        let fd = self.open_root_dir()?;
+        let stat = fd.fstat()?;
+
+        Ok(ChrootIdentity {
+            path:	self.root.clone(),
+            dev:	stat.st_dev,
+            ino:	stat.st_ino,
+            mnt_id:	fd.mount_id()?,
+        })
+    }
+
+    /// Creates a chroot for a previously stored identity.
+    ///
+    /// Method fails when the directory at the stored path is not the
+    /// same one anymore (e.g. it was replaced or something else was
+    /// mounted over it).  The root is opened like by
+    /// `Self::open_root()` and the checked directory is kept, so that
+    /// a later replacement of the path does not affect the result.
+    pub fn reattach(identity: &ChrootIdentity) -> Result<Self> {
+        let res = Self::open_root(&identity.path)?;
+        let now = res.identity()?;
+
+        ensure!(now == *identity, "chroot {:?} was replaced", identity.path);
+
+        Ok(res)
+    }
+
+    /// Opens the top level directory of the chroot directory and
+    /// returns the filedescriptor.
+    ///
+    /// The directory will be opened with `O_CLOEXEC` flag being set.
+    pub fn root_fdraw(&self) -> Result<FdRaw> {
+        let open_flags = libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY;
+
+        match self.root_dir {
+            Some(ref fd)	=> fd.reopen(open_flags),
+            None		=> FdRaw::open(&self.root, open_flags),
+        }
+    }
+
+    pub fn root_fd(&self) -> Result<Fd> {
+        self.root_fdraw().map(Fd::from_rawfd)
+    }
+
+    /// Opens the top directory with `O_PATH`; like the intermediate
+    /// directories of a lookup, it requires search permission only.
+    fn open_root_dir(&self) -> Result<Fd> {
+        match self.root_dir {
+            Some(ref fd)	=> fd.dupfd(true).map(Fd::from_rawfd),
+            None		=> Fd::open(&self.root, PATH_OPEN_FLAGS & !libc::O_NOFOLLOW),
+        }
+    }
+
+    fn dir_info(&self, dir_fd: &Fd, env: &mut ChdirLoopEnv) -> Result<DirInfo> {
+        if env.root_stat.is_none() {
+            env.root_stat = Some(match self.root_dir {
+                Some(ref fd)	=> fd.fstat()?,
+                None		=> Fd::cwd().fstatat(&self.root, true)?,
+            });
+        }
+
+        let root_stat = env.root_stat.as_ref().unwrap();
+
+        let stat = dir_fd.fstatat(&".", false)?;
+        let is_root =
+            (stat.st_dev == root_stat.st_dev) &&
+            (stat.st_ino == root_stat.st_ino);
+
+        Ok(DirInfo {
+            stat: stat,
+            is_root: is_root,
+        })
+    }
+
+    /// Opens the directory at `path` within the chroot.
+    ///
+    /// Every intermediate symlinks will be resolved relative to to
+    /// the chroot.
+    ///
+    /// Intermediate directories are opened with `O_PATH` so that they
+    /// need search permission only; the returned descriptor is opened
+    /// with `O_RDONLY`.
+    ///
+    /// Restrictions: `path` must be absolute.
+    pub fn chdir<T>(&self, path: &T) -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        let path : &Path = path.as_ref();
+
+        ensure!(path.is_absolute(), "path '{:?}' not absolute", path);
+
+        self.audited_fd("chdir", path, || self.read_lookup(path, |c| {
+            let mut env: ChdirLoopEnv = ChdirLoopEnv::new();
+
+            c.chdir_internal(Fd::cwd(), path, &mut env)?
+                .openat(&".", DIR_OPEN_FLAGS)
+        }))
+    }
+
+    /// Opens a directory `path` in the chroot environment relative
+    /// to `fd`.
+    ///
+    /// Behaviour is unspecified if `fd` lies outside the chroot.
+    /// `path` can be relative.
+    pub fn chdirat<T>(&self, dir_fd: &Fd, path:  &T) -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        self.audited_fd("chdirat", path, || self.read_lookup(path, |c| {
+            let mut env: ChdirLoopEnv = ChdirLoopEnv::new();
+
+            c.chdir_internal(dir_fd.clone(), path, &mut env)?
+                .openat(&".", DIR_OPEN_FLAGS)
+        }))
+    }
+
+    /// Resolves a single path component relative to `dir_fd` and
+    /// reports what happened.
+    ///
+    /// This is the primitive behind `Self::chdir()` for custom
+    /// traversals.  A symlink is resolved completely like by
+    /// `Self::chdir()`.  The returned descriptor is opened with
+    /// `O_PATH`; for non-directories, the next step fails with
+    /// `ENOTDIR`.
+    ///
+    /// Requires the `unstable-resolver` feature; the function and
+    /// `ComponentStep` might change without a major version bump.
+    #[cfg(feature = "unstable-resolver")]
+    pub fn resolve_component(&self, dir_fd: &Fd, component: std::path::Component)
+                             -> Result<(Fd, ComponentStep)>
+    {
+        use std::path::Component;
+
+        let mut env = ChdirLoopEnv::new();
+
+        self.check_interrupt(&mut env.deadline)?;
+
+        match component {
+            Component::Normal(name) if dir_fd.is_lnkat(&Path::new(name)) => {
+                let name = Path::new(name);
+                let id = link_id(dir_fd, name)?;
+                let target = PathBuf::from(self.read_link(dir_fd, name, &id, &mut env)?);
+
+                if target.is_absolute() {
+                    Stats::inc(&self.stats.absolute_links);
+                }
+
+                env.links.push(id);
+
+                let fd = self.chdir_internal(dir_fd.clone(), &target, &mut env)?;
+
+                Ok((fd, ComponentStep::Symlink(target)))
+            },
+
+            Component::ParentDir if self.dir_info(dir_fd, &mut env)?.is_root => {
+                Stats::inc(&self.stats.root_clamps);
+                Ok((dir_fd.clone(), ComponentStep::RootClamp))
+            },
+
+            c => {
+                let step = match c {
+                    Component::RootDir => ComponentStep::Root,
+                    Component::ParentDir => ComponentStep::Parent,
+                    _ => ComponentStep::Normal,
+                };
+
+                Ok((self.open_component(dir_fd.clone(), c, &mut env)?, step))
+            },
+        }
+    }
+
+    fn open_component(&self, dir_fd: Fd,
+                      path: std::path::Component,
+                      env: &mut ChdirLoopEnv) -> Result<Fd>
+    {
+        let open_flags = PATH_OPEN_FLAGS;
+
+        match path {
+            std::path::Component::Prefix(_) => {
+                unreachable!();
+            },
+
+            std::path::Component::ParentDir => {
+                let info = self.dir_info(&dir_fd, env)?;
+
+                if info.is_root {
+                    Stats::inc(&self.stats.root_clamps);
+                    Ok(dir_fd)
+                } else {
+                    dir_fd.openat(&"..", open_flags)
+                }
+            },
+
+            std::path::Component::RootDir => {
+                self.open_root_dir()
+            },
+
+            std::path::Component::CurDir => {
+                Ok(dir_fd)
+            },
+
+            // no O_DIRECTORY; non-directories fail with ENOTDIR at
+            // the next lookup
+            std::path::Component::Normal(p) => {
+                self.check_mount(dir_fd.open_path_at(&p)?, env)
+            },
+        }
+    }
+
+    /// Resolves `path` relative to `dir_fd`.  Symlink targets are
+    /// expanded on an explicit stack instead of by recursion, so the
+    /// stack usage does not depend on the nesting of links.
+    fn chdir_internal(&self, dir_fd: Fd, path: &Path,
+                      env: &mut ChdirLoopEnv) -> Result<Fd>
+    {
+        let depth = env.links.len();
+        let res = self.chdir_steps(dir_fd, path, env);
+
+        // links of an aborted lookup are still active
+        env.links.truncate(depth);
+
+        res
+    }
+
+    fn chdir_steps(&self, dir_fd: Fd, path: &Path,
+                   env: &mut ChdirLoopEnv) -> Result<Fd>
+    {
+        use std::path::Component;
+
+        let mut dir_fd = dir_fd;
+        let mut steps = Vec::new();
+
+        push_steps(&mut steps, path);
+
+        while let Some(step) = steps.pop() {
+            let name = match step {
+                Step::LinkEnd => {
+                    env.links.pop();
+                    continue;
+                },
+                Step::Name(name) => name,
+                s => {
+                    self.consume_component(env)?;
+
+                    let c = match s {
+                        Step::Root	=> Component::RootDir,
+                        _		=> Component::ParentDir,
+                    };
+
+                    dir_fd = self.open_component(dir_fd, c, env)?;
+                    continue;
+                },
+            };
+
+            self.consume_component(env)?;
+
+            let tmp = Path::new(&name);
+
+            if !dir_fd.is_lnkat(&tmp) {
+                dir_fd = self.open_component(dir_fd, Component::Normal(&name), env)?;
+                continue;
+            }
+
+            let id = link_id(&dir_fd, tmp)?;
+
+            self.check_link(&id, &env.links, path)?;
+
+            let new_path = self.read_link(&dir_fd, tmp, &id, env)?;
+            let link = Path::new(&new_path);
+
+            self.trace_link(&dir_fd, tmp, link, env)?;
+
+            if link.is_absolute() {
+                Stats::inc(&self.stats.absolute_links);
+            }
+
+            env.links.push(id);
+            steps.push(Step::LinkEnd);
+            push_steps(&mut steps, link);
+        }
+
+        Ok(dir_fd)
+    }
+
+    /// Accounts a resolved component against the budget and checks
+    /// for interruptions.
+    fn consume_component(&self, env: &mut ChdirLoopEnv) -> Result<()> {
+        self.check_interrupt(&mut env.deadline)?;
+
+        env.components += 1;
+
+        if env.components > self.component_budget {
+            Stats::inc(&self.stats.loop_limits);
+            bail!(ErrorKind::BudgetExceeded("components", self.component_budget));
+        }
+
+        Ok(())
+    }
+
+    /// Splits `path` into the directory which must be resolved and
+    /// the final component.
+    pub(crate) fn split_path(path: &Path) -> (&Path, OsString) {
+        let current_dir = OsString::from(".");
+
+        match path.parent() {
+            None => (path, current_dir),
+
+            #[allow(clippy::unnecessary_lazy_evaluations)]
+            Some(p) => {
+                (p, path.file_name()
+                     .unwrap_or_else(|| current_dir.as_os_str())
+                     .to_os_string())
+            }
+        }
+    }
+
+    fn opendir_internal(&self, dir_fd: &Fd, path: &Path, env: &mut ChdirLoopEnv)
+                 -> Result<(Fd, OsString)>
+    {
+        let (dir, comp) = Self::split_path(path);
+
+        Ok((self.chdir_internal(dir_fd.clone(), dir, env)?, comp))
+    }
+
+    /// Opens a file in the chroot relative to an open directory `fd`.
+    ///
+    /// Method first opens the directory containing `path` as described
+    /// by `Self::chdirat()` and calls `openat()` with `O_NOFOLLOW
+    /// being set there.  Files created by `O_CREAT` get the mode set
+    /// by `Self::with_create_modes()` (modified by the umask).
+    pub fn openat<T>(&self, dir_fd: &Fd, path: &T, flags: libc::c_int)
+                     -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let is_write = (flags & libc::O_ACCMODE) != libc::O_RDONLY ||
+            (flags & (libc::O_CREAT | libc::O_TRUNC)) != 0;
+        let open = |c: &Chroot| {
+            let mut env = ChdirLoopEnv::new();
+
+            c.openat_internal(dir_fd, path, flags, c.file_mode, &mut env)
+        };
+
+        self.audited_fd("openat", path, || {
+            if is_write {
+                self.retry_stale(|| open(self))
+            } else {
+                self.read_lookup(path, open)
+            }
+        })
+    }
+
+    fn openat_internal(&self, dir_fd: &Fd, path: &Path, flags: libc::c_int,
+                       mode: u32, env: &mut ChdirLoopEnv) -> Result<Fd>
+    {
+        let (parent_fd, comp) = self.resolve_final(dir_fd, path, env)?;
+        let guarded = self.type_guard && flags & libc::O_PATH == 0;
+
+        if flags & libc::O_CREAT != 0 {
+            crate::pathutil::validate_component(&comp)?;
+        }
+
+        let size_limit = self.max_file_size.filter(|_| {
+            flags & (libc::O_PATH | libc::O_TRUNC) == 0 &&
+                flags & libc::O_ACCMODE != libc::O_WRONLY
+        });
+        let mut open_flags = flags | libc::O_NOFOLLOW;
+
+        if guarded {
+            match parent_fd.fstatat(&comp, false) {
+                Ok(st) => self.check_file_type(&st, path)?,
+                Err(ref e) if e.errno() == Some(libc::ENOENT) => {},
+                Err(e) => return Err(e),
+            }
+
+            open_flags |= libc::O_NONBLOCK;
+        }
+
+        let fd = if flags & libc::O_CREAT != 0 {
+            let fd = self.create_entry(&parent_fd, &comp, open_flags, mode);
+
+            self.clear_negative_cache();
+            fd?
+        } else {
+            parent_fd.openat(&comp, open_flags)?
+        };
+
+        if guarded || size_limit.is_some() {
+            // the entry might have been replaced after the first check
+            let st = fd.fstat()?;
+
+            if guarded {
+                self.check_file_type(&st, path)?;
+            }
+
+            // directories and other non-regular files report sizes
+            // which are unrelated to the data read from them
+            match size_limit {
+                Some(limit) if st.st_mode & libc::S_IFMT == libc::S_IFREG =>
+                    self.check_file_size(st.st_size as u64, limit, path)?,
+                _ => {},
+            }
+        }
+
+        if guarded && flags & libc::O_NONBLOCK == 0 {
+            fd.set_nonblocking(false)?;
+        }
+
+        self.check_mount(fd, env)
+    }
+
+    /// Implements `Self::with_max_file_size()`
+    fn check_file_size(&self, size: u64, limit: u64, path: &Path) -> Result<()> {
+        if size > limit {
+            Stats::inc(&self.stats.policy_rejections);
+            bail!(ErrorKind::FileTooLarge(path.to_path_buf(), limit))
+        }
+
+        Ok(())
+    }
+
+    /// Implements `Self::with_type_guard()`
+    fn check_file_type(&self, st: &libc::stat, path: &Path) -> Result<()> {
+        match st.st_mode & libc::S_IFMT {
+            libc::S_IFCHR | libc::S_IFBLK | libc::S_IFIFO => {
+                Stats::inc(&self.stats.policy_rejections);
+                bail!(ErrorKind::SpecialFile(path.to_path_buf()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolves `path` including a symlink in the final component and
+    /// returns the directory and the name of the resulting (possibly
+    /// missing) non-symlink entry.
+    fn resolve_final(&self, dir_fd: &Fd, path: &Path, env: &mut ChdirLoopEnv)
+                     -> Result<(Fd, OsString)>
+    {
+        let mut dir_fd = dir_fd.clone();
+        let mut path = path.to_owned();
+        // final components are followed one after another; every
+        // repeated link closes a cycle
+        let mut links = Vec::new();
+
+        loop {
+            self.check_interrupt(&mut env.deadline)?;
+
+            let (parent_fd, comp) =
+                self.opendir_internal(&dir_fd, &path, env)?;
+
+            assert!(env.links.is_empty());
+
+            if !parent_fd.is_lnkat(&comp) {
+                return Ok((parent_fd, comp));
+            }
+
+            let id = link_id(&parent_fd, Path::new(&comp))?;
+
+            self.check_link(&id, &links, &path)?;
+            links.push(id);
+
+            // the link target is relative to the directory containing
+            // the link
+            path = self.read_link(&parent_fd, Path::new(&comp), &id, env)?.into();
+            self.trace_link(&parent_fd, Path::new(&comp), &path, env)?;
+            dir_fd = parent_fd;
+
+            if path.is_absolute() {
+                Stats::inc(&self.stats.absolute_links);
+            }
+        }
+    }
+
+    /// Opens a file in the chroot environment.
+    ///
+    /// Method first opens the directory containing `path` as described
+    /// by `Self::chdir()` and calls `openat()` with `O_NOFOLLOW being
+    /// set there.
+    ///
+    /// All descriptors are opened with `O_CLOEXEC`.  When `flags`
+    /// contain `OpenFlags::INHERIT`, the returned one is opened
+    /// without it so that it is inherited by executed programs; the
+    /// intermediate directories are never inherited.
+    pub fn open<T>(&self, path: &T, flags: libc::c_int)
+                     -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        self.openat(&self.root_fd()?, path, flags)
+    }
+
+    /// Opens a file like `Self::open()` with `O_NONBLOCK` on the final
+    /// component.  The flag stays set on the returned descriptor.
+    ///
+    /// This avoids hanging on FIFOs or devices while probing paths.
+    /// Such files are still refused while `Self::with_type_guard()` is
+    /// enabled.
+    pub fn open_nonblocking<T>(&self, path: &T, flags: libc::c_int)
+                               -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        self.open(path, flags | libc::O_NONBLOCK)
+    }
+
+    /// Checks whether path is a symlink
+    ///
+    /// Method returns when errors occurred while performing the
+    /// lookup.
+    pub fn is_lnkat<T>(&self, dir_fd: &Fd, path: &T) -> bool
+    where
+        T: AsRef<Path>,
+    {
+        self.fstatat(dir_fd, path)
+            .map(|st| st.st_mode & libc::S_IFMT == libc::S_IFLNK)
+            .unwrap_or(false)
+    }
+
+    /// Checks whether path is a directory
+    ///
+    /// Method returns when errors occurred while performing the
+    /// lookup.
+    pub fn is_dirat<T>(&self, dir_fd: &Fd, path: &T) -> bool
+    where
+        T: AsRef<Path>,
+    {
+        self.fstatat(dir_fd, path)
+            .map(|st| st.st_mode & libc::S_IFMT == libc::S_IFDIR)
+            .unwrap_or(false)
+    }
+
+    /// Checks whether path is a regular file
+    ///
+    /// Method returns when errors occurred while performing the
+    /// lookup.
+    pub fn is_regat<T>(&self, dir_fd: &Fd, path: &T) -> bool
+    where
+        T: AsRef<Path>,
+    {
+        self.fstatat(dir_fd, path)
+            .map(|st| st.st_mode & libc::S_IFMT == libc::S_IFREG)
+            .unwrap_or(false)
+    }
+
+    /// Returns fstat information
+    ///
+    /// Intermediate directories are walked with `O_PATH` descriptors
+    /// so that only search permission is required on them.
+    pub fn fstatat<T>(&self, dir_fd: &Fd, fname: &T) -> Result<libc::stat>
+    where
+        T: AsRef<Path>,
+    {
+        let do_follow = false;
+
+        self.read_lookup(fname.as_ref(), |c| {
+            let mut env = ChdirLoopEnv::new();
+
+            c.opendir_internal(dir_fd, fname.as_ref(), &mut env)
+                .map(|(dir_fd, comp)| dir_fd.fstatat(&comp, do_follow))?
+        })
+    }
+
+    /// Checks whether path exists
+    ///
+    /// Unlike the `is_*at()` methods, a symlink in the last component
+    /// is resolved.  Method returns `false` when errors occurred while
+    /// performing the lookup.
+    pub fn existsat<T>(&self, dir_fd: &Fd, path: &T) -> bool
+    where
+        T: AsRef<Path>,
+    {
+        self.openat(dir_fd, path, libc::O_PATH | libc::O_CLOEXEC)
+            .is_ok()
+    }
+
+    /// Reads the target of the symlink at `path`
+    pub fn readlinkat<T>(&self, dir_fd: &Fd, path: &T) -> Result<OsString>
+    where
+        T: AsRef<Path>,
+    {
+        self.read_lookup(path.as_ref(), |c| {
+            let mut env = ChdirLoopEnv::new();
+
+            c.opendir_internal(dir_fd, path.as_ref(), &mut env)
+                .map(|(dir_fd, comp)| dir_fd.readlinkat(&comp))?
+        })
+    }
+
+    /// Exchanges `a` and `b` atomically.
+    ///
+    /// Paths are resolved relative to the chroot top directory like
+    /// `Self::open()` does; symlinks in the last components are not
+    /// followed but swapped themselves.  This allows e.g. atomic
+    /// switches between `current` and `next` release directories.
+    pub fn swap<A, B>(&self, a: &A, b: &B) -> Result<()>
+    where
+        A: AsRef<Path>,
+        B: AsRef<Path>,
+    {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
+
+        let (dir_a, comp_a) = self.opendir_internal(&root_fd, a.as_ref(), &mut env)?;
+        let (dir_b, comp_b) = self.opendir_internal(&root_fd, b.as_ref(), &mut env)?;
+
+        ensure!(comp_a != "." && comp_b != ".",
+                "can not swap {:?} and {:?}", a.as_ref(), b.as_ref());
+
+        self.ensure_writable(&dir_a, a.as_ref())?;
+        self.ensure_writable(&dir_b, b.as_ref())?;
+
+        let res = self.keeping_dir_times(&[&dir_a, &dir_b], || {
+            dir_a.exchangeat(&comp_a, &dir_b, &comp_b)
+        });
+
+        self.clear_negative_cache();
+        res?;
+
+        self.sync_dir(&dir_a)?;
+        self.sync_dir(&dir_b)
+    }
+
+    /// Renames `from` to `to`.
+    ///
+    /// Paths are resolved like by `Self::swap()`; symlinks in the
+    /// last components are not followed.  Fails with
+    /// `ErrorKind::CrossesFilesystem` when both are on different
+    /// mounts (see `Self::rename_or_copy()`) and with
+    /// `ErrorKind::ReadOnlyMount` when one is read-only.  The error
+    /// carries the mount ids or, before Linux 5.8, the device numbers.
+    pub fn rename<A, B>(&self, from: &A, to: &B) -> Result<()>
+    where
+        A: AsRef<Path>,
+        B: AsRef<Path>,
+    {
+        self.rename_internal(from.as_ref(), to.as_ref(), false)
+    }
+
+    /// Like `Self::rename()` but copies regular files to the other
+    /// filesystem instead of failing.
+    ///
+    /// The copy is written to a temporary file next to `to`, gets the
+    /// mode, ownership (when permitted) and times of `from`, is
+    /// flushed and renamed to `to` before `from` is removed.  Other
+    /// file types still fail with `ErrorKind::CrossesFilesystem`.
+    pub fn rename_or_copy<A, B>(&self, from: &A, to: &B) -> Result<()>
+    where
+        A: AsRef<Path>,
+        B: AsRef<Path>,
+    {
+        self.rename_internal(from.as_ref(), to.as_ref(), true)
+    }
+
+    fn rename_internal(&self, from: &Path, to: &Path, copy: bool) -> Result<()> {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
+
+        let (dir_from, comp_from) = self.opendir_internal(&root_fd, from, &mut env)?;
+        let (dir_to, comp_to) = self.opendir_internal(&root_fd, to, &mut env)?;
+
+        ensure!(comp_from != "." && comp_to != ".",
+                "can not rename {:?} to {:?}", from, to);
+
+        crate::pathutil::validate_component(&comp_to)?;
+
+        self.ensure_writable(&dir_from, from)?;
+        self.ensure_writable(&dir_to, to)?;
+
+        let res = self.keeping_dir_times(&[&dir_from, &dir_to], || {
+            match dir_from.renameat(&comp_from, &dir_to, &comp_to) {
+                Err(ref e) if e.errno() == Some(libc::EXDEV) => {
+                    let err = || -> Result<ErrorKind> {
+                        Ok(ErrorKind::CrossesFilesystem(dir_from.mount_id_or_dev()?,
+                                                        dir_to.mount_id_or_dev()?))
+                    };
+
+                    if !copy {
+                        return Err(err()?.into());
+                    }
+
+                    match self.copy_across(&dir_from, &comp_from, &dir_to, &comp_to) {
+                        Ok(()) => Ok(()),
+                        Err(e) => Err(Error::with_chain(e, err()?)),
+                    }
+                },
+                r => r,
+            }
+        });
+
+        self.clear_negative_cache();
+        res?;
+
+        self.sync_dir(&dir_from)?;
+        self.sync_dir(&dir_to)
+    }
+
+    /// Implements the fallback of `Self::rename_or_copy()`
+    fn copy_across(&self, dir_from: &Fd, comp_from: &OsString,
+                   dir_to: &Fd, comp_to: &OsString) -> Result<()>
+    {
+        let src = dir_from.openat(comp_from, libc::O_RDONLY | libc::O_CLOEXEC |
+                                  libc::O_NOFOLLOW | libc::O_NONBLOCK)?;
+        let md = src.metadata()?;
+
+        ensure!(md.is_file(), "{:?} is not a regular file", comp_from);
+
+        let (tmp_name, dst) = self.create_tmp(dir_to, comp_to, 0o600)?;
+
+        let res = dst.reflink_or_copy_from(&src)
+            .and_then(|_| {
+                // ownership first because it clears setuid bits
+                match dst.fchownat(&"", Some(md.uid()), Some(md.gid()), false) {
+                    Err(ref e) if e.errno() == Some(libc::EPERM) => {},
+                    r => r?,
+                }
+
+                dst.fchmod(md.mode())?;
+                dst.futimens(md.atime(), md.mtime())?;
+
+                Barrier::new().file(&dst).commit()?;
+
+                // dirty pages are not dropped; they were flushed above
+                self.advise_copied(&[&src, &dst]);
+
+                Ok(())
+            })
+            .and_then(|_| dir_to.renameat(&tmp_name, dir_to, comp_to));
+
+        if let Err(e) = res {
+            let _ = dir_to.unlinkat(&tmp_name, 0);
+            return Err(e);
+        }
+
+        self.sync_dir(dir_to)?;
+
+        dir_from.unlinkat(comp_from, 0)
+    }
+
+    /// Creates missing directories of `path`.  When `attrs` is given,
+    /// it is called with the path prefix of every created directory
+    /// and the returned attributes are applied exactly.  Else, the
+    /// directories are created with `mode` minus the umask (see
+    /// `Self::with_umask()`).
+    fn create_dir_all_internal(&self, dir_fd: Fd, path: &Path, mode: u32,
+                               mut attrs: Option<&mut dyn FnMut(&Path) -> DirAttrs>,
+                               env: &mut ChdirLoopEnv) -> Result<Fd>
+    {
+        use std::path::Component;
+
+        let mut dir_fd = dir_fd;
+        let mut prefix = PathBuf::new();
+        let mut checked = false;
+
+        for p in path.components() {
+            prefix.push(p);
+
+            let name = match p {
+                // names which fail the check can not exist either
+                Component::Normal(name) => {
+                    crate::pathutil::validate_component(&name)?;
+                    Path::new(name)
+                },
+                _ => {
+                    dir_fd = self.open_component(dir_fd, p, env)?;
+                    continue;
+                }
+            };
+
+            dir_fd = match self.chdir_internal(dir_fd.clone(), name, env) {
+                Err(ref e) if e.errno() == Some(libc::ENOENT) => {
+                    // the remaining directories are created below this
+                    // one on the same mount
+                    if !checked {
+                        self.ensure_writable(&dir_fd, &prefix)?;
+                        checked = true;
+                    }
+
+                    let attr = attrs.as_mut().map(|f| f(&prefix));
+                    let mode = attr.as_ref().map_or(mode, |a| a.mode);
+
+                    let res = self.keeping_dir_times(&[&dir_fd], || {
+                        dir_fd.mkdirat(&name, self.umask.map_or(mode, |m| mode & !m))
+                    });
+
+                    self.clear_negative_cache();
+
+                    let created = match res {
+                        // somebody else created it meanwhile
+                        Err(ref e) if e.errno() == Some(libc::EEXIST) => false,
+                        r => { r?; true },
+                    };
+
+                    let new_fd = self.chdir_internal(dir_fd, name, env)?;
+
+                    match (created, attr) {
+                        (true, Some(attr)) => {
+                            // chown() first; it clears the setgid bit
+                            if attr.uid.is_some() || attr.gid.is_some() {
+                                new_fd.fchownat(&"", attr.uid, attr.gid, false)?;
+                            }
+
+                            new_fd.fchmod(attr.mode)?;
+                        },
+                        (true, None) => self.apply_umask(&new_fd, mode)?,
+                        _ => {},
+                    }
+
+                    new_fd
+                },
+                r => r?,
+            };
+        }
+
+        Ok(dir_fd)
+    }
+
+    /// Creates the directory `path` and all its missing parents.
+    ///
+    /// Path is resolved relative to the chroot top directory; symlinks
+    /// are followed but dangling ones are not created.  Method
+    /// returns the opened directory.
+    pub fn create_dir_all<T>(&self, path: &T, mode: u32) -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
 
-        FdRaw::open(&self.root, open_flags)
+        self.create_dir_all_internal(root_fd, path.as_ref(), mode, None, &mut env)?
+            .openat(&".", libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY)
     }
 
-    pub fn root_fd(&self) -> Result<Fd> {
-        let open_flags = libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY;
+    /// Creates the directory `path` and all its missing parents with
+    /// individual attributes.
+    ///
+    /// `attrs` is called with the path prefix (as given in `path`) of
+    /// every directory which is created.  The returned mode is set
+    /// exactly, ignoring the umask, and owner and group are changed
+    /// when they are given.  Existing directories are not modified.
+    pub fn create_dir_all_with<T, F>(&self, path: &T, mut attrs: F) -> Result<Fd>
+    where
+        T: AsRef<Path>,
+        F: FnMut(&Path) -> DirAttrs,
+    {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
 
-        Fd::open(&self.root, open_flags)
+        self.create_dir_all_internal(root_fd, path.as_ref(), 0o700,
+                                     Some(&mut attrs), &mut env)?
+            .openat(&".", DIR_OPEN_FLAGS)
     }
 
-    fn dir_info(&self, dir_fd: &Fd, env: &mut ChdirLoopEnv) -> Result<DirInfo> {
-        if env.root_stat.is_none() {
-            env.root_stat = Some(Fd::cwd().fstatat(&self.root, true)?);
-        }
+    /// Creates (or opens) the file at `path`.
+    ///
+    /// Path is resolved relative to the chroot top directory like
+    /// `Self::open()` does.  When `options.create_parents` is set,
+    /// missing parent directories are created like
+    /// `Self::create_dir_all()` does.
+    pub fn create_with<T>(&self, path: &T, options: &CreateOptions) -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
 
-        let root_stat = env.root_stat.as_ref().unwrap();
+        self.audited_fd("create_with", path, || self.retry_stale(|| {
+            let mut env = ChdirLoopEnv::new();
+            let root_fd = self.open_root_dir()?;
 
-        let stat = dir_fd.fstatat(&".", false)?;
-        let is_root =
-            (stat.st_dev == root_stat.st_dev) &&
-            (stat.st_ino == root_stat.st_ino);
+            if options.create_parents {
+                let (dir, _) = Self::split_path(path);
 
-        Ok(DirInfo {
-            stat: stat,
-            is_root: is_root,
-        })
+                self.create_dir_all_internal(root_fd.clone(), dir,
+                                             options.parent_mode, None, &mut env)?;
+            }
+
+            self.openat_internal(&root_fd, path, options.flags | libc::O_CREAT,
+                                 options.mode, &mut env)
+        }))
     }
 
-    /// Opens the directory at `path` within the chroot.
+    /// Changes owner and group of `path` to the given names.
     ///
-    /// Every intermediate symlinks will be resolved relative to to
-    /// the chroot.
+    /// Names are resolved either in the host database or in the
+    /// `/etc/passwd` and `/etc/group` files of the chroot.  `None`
+    /// keeps the corresponding id.  A symlink in the last component
+    /// is resolved within the chroot.
+    #[cfg(feature = "users")]
+    pub fn chown_by_name<T>(&self, path: &T, user: Option<&str>,
+                            group: Option<&str>,
+                            lookup: crate::users::NameLookup) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        use crate::users::*;
+
+        let uid = match user {
+            None => None,
+            Some(name) => Some(match lookup {
+                NameLookup::Host => user_id(name)?,
+                NameLookup::Chroot => chroot_user_id(self, name)?,
+            }.ok_or_else(|| format!("unknown user '{}'", name))?),
+        };
+
+        let gid = match group {
+            None => None,
+            Some(name) => Some(match lookup {
+                NameLookup::Host => group_id(name)?,
+                NameLookup::Chroot => chroot_group_id(self, name)?,
+            }.ok_or_else(|| format!("unknown group '{}'", name))?),
+        };
+
+        self.open(path, libc::O_PATH | libc::O_CLOEXEC)?
+            .fchownat(&"", uid, gid, false)
+    }
+
+    /// Opens directory `path` and returns both its filedescriptor and
+    /// an iterator over its entries.
     ///
-    /// Restrictions: `path` must be absolute.
-    pub fn chdir<T>(&self, path: &T) -> Result<Fd>
+    /// The iterator works on a duplicate of the filedescriptor so that
+    /// no second lookup of `path` is needed.  Both share the file
+    /// offset; the returned `Fd` is meant for `fstatat()` and similar
+    /// operations on the entries.
+    pub fn read_dir_fd<T>(&self, path: &T) -> Result<(Fd, ReadDir)>
     where
         T: AsRef<Path>,
     {
-        let path : &Path = path.as_ref();
+        let fd = self.open(path, libc::O_DIRECTORY | libc::O_RDONLY |
+                           libc::O_CLOEXEC)?;
+        let dir = Dir::from_fd_owned(fd.dupfd(true)?)?;
 
-        ensure!(path.is_absolute(), "path '{:?}' not absolute", path);
+        Ok((fd, dir.readdir()))
+    }
 
-        let mut env: ChdirLoopEnv = ChdirLoopEnv::new();
+    /// Opens `path` for reading and returns a buffered reader.
+    pub fn buf_reader<T>(&self, path: &T) -> Result<std::io::BufReader<std::fs::File>>
+    where
+        T: AsRef<Path>,
+    {
+        let fd = self.open(path, libc::O_RDONLY | libc::O_CLOEXEC)?;
 
-        self.chdir_internal(Fd::cwd(), path, &mut env)
+        Ok(std::io::BufReader::new(fd_into_file(fd)?))
     }
 
-    /// Opens a directory `path` in the chroot environment relative
-    /// to `fd`.
+    /// Reads the whole file at `path`.
     ///
-    /// Behaviour is unspecified if `fd` lies outside the chroot.
-    /// `path` can be relative.
-    pub fn chdirat<T>(&self, dir_fd: &Fd, path:  &T) -> Result<Fd>
+    /// The size limit of `Self::with_max_file_size()` is enforced on
+    /// the read data too.
+    pub fn read<T>(&self, path: &T) -> Result<Vec<u8>>
     where
         T: AsRef<Path>,
     {
-        let mut env: ChdirLoopEnv = ChdirLoopEnv::new();
+        use std::io::Read;
+
+        let path = path.as_ref();
+        let file = fd_into_file(self.open(&path, libc::O_RDONLY | libc::O_CLOEXEC)?)?;
+        let limit = self.max_file_size.unwrap_or(u64::MAX);
+        let mut res = Vec::new();
+
+        file.take(limit.saturating_add(1)).read_to_end(&mut res)?;
+        self.check_file_size(res.len() as u64, limit, path)?;
 
-        self.chdir_internal(dir_fd.clone(), path.as_ref(), &mut env)
+        Ok(res)
     }
 
-    fn open_component(&self, dir_fd: Fd,
-                      path: std::path::Component,
-                      env: &mut ChdirLoopEnv) -> Result<Fd>
+    /// Reads the whole file at `path` like `Self::read()` and checks
+    /// that it is valid UTF-8.
+    pub fn read_to_string<T>(&self, path: &T) -> Result<String>
+    where
+        T: AsRef<Path>,
     {
-	#[allow(clippy::identity_op)]
-        let open_flags = 0
-            | libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY
-            | libc::O_NOFOLLOW;
+        String::from_utf8(self.read(path)?)
+            .chain_err(|| format!("{:?} is not valid UTF-8", path.as_ref()))
+    }
 
-        match path {
-            std::path::Component::Prefix(_) => {
-                unreachable!();
-            },
+    /// Creates `path` as described by `Self::create_with()` and
+    /// returns a buffered writer.
+    pub fn buf_writer<T>(&self, path: &T, options: &CreateOptions)
+                         -> Result<std::io::BufWriter<std::fs::File>>
+    where
+        T: AsRef<Path>,
+    {
+        let fd = self.create_with(path, options)?;
 
-            std::path::Component::ParentDir => {
-                let info = self.dir_info(&dir_fd, env)?;
+        Ok(std::io::BufWriter::new(fd_into_file(fd)?))
+    }
 
-                if info.is_root {
-                    Ok(dir_fd)
-                } else {
-                    dir_fd.openat(&"..", open_flags)
-                }
-            },
+    /// Replaces the content of `path` atomically with `data`.
+    ///
+    /// Data is written into a temporary file in the same directory
+    /// which is renamed over `path` after being flushed.  Path is
+    /// resolved relative to the chroot top directory like
+    /// `Self::open()` does; a symlink in the last component is
+    /// replaced but not followed.  A replaced regular file keeps its
+    /// permissions and, when the caller may set it, its owner; new
+    /// files are created with `Self::with_create_modes()`.  Fails with
+    /// `ErrorKind::ReadOnlyMount` before anything is created when the
+    /// directory is on a read-only mount.
+    pub fn write_atomic<T>(&self, path: &T, data: &[u8]) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
 
-            std::path::Component::RootDir => {
-                self.root_fd()
-            },
+        let (dir_fd, comp) = self.opendir_internal(&root_fd, path.as_ref(), &mut env)?;
 
-            std::path::Component::CurDir => {
-                Ok(dir_fd)
-            },
+        ensure!(comp != ".", "can not write to {:?}", path.as_ref());
 
-            std::path::Component::Normal(p) => {
-                dir_fd.openat(&p, open_flags)
-            },
-        }
+        self.ensure_writable(&dir_fd, path.as_ref())?;
+        self.write_atomic_in(&dir_fd, &comp, data)
     }
 
-    fn chdir_internal(&self, dir_fd: Fd, path: &Path,
-                      env: &mut ChdirLoopEnv) -> Result<Fd>
+    /// Sets the size of the file at `path` to `len` bytes like
+    /// `FdRaw::ftruncate()`.  The path is resolved like by
+    /// `Self::open()`; a symlink in the last component is followed
+    /// within the chroot.
+    pub fn truncate<T>(&self, path: &T, len: u64) -> Result<()>
+    where
+        T: AsRef<Path>,
     {
-        let mut dir_fd = dir_fd;
+        self.open_for_write(path.as_ref())?.ftruncate(len)
+    }
 
-        for p in path.components() {
-            use std::path::Component;
-
-            dir_fd = match p {
-                Component::Prefix(_) |
-                Component::RootDir |
-                Component::CurDir |
-                Component::ParentDir =>
-                    self.open_component(dir_fd, p, env)?,
-
-                Component::Normal(path_name) => {
-                    let tmp = Path::new(path_name);
-
-                    if !dir_fd.is_lnkat(&tmp) {
-                        self.open_component(dir_fd, p, env)?
-                    } else if env.counter == 0 {
-                        bail!("too much loops while resolving symbolic link '{:?}'",
-                              path);
-                    } else {
-                        let new_path = dir_fd.readlinkat(&tmp)?;
-                        let link = Path::new(&new_path);
-
-                        env.counter -= 1;
-                        let res = self.chdir_internal(dir_fd, link, env);
-                        env.counter += 1;
-
-                        res?
-                    }
-                }
-            };
-        }
+    /// Discards the first `len` bytes of the file at `path` like
+    /// `FdRaw::punch_front()`; e.g. to limit the disk usage of logs
+    /// which are still written and read.
+    pub fn truncate_front<T>(&self, path: &T, len: u64) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        self.open_for_write(path.as_ref())?.punch_front(len)
+    }
 
-        Ok(dir_fd)
+    /// Opens `path` with `O_WRONLY` like `Self::open()`; `EROFS` is
+    /// reported as `ErrorKind::ReadOnlyMount`.
+    fn open_for_write(&self, path: &Path) -> Result<Fd> {
+        self.open(&path, libc::O_WRONLY | libc::O_CLOEXEC | libc::O_NOCTTY)
+            .map_err(|e| match e.errno() {
+                Some(libc::EROFS) =>
+                    Error::with_chain(e, ErrorKind::ReadOnlyMount(path.to_path_buf())),
+                _ => e,
+            })
     }
 
-    fn opendir_internal(&self, dir_fd: &Fd, path: &Path, env: &mut ChdirLoopEnv)
-                 -> Result<(Fd, OsString)>
+    /// Implements `Self::write_atomic()` for the entry `comp` in the
+    /// already resolved `dir_fd`.
+    pub(crate) fn write_atomic_in(&self, dir_fd: &Fd, comp: &std::ffi::OsStr,
+                                  data: &[u8]) -> Result<()>
     {
-        let current_dir = OsString::from(".");
-        let fdrc = dir_fd.clone();
+        use std::io::Write;
+
+        crate::pathutil::validate_component(&comp)?;
+
+        let target = match dir_fd.fstatat(&comp, false) {
+            Ok(st) if st.st_mode & libc::S_IFMT == libc::S_IFREG => Some(st),
+            Ok(_) => None,
+            Err(ref e) if e.errno() == Some(libc::ENOENT) => None,
+            Err(e) => return Err(e),
+        };
+
+        let res = self.keeping_dir_times(&[dir_fd], || {
+            let (tmp_name, file) = self.create_tmp(dir_fd, comp, self.file_mode)?;
+
+            let res = Self::copy_owner_mode(&file, target.as_ref())
+                .and_then(|_| fd_into_file(file))
+                .and_then(|mut f| {
+                    f.write_all(data)?;
+                    Barrier::new().file(&f).commit()
+                })
+                .and_then(|_| dir_fd.renameat(&tmp_name, dir_fd, &comp));
+
+            if res.is_err() {
+                let _ = dir_fd.unlinkat(&tmp_name, 0);
+            }
 
-        match path.parent() {
-            None =>
-                Ok((self.chdir_internal(fdrc, path, env)?, current_dir)),
+            res
+        });
 
-            Some(p) => {
-                Ok((
-                    self.chdir_internal(fdrc, p, env)?,
-                    path.file_name()
-                        .unwrap_or_else(|| current_dir.as_os_str())
-                        .to_os_string()))
-            }
+        self.clear_negative_cache();
+        res?;
+
+        self.sync_dir(dir_fd)
+    }
+
+    /// Gives `fd` the permissions and, when permitted, the owner of
+    /// the replaced file `target`.  The owner is set first because
+    /// `chown()` clears the set-id bits.
+    fn copy_owner_mode(fd: &Fd, target: Option<&libc::stat>) -> Result<()> {
+        let st = match target {
+            Some(st) => st,
+            None => return Ok(()),
+        };
+
+        match fd.fchown(Some(st.st_uid), Some(st.st_gid)) {
+            Err(ref e) if e.errno() == Some(libc::EPERM) => {},
+            r => r?,
         }
+
+        fd.fchmod(st.st_mode & 0o7777)
     }
 
-    /// Opens a file in the chroot relative to an open directory `fd`.
+    /// Returns fstat information for a list of paths.
     ///
-    /// Method first opens the directory containing `path` as described
-    /// by `Self::chdirat()` and calls `openat()` with `O_NOFOLLOW
-    /// being set there.
-    pub fn openat<T>(&self, dir_fd: &Fd, path: &T, flags: libc::c_int)
-                     -> Result<Fd>
+    /// Paths are resolved relative to the chroot top directory like
+    /// `Self::open()` does.  Inputs are processed in sorted order so
+    /// that entries with a common prefix share the resolution of
+    /// their parent directories.  The returned vector has the same
+    /// order as `paths`.
+    pub fn metadata_many<T>(&self, paths: &[T]) -> Result<Vec<Result<libc::stat>>>
     where
         T: AsRef<Path>,
     {
+        let do_follow = false;
 
         let mut env = ChdirLoopEnv::new();
-        let mut path = path.as_ref().to_owned();
-        let mut num_loops = MAX_LOOP_CNT;
+        let mut cache = DirCache::new(self.open_root_dir()?);
 
-        while num_loops > 0 {
-            let (dir_fd, comp) =
-                self.opendir_internal(dir_fd, &path, &mut env)?;
+        let mut order: Vec<usize> = (0..paths.len()).collect();
+        order.sort_by(|a, b| paths[*a].as_ref().cmp(paths[*b].as_ref()));
 
-            assert_eq!(env.counter, MAX_LOOP_CNT);
+        let mut res: Vec<Option<Result<libc::stat>>> =
+            paths.iter().map(|_| None).collect();
 
-            if !dir_fd.is_lnkat(&comp) {
-                return dir_fd.openat(&comp, flags | libc::O_NOFOLLOW);
-            }
+        for idx in order {
+            let (dir, comp) = Self::split_path(paths[idx].as_ref());
+
+            env.components = 0;
+            res[idx] = Some(cache.resolve(self, dir, &mut env)
+                            .and_then(|dir_fd| dir_fd.fstatat(&comp, do_follow)));
+        }
+
+        let mut res: Vec<Result<libc::stat>> =
+            res.into_iter().map(Option::unwrap).collect();
 
-            path = Path::new(&dir_fd.readlinkat(&comp)?).to_owned();
+        if let Some(ref root) = self.fallback {
+            let fallback = self.derive(root);
 
-            num_loops -= 1;
+            for (r, p) in res.iter_mut().zip(paths) {
+                match r {
+                    Err(ref e) if e.errno() == Some(libc::ENOENT) &&
+                        p.as_ref().is_absolute() => {
+                        Stats::inc(&self.stats.fallback_reads);
+                        *r = fallback.fstatat(&Fd::cwd(), p);
+                    },
+                    _ => {},
+                }
+            }
         }
 
-        bail!("too much loops while resolving symbolic link '{:?}'",
-              path);
+        Ok(res)
     }
 
-    /// Opens a file in the chroot environment.
+    /// Checks whether the entries of a list of paths exist.
     ///
-    /// Method first opens the directory containing `path` as described
-    /// by `Self::chdir()` and calls `openat()` with `O_NOFOLLOW being
-    /// set there.
-    pub fn open<T>(&self, path: &T, flags: libc::c_int)
-                     -> Result<Fd>
+    /// Like `Self::existsat()`, a symlink in the last component is
+    /// resolved; paths are resolved relative to the chroot top
+    /// directory and share the resolution of their parent directories
+    /// like in `Self::metadata_many()`.  Missing entries (`ENOENT` or
+    /// `ENOTDIR`) give `false`, other errors are returned.  Absolute
+    /// paths are answered by and recorded in the negative cache and
+    /// retried on the fallback root.
+    pub fn exists_many<T>(&self, paths: &[T]) -> Result<Vec<Result<bool>>>
     where
         T: AsRef<Path>,
     {
-        self.openat(&self.root_fd()?, path, flags)
-    }
+        let cache = self.negative_cache.as_ref();
+        let generation = cache.map(NegativeCache::generation);
 
-    /// Checks whether path is a symlink
-    ///
-    /// Method returns when errors occurred while performing the
-    /// lookup.
-    pub fn is_lnkat<T>(&self, dir_fd: &Fd, path: &T) -> bool
-    where
-        T: AsRef<Path>,
-    {
         let mut env = ChdirLoopEnv::new();
+        let mut dirs = DirCache::new(self.open_root_dir()?);
 
-        self.opendir_internal(dir_fd, path.as_ref(), &mut env)
-            .map(|(dir_fd, comp)| dir_fd.is_lnkat(&comp))
-            .unwrap_or(false)
-    }
+        let mut order: Vec<usize> = (0..paths.len()).collect();
+        order.sort_by(|a, b| paths[*a].as_ref().cmp(paths[*b].as_ref()));
 
-    /// Checks whether path is a directory
-    ///
-    /// Method returns when errors occurred while performing the
-    /// lookup.
-    pub fn is_dirat<T>(&self, dir_fd: &Fd, path: &T) -> bool
-    where
-        T: AsRef<Path>,
-    {
-        let mut env = ChdirLoopEnv::new();
+        let mut res: Vec<Option<Result<bool>>> =
+            paths.iter().map(|_| None).collect();
 
-        self.opendir_internal(dir_fd, path.as_ref(), &mut env)
-            .map(|(dir_fd, comp)| dir_fd.is_dirat(&comp))
-            .unwrap_or(false)
+        for idx in order {
+            let path = paths[idx].as_ref();
+
+            if path.is_absolute() && cache.map(|c| c.contains(path)).unwrap_or(false) {
+                Stats::inc(&self.stats.negative_hits);
+                res[idx] = Some(Ok(false));
+                continue;
+            }
+
+            let (dir, comp) = Self::split_path(path);
+
+            env.components = 0;
+
+            let found = dirs.resolve(self, dir, &mut env)
+                .and_then(|dir_fd| match dir_fd.fstatat(&comp, false)?.st_mode & libc::S_IFMT {
+                    // follow it within the same budget and without an
+                    // audit event per probe
+                    libc::S_IFLNK =>
+                        self.openat_internal(&dir_fd, Path::new(&comp),
+                                             libc::O_PATH | libc::O_CLOEXEC,
+                                             self.file_mode, &mut env),
+                    _ => Ok(dir_fd),
+                });
+
+            res[idx] = Some(match found {
+                Ok(_) => Ok(true),
+                Err(ref e) if e.errno() == Some(libc::ENOENT) ||
+                    e.errno() == Some(libc::ENOTDIR) => Ok(false),
+                Err(e) => Err(e),
+            });
+        }
+
+        let mut res: Vec<Result<bool>> =
+            res.into_iter().map(Option::unwrap).collect();
+
+        let missing: Vec<usize> = (0..paths.len())
+            .filter(|i| paths[*i].as_ref().is_absolute() && matches!(res[*i], Ok(false)))
+            .collect();
+
+        if let (Some(root), false) = (&self.fallback, missing.is_empty()) {
+            let fallback = self.derive(root);
+            let fallback_paths: Vec<&Path> = missing.iter().map(|i| paths[*i].as_ref()).collect();
+
+            for (i, r) in missing.iter().zip(fallback.exists_many(&fallback_paths)?) {
+                Stats::inc(&self.stats.fallback_reads);
+                res[*i] = r;
+            }
+        }
+
+        if let (Some(c), Some(g)) = (cache, generation) {
+            for i in missing {
+                if let Ok(false) = res[i] {
+                    let path = paths[i].as_ref();
+
+                    c.insert(path, g, |w| self.watch_missing(w, path));
+                }
+            }
+        }
+
+        Ok(res)
     }
 
-    /// Checks whether path is a regular file
+    /// Opens a list of files with individual flags.
     ///
-    /// Method returns when errors occurred while performing the
-    /// lookup.
-    pub fn is_regat<T>(&self, dir_fd: &Fd, path: &T) -> bool
+    /// Requests are grouped by their parent directories like in
+    /// `Self::metadata_many()` so that every directory is resolved
+    /// once.  Final components are opened like by `Self::open()`.
+    /// The returned vector has the same order as `requests`.
+    pub fn open_many<T>(&self, requests: &[(T, libc::c_int)])
+                        -> Result<Vec<Result<Fd>>>
     where
         T: AsRef<Path>,
     {
         let mut env = ChdirLoopEnv::new();
+        let mut cache = DirCache::new(self.open_root_dir()?);
 
-        self.opendir_internal(dir_fd, path.as_ref(), &mut env)
-            .map(|(dir_fd, comp)| dir_fd.is_regat(&comp))
-            .unwrap_or(false)
-    }
+        let mut order: Vec<usize> = (0..requests.len()).collect();
+        order.sort_by(|a, b| requests[*a].0.as_ref().cmp(requests[*b].0.as_ref()));
 
-    /// Returns fstat information
-    pub fn fstatat<T>(&self, dir_fd: &Fd, fname: &T) -> Result<libc::stat>
-    where
-        T: AsRef<Path>,
-    {
-        let do_follow = false;
+        let mut res: Vec<Option<Result<Fd>>> =
+            requests.iter().map(|_| None).collect();
 
-        let mut env = ChdirLoopEnv::new();
+        for idx in order {
+            let (ref path, flags) = requests[idx];
+            let (dir, comp) = Self::split_path(path.as_ref());
+
+            env.components = 0;
+            res[idx] = Some(cache.resolve(self, dir, &mut env)
+                            .and_then(|dir_fd| self.openat_internal(
+                                &dir_fd, Path::new(&comp), flags, self.file_mode, &mut env)));
+        }
 
-        self.opendir_internal(dir_fd, fname.as_ref(), &mut env)
-            .map(|(dir_fd, comp)| dir_fd.fstatat(&comp, do_follow))?
+        Ok(res.into_iter().map(Option::unwrap).collect())
     }
 
+    /// Checks whether `entry` of `dir_fd` is the directory described
+    /// by `info`.  The inode number reported by `readdir()` is not
+    /// checked when `info` is the root of a mount because it refers
+    /// to the covered directory then.
     fn check_and_get_entry(dir_fd: &Fd, entry: &DirEntry,
-                           info: &DirInfo) -> Result<Option<OsString>> {
+                           info: &DirInfo, is_mount_root: bool)
+                           -> Result<Option<OsString>> {
         //const DT_UNKNOWN: u8 = libc::DT_UNKNOWN;
         const DT_UNKNOWN: u8 = 0;
         const DT_DIR: u8 = libc::DT_DIR;
 
-        if entry.d_ino != info.stat.st_ino {
+        if entry.d_ino != info.stat.st_ino && !is_mount_root {
             return Ok(None);
         }
 
@@ -385,55 +2652,82 @@ impl Chroot {
         Ok(Some(name))
     }
 
+    /// Checks whether `path` still resolves to the file opened as
+    /// `fd`.
+    ///
+    /// `path` is resolved like by `Self::open()` but with `O_PATH`
+    /// so that no permissions on the file itself are required.  A
+    /// `path` which does not exist anymore yields `false`.
+    pub fn verify<T>(&self, path: &T, fd: &Fd) -> Result<bool>
+    where
+        T: AsRef<Path>,
+    {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
+
+        let now = match self.openat_internal(&root_fd, path.as_ref(),
+                                             libc::O_PATH | libc::O_CLOEXEC,
+                                             0, &mut env) {
+            Ok(fd) => fd,
+            Err(ref e) if e.errno() == Some(libc::ENOENT) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        Ok(same_file_by_stat(&now.fstat()?, &fd.fstat()?))
+    }
+
+    /// Returns the symlinks in the order they are followed when
+    /// opening `path` within the chroot.
+    ///
+    /// This is a debugging aid; it is expensive because every found
+    /// link is translated by `Self::full_path()`.
+    pub fn link_chain<T>(&self, path: &T) -> Result<Vec<LinkHop>>
+    where
+        T: AsRef<Path>,
+    {
+        let mut env = ChdirLoopEnv::new();
+        let root_fd = self.open_root_dir()?;
+
+        env.trace = Some(Vec::new());
+
+        self.openat_internal(&root_fd, path.as_ref(),
+                             libc::O_PATH | libc::O_CLOEXEC, 0, &mut env)?;
+
+        Ok(env.trace.unwrap_or_default())
+    }
+
     /// Transforms `fd` into an absolute path relative to the chroot
     /// and appends `fname` optionally.
     ///
     /// Note: this operation is expensive because it recurses into the
     /// parent directories of `fd` and iterates over their contents to
     /// look for a matching subdirectory.
+    ///
+    /// When an ancestor can not be read (e.g. because of missing
+    /// permissions), `ErrorKind::PartialPath` with the path below it
+    /// is returned; the original error is chained.
     pub fn full_path<T>(&self, dir_fd: &Fd, fname: Option<&T>)
                         -> Result<OsString>
     where
         T: AsRef<Path>,
     {
-        let parent_dir = Path::new("..");
         let mut res = Vec::new();
-        let mut dir_fd = dir_fd.clone();
-        let mut env: ChdirLoopEnv = ChdirLoopEnv::new();
-        let mut total_size = 0;
 
-        loop {
-            let info = self.dir_info(&dir_fd, &mut env)?;
+        if let Err(e) = self.collect_ancestors(dir_fd, &mut res) {
+            let mut suffix: PathBuf = res.into_iter().rev().collect();
 
-            assert_eq!(env.counter, MAX_LOOP_CNT);
-
-            if info.is_root {
-                break;
+            if let Some(f) = fname {
+                suffix.push(f);
             }
 
-            dir_fd = dir_fd.openat(&parent_dir,
-                                   libc::O_CLOEXEC | libc::O_RDONLY |
-                                   libc::O_DIRECTORY)?;
-
-            let dir = Dir::fdopendir(&dir_fd)?;
-
-            for e in ReadDir::new(dir) {
-                let e_name = Self::check_and_get_entry(&dir_fd, &e?, &info)?;
-
-                if let Some(name) = e_name {
-                    total_size += name.len() + 1;
-                    res.push(name);
-
-                    break;
-                }
-            }
+            let errno = e.errno();
 
-            if res.is_empty() {
-                bail!("full_path(): no entry found");
-            }
+            return Err(e).chain_err(|| ErrorKind::PartialPath(suffix, errno));
         }
 
         res.reverse();
+
+        let total_size = res.iter().map(|p| p.len() + 1).sum();
         let mut path = OsString::with_capacity(total_size);
 
         if res.is_empty() && fname.is_none() {
@@ -455,6 +2749,55 @@ impl Chroot {
 
         Ok(path)
     }
+
+    /// Pushes the names of `dir_fd` and its parent directories up to
+    /// the root onto `res`, starting with the innermost one.
+    fn collect_ancestors(&self, dir_fd: &Fd, res: &mut Vec<OsString>) -> Result<()> {
+        let parent_dir = Path::new("..");
+        let mut dir_fd = dir_fd.clone();
+        let mut env: ChdirLoopEnv = ChdirLoopEnv::new();
+
+        loop {
+            let info = self.dir_info(&dir_fd, &mut env)?;
+
+            assert!(env.links.is_empty());
+
+            if info.is_root {
+                break Ok(());
+            }
+
+            let parent_fd = dir_fd.openat(&parent_dir,
+                                          libc::O_CLOEXEC | libc::O_RDONLY |
+                                          libc::O_DIRECTORY)?;
+            // both fail on kernels without mount ids
+            let is_mount_root = dir_fd.mount_id().ok() != parent_fd.mount_id().ok();
+
+            dir_fd = parent_fd;
+
+            let dir = Dir::fdopendir(&dir_fd)?;
+            let mut found = false;
+
+            for e in ReadDir::new(dir) {
+                let e_name = match Self::check_and_get_entry(&dir_fd, &e?, &info,
+                                                             is_mount_root) {
+                    // entry was removed after readdir()
+                    Err(ref e) if e.errno() == Some(libc::ENOENT) => continue,
+                    r => r?,
+                };
+
+                if let Some(name) = e_name {
+                    res.push(name);
+                    found = true;
+
+                    break;
+                }
+            }
+
+            if !found {
+                bail!("full_path(): no entry found");
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -463,4 +2806,4 @@ mod testdata;
 
 #[cfg(test)]
 #[path="tests/chroot.inc.rs"]
-mod test;
+pub(crate) mod test;
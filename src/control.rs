@@ -0,0 +1,71 @@
+//! Progress reports and cancellation of long running operations
+//!
+//! A `Control` registered by `Chroot::with_control()` is asked
+//! whether to stop at the same points where the cancel flag and the
+//! timeout are checked.  Recursive operations report their progress
+//! to it: `Chroot::visit()` and the operations built on it
+//! (`Chroot::archive()`, `Chroot::export_manifest()`),
+//! `Chroot::snapshot()`, `Chroot::mirror()`, `Chroot::remove_dir_all()`,
+//! `Chroot::clear_dir()` and `Chroot::audit()`.
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Observer of running operations
+pub trait Control: Send + Sync {
+    /// Returns `true` when the running operation shall be aborted; it
+    /// fails with `ErrorKind::Cancelled` then.
+    fn should_stop(&self) -> bool {
+        false
+    }
+
+    /// Called with the number of entries processed by the running
+    /// operation so far and the number of bytes it read or wrote for
+    /// them.
+    fn on_progress(&self, _entries: u64, _bytes: u64) {}
+}
+
+/// Stops when the flag is set, like `Chroot::with_cancel_flag()`
+impl Control for AtomicBool {
+    fn should_stop(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
+
+/// Control registered by `Chroot::with_control()`
+pub type ControlRef = Arc<dyn Control>;
+
+/// Counters of a single operation which are reported to its control
+pub(crate) struct Progress<'a> {
+    control:	Option<&'a dyn Control>,
+    entries:	Cell<u64>,
+    bytes:	Cell<u64>,
+}
+
+impl<'a> Progress<'a> {
+    pub(crate) fn new(control: Option<&'a dyn Control>) -> Self {
+        Progress {
+            control:	control,
+            entries:	Cell::new(0),
+            bytes:	Cell::new(0),
+        }
+    }
+
+    /// Accounts processed `entries` and `bytes` and reports the new
+    /// totals.
+    pub(crate) fn add(&self, entries: u64, bytes: u64) {
+        let control = match self.control {
+            Some(c) => c,
+            None => return,
+        };
+
+        self.entries.set(self.entries.get() + entries);
+        self.bytes.set(self.bytes.get().saturating_add(bytes));
+
+        control.on_progress(self.entries.get(), self.bytes.get());
+    }
+}
+
+#[cfg(test)]
+#[path="tests/control.inc.rs"]
+mod test;
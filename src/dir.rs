@@ -1,15 +1,17 @@
 extern crate libc;
 extern crate errno;
 
+use std::collections::VecDeque;
 use std::io::Error;
 use std::fmt;
 use std::ffi::{CStr, OsString, OsStr};
 use std::os::unix::ffi::OsStrExt;
 
-use crate::fd::Fd;
+use crate::fd::{Fd, FdRaw};
+use crate::metadata::Metadata;
 use crate::errors::*;
 
-extern {
+extern "C" {
     // this is missing in libc crate :(
     pub fn fdopendir(fd: libc::c_int) -> *mut libc::DIR;
 }
@@ -20,6 +22,10 @@ pub struct Dir {
     dirp: *mut libc::DIR,
 }
 
+// the DIR stream is owned exclusively and can be moved between
+// threads
+unsafe impl Send for Dir {}
+
 impl Drop for Dir {
     fn drop(&mut self)
     {
@@ -43,14 +49,23 @@ impl Dir {
         // usually not wanted
         let fd = fd.to_fdraw().openat(&OsString::from("."), FLAGS)?;
 
-        let dir = {
-            let dir = unsafe { fdopendir(fd.fd) };
-            ensure!(!dir.is_null(), Error::last_os_error());
+        Self::from_fd_owned(fd)
+    }
 
-            fd.is_managed.set(false);
+    /// Creates a DIR stream on `fd` without reopening it.  `fd` must
+    /// be a directory opened for reading (not `O_PATH`); its file
+    /// offset is used by the stream.
+    ///
+    /// Unlike `Self::fdopendir()`, this avoids an additional
+    /// `openat()` for callers which just opened the directory
+    /// themselves.  The descriptor is closed with the stream.
+    pub fn from_fd_owned(fd: FdRaw) -> Result<Dir>
+    {
+        let dir = unsafe { fdopendir(fd.fd) };
+        ensure!(!dir.is_null(), Error::last_os_error());
 
-            dir
-        };
+        // ownership moved to the DIR stream
+        fd.leak();
 
         Ok(Dir {
             dirp: dir,
@@ -72,6 +87,132 @@ impl Dir {
     pub fn readdir(self) -> ReadDir {
         ReadDir::new(self)
     }
+
+    /// Reads all entries from the start of the stream
+    fn read_all(&mut self) -> Result<Vec<DirEntry>> {
+        let mut res = Vec::new();
+
+        unsafe { libc::rewinddir(self.dirp) };
+
+        loop {
+            let entry_raw = self.libc_readdir().chain_err(|| "readdir() failed")?;
+
+            if entry_raw.is_null() {
+                break;
+            }
+
+            let entry = DirEntry::from_dirent(unsafe { *entry_raw });
+
+            if !matches!(entry.name().as_bytes(), b"." | b"..") {
+                res.push(entry);
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Lists the directory twice and reconciles both passes.
+    ///
+    /// `readdir()` gives no guarantees about entries which are
+    /// created, removed or renamed while the directory is read; they
+    /// might be missing or reported twice.  Entries which were seen
+    /// with the same name and inode in both passes are stable; the
+    /// others are reported as appeared or disappeared.  This is a
+    /// best effort; changes which were undone between the passes are
+    /// not noticed.
+    pub fn stable_entries(&mut self) -> Result<StableEntries> {
+        let first = self.read_all()?;
+        let second = self.read_all()?;
+
+        Ok(StableEntries::reconcile(first, second))
+    }
+
+    /// Moves the stream to `offset` (`seekdir()`)
+    pub fn seek(&mut self, offset: DirOffset) {
+        unsafe { libc::seekdir(self.dirp, offset.0 as libc::c_long) };
+    }
+
+    /// Returns the descriptor owned by the stream (`dirfd()`)
+    pub(crate) fn raw_fd(&self) -> libc::c_int {
+        unsafe { libc::dirfd(self.dirp) }
+    }
+
+    /// Returns the current position of the stream (`telldir()`)
+    pub fn tell(&self) -> Result<DirOffset> {
+        let pos = unsafe { libc::telldir(self.dirp) };
+
+        ensure!(pos >= 0, Error::last_os_error());
+
+        Ok(DirOffset(pos as libc::loff_t))
+    }
+}
+
+/// Result of `Dir::stable_entries()`; every list is sorted by name
+#[derive(Clone, Debug, Default)]
+pub struct StableEntries {
+    /// entries seen in both passes
+    pub entries:	Vec<DirEntry>,
+    /// entries seen in the second pass only
+    pub appeared:	Vec<DirEntry>,
+    /// entries seen in the first pass only
+    pub disappeared:	Vec<DirEntry>,
+}
+
+impl StableEntries {
+    fn reconcile(first: Vec<DirEntry>, second: Vec<DirEntry>) -> Self {
+        use std::collections::{HashMap, HashSet};
+
+        let key = |e: &DirEntry| (e.d_name.clone(), e.d_ino);
+
+        let mut old: HashMap<_, DirEntry> = first.into_iter()
+            .map(|e| (key(&e), e))
+            .collect();
+        let mut seen = HashSet::new();
+        let mut res = StableEntries::default();
+
+        for e in second {
+            // entries might be reported twice
+            if !seen.insert(key(&e)) {
+                continue;
+            }
+
+            match old.remove(&key(&e)) {
+                Some(_) => res.entries.push(e),
+                None => res.appeared.push(e),
+            }
+        }
+
+        res.disappeared = old.into_values().collect();
+
+        for v in [&mut res.entries, &mut res.appeared, &mut res.disappeared] {
+            v.sort_by(|a, b| a.d_name.cmp(&b.d_name));
+        }
+
+        res
+    }
+}
+
+/// Position in a directory
+///
+/// Wraps the `d_off` cookie of an entry which points behind this
+/// entry.  Cookies are plain numbers which can be stored or sent
+/// elsewhere and used with a later opened stream of the same
+/// directory; most filesystems keep them stable while entries are
+/// added or removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DirOffset(libc::loff_t);
+
+impl DirOffset {
+    /// position of the first entry
+    pub const START: DirOffset = DirOffset(0);
+
+    pub fn from_raw(cookie: libc::loff_t) -> Self {
+        DirOffset(cookie)
+    }
+
+    pub fn as_raw(self) -> libc::loff_t {
+        self.0
+    }
 }
 
 #[derive(Clone)]
@@ -95,9 +236,51 @@ impl DirEntry {
 	}
     }
 
+    /// Parses the `linux_dirent64` record at the start of `buf` as
+    /// returned by `getdents64()`; e.g. for listings which are received
+    /// from other sources like a FUSE server.  Returns the entry
+    /// together with the record length (`d_reclen`) so that the next
+    /// record starts at this offset.
+    pub fn from_raw_bytes(buf: &[u8]) -> Result<(Self, usize)> {
+        // d_ino, d_off, d_reclen and d_type
+        const HDR_LEN: usize = 8 + 8 + 2 + 1;
+
+        ensure!(buf.len() >= HDR_LEN, "truncated dirent64 record");
+
+        let field = |pos: usize, len: usize| {
+            let mut tmp = [0u8; 8];
+
+            tmp[..len].copy_from_slice(&buf[pos..pos + len]);
+            u64::from_ne_bytes(tmp)
+        };
+
+        let reclen = u16::from_ne_bytes([buf[16], buf[17]]) as usize;
+
+        ensure!(reclen > HDR_LEN && reclen <= buf.len(),
+                "bad dirent64 record length {}", reclen);
+
+        let name = &buf[HDR_LEN..reclen];
+        let name = match name.iter().position(|c| *c == 0) {
+            Some(0) | None => bail!("bad name in dirent64 record"),
+            Some(len) => &name[..len],
+        };
+
+        Ok((Self {
+            d_name:	OsStr::from_bytes(name).into(),
+            d_ino:	field(0, 8) as libc::c_ulong,
+            d_off:	field(8, 8) as libc::loff_t,
+            d_type:	buf[18],
+        }, reclen))
+    }
+
     pub fn name(&self) -> &OsStr {
 	&self.d_name
     }
+
+    /// Returns the position behind this entry
+    pub fn offset(&self) -> DirOffset {
+	DirOffset(self.d_off)
+    }
 }
 
 impl fmt::Debug for DirEntry {
@@ -109,9 +292,23 @@ impl fmt::Debug for DirEntry {
     }
 }
 
+/// Handling of `readdir()` errors by `ReadDir`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// return the error and end the iteration
+    #[default]
+    Stop,
+    /// drop the error and continue with the next entry
+    Skip,
+    /// like `Skip` but keep the error; see `ReadDir::errors()`
+    Collect,
+}
+
 pub struct ReadDir {
     dir: Dir,
     failed: bool,
+    policy: ErrorPolicy,
+    errors: Vec<crate::errors::Error>,
 }
 
 impl ReadDir {
@@ -119,23 +316,90 @@ impl ReadDir {
         ReadDir {
             dir: dir,
             failed: false,
+            policy: ErrorPolicy::default(),
+            errors: Vec::new(),
         }
     }
+
+    /// Returns the descriptor of the underlying `Dir`
+    pub(crate) fn raw_fd(&self) -> libc::c_int {
+        self.dir.raw_fd()
+    }
+
+    /// Sets the policy for `readdir()` errors.  With `Skip` and
+    /// `Collect`, the iteration ends when `readdir()` fails twice in
+    /// a row because the stream can not advance anymore.
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Continues the listing at `offset`; following calls of `next()`
+    /// return the entries behind it.
+    pub fn seek(&mut self, offset: DirOffset) {
+        self.dir.seek(offset);
+        self.failed = false;
+    }
+
+    /// Returns the first entry at `offset`; e.g. from `DirEntry::offset()`
+    /// of the last entry of a previous listing.
+    pub fn next_from(&mut self, offset: DirOffset) -> Option<Result<DirEntry>> {
+        self.seek(offset);
+        self.next()
+    }
+
+    /// Returns an iterator which reads `batch` entries ahead and
+    /// returns them together with their `lstat()` information.
+    ///
+    /// Reading a batch of entries before the inodes are looked up
+    /// keeps the `getdents()` and `stat()` calls from interleaving,
+    /// which helps consumers like `du` or indexers on filesystems
+    /// with a high latency.  Errors of the lookups are reported per
+    /// entry; e.g. `ENOENT` for entries which were removed meanwhile.
+    pub fn prefetch_stats(self, batch: usize) -> PrefetchStats {
+        PrefetchStats {
+            dir:	self,
+            batch:	batch.max(1),
+            queue:	VecDeque::new(),
+        }
+    }
+
+    /// Returns the errors gathered by the `Collect` policy
+    pub fn errors(&self) -> &[crate::errors::Error] {
+        &self.errors
+    }
+
+    /// Returns and clears the errors gathered by the `Collect` policy
+    pub fn take_errors(&mut self) -> Vec<crate::errors::Error> {
+        std::mem::take(&mut self.errors)
+    }
 }
 
 impl Iterator for ReadDir {
     type Item = Result<DirEntry>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.failed && self.policy == ErrorPolicy::Stop {
+            return None;
+        }
+
         loop {
             let entryp = self.dir.libc_readdir();
             let entry_raw = match entryp {
+                Err(_) if self.failed => break None,
+
                 Err(e) => {
-                    if self.failed {
-                        break None;
-                    } else {
-                        self.failed = true;
-                        break Some(Err(e).chain_err(|| "readdir() failed"));
+                    let e = Err(e).chain_err(|| "readdir() failed");
+
+                    self.failed = true;
+
+                    match self.policy {
+                        ErrorPolicy::Stop	=> break Some(e),
+                        ErrorPolicy::Skip	=> continue,
+                        ErrorPolicy::Collect	=> {
+                            self.errors.push(e.unwrap_err());
+                            continue;
+                        }
                     }
                 }
                 Ok(e) => e,
@@ -157,3 +421,144 @@ impl Iterator for ReadDir {
         }
     }
 }
+
+/// Iterator returned by `ReadDir::prefetch_stats()`
+pub struct PrefetchStats {
+    dir:	ReadDir,
+    batch:	usize,
+    queue:	VecDeque<Result<(DirEntry, Result<Metadata>)>>,
+}
+
+impl PrefetchStats {
+    fn lstat(&self, name: &OsStr) -> Result<Metadata> {
+        // the descriptor stays owned by the stream
+        let fd = FdRaw::_new_unmanaged(self.dir.raw_fd());
+
+        fd.fstatat(&std::path::Path::new(name), false).map(Metadata::from_stat)
+    }
+
+    fn fill(&mut self) {
+        let mut entries = Vec::with_capacity(self.batch);
+
+        for e in self.dir.by_ref().take(self.batch) {
+            let failed = e.is_err();
+
+            entries.push(e);
+
+            // keep the order of entries and readdir() errors
+            if failed {
+                break;
+            }
+        }
+
+        for e in entries {
+            let item = e.map(|e| {
+                let md = self.lstat(e.name());
+                (e, md)
+            });
+
+            self.queue.push_back(item);
+        }
+    }
+}
+
+impl Iterator for PrefetchStats {
+    type Item = Result<(DirEntry, Result<Metadata>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.queue.is_empty() {
+            self.fill();
+        }
+
+        self.queue.pop_front()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SnapshotEntry {
+    ino:	libc::ino_t,
+    mtime:	(libc::time_t, libc::c_long),
+    size:	libc::off_t,
+}
+
+/// State of a directory for poll based change detection
+///
+/// Stores name, inode number, modification time and size of every
+/// entry.  Symlinks are not followed.
+#[derive(Clone, Debug)]
+pub struct DirSnapshot {
+    entries: std::collections::BTreeMap<OsString, SnapshotEntry>,
+}
+
+/// Differences between a `DirSnapshot` and the current directory
+#[derive(Clone, Debug, Default)]
+pub struct DirChanges {
+    pub added:		Vec<OsString>,
+    pub removed:	Vec<OsString>,
+    pub modified:	Vec<OsString>,
+}
+
+impl DirChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() &&
+            self.modified.is_empty()
+    }
+}
+
+impl DirSnapshot {
+    pub fn capture(fd: &Fd) -> Result<Self>
+    {
+        let mut entries = std::collections::BTreeMap::new();
+
+        for e in fd.read_dir()? {
+            let e = e?;
+
+            let stat = match fd.fstatat(&e.name(), false) {
+                Ok(stat) => stat,
+                // entry was removed after readdir()
+                Err(ref e) if e.errno() == Some(libc::ENOENT) => continue,
+                Err(e) => return Err(e),
+            };
+
+            entries.insert(e.d_name, SnapshotEntry {
+                ino:	stat.st_ino,
+                mtime:	(stat.st_mtime, stat.st_mtime_nsec),
+                size:	stat.st_size,
+            });
+        }
+
+        Ok(DirSnapshot {
+            entries: entries,
+        })
+    }
+
+    /// Compares the snapshot with the current content of `fd`.
+    ///
+    /// An entry is reported as modified when its inode number,
+    /// modification time or size changed.
+    pub fn changes_since(&self, fd: &Fd) -> Result<DirChanges>
+    {
+        let now = Self::capture(fd)?;
+        let mut res = DirChanges::default();
+
+        for (name, entry) in &now.entries {
+            match self.entries.get(name) {
+                None => res.added.push(name.clone()),
+                Some(old) if old != entry => res.modified.push(name.clone()),
+                Some(_) => {},
+            }
+        }
+
+        for name in self.entries.keys() {
+            if !now.entries.contains_key(name) {
+                res.removed.push(name.clone());
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/dir.inc.rs"]
+mod test;
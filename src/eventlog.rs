@@ -0,0 +1,107 @@
+//! Machine-readable export of audit events
+//!
+//! `json_lines_hook()` creates a hook for `Chroot::with_audit_hook()`
+//! which writes one JSON object per event, e.g.
+//!
+//! ```text
+//! {"op":"openat","path":"/etc/passwd","canonical":"/etc/passwd","result":"ok","errno":null,"latency_us":12}
+//! ```
+//!
+//! Bytes of paths which are not valid UTF-8 are written as the lone
+//! surrogates `\udc80` to `\udcff`; see
+//! `pathutil::surrogate_escape()`.
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::chroot::{AuditEvent, AuditHook};
+use crate::pathutil::surrogate_escape;
+
+fn push_json_char(out: &mut String, c: char) {
+    match c {
+        '"'			=> out.push_str("\\\""),
+        '\\'		=> out.push_str("\\\\"),
+        '\n'		=> out.push_str("\\n"),
+        '\r'		=> out.push_str("\\r"),
+        '\t'		=> out.push_str("\\t"),
+        c if c < ' '	=> { let _ = write!(out, "\\u{:04x}", c as u32); },
+        c			=> out.push(c),
+    }
+}
+
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    s.chars().for_each(|c| push_json_char(out, c));
+    out.push('"');
+}
+
+fn push_json_path(out: &mut String, path: Option<&Path>) {
+    let p = match path {
+        Some(p)	=> p,
+        None	=> return out.push_str("null"),
+    };
+
+    out.push('"');
+
+    for c in std::char::decode_utf16(surrogate_escape(&p)) {
+        match c {
+            Ok(c)	=> push_json_char(out, c),
+            Err(e)	=> { let _ = write!(out, "\\u{:04x}", e.unpaired_surrogate()); },
+        }
+    }
+
+    out.push('"');
+}
+
+/// Formats `ev` as a single JSON object without trailing newline
+pub fn format_json(ev: &AuditEvent) -> String {
+    let mut res = String::with_capacity(128);
+
+    res.push_str("{\"op\":");
+    push_json_str(&mut res, ev.op);
+    res.push_str(",\"path\":");
+    push_json_path(&mut res, Some(ev.path));
+    res.push_str(",\"canonical\":");
+    push_json_path(&mut res, ev.canonical);
+
+    let errno = ev.error.and_then(|e| e.errno());
+
+    let _ = write!(res, ",\"result\":\"{}\",\"errno\":",
+                   if ev.error.is_none() { "ok" } else { "error" });
+
+    match errno {
+        Some(e)	=> { let _ = write!(res, "{}", e); },
+        None	=> res.push_str("null"),
+    }
+
+    let _ = write!(res, ",\"latency_us\":{}}}", ev.latency.as_micros());
+
+    res
+}
+
+/// Creates an audit hook which writes every event as a line of JSON
+/// (see `format_json()`) into `w`.
+///
+/// Write errors are ignored; the operations of the chroot must not
+/// fail because the log can not be written.
+pub fn json_lines_hook<W>(w: W) -> AuditHook
+where
+    W: Write + Send + 'static,
+{
+    let w = Mutex::new(w);
+
+    Arc::new(move |ev: &AuditEvent| {
+        let mut line = format_json(ev);
+
+        line.push('\n');
+
+        if let Ok(mut w) = w.lock() {
+            let _ = w.write_all(line.as_bytes());
+        }
+    })
+}
+
+#[cfg(test)]
+#[path="tests/eventlog.inc.rs"]
+mod test;
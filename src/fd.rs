@@ -2,17 +2,21 @@ extern crate errno;
 extern crate libc;
 
 use std;
-use std::cell::Cell;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
 use std::io::Error;
 use std::path::Path;
 use std::ffi::OsString;
 use std::os::unix::ffi::OsStringExt;
 
 use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::errors::*;
 
 use crate::LibcString;
+use crate::metadata::Metadata;
+use crate::dir::{Dir, ReadDir};
 
 macro_rules! try_errno {
     ($expr:expr) => {{
@@ -27,16 +31,118 @@ macro_rules! try_errno {
 #[allow(non_camel_case_types)]
 type int = libc::c_int;
 
+/// Timestamp for `FdRaw::futimens()` and `FdRaw::utimensat()` which
+/// sets the current time
+pub const UTIME_NOW: libc::timespec = libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_NOW };
+
+/// Timestamp for `FdRaw::futimens()` and `FdRaw::utimensat()` which
+/// leaves the time unchanged
+pub const UTIME_OMIT: libc::timespec = libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_OMIT };
+
+/// Converts `time` into a `timespec`; times before the epoch have a
+/// negative `tv_sec` and a non-negative `tv_nsec`.
+pub fn timespec_of(time: SystemTime) -> libc::timespec {
+    let (sec, nsec) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        },
+    };
+
+    libc::timespec {
+        tv_sec:		sec as libc::time_t,
+        tv_nsec:	nsec as _,
+    }
+}
+
+/// Adds `AT_EMPTY_PATH` to `flags` when `path` is empty so that the
+/// `*at()` syscalls operate on the filedescriptor itself.
+fn at_flags(path: &Path, flags: int) -> int {
+    if path.as_os_str().is_empty() {
+        flags | libc::AT_EMPTY_PATH
+    } else {
+        flags
+    }
+}
+
+/// Filedescriptor borrowed from an `FdRaw`
+///
+/// Derefs to an `FdRaw` which is never closed and can not outlive
+/// its owner.
+#[derive(Debug)]
+pub struct BorrowedFdRef<'a> {
+    raw:	FdRaw,
+    owner:	PhantomData<&'a FdRaw>,
+}
+
+impl Clone for BorrowedFdRef<'_> {
+    fn clone(&self) -> Self {
+        BorrowedFdRef {
+            raw:	FdRaw::_new_unmanaged(self.raw.fd),
+            owner:	PhantomData,
+        }
+    }
+}
+
+impl std::ops::Deref for BorrowedFdRef<'_> {
+    type Target = FdRaw;
+
+    fn deref(&self) -> &FdRaw {
+        &self.raw
+    }
+}
+
+/// Flags of this crate which are passed together with the `O_*`
+/// flags to the open functions
+pub enum OpenFlags {}
+
+impl OpenFlags {
+    /// Descriptors are opened with `O_CLOEXEC` even when it is not
+    /// given; with this flag, it is never set so that the descriptor
+    /// is inherited by executed programs.  The value is a bit which
+    /// no `O_*` flag uses on Linux.
+    pub const INHERIT: int = 0x4000_0000;
+
+    /// Translates `flags` into the ones passed to the kernel
+    pub(crate) fn to_kernel(flags: int) -> int {
+        if flags & Self::INHERIT != 0 {
+            flags & !(Self::INHERIT | libc::O_CLOEXEC)
+        } else {
+            flags | libc::O_CLOEXEC
+        }
+    }
+}
+
+// `OpenFlags::INHERIT` must not be mistaken for a kernel flag
+const _: () = assert!(OpenFlags::INHERIT & (libc::O_ACCMODE | libc::O_APPEND |
+                                            libc::O_ASYNC | libc::O_CLOEXEC |
+                                            libc::O_CREAT | libc::O_DIRECT |
+                                            libc::O_DIRECTORY | libc::O_DSYNC |
+                                            libc::O_EXCL | libc::O_LARGEFILE |
+                                            libc::O_NOATIME | libc::O_NOCTTY |
+                                            libc::O_NOFOLLOW | libc::O_NONBLOCK |
+                                            libc::O_PATH | libc::O_SYNC |
+                                            libc::O_TMPFILE | libc::O_TRUNC) == 0);
+
+/// `F_SETSIG` from `<asm-generic/fcntl.h>`; the libc crate defines it
+/// for few targets only
+const F_SETSIG: int = 10;
+
 // wrap a file descriptor and close it automatically
 #[derive(Debug)]
 pub struct FdRaw {
     pub(crate) fd: libc::c_int,
-    pub(crate) is_managed: Cell<bool>,
+    is_managed: bool,
 }
 
 impl Drop for FdRaw {
     fn drop(&mut self) {
-        if self.is_managed.get() {
+        if self.is_managed {
             let rc = unsafe { libc::close(self.fd) };
 
             if rc < 0 {
@@ -47,52 +153,117 @@ impl Drop for FdRaw {
     }
 }
 
+/// Moves `fd` above stderr when it took the place of a closed
+/// stdin, stdout or stderr.  Else, a later `dup2()` to that standard
+/// descriptor or a library writing diagnostics to it would clobber
+/// the file.  The `FD_CLOEXEC` flag is kept.
+fn above_stdio(fd: int) -> int {
+    if !(0..3).contains(&fd) {
+        return fd;
+    }
+
+    let cmd = match unsafe { libc::fcntl(fd, libc::F_GETFD) } {
+        flags if flags >= 0 && flags & libc::FD_CLOEXEC == 0 => libc::F_DUPFD,
+        _ => libc::F_DUPFD_CLOEXEC,
+    };
+
+    let new_fd = unsafe { libc::fcntl(fd, cmd, 3) };
+
+    if new_fd < 0 {
+        warn!("failed to move fd {} above stderr: {:?}", fd, Error::last_os_error());
+        return fd;
+    }
+
+    unsafe { libc::close(fd) };
+
+    new_fd
+}
+
 impl FdRaw {
-    fn _new(fd: int) -> Self {
+    /// Wraps a descriptor which was just created by this crate; it is
+    /// moved above stderr.
+    pub(crate) fn _new(fd: int) -> Self {
+        let fd = above_stdio(fd);
+
         Self {
             fd: fd,
-            is_managed: Cell::new(fd >= 0 && fd != libc::AT_FDCWD),
+            is_managed: fd >= 0 && fd != libc::AT_FDCWD,
         }
     }
 
-    fn _new_unmanaged(fd: int) -> Self {
+    /// Wraps a descriptor which is owned elsewhere; it is not closed
+    /// on drop.
+    pub(crate) fn _new_unmanaged(fd: int) -> Self {
         Self {
             fd: fd,
-            is_managed: Cell::new(false),
+            is_managed: false,
         }
     }
 
     pub fn into_file(self) -> Result<std::fs::File> {
         use std::os::unix::io::FromRawFd;
 
-        let res = unsafe { std::fs::File::from_raw_fd(self.fd) };
-        self.is_managed.set(false);
+        Ok(unsafe { std::fs::File::from_raw_fd(self.leak()) })
+    }
 
-        Ok(res)
+    /// Gives up the ownership and returns the raw filedescriptor.  It
+    /// will not be closed anymore; e.g. when it is handed to C code
+    /// or kept open across `exec()`.
+    pub fn leak(self) -> int {
+        let fd = self.fd;
+
+        mem::forget(self);
+        fd
     }
 
+    /// Converts the filedescriptor into a `std` `OwnedFd`.
+    ///
+    /// Fails for `Self::cwd()` which does not own a descriptor.
+    pub fn into_owned_fd(self) -> Result<std::os::unix::io::OwnedFd> {
+        use std::os::unix::io::FromRawFd;
+
+        ensure!(self.is_managed, "filedescriptor {} is not owned", self.fd);
+
+        Ok(unsafe { std::os::unix::io::OwnedFd::from_raw_fd(self.leak()) })
+    }
+
+    /// Opens `path` (`open()`).  `O_CLOEXEC` is always set unless
+    /// `flags` contain `OpenFlags::INHERIT`.
     pub fn open<T: AsRef<Path>>(path: &T, flags: int) -> Result<Self> {
         let fd = try_errno!(unsafe {
-            libc::open(path.as_ref().as_libc().0, flags)
+            libc::open(path.as_ref().as_libc()?.0, OpenFlags::to_kernel(flags))
         });
 
         Ok(Self::_new(fd))
     }
 
+    /// Opens `path` relative to the descriptor (`openat()`); see
+    /// `Self::open()` for `O_CLOEXEC`.
     pub fn openat<T: AsRef<Path>>(&self, path: &T, flags: int) -> Result<Self> {
         let fd = try_errno!(unsafe {
-            libc::openat(self.fd, path.as_ref().as_libc().0, flags)
+            libc::openat(self.fd, path.as_ref().as_libc()?.0, OpenFlags::to_kernel(flags))
         });
 
         Ok(Self::_new(fd))
     }
 
+    /// Opens `path` with `O_PATH | O_NOFOLLOW | O_CLOEXEC`.
+    ///
+    /// The returned descriptor can be used as the directory argument
+    /// of `*at()` functions and for `fstat()` but not for I/O.  Only
+    /// search permission on the parent directory is required.
+    pub fn open_path_at<T: AsRef<Path>>(&self, path: &T) -> Result<Self> {
+        self.openat(path, libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+    }
+
+    /// Opens `path` relative to the descriptor with `O_CREAT`; see
+    /// `Self::open()` for `O_CLOEXEC`.
     pub fn createat<T: AsRef<Path>>(&self, path: &T, flags: int,
                                     mode: u32) -> Result<Self>
     {
         let fd = try_errno!(unsafe {
-            libc::openat(self.fd, path.as_ref().as_libc().0,
-                         flags | libc::O_CREAT, mode)
+            libc::openat(self.fd, path.as_ref().as_libc()?.0,
+                         OpenFlags::to_kernel(flags | libc::O_CREAT), mode)
         });
 
         Ok(Self::_new(fd))
@@ -100,7 +271,7 @@ impl FdRaw {
 
     pub fn mkdirat<T: AsRef<Path>>(&self, path: &T, mode: u32) -> Result<()> {
         try_errno!(unsafe {
-            libc::mkdirat(self.fd, path.as_ref().as_libc().0, mode)
+            libc::mkdirat(self.fd, path.as_ref().as_libc()?.0, mode)
         });
 
         Ok(())
@@ -112,26 +283,122 @@ impl FdRaw {
         T: AsRef<Path>,
     {
         try_errno!(unsafe {
-            libc::symlinkat(target.as_ref().as_libc().0,
+            libc::symlinkat(target.as_ref().as_libc()?.0,
                             self.fd,
-                            path.as_ref().as_libc().0)
+                            path.as_ref().as_libc()?.0)
         });
 
         Ok(())
     }
 
+    /// Renames `path` relative to the filedescriptor to `new_path`
+    /// relative to `new_dir`; an existing `new_path` is replaced
+    /// atomically.
+    pub fn renameat<S,T>(&self, path: &S, new_dir: &FdRaw,
+                         new_path: &T) -> Result<()>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        try_errno!(unsafe {
+            libc::renameat(self.fd, path.as_ref().as_libc()?.0,
+                           new_dir.fd, new_path.as_ref().as_libc()?.0)
+        });
+
+        Ok(())
+    }
+
+    /// Like `Self::renameat()` but with `renameat2()` flags;
+    /// `RENAME_NOREPLACE` fails with `EEXIST` instead of replacing
+    /// `new_path` and `RENAME_EXCHANGE` swaps both entries.
+    pub fn renameat2<S,T>(&self, path: &S, new_dir: &FdRaw,
+                          new_path: &T, flags: libc::c_uint) -> Result<()>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        try_errno!(unsafe {
+            libc::renameat2(self.fd, path.as_ref().as_libc()?.0,
+                            new_dir.fd, new_path.as_ref().as_libc()?.0, flags)
+        });
+
+        Ok(())
+    }
+
+    /// Creates the hard link `new_path` relative to `new_dir` for
+    /// `path` relative to the filedescriptor.
+    ///
+    /// `flags` can contain `AT_SYMLINK_FOLLOW` to link the target of a
+    /// symlink instead of the link itself and `AT_EMPTY_PATH` to link
+    /// the file referred by this descriptor when `path` is empty (this
+    /// requires `CAP_DAC_READ_SEARCH` on most kernels).
+    pub fn linkat<S,T>(&self, path: &S, new_dir: &FdRaw,
+                       new_path: &T, flags: int) -> Result<()>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        try_errno!(unsafe {
+            libc::linkat(self.fd, path.as_ref().as_libc()?.0,
+                         new_dir.fd, new_path.as_ref().as_libc()?.0, flags)
+        });
+
+        Ok(())
+    }
+
+    /// Removes `path` relative to the filedescriptor; `flags` is `0`
+    /// for files and `AT_REMOVEDIR` for empty directories.
+    pub fn unlinkat<T: AsRef<Path>>(&self, path: &T, flags: int) -> Result<()> {
+        try_errno!(unsafe {
+            libc::unlinkat(self.fd, path.as_ref().as_libc()?.0, flags)
+        });
+
+        Ok(())
+    }
+
+    /// Exchanges `path` relative to the filedescriptor atomically
+    /// with `new_path` relative to `new_dir`.
+    ///
+    /// Both paths must exist; they can be of different types.
+    pub fn exchangeat<S,T>(&self, path: &S, new_dir: &FdRaw,
+                           new_path: &T) -> Result<()>
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        self.renameat2(path, new_dir, new_path, libc::RENAME_EXCHANGE)
+    }
+
+    /// Wraps an existing filedescriptor which will be closed when
+    /// the object is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open filedescriptor which is not owned by
+    /// anything else.
     pub unsafe fn new(fd: int) -> Self {
         assert!(fd >= 0);
 
-        Self::_new(fd)
+        Self {
+            fd: fd,
+            is_managed: true,
+        }
     }
 
+    /// Returns `AT_FDCWD`; relative paths are resolved against the
+    /// working directory at the time of the operation.  See
+    /// `current_dir()` for a stable reference.
     pub fn cwd() -> Self {
         Self::_new(libc::AT_FDCWD)
     }
 
-    pub unsafe fn as_unmanaged(&self) -> Self {
-        Self::_new_unmanaged(self.fd)
+    /// Returns a borrowed reference to the filedescriptor which
+    /// does not close it.
+    pub fn borrow(&self) -> BorrowedFdRef<'_> {
+        BorrowedFdRef {
+            raw:	Self::_new_unmanaged(self.fd),
+            owner:	PhantomData,
+        }
     }
 
     pub fn into_fd(self) -> Fd {
@@ -152,6 +419,391 @@ impl FdRaw {
         Ok(Self::_new(fd))
     }
 
+    /// Sets and clears file status flags (`F_SETFL`)
+    fn update_status_flags(&self, set: int, clear: int) -> Result<()> {
+        let flags = try_errno!(unsafe { libc::fcntl(self.fd, libc::F_GETFL) });
+
+        try_errno!(unsafe {
+            libc::fcntl(self.fd, libc::F_SETFL, (flags & !clear) | set)
+        });
+
+        Ok(())
+    }
+
+    /// Sets the process (or process group when negative) which
+    /// receives `SIGIO` and `SIGURG` signals for events on the
+    /// filedescriptor.
+    pub fn set_owner(&self, pid: libc::pid_t) -> Result<()> {
+        try_errno!(unsafe { libc::fcntl(self.fd, libc::F_SETOWN, pid) });
+
+        Ok(())
+    }
+
+    /// Enables or disables signal driven I/O (`O_ASYNC`)
+    pub fn set_async(&self, enable: bool) -> Result<()> {
+        if enable {
+            self.update_status_flags(libc::O_ASYNC, 0)
+        } else {
+            self.update_status_flags(0, libc::O_ASYNC)
+        }
+    }
+
+    /// Sets the size of the file to `len` bytes (`ftruncate()`); data
+    /// behind it is discarded, extended parts read as zeros.  The
+    /// file must be opened for writing.
+    pub fn ftruncate(&self, len: u64) -> Result<()> {
+        let len = libc::off_t::try_from(len)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+
+        try_errno!(unsafe { libc::ftruncate(self.fd, len) });
+
+        Ok(())
+    }
+
+    /// Deallocates the first `len` bytes of the file
+    /// (`FALLOC_FL_PUNCH_HOLE`); they read as zeros afterwards.  The
+    /// file size and the offsets of the remaining data are kept so
+    /// that readers of the tail are not disturbed.  Nothing is done
+    /// for a `len` of `0`.
+    pub fn punch_front(&self, len: u64) -> Result<()> {
+        // fallocate() rejects empty ranges with EINVAL
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.fallocate(libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE, 0, len)
+    }
+
+    /// Manipulates the space of the range `offset..offset + len`
+    /// (`fallocate()`).  `mode` 0 allocates it and extends the file
+    /// when needed; `FALLOC_FL_KEEP_SIZE` preallocates without
+    /// changing the size and `FALLOC_FL_PUNCH_HOLE` (which requires
+    /// it) deallocates the range.  Fails with `EOPNOTSUPP` when the
+    /// filesystem does not support `mode`.
+    pub fn fallocate(&self, mode: int, offset: u64, len: u64) -> Result<()> {
+        let offset = libc::off_t::try_from(offset)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+        let len = libc::off_t::try_from(len)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+
+        try_errno!(unsafe { libc::fallocate(self.fd, mode, offset, len) });
+
+        Ok(())
+    }
+
+    /// Removes the cached pages of the whole file from memory
+    /// (`POSIX_FADV_DONTNEED`); e.g. after it was read once by a
+    /// backup.  Dirty pages are kept until they were written back.
+    pub fn drop_page_cache(&self) -> Result<()> {
+        let rc = unsafe { libc::posix_fadvise(self.fd, 0, 0, libc::POSIX_FADV_DONTNEED) };
+
+        ensure!(rc == 0, Error::from_raw_os_error(rc));
+
+        Ok(())
+    }
+
+    /// Reads from the current file offset into `buf` which does not
+    /// need to be initialized and returns the filled part of it.
+    pub fn read_uninit<'b>(&self, buf: &'b mut [mem::MaybeUninit<u8>])
+                           -> Result<&'b mut [u8]>
+    {
+        let rc = try_errno!(unsafe {
+            libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len())
+        });
+
+        // the kernel initialized the first 'rc' bytes
+        Ok(unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8,
+                                                   rc as usize) })
+    }
+
+    /// Like `Self::read_uninit()` but reads at `offset` (`pread()`)
+    /// without changing the file offset.
+    pub fn pread_uninit<'b>(&self, buf: &'b mut [mem::MaybeUninit<u8>], offset: u64)
+                            -> Result<&'b mut [u8]>
+    {
+        let offset = libc::off_t::try_from(offset)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+
+        let rc = try_errno!(unsafe {
+            libc::pread(self.fd, buf.as_mut_ptr() as *mut _, buf.len(), offset)
+        });
+
+        Ok(unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8,
+                                                   rc as usize) })
+    }
+
+    /// Reads up to `buf.len()` bytes at `offset` (`pread()`) without
+    /// changing the file offset; returns the number of read bytes.
+    pub fn pread(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let offset = libc::off_t::try_from(offset)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+
+        let rc = try_errno!(unsafe {
+            libc::pread(self.fd, buf.as_mut_ptr() as *mut _, buf.len(), offset)
+        });
+
+        Ok(rc as usize)
+    }
+
+    /// Writes `buf` at `offset` (`pwrite()`) without changing the file
+    /// offset; returns the number of written bytes which might be
+    /// less than `buf.len()`.
+    pub fn pwrite(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let offset = libc::off_t::try_from(offset)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+
+        let rc = try_errno!(unsafe {
+            libc::pwrite(self.fd, buf.as_ptr() as *const _, buf.len(), offset)
+        });
+
+        Ok(rc as usize)
+    }
+
+    /// Reads into `bufs` in order (`readv()`); returns the number of
+    /// read bytes.  Only the first `UIO_MAXIOV` buffers are used.
+    pub fn readv(&self, bufs: &mut [std::io::IoSliceMut<'_>]) -> Result<usize> {
+        let cnt = bufs.len().min(libc::UIO_MAXIOV as usize) as int;
+
+        // IoSliceMut is ABI compatible with struct iovec
+        let rc = try_errno!(unsafe {
+            libc::readv(self.fd, bufs.as_ptr() as *const libc::iovec, cnt)
+        });
+
+        Ok(rc as usize)
+    }
+
+    /// Writes `bufs` in order (`writev()`); returns the number of
+    /// written bytes which might be less than their total length.
+    /// Only the first `UIO_MAXIOV` buffers are used.
+    pub fn writev(&self, bufs: &[std::io::IoSlice<'_>]) -> Result<usize> {
+        let cnt = bufs.len().min(libc::UIO_MAXIOV as usize) as int;
+
+        // IoSlice is ABI compatible with struct iovec
+        let rc = try_errno!(unsafe {
+            libc::writev(self.fd, bufs.as_ptr() as *const libc::iovec, cnt)
+        });
+
+        Ok(rc as usize)
+    }
+
+    /// Converts the offset of `preadv2()` and `pwritev2()`; `-1`
+    /// selects the file offset.
+    fn rwf_offset(offset: Option<u64>) -> Result<libc::off_t> {
+        match offset {
+            None => Ok(-1),
+            Some(o) => libc::off_t::try_from(o)
+                .map_err(|_| Error::from_raw_os_error(libc::EINVAL).into()),
+        }
+    }
+
+    /// Like `Self::readv()` but reads at `offset` or, with `None`, at
+    /// the file offset and advances it (`preadv2()`).
+    ///
+    /// `flags` are `RWF_*` values; e.g. with `libc::RWF_NOWAIT` the
+    /// call fails with `EAGAIN` instead of waiting for data which is
+    /// not in the page cache.  Kernels before 4.6 fail with `ENOSYS`,
+    /// unknown flags with `EOPNOTSUPP`.
+    pub fn preadv2(&self, bufs: &mut [std::io::IoSliceMut<'_>], offset: Option<u64>,
+                   flags: int) -> Result<usize> {
+        let cnt = bufs.len().min(libc::UIO_MAXIOV as usize) as int;
+        let offset = Self::rwf_offset(offset)?;
+
+        let rc = try_errno!(unsafe {
+            libc::preadv2(self.fd, bufs.as_ptr() as *const libc::iovec, cnt, offset, flags)
+        });
+
+        Ok(rc as usize)
+    }
+
+    /// Like `Self::writev()` but writes at `offset` or, with `None`,
+    /// at the file offset (`pwritev2()`); see `Self::preadv2()`.
+    /// E.g. `libc::RWF_DSYNC` makes the written data durable like
+    /// `O_DSYNC` for this call only.
+    pub fn pwritev2(&self, bufs: &[std::io::IoSlice<'_>], offset: Option<u64>,
+                    flags: int) -> Result<usize> {
+        let cnt = bufs.len().min(libc::UIO_MAXIOV as usize) as int;
+        let offset = Self::rwf_offset(offset)?;
+
+        let rc = try_errno!(unsafe {
+            libc::pwritev2(self.fd, bufs.as_ptr() as *const libc::iovec, cnt, offset, flags)
+        });
+
+        Ok(rc as usize)
+    }
+
+    /// Enables or disables non-blocking I/O (`O_NONBLOCK`)
+    pub fn set_nonblocking(&self, enable: bool) -> Result<()> {
+        if enable {
+            self.update_status_flags(libc::O_NONBLOCK, 0)
+        } else {
+            self.update_status_flags(0, libc::O_NONBLOCK)
+        }
+    }
+
+    /// Sets or clears `FD_CLOEXEC`; e.g. for descriptors which were
+    /// received from elsewhere.  Descriptors opened by this crate get
+    /// `FD_CLOEXEC` unless `OpenFlags::INHERIT` is given.
+    pub fn set_cloexec(&self, enable: bool) -> Result<()> {
+        let flags = try_errno!(unsafe { libc::fcntl(self.fd, libc::F_GETFD) });
+        let new_flags = match enable {
+            true	=> flags | libc::FD_CLOEXEC,
+            false	=> flags & !libc::FD_CLOEXEC,
+        };
+
+        if new_flags != flags {
+            try_errno!(unsafe { libc::fcntl(self.fd, libc::F_SETFD, new_flags) });
+        }
+
+        Ok(())
+    }
+
+    /// Selects the signal which is sent instead of `SIGIO` when I/O
+    /// becomes possible (`F_SETSIG`); `0` restores `SIGIO`.
+    ///
+    /// When the signal is blocked and a `signalfd` listens for it,
+    /// I/O readiness can be consumed by reading that descriptor; see
+    /// `Self::route_signals()`.
+    pub fn set_signal(&self, sig: int) -> Result<()> {
+        try_errno!(unsafe { libc::fcntl(self.fd, F_SETSIG, sig) });
+
+        Ok(())
+    }
+
+    /// Opens the file referred by the filedescriptor again with
+    /// different `flags`.
+    ///
+    /// Reopening happens through `/proc/self/fd` and creates a new
+    /// open file description; e.g. an `O_PATH` or `O_RDONLY`
+    /// descriptor can be upgraded to `O_RDWR` when permissions allow
+    /// it.  `O_NOFOLLOW` is ignored because the magic link must be
+    /// followed.  `O_CLOEXEC` is handled like by `Self::open()`.
+    /// Method fails when the new descriptor does not refer to the
+    /// same file.
+    pub fn reopen(&self, flags: int) -> Result<Self> {
+        ensure!(flags & libc::O_CREAT == 0,
+                "O_CREAT not supported by reopen()");
+
+        let path = format!("/proc/self/fd/{}", self.fd);
+        let res = Self::open(&path, flags & !libc::O_NOFOLLOW)?;
+
+        ensure!(same_file_by_stat(&self.fstat()?, &res.fstat()?),
+                "reopen() of fd {} resulted in a different file", self.fd);
+
+        Ok(res)
+    }
+
+    /// Changes owner and group of the file itself; `None` keeps the
+    /// corresponding id.  Works with `O_PATH` descriptors too and
+    /// changes a symlink itself then.
+    pub fn fchown(&self, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>)
+                  -> Result<()> {
+        self.fchownat(&"", uid, gid, false)
+    }
+
+    /// Changes owner and group of `path`; `None` keeps the
+    /// corresponding id.
+    ///
+    /// An empty `path` refers to the filedescriptor itself.
+    pub fn fchownat<T>(&self, path: &T, uid: Option<libc::uid_t>,
+                       gid: Option<libc::gid_t>, do_follow: bool)
+                       -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let flags = at_flags(path.as_ref(), if do_follow {
+            0
+        } else {
+            libc::AT_SYMLINK_NOFOLLOW
+        });
+
+        try_errno!(unsafe {
+            libc::fchownat(self.fd, path.as_ref().as_libc()?.0,
+                           uid.unwrap_or(!0), gid.unwrap_or(!0), flags)
+        });
+
+        Ok(())
+    }
+
+    /// Changes the mode of `path` relative to the filedescriptor.
+    ///
+    /// An empty `path` refers to the filedescriptor itself like in
+    /// `Self::fchmod()`.  Without `do_follow`, symlinks are not
+    /// followed; changing the mode of a symlink itself fails with
+    /// `EOPNOTSUPP` on Linux.
+    pub fn fchmodat<T>(&self, path: &T, mode: u32, do_follow: bool) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        if path.as_ref().as_os_str().is_empty() {
+            return self.fchmod(mode);
+        }
+
+        let flags = if do_follow {
+            0
+        } else {
+            libc::AT_SYMLINK_NOFOLLOW
+        };
+
+        try_errno!(unsafe {
+            libc::fchmodat(self.fd, path.as_ref().as_libc()?.0, mode, flags)
+        });
+
+        Ok(())
+    }
+
+    /// Returns the id of the mount the file belongs to.
+    ///
+    /// Unlike `st_dev`, this distinguishes bind mounts of the same
+    /// filesystem.  Requires Linux 5.8 or later.
+    pub fn mount_id(&self) -> Result<u64> {
+        let mut stx = mem::MaybeUninit::<libc::statx>::uninit();
+
+        try_errno!(unsafe {
+            libc::statx(self.fd, b"\0".as_ptr() as *const _, libc::AT_EMPTY_PATH,
+                        libc::STATX_MNT_ID, stx.as_mut_ptr())
+        });
+
+        let stx = unsafe { stx.assume_init() };
+
+        ensure!(stx.stx_mask & libc::STATX_MNT_ID != 0,
+                "kernel does not report mount ids");
+
+        Ok(stx.stx_mnt_id)
+    }
+
+    /// Returns `Self::mount_id()` or, on kernels which do not report
+    /// mount ids, the `st_dev` of the file.  Values are comparable
+    /// with the ones of other files only; bind mounts of the same
+    /// filesystem are not distinguished by the fallback.
+    pub(crate) fn mount_id_or_dev(&self) -> Result<u64> {
+        match self.mount_id() {
+            Ok(id) => Ok(id),
+            Err(ref e) if e.errno().map_or(true, |e| e == libc::ENOSYS) =>
+                Ok(self.fstat()?.st_dev),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns whether the file lives on a mount (or filesystem)
+    /// which is read-only; writes fail with `EROFS` there.
+    pub fn is_on_readonly_mount(&self) -> Result<bool> {
+        let mut st = mem::MaybeUninit::<libc::statvfs>::uninit();
+
+        try_errno!(unsafe { libc::fstatvfs(self.fd, st.as_mut_ptr()) });
+
+        Ok(unsafe { st.assume_init() }.f_flag & libc::ST_RDONLY != 0)
+    }
+
+    /// Flushes the metadata of a directory (e.g. after creating,
+    /// renaming or removing entries).
+    ///
+    /// Filedescriptor must not be opened with `O_PATH`.
+    pub fn sync_dir(&self) -> Result<()> {
+        try_errno!(unsafe { libc::fsync(self.fd) });
+
+        Ok(())
+    }
+
     fn is_file_type(&self, fname: &Path, file_type: u32) -> bool {
         let stat = self.fstatat(&fname, false);
         match stat {
@@ -176,51 +828,80 @@ impl FdRaw {
     where
         T: AsRef<Path>
     {
-	#[allow(clippy::uninit_assumed_init)]
-        let mut stat: libc::stat = unsafe { mem::MaybeUninit::uninit().assume_init() };
+        let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
 
         try_errno!(unsafe {
             if do_follow {
-                libc::stat(fname.as_ref().as_libc().0, &mut stat)
+                libc::stat(fname.as_ref().as_libc()?.0, stat.as_mut_ptr())
             } else {
-                libc::lstat(fname.as_ref().as_libc().0, &mut stat)
+                libc::lstat(fname.as_ref().as_libc()?.0, stat.as_mut_ptr())
             }
         });
 
-        Ok(stat)
+        Ok(unsafe { stat.assume_init() })
     }
 
+    /// Returns stat information of `fname` relative to the
+    /// filedescriptor.
+    ///
+    /// An empty `fname` refers to the filedescriptor itself
+    /// (`AT_EMPTY_PATH`) which works for non-directory and `O_PATH`
+    /// descriptors too.
     pub fn fstatat<T>(&self, fname: &T, do_follow: bool) -> Result<libc::stat>
     where
         T: AsRef<Path>
     {
-        let flags = if do_follow {
+        let flags = at_flags(fname.as_ref(), if do_follow {
             0
         } else {
             libc::AT_SYMLINK_NOFOLLOW
-        };
+        });
 
-	#[allow(clippy::uninit_assumed_init)]
-        let mut stat: libc::stat = unsafe { mem::MaybeUninit::uninit().assume_init() };
+        let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
 
         try_errno!(unsafe {
-            libc::fstatat(self.fd, fname.as_ref().as_libc().0, &mut stat, flags)
+            libc::fstatat(self.fd, fname.as_ref().as_libc()?.0, stat.as_mut_ptr(),
+                          flags)
         });
 
-        Ok(stat)
+        Ok(unsafe { stat.assume_init() })
     }
 
     pub fn fstat(&self) -> Result<libc::stat> {
-	#[allow(clippy::uninit_assumed_init)]
-        let mut stat: libc::stat = unsafe { mem::MaybeUninit::uninit().assume_init() };
+        let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
 
         try_errno!(unsafe {
-            libc::fstat(self.fd, &mut stat)
+            libc::fstat(self.fd, stat.as_mut_ptr())
         });
 
-        Ok(stat)
+        Ok(unsafe { stat.assume_init() })
+    }
+
+    /// Returns the metadata of the file referred by the
+    /// filedescriptor.
+    ///
+    /// Works with `O_PATH` descriptors too; when these were opened
+    /// with `O_NOFOLLOW`, a symlink itself will be described.
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.fstat().map(Metadata::from_stat)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.metadata().map(|m| m.is_dir()).unwrap_or(false)
     }
 
+    pub fn is_file(&self) -> bool {
+        self.metadata().map(|m| m.is_file()).unwrap_or(false)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.metadata().map(|m| m.is_symlink()).unwrap_or(false)
+    }
+
+    /// Reads the target of symlink `fname`.
+    ///
+    /// An empty `fname` reads the symlink referred by a descriptor
+    /// opened with `O_PATH | O_NOFOLLOW`.
     pub fn readlinkat<T: AsRef<Path>>(&self, fname: &T) -> Result<OsString> {
         let mut buf = Vec::with_capacity(256);
 
@@ -228,7 +909,7 @@ impl FdRaw {
             let buf_sz = try_errno!(unsafe {
                 // on overflow, readlinkat() returns buf.capacity();
                 // else the number of actually written bytes
-                libc::readlinkat(self.fd, fname.as_ref().as_libc().0,
+                libc::readlinkat(self.fd, fname.as_ref().as_libc()?.0,
                                  buf.as_mut_ptr() as *mut _,
                                  buf.capacity())
             }) as usize;
@@ -250,6 +931,198 @@ impl FdRaw {
             buf.reserve(256);
         }
     }
+
+    /// Runs a descriptor based operation (e.g. `f*xattr()`) and
+    /// retries it with the path based variant through `/proc/self/fd`
+    /// when the descriptor was opened with `O_PATH`.
+    fn fd_or_proc_op<F, P>(&self, fop: F, pop: P) -> Result<isize>
+    where
+        F: Fn(int) -> isize,
+        P: Fn(*const libc::c_char) -> isize,
+    {
+        let rc = fop(self.fd);
+
+        if rc >= 0 {
+            return Ok(rc);
+        }
+
+        let err = Error::last_os_error();
+
+        if err.raw_os_error() != Some(libc::EBADF) {
+            return Err(err.into());
+        }
+
+        let path = std::ffi::CString::new(format!("/proc/self/fd/{}", self.fd))
+            .unwrap();
+
+        Ok(try_errno!(pop(path.as_ptr())))
+    }
+
+    /// Changes the permissions (`fchmod()`); works with `O_PATH`
+    /// descriptors too.
+    pub fn fchmod(&self, mode: u32) -> Result<()> {
+        self.fd_or_proc_op(|fd| unsafe { libc::fchmod(fd, mode) } as isize,
+                           |p| unsafe { libc::chmod(p, mode) } as isize)?;
+
+        Ok(())
+    }
+
+    /// Sets access and modification time (`futimens()`); works with
+    /// `O_PATH` descriptors too but follows symlinks then.
+    pub fn futimens(&self, atime: libc::timespec, mtime: libc::timespec)
+                    -> Result<()> {
+        let times = [atime, mtime];
+
+        self.fd_or_proc_op(|fd| unsafe { libc::futimens(fd, times.as_ptr()) } as isize,
+                           |p| unsafe {
+                               libc::utimensat(libc::AT_FDCWD, p, times.as_ptr(), 0)
+                           } as isize)?;
+
+        Ok(())
+    }
+
+    /// Sets access and modification time of `path` relative to the
+    /// filedescriptor (`utimensat()`); `UTIME_NOW` and `UTIME_OMIT`
+    /// can be passed for either of them.
+    ///
+    /// An empty `path` refers to the filedescriptor itself like in
+    /// `Self::futimens()`.  Without `do_follow`, the times of symlinks
+    /// themselves are changed.
+    pub fn utimensat<T>(&self, path: &T, atime: libc::timespec, mtime: libc::timespec,
+                        do_follow: bool) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        if path.as_ref().as_os_str().is_empty() {
+            return self.futimens(atime, mtime);
+        }
+
+        let times = [atime, mtime];
+        let flags = if do_follow {
+            0
+        } else {
+            libc::AT_SYMLINK_NOFOLLOW
+        };
+
+        try_errno!(unsafe {
+            libc::utimensat(self.fd, path.as_ref().as_libc()?.0, times.as_ptr(), flags)
+        });
+
+        Ok(())
+    }
+
+    /// Like `Self::futimens()` but with `SystemTime`; `None` leaves
+    /// the corresponding time unchanged.
+    pub fn set_times(&self, atime: Option<SystemTime>, mtime: Option<SystemTime>)
+                     -> Result<()> {
+        self.futimens(atime.map_or(UTIME_OMIT, timespec_of),
+                      mtime.map_or(UTIME_OMIT, timespec_of))
+    }
+
+    /// Like `Self::utimensat()` but with `SystemTime`; `None` leaves
+    /// the corresponding time unchanged.
+    pub fn set_times_at<T>(&self, path: &T, atime: Option<SystemTime>,
+                           mtime: Option<SystemTime>, do_follow: bool) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        self.utimensat(path, atime.map_or(UTIME_OMIT, timespec_of),
+                       mtime.map_or(UTIME_OMIT, timespec_of), do_follow)
+    }
+
+    /// Returns the value of extended attribute `name` or `None` when
+    /// it does not exist.
+    pub fn getxattr(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let name = std::ffi::CString::new(name).chain_err(|| "invalid xattr name")?;
+        let name = name.as_ptr();
+
+        loop {
+            let sz = match self.fd_or_proc_op(
+                |fd| unsafe { libc::fgetxattr(fd, name, std::ptr::null_mut(), 0) },
+                |p| unsafe { libc::getxattr(p, name, std::ptr::null_mut(), 0) }) {
+                Err(ref e) if e.errno() == Some(libc::ENODATA) => return Ok(None),
+                r => r? as usize,
+            };
+
+            let mut buf: Vec<u8> = Vec::with_capacity(sz);
+            let ptr = buf.as_mut_ptr() as *mut libc::c_void;
+
+            match self.fd_or_proc_op(
+                |fd| unsafe { libc::fgetxattr(fd, name, ptr, sz) },
+                |p| unsafe { libc::getxattr(p, name, ptr, sz) }) {
+                // attribute grew meanwhile
+                Err(ref e) if e.errno() == Some(libc::ERANGE) => continue,
+                Err(ref e) if e.errno() == Some(libc::ENODATA) => return Ok(None),
+                r => {
+                    let len = r? as usize;
+
+                    assert!(len <= sz);
+
+                    unsafe { buf.set_len(len) };
+
+                    return Ok(Some(buf));
+                }
+            }
+        }
+    }
+
+    /// Sets extended attribute `name`; `flags` can be `0`,
+    /// `XATTR_CREATE` or `XATTR_REPLACE`.
+    pub fn setxattr(&self, name: &str, value: &[u8], flags: int) -> Result<()> {
+        let name = std::ffi::CString::new(name).chain_err(|| "invalid xattr name")?;
+        let name = name.as_ptr();
+        let ptr = value.as_ptr() as *const libc::c_void;
+
+        self.fd_or_proc_op(
+            |fd| unsafe { libc::fsetxattr(fd, name, ptr, value.len(), flags) as isize },
+            |p| unsafe { libc::setxattr(p, name, ptr, value.len(), flags) as isize })?;
+
+        Ok(())
+    }
+
+    /// Removes extended attribute `name`
+    pub fn removexattr(&self, name: &str) -> Result<()> {
+        let name = std::ffi::CString::new(name).chain_err(|| "invalid xattr name")?;
+        let name = name.as_ptr();
+
+        self.fd_or_proc_op(
+            |fd| unsafe { libc::fremovexattr(fd, name) as isize },
+            |p| unsafe { libc::removexattr(p, name) as isize })?;
+
+        Ok(())
+    }
+
+    /// Returns the names of all extended attributes
+    pub fn listxattr(&self) -> Result<Vec<String>> {
+        loop {
+            let sz = self.fd_or_proc_op(
+                |fd| unsafe { libc::flistxattr(fd, std::ptr::null_mut(), 0) },
+                |p| unsafe { libc::listxattr(p, std::ptr::null_mut(), 0) })? as usize;
+
+            let mut buf: Vec<u8> = Vec::with_capacity(sz);
+            let ptr = buf.as_mut_ptr() as *mut libc::c_char;
+
+            match self.fd_or_proc_op(
+                |fd| unsafe { libc::flistxattr(fd, ptr, sz) },
+                |p| unsafe { libc::listxattr(p, ptr, sz) }) {
+                // attributes were added meanwhile
+                Err(ref e) if e.errno() == Some(libc::ERANGE) => continue,
+                r => {
+                    let len = r? as usize;
+
+                    assert!(len <= sz);
+
+                    unsafe { buf.set_len(len) };
+
+                    return buf.split(|c| *c == 0)
+                        .filter(|n| !n.is_empty())
+                        .map(|n| String::from_utf8(n.to_vec())
+                             .chain_err(|| "invalid xattr name"))
+                        .collect();
+                }
+            }
+        }
+    }
 }
 
 #[cfg(not(feature = "atomic-rc"))]
@@ -270,14 +1143,22 @@ impl Fd {
         &self.0
     }
 
+    /// Like `FdRaw::open()`; `O_CLOEXEC` is set unless `flags`
+    /// contain `OpenFlags::INHERIT`.
     pub fn open<T: AsRef<Path>>(path: &T, flags: int) -> Result<Self> {
         FdRaw::open(path, flags).map(Self::from_rawfd)
     }
 
+    /// Like `FdRaw::openat()`; see `Self::open()` for `O_CLOEXEC`.
     pub fn openat<T: AsRef<Path>>(&self, path: &T, flags: int) -> Result<Self> {
         self.0.openat(path, flags).map(Self::from_rawfd)
     }
 
+    pub fn open_path_at<T: AsRef<Path>>(&self, path: &T) -> Result<Self> {
+        self.0.open_path_at(path).map(Self::from_rawfd)
+    }
+
+    /// Like `FdRaw::createat()`; see `Self::open()` for `O_CLOEXEC`.
     pub fn createat<T: AsRef<Path>>(&self, path: &T, flags:
                                     int, mode: u32) -> Result<Self> {
         self.0.createat(path, flags, mode).map(Self::from_rawfd)
@@ -287,6 +1168,10 @@ impl Fd {
         Self::from_rawfd(FdRaw::cwd())
     }
 
+    pub fn reopen(&self, flags: int) -> Result<Self> {
+        self.0.reopen(flags).map(Self::from_rawfd)
+    }
+
     pub fn into_rawfd(self) -> std::result::Result<FdRaw, Fd> {
         match Rc::try_unwrap(self.0) {
             Err(fd) => Err(Fd(fd)),
@@ -294,10 +1179,123 @@ impl Fd {
         }
     }
 
+    /// Converts the filedescriptor into a `std::fs::File`.
+    ///
+    /// # Safety
+    ///
+    /// Method panics when other references to the filedescriptor
+    /// exist.
     pub unsafe fn into_file(self) -> Result<std::fs::File>
     {
         self.into_rawfd().unwrap().into_file()
     }
+
+    /// Returns a reader which starts at the beginning of the file and
+    /// does not change the file offset of `self`.
+    pub fn reader(&self) -> FdReader {
+        FdReader::new(self.clone())
+    }
+
+    /// Iterates over the lines of the file as described by
+    /// `Self::reader()`.
+    pub fn lines(&self) -> std::io::Lines<std::io::BufReader<FdReader>> {
+        use std::io::BufRead;
+
+        std::io::BufReader::new(self.reader()).lines()
+    }
+
+    /// Iterates over the entries of the directory.
+    ///
+    /// Directory is reopened internally so that the file offset of
+    /// `self` is not affected.
+    pub fn read_dir(&self) -> Result<ReadDir> {
+        Dir::fdopendir(self).map(Dir::readdir)
+    }
+
+    /// Opens the subdirectory `path` and iterates over its entries.
+    pub fn read_dir_at<T: AsRef<Path>>(&self, path: &T) -> Result<ReadDir> {
+        let flags = libc::O_DIRECTORY | libc::O_CLOEXEC | libc::O_RDONLY;
+
+        Dir::from_fd_owned(self.0.openat(path, flags)?).map(Dir::readdir)
+    }
+}
+
+/// `Read` adapter which reads with `pread()` from an `Fd`
+///
+/// The file offset is tracked by the reader itself so that multiple
+/// readers on clones of the same `Fd` do not interfere.
+#[derive(Debug)]
+pub struct FdReader {
+    fd: Fd,
+    offset: u64,
+}
+
+impl FdReader {
+    pub fn new(fd: Fd) -> Self {
+        FdReader {
+            fd: fd,
+            offset: 0,
+        }
+    }
+
+    /// Returns the offset of the next read
+    pub fn position(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl std::io::Read for FdReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let offset = libc::off_t::try_from(self.offset)
+            .map_err(|_| Error::from_raw_os_error(libc::EINVAL))?;
+
+        let rc = unsafe {
+            libc::pread(self.fd.fd, buf.as_mut_ptr() as *mut _, buf.len(), offset)
+        };
+
+        if rc < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        self.offset += rc as u64;
+
+        Ok(rc as usize)
+    }
+}
+
+/// Adds the signed `delta` to `base`; `None` on over- or underflow
+fn add_signed(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.unsigned_abs())
+    }
+}
+
+impl std::io::Seek for FdReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        use std::io::SeekFrom;
+
+        let offset = match pos {
+            SeekFrom::Start(o) => Some(o),
+            SeekFrom::Current(o) => add_signed(self.offset, o),
+            SeekFrom::End(o) => {
+                let size = self.fd.fstat()
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other,
+                                                     e.to_string()))?
+                    .st_size as u64;
+
+                add_signed(size, o)
+            },
+        };
+
+        // offsets above i64::MAX can not be passed to pread()
+        self.offset = offset
+            .filter(|o| libc::off_t::try_from(*o).is_ok())
+            .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))?;
+
+        Ok(self.offset)
+    }
 }
 
 impl std::ops::Deref for Fd {
@@ -308,6 +1306,64 @@ impl std::ops::Deref for Fd {
     }
 }
 
+impl std::os::unix::io::AsRawFd for FdRaw {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.fd
+    }
+}
+
+impl std::os::unix::io::AsRawFd for Fd {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.fd
+    }
+}
+
 pub fn same_file_by_stat(a: &libc::stat, b: &libc::stat) -> bool {
     a.st_dev == b.st_dev && a.st_ino == b.st_ino && a.st_mode == b.st_mode
 }
+
+/// Opens the current working directory.
+///
+/// Unlike `Fd::cwd()` (`AT_FDCWD`), the returned descriptor keeps
+/// referring to the same directory when the process changes its
+/// working directory later.  It is opened with `O_PATH` and can be
+/// passed to `set_current_dir()`.
+pub fn current_dir() -> Result<Fd> {
+    Fd::open(&".", libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC)
+}
+
+/// Makes `fd` the working directory of the process (`fchdir()`)
+pub fn set_current_dir(fd: &FdRaw) -> Result<()> {
+    try_errno!(unsafe { libc::fchdir(fd.fd) });
+
+    Ok(())
+}
+
+/// Opens `relpath` below `/proc/<pid>` (`/proc/self` for `None`).
+///
+/// The path is resolved by a `Chroot` at the process directory which
+/// follows no symlinks at all, so magic links like `cwd`, `root` or
+/// `fd/*` and components on other mounts are refused and `..` can not
+/// leave the directory.  Fails with `ErrorKind::InvalidRoot` when
+/// `/proc` is not a procfs.
+pub fn proc_open<T: AsRef<Path>>(pid: Option<libc::pid_t>, relpath: &T,
+                                 flags: int) -> Result<Fd> {
+    let dir = match pid {
+        Some(pid) => format!("/proc/{}", pid),
+        None => "/proc/self".to_string(),
+    };
+
+    let chroot = crate::chroot::Chroot::open_root(&dir)?
+        .with_symlink_limit(0)
+        .with_mask_foreign_mounts(true);
+
+    let mut st = mem::MaybeUninit::<libc::statfs>::uninit();
+
+    try_errno!(unsafe { libc::fstatfs(chroot.root_fdraw()?.fd, st.as_mut_ptr()) });
+
+    if unsafe { st.assume_init() }.f_type != libc::PROC_SUPER_MAGIC {
+        bail!(ErrorKind::InvalidRoot(dir.into(), libc::EINVAL));
+    }
+
+    chroot.open(relpath, flags)
+}
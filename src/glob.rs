@@ -0,0 +1,509 @@
+//! Shell-like patterns which are matched below directory descriptors
+//!
+//! Names are matched byte-wise on their raw `OsStr` representation so
+//! that results do not depend on the locale of the host.  Patterns
+//! support `*`, `?`, bracket expressions with ranges, negation (`!`
+//! or `^`) and the ASCII classes like `[:alpha:]`, nested brace
+//! expansion (`{a,b}`) and backslash escapes.  Wildcards never match
+//! `/` and match a leading `.` only with `GlobOptions::match_hidden`.
+extern crate libc;
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::chroot::Chroot;
+use crate::fd::Fd;
+use crate::errors::*;
+
+/// maximum number of patterns produced by brace expansion
+const MAX_EXPANSIONS: u64 = 4096;
+
+/// Options for `Chroot::glob_with()` and `Fd::glob_at_with()`
+#[derive(Clone, Debug, Default)]
+pub struct GlobOptions {
+    /// reject malformed patterns (unterminated brackets or braces,
+    /// trailing backslashes and escapes of ordinary characters) with
+    /// `ErrorKind::InvalidPattern` instead of matching them literally
+    pub strict:		bool,
+    /// let wildcards and bracket expressions match a leading `.`
+    pub match_hidden:	bool,
+}
+
+#[derive(Clone, Debug)]
+enum ClassItem {
+    Range(u8, u8),
+    Named(fn(&u8) -> bool),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    negated:	bool,
+    items:	Vec<ClassItem>,
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Literal(u8),
+    Any,
+    Star,
+    Class(Class),
+}
+
+impl Token {
+    fn matches(&self, c: u8) -> bool {
+        match self {
+            Token::Literal(l)	=> *l == c,
+            Token::Any		=> true,
+            Token::Star		=> false,
+            Token::Class(class)	=> class.negated != class.items.iter().any(|i| match i {
+                ClassItem::Range(lo, hi)	=> (*lo..=*hi).contains(&c),
+                ClassItem::Named(f)	=> f(&c),
+            }),
+        }
+    }
+}
+
+fn is_blank(c: &u8) -> bool {
+    *c == b' ' || *c == b'\t'
+}
+
+fn is_print(c: &u8) -> bool {
+    *c == b' ' || c.is_ascii_graphic()
+}
+
+fn named_class(name: &[u8]) -> Option<fn(&u8) -> bool> {
+    Some(match name {
+        b"alnum"	=> u8::is_ascii_alphanumeric,
+        b"alpha"	=> u8::is_ascii_alphabetic,
+        b"blank"	=> is_blank,
+        b"cntrl"	=> u8::is_ascii_control,
+        b"digit"	=> u8::is_ascii_digit,
+        b"graph"	=> u8::is_ascii_graphic,
+        b"lower"	=> u8::is_ascii_lowercase,
+        b"print"	=> is_print,
+        b"punct"	=> u8::is_ascii_punctuation,
+        b"space"	=> u8::is_ascii_whitespace,
+        b"upper"	=> u8::is_ascii_uppercase,
+        b"xdigit"	=> u8::is_ascii_hexdigit,
+        _		=> return None,
+    })
+}
+
+/// Returns the (possibly escaped) character at `pos` of a bracket
+/// expression and the position behind it.
+fn class_char(pat: &[u8], pos: usize) -> Option<(u8, usize)> {
+    match *pat.get(pos)? {
+        b'\\'	=> Some((*pat.get(pos + 1)?, pos + 2)),
+        c	=> Some((c, pos + 1)),
+    }
+}
+
+/// Parses the bracket expression behind the `[` at `pos - 1`; returns
+/// the class and the position behind the closing `]` or `None` when
+/// the expression is malformed or not terminated.
+fn parse_class(pat: &[u8], mut pos: usize) -> Option<(Class, usize)> {
+    let negated = matches!(pat.get(pos), Some(b'!') | Some(b'^'));
+    let mut items = Vec::new();
+
+    if negated {
+        pos += 1;
+    }
+
+    // a leading ']' is a member of the class
+    let first = pos;
+
+    loop {
+        match *pat.get(pos)? {
+            b']' if pos != first => break Some((Class { negated: negated, items: items }, pos + 1)),
+
+            b'[' if pat.get(pos + 1) == Some(&b':') => {
+                let rest = &pat[pos + 2..];
+                let end = rest.windows(2).position(|w| w == b":]")?;
+
+                items.push(ClassItem::Named(named_class(&rest[..end])?));
+                pos += end + 4;
+            },
+
+            _ => {
+                let (lo, next) = class_char(pat, pos)?;
+
+                pos = next;
+
+                if pat.get(pos) == Some(&b'-') && pat.get(pos + 1).map_or(false, |c| *c != b']') {
+                    let (hi, next) = class_char(pat, pos + 1)?;
+
+                    if lo > hi {
+                        return None;
+                    }
+
+                    items.push(ClassItem::Range(lo, hi));
+                    pos = next;
+                } else {
+                    items.push(ClassItem::Range(lo, lo));
+                }
+            },
+        }
+    }
+}
+
+/// Returns the position behind the bracket expression starting at
+/// `pos`; a `[` which does not start one is skipped alone.
+fn skip_class(pat: &[u8], pos: usize) -> usize {
+    parse_class(pat, pos + 1).map_or(pos + 1, |(_, next)| next)
+}
+
+/// Compiled pattern for a single path component
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    tokens:		Vec<Token>,
+    match_hidden:	bool,
+}
+
+impl Pattern {
+    /// Compiles `component`; it must not contain `/` or unexpanded
+    /// brace groups (see `expand_braces()`).
+    pub fn new<T: AsRef<OsStr>>(component: &T, opts: &GlobOptions) -> Result<Self> {
+        let component = component.as_ref();
+        let pat = component.as_bytes();
+        let invalid = |reason| ErrorKind::InvalidPattern(component.to_owned(), reason);
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+
+        ensure!(!pat.contains(&b'/'), invalid("contains '/'"));
+
+        while pos < pat.len() {
+            let (token, next) = match pat[pos] {
+                b'*' if matches!(tokens.last(), Some(Token::Star)) => {
+                    pos += 1;
+                    continue;
+                },
+                b'*'	=> (Token::Star, pos + 1),
+                b'?'	=> (Token::Any, pos + 1),
+
+                b'[' => match parse_class(pat, pos + 1) {
+                    Some((class, next))	=> (Token::Class(class), next),
+                    None if opts.strict	=> bail!(invalid("malformed bracket expression")),
+                    None		=> (Token::Literal(b'['), pos + 1),
+                },
+
+                b'\\' => match pat.get(pos + 1) {
+                    Some(c) if !opts.strict || b"*?[]{},\\".contains(c) =>
+                        (Token::Literal(*c), pos + 2),
+                    Some(_)		=> bail!(invalid("escape of ordinary character")),
+                    None if opts.strict	=> bail!(invalid("trailing backslash")),
+                    None		=> (Token::Literal(b'\\'), pos + 1),
+                },
+
+                c	=> (Token::Literal(c), pos + 1),
+            };
+
+            tokens.push(token);
+            pos = next;
+        }
+
+        Ok(Pattern {
+            tokens:		tokens,
+            match_hidden:	opts.match_hidden,
+        })
+    }
+
+    /// Returns the name matched by the pattern when it does not
+    /// contain wildcards or bracket expressions.
+    pub fn literal(&self) -> Option<OsString> {
+        self.tokens.iter()
+            .map(|t| match t {
+                Token::Literal(c)	=> Some(*c),
+                _			=> None,
+            })
+            .collect::<Option<Vec<u8>>>()
+            .map(OsString::from_vec)
+    }
+
+    pub fn matches<T: AsRef<OsStr>>(&self, name: &T) -> bool {
+        let name = name.as_ref().as_bytes();
+
+        if !self.match_hidden && name.first() == Some(&b'.') &&
+            !matches!(self.tokens.first(), Some(Token::Literal(b'.'))) {
+            return false;
+        }
+
+        let (mut t, mut n) = (0, 0);
+        // last star and the position in `name` where its match ends
+        let mut star = None;
+
+        while n < name.len() {
+            match self.tokens.get(t) {
+                Some(Token::Star) => {
+                    star = Some((t, n));
+                    t += 1;
+                    continue;
+                },
+                Some(token) if token.matches(name[n]) => {
+                    t += 1;
+                    n += 1;
+                    continue;
+                },
+                _ => {},
+            }
+
+            // let the last star consume one more character
+            match star {
+                Some((st, sn)) => {
+                    star = Some((st, sn + 1));
+                    t = st + 1;
+                    n = sn + 1;
+                },
+                None => return false,
+            }
+        }
+
+        self.tokens[t..].iter().all(|t| matches!(t, Token::Star))
+    }
+}
+
+/// Finds the brace group starting at `open`; returns the position of
+/// the closing `}` and of the top-level commas.
+fn brace_group(pat: &[u8], open: usize) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut pos = open + 1;
+
+    while pos < pat.len() {
+        match pat[pos] {
+            b'\\' => pos += 1,
+            b'[' => {
+                pos = skip_class(pat, pos);
+                continue;
+            },
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some((pos, commas)),
+            b'}' => depth -= 1,
+            b',' if depth == 0 => commas.push(pos),
+            _ => {},
+        }
+
+        pos += 1;
+    }
+
+    None
+}
+
+fn expand(orig: &OsStr, pat: &[u8], strict: bool, res: &mut Vec<OsString>) -> Result<()> {
+    let mut pos = 0;
+
+    while pos < pat.len() {
+        let (close, commas) = match pat[pos] {
+            b'\\' => {
+                pos += 2;
+                continue;
+            },
+            b'[' => {
+                pos = skip_class(pat, pos);
+                continue;
+            },
+            b'{' => match brace_group(pat, pos) {
+                Some(group) => group,
+                None if strict =>
+                    bail!(ErrorKind::InvalidPattern(orig.to_owned(), "unterminated brace group")),
+                None => {
+                    pos += 1;
+                    continue;
+                },
+            },
+            _ => {
+                pos += 1;
+                continue;
+            },
+        };
+
+        // groups without alternatives are kept like in the shell
+        if commas.is_empty() {
+            pos += 1;
+            continue;
+        }
+
+        let bounds = std::iter::once(pos)
+            .chain(commas)
+            .chain(std::iter::once(close));
+        let bounds: Vec<usize> = bounds.collect();
+
+        for alt in bounds.windows(2) {
+            let mut p = pat[..pos].to_vec();
+
+            p.extend_from_slice(&pat[alt[0] + 1..alt[1]]);
+            p.extend_from_slice(&pat[close + 1..]);
+
+            expand(orig, &p, strict, res)?;
+        }
+
+        return Ok(());
+    }
+
+    ensure!((res.len() as u64) < MAX_EXPANSIONS,
+            ErrorKind::BudgetExceeded("brace expansions", MAX_EXPANSIONS));
+
+    res.push(OsString::from_vec(pat.to_vec()));
+
+    Ok(())
+}
+
+/// Expands the brace groups of `pattern`; e.g. `{a,b{c,d}}e` into
+/// `ae`, `bce` and `bde`.  Escaped braces and braces in bracket
+/// expressions are kept for `Pattern::new()`.
+pub fn expand_braces<T: AsRef<OsStr>>(pattern: &T, strict: bool) -> Result<Vec<OsString>> {
+    let pattern = pattern.as_ref();
+    let mut res = Vec::new();
+
+    expand(pattern, pattern.as_bytes(), strict, &mut res)?;
+
+    Ok(res)
+}
+
+/// Opens the subdirectory `name` of a directory for the next
+/// component; returns `None` when it is not a directory.
+type DescendFn<'a> = dyn Fn(&Fd, &OsStr) -> Result<Option<Fd>> + 'a;
+
+fn is_not_dir(e: &Error) -> bool {
+    matches!(e.errno(), Some(libc::ENOENT) | Some(libc::ENOTDIR) | Some(libc::ELOOP))
+}
+
+fn walk(descend: &DescendFn, dir_fd: &Fd, prefix: PathBuf, comps: &[Pattern],
+        dir_only: bool, res: &mut Vec<PathBuf>) -> Result<()>
+{
+    let (pat, rest) = match comps.split_first() {
+        Some(c)	=> c,
+        None	=> {
+            res.push(prefix);
+            return Ok(());
+        },
+    };
+
+    let names = match pat.literal() {
+        Some(name) if rest.is_empty() && !dir_only => match dir_fd.fstatat(&name, false) {
+            Ok(_)	=> vec![name],
+            Err(ref e) if e.errno() == Some(libc::ENOENT) => vec![],
+            Err(e)	=> return Err(e),
+        },
+        Some(name)	=> vec![name],
+        None		=> {
+            let mut names = Vec::new();
+
+            for e in dir_fd.read_dir()? {
+                let e = e?;
+
+                if pat.matches(&e.d_name) {
+                    names.push(e.d_name);
+                }
+            }
+
+            names
+        },
+    };
+
+    for name in names {
+        let path = prefix.join(&name);
+
+        if rest.is_empty() && !dir_only {
+            res.push(path);
+        } else if let Some(sub_fd) = descend(dir_fd, &name)? {
+            walk(descend, &sub_fd, path, rest, dir_only, res)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands `pattern` below `dir_fd`; returns the sorted matches
+/// relative to `prefix`.
+fn glob(descend: &DescendFn, dir_fd: &Fd, prefix: &Path, pattern: &OsStr,
+        opts: &GlobOptions) -> Result<Vec<PathBuf>>
+{
+    let mut res = Vec::new();
+
+    for p in expand_braces(&pattern, opts.strict)? {
+        let bytes = p.as_bytes();
+        let comps = bytes.split(|c| *c == b'/')
+            .filter(|c| !c.is_empty())
+            .map(|c| Pattern::new(&OsStr::from_bytes(c), opts))
+            .collect::<Result<Vec<_>>>()?;
+
+        // a trailing '/' matches directories only
+        let dir_only = bytes.len() > 1 && bytes.ends_with(b"/");
+
+        walk(descend, dir_fd, prefix.to_path_buf(), &comps, dir_only, &mut res)?;
+    }
+
+    res.sort();
+    res.dedup();
+
+    Ok(res)
+}
+
+impl Fd {
+    /// Returns the entries below this directory which match the
+    /// relative `pattern`, sorted by their path.
+    ///
+    /// Symlinks to directories are not followed; the descriptor does
+    /// not confine `..` components.  See `Chroot::glob()` for
+    /// patterns from untrusted sources.
+    pub fn glob_at<T: AsRef<OsStr>>(&self, pattern: &T) -> Result<Vec<PathBuf>> {
+        self.glob_at_with(pattern, &GlobOptions::default())
+    }
+
+    /// Like `Self::glob_at()` but with explicit options
+    pub fn glob_at_with<T>(&self, pattern: &T, opts: &GlobOptions) -> Result<Vec<PathBuf>>
+    where
+        T: AsRef<OsStr>,
+    {
+        let pattern = pattern.as_ref();
+
+        ensure!(!pattern.as_bytes().starts_with(b"/"),
+                ErrorKind::InvalidPattern(pattern.to_owned(), "not relative"));
+
+        let descend = |dir: &Fd, name: &OsStr| {
+            match dir.openat(&name, libc::O_DIRECTORY | libc::O_RDONLY |
+                             libc::O_CLOEXEC | libc::O_NOFOLLOW) {
+                Ok(fd)				=> Ok(Some(fd)),
+                Err(ref e) if is_not_dir(e)	=> Ok(None),
+                Err(e)				=> Err(e),
+            }
+        };
+
+        glob(&descend, self, Path::new(""), pattern, opts)
+    }
+}
+
+impl Chroot {
+    /// Returns the absolute paths within the chroot which match
+    /// `pattern`, sorted.  Directories and symlinks in the matched
+    /// paths are resolved like by `Self::chdir()`.
+    pub fn glob<T: AsRef<OsStr>>(&self, pattern: &T) -> Result<Vec<PathBuf>> {
+        self.glob_with(pattern, &GlobOptions::default())
+    }
+
+    /// Like `Self::glob()` but with explicit options
+    pub fn glob_with<T>(&self, pattern: &T, opts: &GlobOptions) -> Result<Vec<PathBuf>>
+    where
+        T: AsRef<OsStr>,
+    {
+        let pattern = pattern.as_ref();
+
+        ensure!(pattern.as_bytes().starts_with(b"/"),
+                ErrorKind::InvalidPattern(pattern.to_owned(), "not absolute"));
+
+        let descend = |dir: &Fd, name: &OsStr| {
+            match self.chdirat(dir, &Path::new(name)) {
+                Ok(fd)				=> Ok(Some(fd)),
+                Err(ref e) if is_not_dir(e)	=> Ok(None),
+                Err(e)				=> Err(e),
+            }
+        };
+
+        glob(&descend, &self.chdir(&"/")?, Path::new("/"), pattern, opts)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/glob.inc.rs"]
+mod test;
@@ -0,0 +1,190 @@
+//! Handle tables for file server protocols
+//!
+//! Protocols like SFTP or 9p refer to open files and directories by
+//! small integers which are chosen by the server.  `HandleTable`
+//! maps them to descriptors; a generation counter lets handles which
+//! were closed and whose slot was reused fail instead of silently
+//! referring to another file.
+extern crate libc;
+
+use crate::dir::ReadDir;
+use crate::fd::Fd;
+use crate::errors::*;
+
+/// Handle returned by `HandleTable::insert()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HandleId {
+    index:	u32,
+    generation:	u32,
+}
+
+impl HandleId {
+    /// Encodes the handle for the wire
+    pub fn to_u64(self) -> u64 {
+        (u64::from(self.generation) << 32) | u64::from(self.index)
+    }
+
+    /// Decodes a handle which was encoded by `Self::to_u64()`; the
+    /// result is not validated.
+    pub fn from_u64(v: u64) -> Self {
+        HandleId {
+            index:	v as u32,
+            generation:	(v >> 32) as u32,
+        }
+    }
+}
+
+/// Object kept in a `HandleTable`
+pub enum Handle {
+    File(Fd),
+    Dir(ReadDir),
+}
+
+impl From<Fd> for Handle {
+    fn from(fd: Fd) -> Self {
+        Handle::File(fd)
+    }
+}
+
+impl From<ReadDir> for Handle {
+    fn from(dir: ReadDir) -> Self {
+        Handle::Dir(dir)
+    }
+}
+
+struct Slot {
+    generation:	u32,
+    handle:	Option<Handle>,
+}
+
+/// Maps `HandleId`s to open files and directories
+///
+/// Lookups of unknown, closed or wrongly typed handles fail with
+/// `EBADF`; inserting more than the configured number of handles
+/// fails with `EMFILE`.  Closing a handle drops the reference of the
+/// table only; descriptors which were cloned from it stay open.
+pub struct HandleTable {
+    slots:	Vec<Slot>,
+    free:	Vec<u32>,
+    max:	usize,
+    len:	usize,
+}
+
+fn ebadf<T>() -> Result<T> {
+    Err(std::io::Error::from_raw_os_error(libc::EBADF).into())
+}
+
+impl HandleTable {
+    /// Creates a table which keeps at most `max` handles
+    pub fn new(max: usize) -> Self {
+        HandleTable {
+            slots:	Vec::new(),
+            free:	Vec::new(),
+            max:	max,
+            len:	0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Registers `handle` and returns its id.
+    ///
+    /// Descriptors which would be inherited by child processes are
+    /// rejected with `EINVAL`; handles must not leak into processes
+    /// spawned by the server.
+    pub fn insert<T: Into<Handle>>(&mut self, handle: T) -> Result<HandleId> {
+        let handle = handle.into();
+        let fd = match handle {
+            Handle::File(ref fd) => fd.fd,
+            Handle::Dir(ref dir) => dir.raw_fd(),
+        };
+
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+
+        ensure!(flags >= 0, std::io::Error::last_os_error());
+        ensure!(flags & libc::FD_CLOEXEC != 0,
+                std::io::Error::from_raw_os_error(libc::EINVAL));
+
+        ensure!(self.len < self.max, std::io::Error::from_raw_os_error(libc::EMFILE));
+
+        let index = match self.free.pop() {
+            Some(idx) => idx,
+            None => {
+                ensure!(self.slots.len() < u32::MAX as usize,
+                        std::io::Error::from_raw_os_error(libc::EMFILE));
+
+                self.slots.push(Slot {
+                    generation:	0,
+                    handle:	None,
+                });
+
+                (self.slots.len() - 1) as u32
+            },
+        };
+
+        let slot = &mut self.slots[index as usize];
+
+        slot.handle = Some(handle);
+        self.len += 1;
+
+        Ok(HandleId {
+            index:	index,
+            generation:	slot.generation,
+        })
+    }
+
+    fn slot(&mut self, id: HandleId) -> Result<&mut Handle> {
+        match self.slots.get_mut(id.index as usize) {
+            Some(Slot { generation, handle: Some(h) }) if *generation == id.generation =>
+                Ok(h),
+            _ => ebadf(),
+        }
+    }
+
+    /// Returns the handle `id` of any type
+    pub fn get(&mut self, id: HandleId) -> Result<&mut Handle> {
+        self.slot(id)
+    }
+
+    /// Returns the file registered as `id`
+    pub fn fd(&mut self, id: HandleId) -> Result<&Fd> {
+        match self.slot(id)? {
+            Handle::File(fd) => Ok(fd),
+            Handle::Dir(_) => ebadf(),
+        }
+    }
+
+    /// Returns the directory iterator registered as `id`
+    pub fn dir(&mut self, id: HandleId) -> Result<&mut ReadDir> {
+        match self.slot(id)? {
+            Handle::Dir(dir) => Ok(dir),
+            Handle::File(_) => ebadf(),
+        }
+    }
+
+    /// Removes `id` from the table and returns its object; it is
+    /// closed when the result is dropped.  The id becomes invalid,
+    /// even when its slot is reused.
+    pub fn close(&mut self, id: HandleId) -> Result<Handle> {
+        self.slot(id)?;
+
+        let slot = &mut self.slots[id.index as usize];
+        let handle = slot.handle.take().unwrap();
+
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        self.len -= 1;
+
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/handles.inc.rs"]
+mod test;
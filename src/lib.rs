@@ -9,27 +9,113 @@ extern crate error_chain;
 use std::ffi::CString;
 use std::path::Path;
 
+#[allow(unexpected_cfgs)]
 pub mod errors {
     error_chain! {
         foreign_links {
             Io(::std::io::Error) #[cfg(unix)];
         }
+
+        errors {
+            Cancelled {
+                description("operation cancelled")
+                display("operation cancelled")
+            }
+
+            TimedOut {
+                description("operation timed out")
+                display("operation timed out")
+            }
+
+            SpecialFile(path: ::std::path::PathBuf) {
+                description("refusing to open special file")
+                display("refusing to open special file {:?}", path)
+            }
+
+            InvalidPath(path: ::std::path::PathBuf) {
+                description("path contains NUL characters")
+                display("path {:?} contains NUL characters", path)
+            }
+
+            InvalidName(name: ::std::ffi::OsString, reason: &'static str) {
+                description("invalid file name")
+                display("invalid file name {:?}: {}", name, reason)
+            }
+
+            InvalidPattern(pattern: ::std::ffi::OsString, reason: &'static str) {
+                description("invalid glob pattern")
+                display("invalid glob pattern {:?}: {}", pattern, reason)
+            }
+
+            NotLinkable(reason: &'static str) {
+                description("file can not be linked")
+                display("file can not be linked: {}", reason)
+            }
+
+            FileTooLarge(path: ::std::path::PathBuf, limit: u64) {
+                description("file too large")
+                display("file {:?} exceeds {} bytes", path, limit)
+            }
+
+            InvalidRoot(path: ::std::path::PathBuf, errno: i32) {
+                description("invalid chroot directory")
+                display("invalid chroot directory {:?}: {}", path,
+                        ::std::io::Error::from_raw_os_error(*errno))
+            }
+
+            CrossesFilesystem(from_mnt_id: u64, to_mnt_id: u64) {
+                description("rename crosses filesystems")
+                display("can not rename from mount {} to mount {}", from_mnt_id, to_mnt_id)
+            }
+
+            BudgetExceeded(budget: &'static str, limit: u64) {
+                description("traversal budget exceeded")
+                display("traversal exceeds {} limit of {}", budget, limit)
+            }
+
+            PartialPath(suffix: ::std::path::PathBuf, errno: Option<i32>) {
+                description("path can not be reconstructed")
+                display("can not reconstruct the path above {:?}", suffix)
+            }
+
+            ReadOnlyMount(path: ::std::path::PathBuf) {
+                description("read-only mount")
+                display("{:?} is on a read-only mount", path)
+            }
+        }
+    }
+
+    impl Error {
+        /// Returns the `errno` value when the error was caused by a
+        /// failed syscall.
+        pub fn errno(&self) -> Option<i32> {
+            match self.kind() {
+                ErrorKind::Io(e) => e.raw_os_error(),
+                ErrorKind::PartialPath(_, errno) => *errno,
+                ErrorKind::ReadOnlyMount(_) => Some(libc::EROFS),
+                _ => None,
+            }
+        }
     }
 }
 
 pub(crate) trait LibcString {
-    fn as_libc(&self) -> (*const i8, CString);
+    /// Converts the object into a C string; fails with
+    /// `ErrorKind::InvalidPath` when it contains NUL characters
+    /// which would truncate it.
+    fn as_libc(&self) -> errors::Result<(*const libc::c_char, CString)>;
 }
 
 impl LibcString for Path {
-    fn as_libc(&self) -> (*const i8, CString) {
-        let res = unsafe {
-            use std::os::unix::ffi::OsStrExt;
+    fn as_libc(&self) -> errors::Result<(*const libc::c_char, CString)> {
+        use std::os::unix::ffi::OsStrExt;
 
-            CString::from_vec_unchecked(self.as_os_str().as_bytes().to_vec())
+        let res = match CString::new(self.as_os_str().as_bytes()) {
+            Ok(s) => s,
+            Err(_) => bail!(errors::ErrorKind::InvalidPath(self.to_path_buf())),
         };
 
-        (res.as_ptr() as *const i8, res)
+        Ok((res.as_ptr(), res))
     }
 }
 
@@ -37,6 +123,42 @@ impl LibcString for Path {
 pub mod fd;
 pub mod dir;
 pub mod chroot;
+pub mod metadata;
+pub mod acl;
+pub mod archive;
+pub mod audit;
+pub mod barrier;
+pub mod control;
+pub mod eventlog;
+pub mod glob;
+pub mod handles;
+pub mod lock;
+pub mod manifest;
+pub mod mounts;
+pub mod ops;
+pub mod pathutil;
+pub mod remove;
+pub mod reserve;
+pub mod restore;
+pub mod scoped;
+pub mod signalfd;
+pub mod snapshot;
+pub mod visit;
+mod watch;
+
+/// Extension traits of `mod ops` for glob imports
+pub mod prelude {
+    pub use crate::ops::{DirExt, FileExt, LockExt, XattrExt};
+}
+
+#[cfg(feature = "users")]
+pub mod users;
+
+#[cfg(feature = "async")]
+pub mod stream;
+
+#[cfg(feature = "relink")]
+pub mod relink;
 
 #[cfg(test)]
 extern crate libc;
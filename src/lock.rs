@@ -0,0 +1,166 @@
+//! Advisory file locks and PID files inside a chroot
+extern crate libc;
+
+use std::path::Path;
+
+use crate::barrier::Barrier;
+use crate::chroot::{Chroot, CreateOptions};
+use crate::fd::{Fd, FdRaw, same_file_by_stat};
+use crate::errors::*;
+
+impl FdRaw {
+    /// Applies or removes an advisory lock (`flock()`); `op` is one
+    /// of `LOCK_SH`, `LOCK_EX` or `LOCK_UN`, optionally or'ed with
+    /// `LOCK_NB`.
+    pub fn flock(&self, op: libc::c_int) -> Result<()> {
+        let rc = unsafe { libc::flock(self.fd, op) };
+
+        ensure!(rc >= 0, std::io::Error::last_os_error());
+
+        Ok(())
+    }
+}
+
+/// Holds an advisory lock which is released when the guard is dropped
+#[derive(Debug)]
+pub struct FileLock {
+    fd:		Fd,
+}
+
+impl FileLock {
+    /// Returns the filedescriptor of the locked file
+    pub fn fd(&self) -> &Fd {
+        &self.fd
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.fd.flock(libc::LOCK_UN);
+    }
+}
+
+/// Locked PID file; the file is removed when the guard is dropped
+#[derive(Debug)]
+pub struct PidFile {
+    dir_fd:	Fd,
+    name:	std::ffi::OsString,
+    lock:	FileLock,
+}
+
+impl PidFile {
+    /// Returns the lock which protects the PID file
+    pub fn lock(&self) -> &FileLock {
+        &self.lock
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = self.dir_fd.unlinkat(&self.name, 0);
+    }
+}
+
+impl Chroot {
+    /// Opens `path` within the chroot and locks it.  With `create`,
+    /// the file (but not its parent directories) is created when it
+    /// does not exist yet.
+    ///
+    /// The call blocks until the lock can be acquired.  Because the
+    /// file might have been removed or replaced by its previous
+    /// holder in the meantime, the lock is acquired again until the
+    /// locked file is the one found at `path`.
+    pub fn lock_file<T>(&self, path: &T, exclusive: bool, create: bool)
+                        -> Result<FileLock>
+    where
+        T: AsRef<Path>,
+    {
+        let op = if exclusive { libc::LOCK_EX } else { libc::LOCK_SH };
+
+        self.lock_file_internal(path.as_ref(), op, create)
+    }
+
+    fn lock_file_internal(&self, path: &Path, op: libc::c_int, create: bool)
+                          -> Result<FileLock>
+    {
+        loop {
+            let fd = if create {
+                self.create_with(&path, &CreateOptions {
+                    flags:	libc::O_RDWR | libc::O_CLOEXEC | libc::O_NOCTTY,
+                    mode:	0o644,
+                    .. CreateOptions::default()
+                })?
+            } else {
+                self.open(&path, libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOCTTY)?
+            };
+
+            fd.flock(op)?;
+
+            let lock = FileLock { fd: fd };
+
+            match self.fstatat(&self.root_fd()?, &path) {
+                Ok(st) if same_file_by_stat(&st, &lock.fd.fstat()?)	=> return Ok(lock),
+                Ok(_)							=> continue,
+                Err(ref e) if e.errno() == Some(libc::ENOENT)		=> continue,
+                Err(e)							=> return Err(e),
+            }
+        }
+    }
+
+    /// Creates and exclusively locks the PID file at `path` and writes
+    /// the pid of the current process into it.
+    ///
+    /// Fails when another process holds the lock; the pid recorded
+    /// by it is reported in the error.  A PID file which exists
+    /// but is not locked is stale and will be overwritten.  `path`
+    /// must be absolute.
+    pub fn pid_file<T>(&self, path: &T) -> Result<PidFile>
+    where
+        T: AsRef<Path>,
+    {
+        use std::io::Read;
+
+        let path = path.as_ref();
+
+        // Self::chdir() accepts absolute paths only; fail before the
+        // file is created and locked
+        ensure!(path.is_absolute(), "pid file {:?} is not an absolute path", path);
+
+        let (dir, name) = Self::split_path(path);
+        let dir_fd = self.chdir(&dir)?;
+
+        self.ensure_writable(&dir_fd, path)?;
+
+        let lock = match self.lock_file_internal(path, libc::LOCK_EX | libc::LOCK_NB, true) {
+            Ok(lock)	=> lock,
+            Err(ref e) if e.errno() == Some(libc::EWOULDBLOCK) => {
+                let mut content = String::new();
+                let _ = self.buf_reader(&path)
+                    .map(|mut r| r.read_to_string(&mut content));
+
+                bail!("pid file {:?} is locked by process {}", path,
+                      content.trim());
+            },
+            Err(e)	=> return Err(e),
+        };
+
+        let pid = format!("{}\n", std::process::id());
+
+        lock.fd.ftruncate(0)?;
+
+        let len = lock.fd.pwrite(pid.as_bytes(), 0)?;
+        ensure!(len == pid.len(), "short write to pid file {:?}", path);
+
+        Barrier::new().file(&lock.fd).commit()?;
+
+        Ok(PidFile {
+            dir_fd:	dir_fd,
+            name:	name,
+            lock:	lock,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path="tests/lock.inc.rs"]
+mod test;
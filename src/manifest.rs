@@ -0,0 +1,191 @@
+//! Metadata manifests of directory trees within a chroot
+//!
+//! `Chroot::export_manifest()` records type, mode, ownership, times,
+//! extended attributes, symlink targets and optionally a digest of
+//! the content for every entry of a tree (like `mtree`).
+//! `Chroot::apply_manifest()` restores the metadata on a tree with
+//! the same structure.
+extern crate libc;
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::chroot::Chroot;
+use crate::fd::{Fd, FdRaw};
+use crate::metadata::{Metadata, Xattrs};
+use crate::control::Progress;
+use crate::visit::{VisitAction, VisitOptions};
+use crate::errors::*;
+
+/// Calculates the digest of file content which is read from the
+/// argument; e.g. a SHA-256 implementation.
+pub type DigestFn = Arc<dyn Fn(&mut dyn std::io::Read) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Options for `Chroot::export_manifest()`
+#[derive(Clone, Default)]
+pub struct ManifestOptions {
+    /// record extended attributes
+    pub xattrs:		bool,
+    /// record the digest of regular files
+    pub digest:		Option<DigestFn>,
+}
+
+/// Entry of a manifest
+#[derive(Clone, Debug)]
+pub struct ManifestEntry {
+    /// path relative to the top of the tree; `.` for the top itself
+    pub path:		PathBuf,
+    /// `lstat()` information; type, mode, owner and times are used
+    pub metadata:	Metadata,
+    /// empty unless requested by `ManifestOptions::xattrs`
+    pub xattrs:		Xattrs,
+    pub link_target:	Option<OsString>,
+    pub digest:		Option<Vec<u8>>,
+}
+
+/// Like `Metadata::capture_xattrs()` but returns no attributes for
+/// symlinks and on filesystems without support for them.
+pub(crate) fn capture_xattrs(fd: &FdRaw, md: &Metadata) -> Result<Xattrs> {
+    if md.is_symlink() {
+        return Ok(Vec::new());
+    }
+
+    match Metadata::capture_xattrs(fd) {
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => Ok(Vec::new()),
+        r => r,
+    }
+}
+
+fn manifest_entry(dir_fd: &Fd, name: &Path, path: PathBuf, md: &Metadata,
+                  opts: &ManifestOptions, progress: &Progress) -> Result<ManifestEntry>
+{
+    let mut res = ManifestEntry {
+        path:		path,
+        metadata:	*md,
+        xattrs:		Vec::new(),
+        link_target:	None,
+        digest:		None,
+    };
+
+    if opts.xattrs {
+        let fd = dir_fd.open_path_at(&name)?;
+
+        res.xattrs = capture_xattrs(&fd, md)?;
+    }
+
+    if md.is_symlink() {
+        res.link_target = Some(dir_fd.readlinkat(&name)?);
+    }
+
+    if let (Some(digest), true) = (&opts.digest, md.is_file()) {
+        let fd = dir_fd.openat(&name, libc::O_RDONLY | libc::O_CLOEXEC |
+                               libc::O_NOFOLLOW | libc::O_NOCTTY)?;
+
+        res.digest = Some(digest(&mut fd.reader())?);
+        progress.add(0, md.len());
+    }
+
+    Ok(res)
+}
+
+impl Chroot {
+    /// Returns the manifest of the tree at `path`; the top directory
+    /// comes first and directories precede their content.
+    ///
+    /// `path` is resolved like by `Self::chdir()`; symlinks below it
+    /// are recorded but not followed.  See `Self::visit()` for the
+    /// handling of entries which disappear meanwhile.
+    pub fn export_manifest<T>(&self, path: &T, opts: &ManifestOptions)
+                              -> Result<Vec<ManifestEntry>>
+    where
+        T: AsRef<Path>,
+    {
+        let top = self.canonicalize(path)?;
+        let top_fd = self.chdir(&top)?;
+        let top_md = top_fd.metadata()?;
+        let progress = self.progress();
+
+        let mut res = vec![manifest_entry(&top_fd, Path::new("."), ".".into(),
+                                          &top_md, opts, &progress)?];
+
+        self.visit_internal(top.as_path(), &VisitOptions::default(), &progress, |e| {
+            let rel = match e.path.strip_prefix(&top) {
+                Ok(p) => p.to_path_buf(),
+                Err(_) => bail!("{:?} is not below {:?}", e.path, top.as_path()),
+            };
+
+            match manifest_entry(e.parent, Path::new(&e.entry.d_name), rel,
+                                 e.metadata, opts, &progress) {
+                Ok(m) => res.push(m),
+                // entry was removed meanwhile
+                Err(ref e) if e.errno() == Some(libc::ENOENT) => {},
+                Err(e) => return Err(e),
+            }
+
+            Ok(VisitAction::Descend)
+        })?;
+
+        Ok(res)
+    }
+
+    /// Returns the extended attributes of all entries in the tree at
+    /// `path` which have some; paths are relative to `path`.
+    pub fn list_xattrs_recursive<T>(&self, path: &T) -> Result<Vec<(PathBuf, Xattrs)>>
+    where
+        T: AsRef<Path>,
+    {
+        let opts = ManifestOptions {
+            xattrs:	true,
+            .. ManifestOptions::default()
+        };
+
+        Ok(self.export_manifest(path, &opts)?
+           .into_iter()
+           .filter(|e| !e.xattrs.is_empty())
+           .map(|e| (e.path, e.xattrs))
+           .collect())
+    }
+
+    /// Applies the metadata of `entries` to the tree at `path` like
+    /// `Metadata::apply()` does; recorded extended attributes are set
+    /// but others are not removed.
+    ///
+    /// Every entry must exist with the recorded file type; symlinks
+    /// are not followed.  Entries are processed in reverse order so
+    /// that restrictive directory modes are set after their content
+    /// was handled.
+    pub fn apply_manifest<T>(&self, path: &T, entries: &[ManifestEntry]) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let top = self.canonicalize(path)?;
+
+        for e in entries.iter().rev() {
+            let full = top.as_path().join(&e.path);
+            let (dir, name) = Self::split_path(&full);
+
+            let fd = if e.path == Path::new(".") {
+                self.chdir(&full)?
+            } else {
+                crate::pathutil::validate_component(&name)?;
+                self.chdir(&dir)?.open_path_at(&name)?
+            };
+
+            let md = fd.metadata()?;
+
+            ensure!(md.file_type() == e.metadata.file_type(),
+                    "file type of {:?} does not match the manifest", full);
+
+            self.audited("apply_manifest", &full, Some(&full), || {
+                e.metadata.apply(&fd, Some(&e.xattrs).filter(|x| !x.is_empty()))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path="tests/manifest.inc.rs"]
+mod test;
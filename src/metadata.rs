@@ -0,0 +1,194 @@
+//! File metadata as returned by `stat()`
+extern crate libc;
+
+use std::fmt;
+
+use crate::fd::FdRaw;
+use crate::errors::*;
+
+/// Extended attributes as names and values
+pub type Xattrs = Vec<(String, Vec<u8>)>;
+
+/// Wraps the `stat` information of a file
+#[derive(Clone, Copy)]
+pub struct Metadata(libc::stat);
+
+impl Metadata {
+    pub fn from_stat(stat: libc::stat) -> Self {
+        Metadata(stat)
+    }
+
+    pub fn as_stat(&self) -> &libc::stat {
+        &self.0
+    }
+
+    /// Returns the metadata of `fd` (`fstat()`)
+    pub fn capture(fd: &FdRaw) -> Result<Self> {
+        Ok(Metadata(fd.fstat()?))
+    }
+
+    /// Returns the extended attributes of `fd` for `Self::apply()`
+    pub fn capture_xattrs(fd: &FdRaw) -> Result<Xattrs> {
+        let mut res = Vec::new();
+
+        for name in fd.listxattr()? {
+            // attribute was removed meanwhile
+            if let Some(value) = fd.getxattr(&name)? {
+                res.push((name, value));
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Applies owner, group, `xattrs`, permissions and access and
+    /// modification times to `fd`; the file type is ignored.
+    ///
+    /// The order matters: the ownership comes first because changing
+    /// it clears setuid/setgid bits and file capabilities, xattrs are
+    /// set before the permissions because a read-only mode prevents
+    /// the owner from writing them, and times come last because the
+    /// other steps might modify them.  Only the ownership is applied
+    /// to symlinks.
+    pub fn apply(&self, fd: &FdRaw, xattrs: Option<&Xattrs>) -> Result<()> {
+        let is_symlink = Metadata::capture(fd)?.is_symlink();
+
+        fd.fchownat(&"", Some(self.uid()), Some(self.gid()), false)?;
+
+        if is_symlink {
+            return Ok(());
+        }
+
+        for (name, value) in xattrs.into_iter().flatten() {
+            fd.setxattr(name, value, 0)?;
+        }
+
+        fd.fchmod(self.mode())?;
+
+        fd.futimens(self.atime(), self.mtime())
+    }
+
+    /// Returns the `S_IFMT` bits of the mode
+    pub fn file_type(&self) -> libc::mode_t {
+        self.0.st_mode & libc::S_IFMT
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == libc::S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == libc::S_IFREG
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == libc::S_IFLNK
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        self.file_type() == libc::S_IFIFO
+    }
+
+    pub fn is_socket(&self) -> bool {
+        self.file_type() == libc::S_IFSOCK
+    }
+
+    pub fn is_char_device(&self) -> bool {
+        self.file_type() == libc::S_IFCHR
+    }
+
+    pub fn is_block_device(&self) -> bool {
+        self.file_type() == libc::S_IFBLK
+    }
+
+    /// Returns the permission bits (including setuid, setgid and
+    /// sticky bit) of the mode
+    pub fn mode(&self) -> libc::mode_t {
+        self.0.st_mode & 0o7777
+    }
+
+    pub fn uid(&self) -> libc::uid_t {
+        self.0.st_uid
+    }
+
+    pub fn gid(&self) -> libc::gid_t {
+        self.0.st_gid
+    }
+
+    pub fn len(&self) -> u64 {
+        self.0.st_size as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn dev(&self) -> libc::dev_t {
+        self.0.st_dev
+    }
+
+    pub fn ino(&self) -> libc::ino_t {
+        self.0.st_ino
+    }
+
+    pub fn nlink(&self) -> libc::nlink_t {
+        self.0.st_nlink
+    }
+
+    pub fn rdev(&self) -> libc::dev_t {
+        self.0.st_rdev
+    }
+
+    pub fn atime(&self) -> libc::timespec {
+        libc::timespec {
+            tv_sec:	self.0.st_atime,
+            tv_nsec:	self.0.st_atime_nsec,
+        }
+    }
+
+    pub fn mtime(&self) -> libc::timespec {
+        libc::timespec {
+            tv_sec:	self.0.st_mtime,
+            tv_nsec:	self.0.st_mtime_nsec,
+        }
+    }
+
+    pub fn ctime(&self) -> libc::timespec {
+        libc::timespec {
+            tv_sec:	self.0.st_ctime,
+            tv_nsec:	self.0.st_ctime_nsec,
+        }
+    }
+
+    /// Returns the name of the owner in the host user database
+    #[cfg(feature = "users")]
+    pub fn owner_name(&self) -> Result<Option<std::ffi::OsString>> {
+        crate::users::user_name(self.uid())
+    }
+
+    /// Returns the name of the group in the host group database
+    #[cfg(feature = "users")]
+    pub fn group_name(&self) -> Result<Option<std::ffi::OsString>> {
+        crate::users::group_name(self.gid())
+    }
+
+    /// Checks whether both objects describe the same file
+    pub fn is_same_file(&self, other: &Self) -> bool {
+        crate::fd::same_file_by_stat(&self.0, &other.0)
+    }
+}
+
+impl From<libc::stat> for Metadata {
+    fn from(stat: libc::stat) -> Self {
+        Metadata(stat)
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "metadata {{ dev={:?}, ino={:?}, mode={:o}, uid={:?}, gid={:?}, size={:?} }}",
+               self.0.st_dev, self.0.st_ino, self.0.st_mode,
+               self.0.st_uid, self.0.st_gid, self.0.st_size)
+    }
+}
@@ -0,0 +1,122 @@
+//! Mount points below a chroot
+extern crate libc;
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+use crate::chroot::Chroot;
+use crate::errors::*;
+
+/// Mount point found by `Chroot::foreign_mounts()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MountPoint {
+    /// absolute path within the chroot
+    pub path:		PathBuf,
+    pub mnt_id:		u64,
+    pub fs_type:	OsString,
+    pub source:		OsString,
+}
+
+/// Decodes the octal escapes (e.g. `\040` for a space) of mountinfo
+/// fields.
+fn unescape(field: &[u8]) -> OsString {
+    let mut res = Vec::with_capacity(field.len());
+    let mut pos = 0;
+
+    while pos < field.len() {
+        let oct = field.get(pos + 1..pos + 4)
+            .filter(|o| field[pos] == b'\\' &&
+                    o.iter().all(|c| (b'0'..=b'7').contains(c)));
+
+        match oct {
+            Some(o) => {
+                res.push(o.iter().fold(0u8, |v, c| (v << 3) | (c - b'0')));
+                pos += 4;
+            },
+            None => {
+                res.push(field[pos]);
+                pos += 1;
+            },
+        }
+    }
+
+    OsString::from_vec(res)
+}
+
+/// Parses a line of `/proc/<pid>/mountinfo` into the mount id, the
+/// mount point, the filesystem type and the source.
+fn parse_mountinfo_line(line: &[u8]) -> Option<(u64, OsString, OsString, OsString)> {
+    let fields: Vec<&[u8]> = line.split(|c| *c == b' ').collect();
+    let sep = fields.iter().position(|f| *f == b"-")?;
+
+    let mnt_id = std::str::from_utf8(fields.first()?).ok()?.parse().ok()?;
+    let mount_point = unescape(fields.get(4)?);
+    let fs_type = unescape(fields.get(sep + 1)?);
+    let source = unescape(fields.get(sep + 2)?);
+
+    Some((mnt_id, mount_point, fs_type, source))
+}
+
+impl Chroot {
+    /// Lists the mount points below the root.
+    ///
+    /// Candidates are taken from `/proc/thread-self/mountinfo` and
+    /// are reported only when resolving their path within the chroot
+    /// ends on that mount.  Hidden (over-mounted) mounts and mount
+    /// points which are reached through symlinks only are omitted.
+    pub fn foreign_mounts(&self) -> Result<Vec<MountPoint>> {
+        let root_fd = self.root_fd()?;
+        let root_mnt_id = root_fd.mount_id()?;
+        let root_path = std::fs::read_link(format!("/proc/self/fd/{}",
+                                                   root_fd.fd))?;
+
+        // the mount check must not hide the mounts itself
+        let resolver = self.unmasked();
+        let mountinfo = std::fs::read("/proc/thread-self/mountinfo")?;
+        let mut res = Vec::new();
+
+        for line in mountinfo.split(|c| *c == b'\n').filter(|l| !l.is_empty()) {
+            let (mnt_id, mount_point, fs_type, source) =
+                match parse_mountinfo_line(line) {
+                    Some(info) => info,
+                    None => bail!("bad mountinfo line {:?}",
+                                  OsString::from_vec(line.to_vec())),
+                };
+
+            let rel_path = match Path::new(&mount_point).strip_prefix(&root_path) {
+                Ok(p) if p.as_os_str().is_empty() => continue,
+                Ok(p) => Path::new("/").join(p),
+                Err(_) => continue,
+            };
+
+            if mnt_id == root_mnt_id {
+                continue;
+            }
+
+            let fd = match resolver.open(&rel_path, libc::O_PATH | libc::O_CLOEXEC) {
+                Ok(fd) => fd,
+                Err(_) => continue,
+            };
+
+            if fd.mount_id()? != mnt_id {
+                continue;
+            }
+
+            res.push(MountPoint {
+                path:		rel_path,
+                mnt_id:		mnt_id,
+                fs_type:	fs_type,
+                source:		source,
+            });
+        }
+
+        res.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/mounts.inc.rs"]
+mod test;
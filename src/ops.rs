@@ -0,0 +1,428 @@
+//! Extension traits which group the operations on filedescriptors
+//!
+//! `FdRaw` and `Fd` implement all of them by forwarding to their
+//! inherent methods.  Code which needs only some operations can be
+//! generic over the corresponding traits and be tested with mock
+//! implementations.  Import them with `use unix_fd::prelude::*`.
+//!
+//! The traits contain every wrapper of a system call which works on
+//! the data and attributes of the opened file (`FileExt`) or on
+//! entries relative to a directory descriptor (`DirExt`, including
+//! renaming and removing).  Not part of them are the management of
+//! the descriptor itself (`dupfd()`, `reopen()`, descriptor flags,
+//! signals and `set_owner()`), queries of the mount, conversions,
+//! predicates like `is_dirat()`, the `SystemTime` based `set_times()`
+//! and the operations of optional modules (ACLs, reflinks, relinking).
+extern crate libc;
+
+use std::ffi::OsString;
+use std::io::{IoSlice, IoSliceMut};
+use std::mem::MaybeUninit;
+use std::path::Path;
+
+use crate::fd::{Fd, FdRaw};
+use crate::metadata::Metadata;
+use crate::errors::*;
+
+/// Operations on the opened file itself
+pub trait FileExt {
+    fn fstat(&self) -> Result<libc::stat>;
+    fn metadata(&self) -> Result<Metadata>;
+    fn fchmod(&self, mode: u32) -> Result<()>;
+    fn fchown(&self, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> Result<()>;
+    fn futimens(&self, atime: libc::timespec, mtime: libc::timespec) -> Result<()>;
+    fn pread_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>], offset: u64)
+                        -> Result<&'b mut [u8]>;
+    fn pread(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+    fn pwrite(&self, buf: &[u8], offset: u64) -> Result<usize>;
+    fn readv(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize>;
+    fn writev(&self, bufs: &[IoSlice<'_>]) -> Result<usize>;
+    fn preadv2(&self, bufs: &mut [IoSliceMut<'_>], offset: Option<u64>, flags: libc::c_int)
+               -> Result<usize>;
+    fn pwritev2(&self, bufs: &[IoSlice<'_>], offset: Option<u64>, flags: libc::c_int)
+                -> Result<usize>;
+    fn sync_data_range(&self, offset: u64, len: u64) -> Result<()>;
+    fn read_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>]) -> Result<&'b mut [u8]>;
+    fn punch_front(&self, len: u64) -> Result<()>;
+    fn drop_page_cache(&self) -> Result<()>;
+    fn ftruncate(&self, len: u64) -> Result<()>;
+    fn fallocate(&self, mode: libc::c_int, offset: u64, len: u64) -> Result<()>;
+}
+
+/// Operations on entries of an opened directory
+pub trait DirExt {
+    fn openat(&self, path: &Path, flags: libc::c_int) -> Result<Self>
+    where
+        Self: Sized;
+    fn createat(&self, path: &Path, flags: libc::c_int, mode: u32) -> Result<Self>
+    where
+        Self: Sized;
+    fn mkdirat(&self, path: &Path, mode: u32) -> Result<()>;
+    fn symlinkat(&self, target: &Path, path: &Path) -> Result<()>;
+    fn readlinkat(&self, path: &Path) -> Result<OsString>;
+    fn fstatat(&self, path: &Path, do_follow: bool) -> Result<libc::stat>;
+    fn fchmodat(&self, path: &Path, mode: u32, do_follow: bool) -> Result<()>;
+    fn fchownat(&self, path: &Path, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>,
+                do_follow: bool) -> Result<()>;
+    fn utimensat(&self, path: &Path, atime: libc::timespec, mtime: libc::timespec,
+                 do_follow: bool) -> Result<()>;
+    fn exchangeat(&self, path: &Path, new_dir: &Self, new_path: &Path) -> Result<()>;
+    fn sync_dir(&self) -> Result<()>;
+    fn unlinkat(&self, path: &Path, flags: libc::c_int) -> Result<()>;
+    fn renameat(&self, path: &Path, new_dir: &Self, new_path: &Path) -> Result<()>;
+    fn renameat2(&self, path: &Path, new_dir: &Self, new_path: &Path, flags: libc::c_uint)
+                 -> Result<()>;
+    fn linkat(&self, path: &Path, new_dir: &Self, new_path: &Path, flags: libc::c_int)
+              -> Result<()>;
+}
+
+/// Extended attributes
+pub trait XattrExt {
+    fn getxattr(&self, name: &str) -> Result<Option<Vec<u8>>>;
+    fn setxattr(&self, name: &str, value: &[u8], flags: libc::c_int) -> Result<()>;
+    fn removexattr(&self, name: &str) -> Result<()>;
+    fn listxattr(&self) -> Result<Vec<String>>;
+}
+
+/// Advisory locks
+pub trait LockExt {
+    fn flock(&self, op: libc::c_int) -> Result<()>;
+}
+
+impl FileExt for FdRaw {
+    fn fstat(&self) -> Result<libc::stat> {
+        FdRaw::fstat(self)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        FdRaw::metadata(self)
+    }
+
+    fn fchmod(&self, mode: u32) -> Result<()> {
+        FdRaw::fchmod(self, mode)
+    }
+
+    fn fchown(&self, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> Result<()> {
+        FdRaw::fchown(self, uid, gid)
+    }
+
+    fn futimens(&self, atime: libc::timespec, mtime: libc::timespec) -> Result<()> {
+        FdRaw::futimens(self, atime, mtime)
+    }
+
+    fn pread_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>], offset: u64)
+                        -> Result<&'b mut [u8]> {
+        FdRaw::pread_uninit(self, buf, offset)
+    }
+
+    fn pread(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        FdRaw::pread(self, buf, offset)
+    }
+
+    fn pwrite(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        FdRaw::pwrite(self, buf, offset)
+    }
+
+    fn readv(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        FdRaw::readv(self, bufs)
+    }
+
+    fn writev(&self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        FdRaw::writev(self, bufs)
+    }
+
+    fn preadv2(&self, bufs: &mut [IoSliceMut<'_>], offset: Option<u64>, flags: libc::c_int)
+               -> Result<usize> {
+        FdRaw::preadv2(self, bufs, offset, flags)
+    }
+
+    fn pwritev2(&self, bufs: &[IoSlice<'_>], offset: Option<u64>, flags: libc::c_int)
+                -> Result<usize> {
+        FdRaw::pwritev2(self, bufs, offset, flags)
+    }
+
+    fn sync_data_range(&self, offset: u64, len: u64) -> Result<()> {
+        FdRaw::sync_data_range(self, offset, len)
+    }
+
+    fn read_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>]) -> Result<&'b mut [u8]> {
+        FdRaw::read_uninit(self, buf)
+    }
+
+    fn punch_front(&self, len: u64) -> Result<()> {
+        FdRaw::punch_front(self, len)
+    }
+
+    fn drop_page_cache(&self) -> Result<()> {
+        FdRaw::drop_page_cache(self)
+    }
+
+    fn ftruncate(&self, len: u64) -> Result<()> {
+        FdRaw::ftruncate(self, len)
+    }
+
+    fn fallocate(&self, mode: libc::c_int, offset: u64, len: u64) -> Result<()> {
+        FdRaw::fallocate(self, mode, offset, len)
+    }
+}
+
+impl DirExt for FdRaw {
+    fn openat(&self, path: &Path, flags: libc::c_int) -> Result<Self> {
+        FdRaw::openat(self, &path, flags)
+    }
+
+    fn createat(&self, path: &Path, flags: libc::c_int, mode: u32) -> Result<Self> {
+        FdRaw::createat(self, &path, flags, mode)
+    }
+
+    fn mkdirat(&self, path: &Path, mode: u32) -> Result<()> {
+        FdRaw::mkdirat(self, &path, mode)
+    }
+
+    fn symlinkat(&self, target: &Path, path: &Path) -> Result<()> {
+        FdRaw::symlinkat(self, &target, &path)
+    }
+
+    fn readlinkat(&self, path: &Path) -> Result<OsString> {
+        FdRaw::readlinkat(self, &path)
+    }
+
+    fn fstatat(&self, path: &Path, do_follow: bool) -> Result<libc::stat> {
+        FdRaw::fstatat(self, &path, do_follow)
+    }
+
+    fn fchmodat(&self, path: &Path, mode: u32, do_follow: bool) -> Result<()> {
+        FdRaw::fchmodat(self, &path, mode, do_follow)
+    }
+
+    fn fchownat(&self, path: &Path, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>,
+                do_follow: bool) -> Result<()> {
+        FdRaw::fchownat(self, &path, uid, gid, do_follow)
+    }
+
+    fn utimensat(&self, path: &Path, atime: libc::timespec, mtime: libc::timespec,
+                 do_follow: bool) -> Result<()> {
+        FdRaw::utimensat(self, &path, atime, mtime, do_follow)
+    }
+
+    fn exchangeat(&self, path: &Path, new_dir: &Self, new_path: &Path) -> Result<()> {
+        FdRaw::exchangeat(self, &path, new_dir, &new_path)
+    }
+
+    fn sync_dir(&self) -> Result<()> {
+        FdRaw::sync_dir(self)
+    }
+
+    fn unlinkat(&self, path: &Path, flags: libc::c_int) -> Result<()> {
+        FdRaw::unlinkat(self, &path, flags)
+    }
+
+    fn renameat(&self, path: &Path, new_dir: &Self, new_path: &Path) -> Result<()> {
+        FdRaw::renameat(self, &path, new_dir, &new_path)
+    }
+
+    fn renameat2(&self, path: &Path, new_dir: &Self, new_path: &Path, flags: libc::c_uint)
+                 -> Result<()> {
+        FdRaw::renameat2(self, &path, new_dir, &new_path, flags)
+    }
+
+    fn linkat(&self, path: &Path, new_dir: &Self, new_path: &Path, flags: libc::c_int)
+              -> Result<()> {
+        FdRaw::linkat(self, &path, new_dir, &new_path, flags)
+    }
+}
+
+impl XattrExt for FdRaw {
+    fn getxattr(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        FdRaw::getxattr(self, name)
+    }
+
+    fn setxattr(&self, name: &str, value: &[u8], flags: libc::c_int) -> Result<()> {
+        FdRaw::setxattr(self, name, value, flags)
+    }
+
+    fn removexattr(&self, name: &str) -> Result<()> {
+        FdRaw::removexattr(self, name)
+    }
+
+    fn listxattr(&self) -> Result<Vec<String>> {
+        FdRaw::listxattr(self)
+    }
+}
+
+impl LockExt for FdRaw {
+    fn flock(&self, op: libc::c_int) -> Result<()> {
+        FdRaw::flock(self, op)
+    }
+}
+
+impl FileExt for Fd {
+    fn fstat(&self) -> Result<libc::stat> {
+        self.to_fdraw().fstat()
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        self.to_fdraw().metadata()
+    }
+
+    fn fchmod(&self, mode: u32) -> Result<()> {
+        self.to_fdraw().fchmod(mode)
+    }
+
+    fn fchown(&self, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>) -> Result<()> {
+        self.to_fdraw().fchown(uid, gid)
+    }
+
+    fn futimens(&self, atime: libc::timespec, mtime: libc::timespec) -> Result<()> {
+        self.to_fdraw().futimens(atime, mtime)
+    }
+
+    fn pread_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>], offset: u64)
+                        -> Result<&'b mut [u8]> {
+        self.to_fdraw().pread_uninit(buf, offset)
+    }
+
+    fn pread(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.to_fdraw().pread(buf, offset)
+    }
+
+    fn pwrite(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        self.to_fdraw().pwrite(buf, offset)
+    }
+
+    fn readv(&self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        self.to_fdraw().readv(bufs)
+    }
+
+    fn writev(&self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        self.to_fdraw().writev(bufs)
+    }
+
+    fn preadv2(&self, bufs: &mut [IoSliceMut<'_>], offset: Option<u64>, flags: libc::c_int)
+               -> Result<usize> {
+        self.to_fdraw().preadv2(bufs, offset, flags)
+    }
+
+    fn pwritev2(&self, bufs: &[IoSlice<'_>], offset: Option<u64>, flags: libc::c_int)
+                -> Result<usize> {
+        self.to_fdraw().pwritev2(bufs, offset, flags)
+    }
+
+    fn sync_data_range(&self, offset: u64, len: u64) -> Result<()> {
+        self.to_fdraw().sync_data_range(offset, len)
+    }
+
+    fn read_uninit<'b>(&self, buf: &'b mut [MaybeUninit<u8>]) -> Result<&'b mut [u8]> {
+        self.to_fdraw().read_uninit(buf)
+    }
+
+    fn punch_front(&self, len: u64) -> Result<()> {
+        self.to_fdraw().punch_front(len)
+    }
+
+    fn drop_page_cache(&self) -> Result<()> {
+        self.to_fdraw().drop_page_cache()
+    }
+
+    fn ftruncate(&self, len: u64) -> Result<()> {
+        self.to_fdraw().ftruncate(len)
+    }
+
+    fn fallocate(&self, mode: libc::c_int, offset: u64, len: u64) -> Result<()> {
+        self.to_fdraw().fallocate(mode, offset, len)
+    }
+}
+
+impl DirExt for Fd {
+    fn openat(&self, path: &Path, flags: libc::c_int) -> Result<Self> {
+        Fd::openat(self, &path, flags)
+    }
+
+    fn createat(&self, path: &Path, flags: libc::c_int, mode: u32) -> Result<Self> {
+        Fd::createat(self, &path, flags, mode)
+    }
+
+    fn mkdirat(&self, path: &Path, mode: u32) -> Result<()> {
+        self.to_fdraw().mkdirat(&path, mode)
+    }
+
+    fn symlinkat(&self, target: &Path, path: &Path) -> Result<()> {
+        self.to_fdraw().symlinkat(&target, &path)
+    }
+
+    fn readlinkat(&self, path: &Path) -> Result<OsString> {
+        self.to_fdraw().readlinkat(&path)
+    }
+
+    fn fstatat(&self, path: &Path, do_follow: bool) -> Result<libc::stat> {
+        self.to_fdraw().fstatat(&path, do_follow)
+    }
+
+    fn fchmodat(&self, path: &Path, mode: u32, do_follow: bool) -> Result<()> {
+        self.to_fdraw().fchmodat(&path, mode, do_follow)
+    }
+
+    fn fchownat(&self, path: &Path, uid: Option<libc::uid_t>, gid: Option<libc::gid_t>,
+                do_follow: bool) -> Result<()> {
+        self.to_fdraw().fchownat(&path, uid, gid, do_follow)
+    }
+
+    fn utimensat(&self, path: &Path, atime: libc::timespec, mtime: libc::timespec,
+                 do_follow: bool) -> Result<()> {
+        self.to_fdraw().utimensat(&path, atime, mtime, do_follow)
+    }
+
+    fn exchangeat(&self, path: &Path, new_dir: &Self, new_path: &Path) -> Result<()> {
+        self.to_fdraw().exchangeat(&path, new_dir.to_fdraw(), &new_path)
+    }
+
+    fn sync_dir(&self) -> Result<()> {
+        self.to_fdraw().sync_dir()
+    }
+
+    fn unlinkat(&self, path: &Path, flags: libc::c_int) -> Result<()> {
+        self.to_fdraw().unlinkat(&path, flags)
+    }
+
+    fn renameat(&self, path: &Path, new_dir: &Self, new_path: &Path) -> Result<()> {
+        self.to_fdraw().renameat(&path, new_dir.to_fdraw(), &new_path)
+    }
+
+    fn renameat2(&self, path: &Path, new_dir: &Self, new_path: &Path, flags: libc::c_uint)
+                 -> Result<()> {
+        self.to_fdraw().renameat2(&path, new_dir.to_fdraw(), &new_path, flags)
+    }
+
+    fn linkat(&self, path: &Path, new_dir: &Self, new_path: &Path, flags: libc::c_int)
+              -> Result<()> {
+        self.to_fdraw().linkat(&path, new_dir.to_fdraw(), &new_path, flags)
+    }
+}
+
+impl XattrExt for Fd {
+    fn getxattr(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.to_fdraw().getxattr(name)
+    }
+
+    fn setxattr(&self, name: &str, value: &[u8], flags: libc::c_int) -> Result<()> {
+        self.to_fdraw().setxattr(name, value, flags)
+    }
+
+    fn removexattr(&self, name: &str) -> Result<()> {
+        self.to_fdraw().removexattr(name)
+    }
+
+    fn listxattr(&self) -> Result<Vec<String>> {
+        self.to_fdraw().listxattr()
+    }
+}
+
+impl LockExt for Fd {
+    fn flock(&self, op: libc::c_int) -> Result<()> {
+        self.to_fdraw().flock(op)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/ops.inc.rs"]
+mod test;
@@ -0,0 +1,264 @@
+//! Validation of path components, paths given as raw bytes,
+//! lexical prefix matches and lossless conversion of names to UTF-8
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+
+/// maximum length of a directory entry name in bytes
+pub const NAME_MAX: usize = 255;
+
+/// Checks whether `name` can be used as name of a new directory
+/// entry.
+///
+/// Fails with `ErrorKind::InvalidName` for empty names, `.` and `..`,
+/// names containing `/` or NUL characters and names longer than
+/// `NAME_MAX` bytes.  The latter are rejected by most filesystems
+/// anyway; NUL characters would truncate the name silently when it
+/// is passed to the kernel.
+pub fn validate_component<T: AsRef<OsStr>>(name: &T) -> Result<()> {
+    let name = name.as_ref();
+    let bytes = name.as_bytes();
+
+    let reason = if bytes.is_empty() {
+        "empty name"
+    } else if bytes == b"." || bytes == b".." {
+        "reserved name"
+    } else if bytes.contains(&0) {
+        "embedded NUL character"
+    } else if bytes.contains(&b'/') {
+        "embedded '/'"
+    } else if bytes.len() > NAME_MAX {
+        "name too long"
+    } else {
+        return Ok(());
+    };
+
+    bail!(ErrorKind::InvalidName(name.to_os_string(), reason))
+}
+
+/// Path given as raw bytes
+///
+/// Protocols like SFTP or 9p transfer paths as byte strings.  This
+/// wrapper implements `AsRef<Path>` so that such paths can be passed
+/// to every method of this crate without converting them first:
+///
+/// ```no_run
+/// # use unix_fd::{chroot::Chroot, pathutil::PathBytes};
+/// # fn f(chroot: &Chroot, req: &[u8]) -> unix_fd::errors::Result<Vec<u8>> {
+/// chroot.read(&PathBytes::from(req))
+/// # }
+/// ```
+///
+/// Embedded NUL characters are rejected with `ErrorKind::InvalidPath`
+/// when the path is used.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PathBytes<'a>(Cow<'a, [u8]>);
+
+impl PathBytes<'_> {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.0))
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        OsString::from_vec(self.0.into_owned()).into()
+    }
+}
+
+impl AsRef<Path> for PathBytes<'_> {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl<'a> From<&'a [u8]> for PathBytes<'a> {
+    fn from(path: &'a [u8]) -> Self {
+        PathBytes(Cow::Borrowed(path))
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for PathBytes<'a> {
+    fn from(path: &'a Vec<u8>) -> Self {
+        PathBytes(Cow::Borrowed(path))
+    }
+}
+
+impl From<Vec<u8>> for PathBytes<'static> {
+    fn from(path: Vec<u8>) -> Self {
+        PathBytes(Cow::Owned(path))
+    }
+}
+
+fn normalize_bytes(path: &Path) -> Result<Vec<u8>> {
+    let bytes = path.as_os_str().as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+
+    if bytes.first() == Some(&b'/') {
+        res.push(b'/');
+    }
+
+    for comp in bytes.split(|c| *c == b'/') {
+        match comp {
+            b"" | b"."	=> continue,
+            b".."	=> bail!("{:?} contains '..'", path),
+            _		=> {},
+        }
+
+        if !res.is_empty() && res.last() != Some(&b'/') {
+            res.push(b'/');
+        }
+
+        res.extend_from_slice(comp);
+    }
+
+    Ok(res)
+}
+
+/// Normalizes `path` lexically by removing repeated and trailing `/`
+/// and `.` components.  Fails for paths with `..` components because
+/// they can not be resolved without the filesystem; use
+/// `Chroot::canonicalize()` for them.
+pub fn normalize_path<T: AsRef<Path>>(path: &T) -> Result<PathBuf> {
+    normalize_bytes(path.as_ref()).map(|p| OsString::from_vec(p).into())
+}
+
+/// Returns whether `path` equals `prefix` or lies below it; e.g. for
+/// allow-lists checked against the result of `Chroot::canonicalize()`.
+///
+/// Both are normalized like by `normalize_path()` and compared
+/// component-wise so that `/etc` matches `/etc/passwd` but not
+/// `/etc2`.  Absolute paths never match relative ones and paths with
+/// `..` components never match.  The bytes of the prefix are
+/// compared without an early exit so that the time does not reveal
+/// how much of a denied path matched.
+pub fn matches_prefix<P, Q>(path: &P, prefix: &Q) -> bool
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (path, prefix) = match (normalize_bytes(path.as_ref()),
+                                normalize_bytes(prefix.as_ref())) {
+        (Ok(path), Ok(prefix)) => (path, prefix),
+        _ => return false,
+    };
+
+    if prefix.len() > path.len() ||
+        path.starts_with(b"/") != prefix.starts_with(b"/") {
+        return false;
+    }
+
+    let diff = path.iter().zip(&prefix).fold(0, |acc, (a, b)| acc | (a ^ b));
+    let boundary = match path.get(prefix.len()) {
+        None		=> true,
+        Some(c)		=> *c == b'/' || prefix.is_empty() || prefix == b"/",
+    };
+
+    diff == 0 && boundary
+}
+
+/// Splits `bytes` into valid UTF-8 and the bytes between them and
+/// calls `f` for every part.
+fn for_each_utf8<F>(mut bytes: &[u8], mut f: F)
+where
+    F: FnMut(std::result::Result<&str, u8>),
+{
+    while !bytes.is_empty() {
+        let valid = match std::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => unsafe { std::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+        };
+
+        f(Ok(valid));
+        bytes = &bytes[valid.len()..];
+
+        if let Some((b, rest)) = bytes.split_first() {
+            f(Err(*b));
+            bytes = rest;
+        }
+    }
+}
+
+/// Converts `name` to UTF-8 by replacing `%` and the bytes of
+/// invalid UTF-8 sequences by `%XX`; valid non-ASCII characters are
+/// kept.  The conversion is reversed by `percent_decode()`.
+pub fn percent_encode<T: AsRef<OsStr>>(name: &T) -> String {
+    let mut res = String::with_capacity(name.as_ref().len());
+
+    for_each_utf8(name.as_ref().as_bytes(), |part| match part {
+        Ok(s)	=> res.push_str(&s.replace('%', "%25")),
+        Err(b)	=> res.push_str(&format!("%{:02X}", b)),
+    });
+
+    res
+}
+
+/// Reverses `percent_encode()`.  Fails for `%` which are not followed
+/// by two hex digits.
+pub fn percent_decode(s: &str) -> Result<OsString> {
+    let mut res = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+
+    while let Some(c) = bytes.next() {
+        if c != b'%' {
+            res.push(c);
+            continue;
+        }
+
+        let hex = [bytes.next(), bytes.next()];
+        let hex = match hex {
+            [Some(a), Some(b)] => std::str::from_utf8(&[a, b]).ok()
+                .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+                .and_then(|h| u8::from_str_radix(h, 16).ok()),
+            _ => None,
+        };
+
+        match hex {
+            Some(b)	=> res.push(b),
+            None	=> bail!("bad percent escape in {:?}", s),
+        }
+    }
+
+    Ok(OsString::from_vec(res))
+}
+
+/// Converts `name` to UTF-16 where the bytes of invalid UTF-8
+/// sequences are mapped to the lone surrogates `U+DC80` to `U+DCFF`
+/// (like the `surrogateescape` error handler of Python).  JSON can
+/// represent the result with `\uXXXX` escapes.
+pub fn surrogate_escape<T: AsRef<OsStr>>(name: &T) -> Vec<u16> {
+    let mut res = Vec::with_capacity(name.as_ref().len());
+
+    for_each_utf8(name.as_ref().as_bytes(), |part| match part {
+        Ok(s)	=> res.extend(s.encode_utf16()),
+        Err(b)	=> res.push(0xdc00 | b as u16),
+    });
+
+    res
+}
+
+/// Reverses `surrogate_escape()`.  Fails for lone surrogates which
+/// were not created by it.
+pub fn surrogate_unescape(units: &[u16]) -> Result<OsString> {
+    let mut res = Vec::with_capacity(units.len());
+
+    for c in std::char::decode_utf16(units.iter().cloned()) {
+        match c {
+            Ok(c) => res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            Err(e) if (0xdc80..=0xdcff).contains(&e.unpaired_surrogate()) =>
+                res.push(e.unpaired_surrogate() as u8),
+            Err(e) => bail!("unexpected surrogate {:#x}", e.unpaired_surrogate()),
+        }
+    }
+
+    Ok(OsString::from_vec(res))
+}
+
+#[cfg(test)]
+#[path="tests/pathutil.inc.rs"]
+mod test;
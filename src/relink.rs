@@ -0,0 +1,50 @@
+//! Linking open but unlinked files back into a directory
+extern crate libc;
+
+use std::path::Path;
+
+use crate::fd::FdRaw;
+use crate::LibcString;
+use crate::errors::*;
+
+impl FdRaw {
+    /// Creates the entry `path` relative to `dir` for the file opened
+    /// by this descriptor (`linkat()` through `/proc/self/fd`).
+    ///
+    /// This materializes files whose link count dropped to 0.  The
+    /// kernel permits it only for files created with `O_TMPFILE`
+    /// (without `O_EXCL`); other removed files, filesystems without
+    /// hard links and targets on another filesystem fail with
+    /// `ErrorKind::NotLinkable`.
+    pub fn open_unlinked_link<T>(&self, dir: &FdRaw, path: &T) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let src = format!("/proc/self/fd/{}", self.fd);
+        let rc = unsafe {
+            libc::linkat(libc::AT_FDCWD, Path::new(&src).as_libc()?.0,
+                         dir.fd, path.as_ref().as_libc()?.0,
+                         libc::AT_SYMLINK_FOLLOW)
+        };
+
+        if rc >= 0 {
+            return Ok(());
+        }
+
+        let err = std::io::Error::last_os_error();
+        let reason = match err.raw_os_error() {
+            Some(libc::ENOENT) if self.fstat()?.st_nlink == 0 =>
+                "removed file was not created with O_TMPFILE",
+            Some(libc::EXDEV) => "target is on another filesystem",
+            Some(libc::EPERM) | Some(libc::EOPNOTSUPP) =>
+                "filesystem does not support hard links",
+            _ => return Err(err.into()),
+        };
+
+        Err(Error::with_chain(err, ErrorKind::NotLinkable(reason)))
+    }
+}
+
+#[cfg(test)]
+#[path="tests/relink.inc.rs"]
+mod test;
@@ -0,0 +1,180 @@
+//! Race-safe recursive removal of directory trees within a chroot
+extern crate libc;
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::dir::{DirOffset, ReadDir};
+use crate::fd::Fd;
+use crate::errors::*;
+
+struct Level {
+    dir_fd:	Fd,
+    entries:	ReadDir,
+    /// name in the parent directory; `None` for the top directory
+    name:	Option<OsString>,
+}
+
+impl Chroot {
+    /// Removes everything below `top`.
+    ///
+    /// Subdirectories are entered by `openat()` with `O_NOFOLLOW`
+    /// relative to their parent so that replacing them by symlinks
+    /// can not redirect the removal.  Mount points are not crossed;
+    /// before Linux 5.8, only those of other filesystems are detected.
+    fn remove_contents(&self, top: Fd) -> Result<()> {
+        let mnt_id = top.mount_id_or_dev()?;
+        let progress = self.progress();
+        let mut todo = vec![Level {
+            entries:	top.read_dir()?,
+            dir_fd:	top,
+            name:	None,
+        }];
+        let mut deadline = None;
+
+        while let Some(level) = todo.last_mut() {
+            self.check_interrupt(&mut deadline)?;
+
+            let name = match level.entries.next() {
+                Some(e) => e?.d_name,
+                None => {
+                    let mut level = todo.pop().unwrap();
+                    let (name, parent) = match (&level.name, todo.last()) {
+                        (Some(name), Some(parent)) => (name, parent),
+                        _ => continue,
+                    };
+
+                    match parent.dir_fd.unlinkat(name, libc::AT_REMOVEDIR) {
+                        // entries were added while it was listed
+                        Err(ref e) if e.errno() == Some(libc::ENOTEMPTY) => {
+                            level.entries.seek(DirOffset::START);
+                            todo.push(level);
+                        },
+                        Err(ref e) if e.errno() == Some(libc::ENOENT) => {},
+                        r => { r?; progress.add(1, 0); },
+                    }
+
+                    continue;
+                },
+            };
+
+            let sub_fd = loop {
+                // directories fail with EISDIR
+                match level.dir_fd.unlinkat(&name, 0) {
+                    Err(ref e) if e.errno() == Some(libc::EISDIR) => {},
+                    Err(ref e) if e.errno() == Some(libc::ENOENT) => break None,
+                    r => { r?; progress.add(1, 0); break None; },
+                }
+
+                match level.dir_fd.openat(&name, libc::O_DIRECTORY |
+                                          libc::O_RDONLY | libc::O_CLOEXEC |
+                                          libc::O_NOFOLLOW) {
+                    Ok(fd) => break Some(fd),
+                    Err(ref e) if e.errno() == Some(libc::ENOENT) => break None,
+                    // replaced by a symlink or another non-directory
+                    // meanwhile; unlink it again
+                    Err(ref e) if matches!(e.errno(), Some(libc::ENOTDIR) |
+                                                      Some(libc::ELOOP)) => {},
+                    Err(e) => return Err(e),
+                }
+            };
+
+            let sub_fd = match sub_fd {
+                Some(fd) => fd,
+                None => continue,
+            };
+
+            ensure!(sub_fd.mount_id_or_dev()? == mnt_id,
+                    "refusing to remove mount point {:?}", name);
+
+            todo.push(Level {
+                entries:	sub_fd.read_dir()?,
+                dir_fd:		sub_fd,
+                name:		Some(name),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Removes the directory `path` with all its content.
+    ///
+    /// The parent of `path` is resolved like by `Self::open()`; a
+    /// symlink in the final component is not followed and fails.
+    /// Mount points below `path` are not crossed but let the removal
+    /// fail.
+    pub fn remove_dir_all<T>(&self, path: &T) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let (dir, name) = Self::split_path(path);
+
+        ensure!(name != "." && name != "..", "can not remove {:?}", path);
+
+        let dir_fd = self.chdir(&dir)?;
+        let fd = dir_fd.openat(&name, libc::O_DIRECTORY | libc::O_RDONLY |
+                               libc::O_CLOEXEC | libc::O_NOFOLLOW)?;
+
+        self.ensure_writable(&dir_fd, path)?;
+        self.ensure_writable(&fd, path)?;
+
+        let res = self.keeping_dir_times(&[&dir_fd], || {
+            self.remove_contents(fd)
+                .and_then(|_| dir_fd.unlinkat(&name, libc::AT_REMOVEDIR))
+        });
+
+        self.clear_negative_cache();
+
+        res
+    }
+
+    /// Removes the file `path`; fails for directories.
+    ///
+    /// The parent of `path` is resolved like by `Self::open()`; a
+    /// symlink in the final component is removed itself.
+    pub fn remove_file<T>(&self, path: &T) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let (dir, name) = Self::split_path(path);
+
+        ensure!(name != "." && name != "..", "can not remove {:?}", path);
+
+        let dir_fd = self.chdir(&dir)?;
+
+        self.ensure_writable(&dir_fd, path)?;
+
+        let res = self.keeping_dir_times(&[&dir_fd], || dir_fd.unlinkat(&name, 0));
+
+        self.clear_negative_cache();
+
+        res
+    }
+
+    /// Removes all entries of the directory `path` but keeps the
+    /// directory itself with its metadata.
+    ///
+    /// `path` is resolved like by `Self::chdir()`; the content is
+    /// removed like by `Self::remove_dir_all()`.
+    pub fn clear_dir<T>(&self, path: &T) -> Result<()>
+    where
+        T: AsRef<Path>,
+    {
+        let fd = self.chdir(path)?;
+
+        self.ensure_writable(&fd, path.as_ref())?;
+
+        let res = self.keeping_dir_times(&[&fd], || self.remove_contents(fd.clone()));
+
+        self.clear_negative_cache();
+
+        res
+    }
+}
+
+#[cfg(test)]
+#[path="tests/remove.inc.rs"]
+mod test;
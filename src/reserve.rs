@@ -0,0 +1,94 @@
+//! Disk space reservations within a chroot
+//!
+//! `Chroot::reserve()` allocates space on the filesystem of a
+//! directory by an unnamed file.  Large writes (e.g. extracting or
+//! copying trees) can release it step by step so that they never fill
+//! the filesystem completely while other data lives on it too.
+extern crate libc;
+
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::fd::Fd;
+use crate::errors::*;
+
+/// Space allocated by `Chroot::reserve()`; it is released when the
+/// guard is dropped.
+#[derive(Debug)]
+pub struct SpaceReservation {
+    /// unlinked file holding the allocated blocks
+    fd:		Fd,
+    len:	u64,
+}
+
+impl SpaceReservation {
+    /// Returns the number of reserved bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Releases everything above `len` bytes of the reservation;
+    /// e.g. right before writing data of the released size.
+    pub fn shrink_to(&mut self, len: u64) -> Result<()> {
+        if len >= self.len {
+            return Ok(());
+        }
+
+        self.fd.ftruncate(len)?;
+        self.len = len;
+
+        Ok(())
+    }
+}
+
+impl Chroot {
+    /// Reserves `bytes` on the filesystem of the directory `dir`.
+    ///
+    /// `dir` is resolved like by `Self::chdir()`.  The space is
+    /// allocated by `fallocate()` for an `O_TMPFILE` file which never
+    /// appears in the directory; on filesystems without `O_TMPFILE`,
+    /// a temporary file is created and unlinked right away.  Fails
+    /// with `ENOSPC` when not enough space is free, with
+    /// `EOPNOTSUPP` when the filesystem can not preallocate, with
+    /// `EINVAL` when `bytes` is above `i64::MAX` and with
+    /// `ErrorKind::ReadOnlyMount` on read-only mounts.
+    pub fn reserve<T>(&self, dir: &T, bytes: u64) -> Result<SpaceReservation>
+    where
+        T: AsRef<Path>,
+    {
+        let dir_fd = self.chdir(dir)?;
+
+        self.ensure_writable(&dir_fd, dir.as_ref())?;
+
+        // the unnamed file can not be opened by others; its mode does
+        // not matter
+        let fd = match dir_fd.openat(&".", libc::O_TMPFILE | libc::O_WRONLY |
+                                     libc::O_CLOEXEC) {
+            Err(ref e) if matches!(e.errno(), Some(libc::EOPNOTSUPP) | Some(libc::EISDIR)) =>
+                self.keeping_dir_times(&[&dir_fd], || {
+                    let (name, fd) = self.create_tmp(&dir_fd, ".reserve".as_ref(), 0o600)?;
+
+                    dir_fd.unlinkat(&name, 0)?;
+                    Ok(fd)
+                })?,
+            r => r?,
+        };
+
+        if bytes > 0 {
+            fd.fallocate(0, 0, bytes)?;
+        }
+
+        Ok(SpaceReservation {
+            fd:		fd,
+            len:	bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+#[path="tests/reserve.inc.rs"]
+mod test;
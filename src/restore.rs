@@ -0,0 +1,48 @@
+//! Restoring metadata of files within a chroot
+extern crate libc;
+
+use std::path::{Path, PathBuf};
+
+use crate::chroot::Chroot;
+use crate::fd::Fd;
+use crate::metadata::Metadata;
+use crate::errors::*;
+
+impl Chroot {
+    /// Returns the absolute path within the chroot at which `fd` can
+    /// be found.  A file which was renamed within the chroot since it
+    /// was opened is reported at its new location; fails when it was
+    /// removed or is (or was moved) outside of the chroot.
+    pub(crate) fn fd_path(&self, fd: &Fd) -> Result<PathBuf> {
+        let root_fd = self.root_fd()?;
+        let root_path = std::fs::read_link(format!("/proc/self/fd/{}", root_fd.fd))?;
+        let fd_path = std::fs::read_link(format!("/proc/self/fd/{}", fd.fd))?;
+
+        let path = match fd_path.strip_prefix(&root_path) {
+            Ok(p) => Path::new("/").join(p),
+            Err(_) => bail!("{:?} is not inside the chroot", fd_path),
+        };
+
+        ensure!(self.verify(&path, fd)?,
+                "{:?} does not refer to the opened file anymore", path);
+
+        Ok(path)
+    }
+
+    /// Applies owner, group, permissions and access/modification
+    /// times of `md` to `fd`, which must have been opened within this
+    /// chroot.
+    ///
+    /// The location of `fd` is checked and reported together with the
+    /// result to the hook of `Self::with_audit_hook()`.  See
+    /// `Metadata::apply()` for the order of the steps.
+    pub fn apply_metadata(&self, fd: &Fd, md: &Metadata) -> Result<()> {
+        let path = self.fd_path(fd)?;
+
+        self.audited("apply_metadata", &path, Some(&path), || md.apply(fd, None))
+    }
+}
+
+#[cfg(test)]
+#[path="tests/restore.inc.rs"]
+mod test;
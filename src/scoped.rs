@@ -0,0 +1,184 @@
+//! Paths which are known to be inside a chroot
+extern crate libc;
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::fd::Fd;
+use crate::errors::*;
+
+/// Canonical path within a `Chroot`
+///
+/// Created by `Chroot::canonicalize()`.  It keeps the directory
+/// containing the entry open so that its methods work without
+/// resolving the path again.  Hence, they refer to the old location
+/// when directories are renamed after the canonicalization.
+#[derive(Debug)]
+pub struct ScopedPath<'c> {
+    chroot:	&'c Chroot,
+    /// absolute path relative to the chroot without symlinks
+    path:	std::path::PathBuf,
+    dir_fd:	Fd,
+    name:	OsString,
+}
+
+impl AsRef<Path> for ScopedPath<'_> {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl ScopedPath<'_> {
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the open parent directory; for the chroot top
+    /// directory and paths ending in `..`, this is the directory
+    /// itself.
+    pub fn dir_fd(&self) -> &Fd {
+        &self.dir_fd
+    }
+
+    /// Returns the name of the entry in `Self::dir_fd()`; `None`
+    /// when the path refers to that directory itself.
+    pub fn file_name(&self) -> Option<&std::ffi::OsStr> {
+        if self.name == "." {
+            None
+        } else {
+            Some(&self.name)
+        }
+    }
+
+    fn name_for(&self, op: &str) -> Result<&OsString> {
+        ensure!(self.name != ".", "can not {} {:?}", op, self.path);
+        crate::pathutil::validate_component(&self.name)?;
+
+        Ok(&self.name)
+    }
+
+    /// Opens the entry; `O_NOFOLLOW` is added to `flags`.
+    pub fn open(&self, flags: libc::c_int) -> Result<Fd> {
+        let fd = self.dir_fd.openat(&self.name, flags | libc::O_NOFOLLOW)?;
+
+        self.chroot.check_opened(fd)
+    }
+
+    /// Creates or opens the entry; `O_CREAT | O_NOFOLLOW` are added to
+    /// `flags`.
+    pub fn create(&self, flags: libc::c_int, mode: u32) -> Result<Fd> {
+        let fd = self.chroot.create_entry(&self.dir_fd, self.name_for("create")?,
+                                          flags | libc::O_NOFOLLOW, mode);
+
+        self.chroot.clear_negative_cache();
+
+        let fd = fd?;
+
+        self.chroot.check_opened(fd)
+    }
+
+    /// Replaces the entry atomically like `Chroot::write_atomic()`
+    pub fn write_atomic(&self, data: &[u8]) -> Result<()> {
+        let name = self.name_for("write")?;
+
+        self.chroot.ensure_writable(&self.dir_fd, &self.path)?;
+        self.chroot.write_atomic_in(&self.dir_fd, name, data)
+    }
+
+    /// Removes the entry when it is not a directory
+    pub fn remove_file(&self) -> Result<()> {
+        let name = self.name_for("remove")?;
+        let res = self.chroot.keeping_dir_times(&[&self.dir_fd], || {
+            self.dir_fd.unlinkat(name, 0)
+        });
+
+        self.chroot.clear_negative_cache();
+        res
+    }
+
+    /// Removes the entry when it is an empty directory
+    pub fn remove_dir(&self) -> Result<()> {
+        let name = self.name_for("remove")?;
+        let res = self.chroot.keeping_dir_times(&[&self.dir_fd], || {
+            self.dir_fd.unlinkat(name, libc::AT_REMOVEDIR)
+        });
+
+        self.chroot.clear_negative_cache();
+        res
+    }
+}
+
+impl Chroot {
+    /// Resolves `path` within the chroot and returns its canonical
+    /// form.  Symlinks are followed like by `Self::open()`; the final
+    /// component does not need to exist.
+    ///
+    /// This is expensive because the path is reconstructed by
+    /// `Self::full_path()`.
+    pub fn canonicalize<T>(&self, path: &T) -> Result<ScopedPath<'_>>
+    where
+        T: AsRef<Path>,
+    {
+        let (dir_fd, name) = self.resolve_entry(path.as_ref())?;
+
+        let full = if name == "." {
+            self.full_path::<&Path>(&dir_fd, None)?
+        } else {
+            self.full_path(&dir_fd, Some(&Path::new(&name)))?
+        };
+
+        Ok(ScopedPath {
+            chroot:	self,
+            path:	full.into(),
+            dir_fd:	dir_fd,
+            name:	name,
+        })
+    }
+
+    /// Canonicalizes the longest existing prefix of `path` and appends
+    /// the remaining components lexically (like `realpath -m`).  A
+    /// `..` in the missing part removes the preceding component.
+    ///
+    /// Only missing entries (`ENOENT`) end the existing prefix; other
+    /// errors are reported.
+    pub fn canonicalize_parent<T>(&self, path: &T) -> Result<std::path::PathBuf>
+    where
+        T: AsRef<Path>,
+    {
+        use std::path::Component;
+
+        let mut prefix = path.as_ref();
+        let mut missing = Vec::new();
+
+        let mut res = loop {
+            match self.canonicalize(&prefix) {
+                Ok(p)	=> break p.path,
+                Err(ref e) if e.errno() == Some(libc::ENOENT) => {},
+                Err(e)	=> return Err(e),
+            }
+
+            match (prefix.parent(), prefix.components().next_back()) {
+                (Some(parent), Some(c))	=> {
+                    missing.push(c);
+                    prefix = parent;
+                },
+                _	=> bail!("can not resolve any part of {:?}", path.as_ref()),
+            }
+        };
+
+        for c in missing.into_iter().rev() {
+            match c {
+                Component::Normal(n)	=> res.push(n),
+                Component::ParentDir	=> { res.pop(); },
+                _			=> {},
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/scoped.inc.rs"]
+mod test;
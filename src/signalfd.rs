@@ -0,0 +1,157 @@
+//! Signals received by reading a descriptor
+//!
+//! `SignalFd` wraps `signalfd()`.  `FdRaw::route_signals()` directs the
+//! signals of signal driven I/O (`O_ASYNC`) on pipes, FIFOs or sockets
+//! to it so that their readiness can be consumed like other events.
+extern crate libc;
+
+use std::mem;
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::fd::{Fd, FdRaw};
+use crate::errors::*;
+
+/// `F_SETOWN_EX` and `F_OWNER_TID` from `<asm-generic/fcntl.h>`; they
+/// are missing in the libc crate
+const F_SETOWN_EX: libc::c_int = 15;
+const F_OWNER_TID: libc::c_int = 0;
+
+#[repr(C)]
+struct FOwnerEx {
+    type_:	libc::c_int,
+    pid:	libc::pid_t,
+}
+
+/// Descriptor which receives a set of signals
+///
+/// The signals are blocked in the thread which creates it; they must
+/// be blocked in all other threads too (e.g. by creating it before
+/// spawning them).  Else, they are delivered to those threads.
+pub struct SignalFd {
+    fd:		Fd,
+    mask:	libc::sigset_t,
+}
+
+impl std::fmt::Debug for SignalFd {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SignalFd")
+            .field("fd", &self.fd)
+            .finish()
+    }
+}
+
+impl SignalFd {
+    /// Blocks `signals` in the calling thread and creates a
+    /// non-blocking descriptor which receives them.
+    pub fn new(signals: &[libc::c_int]) -> Result<Self> {
+        let mut mask = mem::MaybeUninit::<libc::sigset_t>::uninit();
+
+        unsafe { libc::sigemptyset(mask.as_mut_ptr()) };
+
+        for sig in signals {
+            let rc = unsafe { libc::sigaddset(mask.as_mut_ptr(), *sig) };
+
+            ensure!(rc >= 0, std::io::Error::last_os_error());
+        }
+
+        let mask = unsafe { mask.assume_init() };
+        let rc = unsafe {
+            libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut())
+        };
+
+        ensure!(rc == 0, std::io::Error::from_raw_os_error(rc));
+
+        let fd = unsafe {
+            libc::signalfd(-1, &mask, libc::SFD_CLOEXEC | libc::SFD_NONBLOCK)
+        };
+
+        ensure!(fd >= 0, std::io::Error::last_os_error());
+
+        Ok(SignalFd {
+            fd:		FdRaw::_new(fd).into_fd(),
+            mask:	mask,
+        })
+    }
+
+    pub fn fd(&self) -> &Fd {
+        &self.fd
+    }
+
+    /// Returns whether `sig` is received by the descriptor
+    pub fn contains(&self, sig: libc::c_int) -> bool {
+        unsafe { libc::sigismember(&self.mask, sig) == 1 }
+    }
+
+    /// Returns the next pending signal; `None` when there is none.
+    ///
+    /// For signals routed by `FdRaw::route_signals()`, `ssi_fd` is the
+    /// descriptor which became ready and `ssi_band` the poll events.
+    pub fn read(&self) -> Result<Option<libc::signalfd_siginfo>> {
+        let mut info = mem::MaybeUninit::<libc::signalfd_siginfo>::uninit();
+        let len = mem::size_of::<libc::signalfd_siginfo>();
+        let rc = unsafe { libc::read(self.fd.fd, info.as_mut_ptr() as *mut _, len) };
+
+        if rc < 0 {
+            let err = std::io::Error::last_os_error();
+
+            if err.raw_os_error() == Some(libc::EAGAIN) {
+                return Ok(None);
+            }
+
+            return Err(err.into());
+        }
+
+        ensure!(rc as usize == len, "short read of {} bytes from signalfd", rc);
+
+        Ok(Some(unsafe { info.assume_init() }))
+    }
+}
+
+impl FdRaw {
+    /// Enables signal driven I/O and routes the signals as `sig` to
+    /// the calling thread where `sfd` receives them.
+    ///
+    /// Fails when `sig` is not in the set of `sfd`; it would be
+    /// delivered like a normal signal then.  The thread must read
+    /// `sfd` itself because signals directed to a thread are not seen
+    /// by others.
+    pub fn route_signals(&self, sfd: &SignalFd, sig: libc::c_int) -> Result<()> {
+        ensure!(sfd.contains(sig), "signal {} is not received by {:?}", sig, sfd);
+
+        let owner = FOwnerEx {
+            type_:	F_OWNER_TID,
+            pid:	unsafe { libc::syscall(libc::SYS_gettid) } as libc::pid_t,
+        };
+
+        self.set_signal(sig)?;
+
+        let rc = unsafe { libc::fcntl(self.fd, F_SETOWN_EX, &owner as *const FOwnerEx) };
+
+        ensure!(rc >= 0, std::io::Error::last_os_error());
+
+        self.set_async(true)
+    }
+}
+
+impl Chroot {
+    /// Opens `path` like `Self::open_nonblocking()` and routes its
+    /// I/O signals to `sfd` like `FdRaw::route_signals()`; e.g. for
+    /// FIFOs which are served together with other descriptors.  They
+    /// are refused unless `Self::with_type_guard()` is disabled.
+    pub fn open_signal_driven<T>(&self, path: &T, flags: libc::c_int, sfd: &SignalFd,
+                                 sig: libc::c_int) -> Result<Fd>
+    where
+        T: AsRef<Path>,
+    {
+        let fd = self.open_nonblocking(path, flags)?;
+
+        fd.route_signals(sfd, sig)?;
+
+        Ok(fd)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/signalfd.inc.rs"]
+mod test;
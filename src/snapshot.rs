@@ -0,0 +1,290 @@
+//! Copy-on-write copies and hardlinked mirrors of directory trees
+//! within a chroot
+extern crate libc;
+
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::fd::{Fd, FdRaw};
+use crate::metadata::Metadata;
+use crate::errors::*;
+
+impl FdRaw {
+    /// Replaces the content of this file by the content of `src`.
+    ///
+    /// The data is shared with `src` (`FICLONE`) when the filesystem
+    /// supports it; else it is copied with `copy_file_range()`.
+    /// Both descriptors must refer to regular files and `self` must
+    /// be opened for writing.
+    pub fn reflink_or_copy_from(&self, src: &FdRaw) -> Result<()> {
+        let rc = unsafe { libc::ioctl(self.fd, libc::FICLONE, src.fd) };
+
+        if rc == 0 {
+            return Ok(());
+        }
+
+        match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOTTY) |
+            Some(libc::EXDEV) | Some(libc::EINVAL) => {},
+            _ => return Err(std::io::Error::last_os_error().into()),
+        }
+
+        let mut src_off: libc::loff_t = 0;
+        let mut dst_off: libc::loff_t = 0;
+
+        loop {
+            let rc = unsafe {
+                libc::copy_file_range(src.fd, &mut src_off, self.fd, &mut dst_off,
+                                      1 << 30, 0)
+            };
+
+            if rc < 0 && dst_off == 0 {
+                // kernels before 5.3 and different filesystem types
+                match std::io::Error::last_os_error().raw_os_error() {
+                    Some(libc::EXDEV) | Some(libc::EOPNOTSUPP) |
+                    Some(libc::EINVAL) | Some(libc::ENOSYS) => return self.copy_from(src),
+                    _ => {},
+                }
+            }
+
+            ensure!(rc >= 0, std::io::Error::last_os_error());
+
+            if rc == 0 {
+                break;
+            }
+        }
+
+        self.ftruncate(dst_off as u64)
+    }
+
+    /// Copies `src` with `pread()` and `pwrite()`
+    fn copy_from(&self, src: &FdRaw) -> Result<()> {
+        let mut buf = vec![std::mem::MaybeUninit::<u8>::uninit(); 1 << 16];
+        let mut off = 0;
+
+        loop {
+            let data = src.pread_uninit(&mut buf, off)?;
+
+            if data.is_empty() {
+                break;
+            }
+
+            let mut pos = 0;
+
+            while pos < data.len() {
+                pos += self.pwrite(&data[pos..], off + pos as u64)?;
+            }
+
+            off += data.len() as u64;
+        }
+
+        self.ftruncate(off)
+    }
+}
+
+/// Recreates the entry `name` of `src_dir` in `dst_dir`.  Returns the
+/// descriptors of both directories when `name` is a directory which
+/// must be descended into.  The mode of directories is not set; it
+/// might prevent creating their content.
+fn snapshot_entry(chroot: &Chroot, src_dir: &Fd, dst_dir: &Fd, name: &std::ffi::OsStr,
+                  md: &Metadata) -> Result<Option<(Fd, Fd)>>
+{
+    let mode = md.mode();
+    let dir_flags = libc::O_DIRECTORY | libc::O_RDONLY | libc::O_CLOEXEC |
+        libc::O_NOFOLLOW;
+    let mut res = None;
+
+    if md.is_dir() {
+        dst_dir.mkdirat(&name, 0o700)?;
+
+        res = Some((src_dir.openat(&name, dir_flags)?,
+                    dst_dir.openat(&name, dir_flags)?));
+    } else if md.is_file() {
+        let src = src_dir.openat(&name, libc::O_RDONLY | libc::O_CLOEXEC |
+                                 libc::O_NOFOLLOW)?;
+        let dst = dst_dir.createat(&name, libc::O_WRONLY | libc::O_EXCL |
+                                   libc::O_CLOEXEC | libc::O_NOFOLLOW, 0o600)?;
+
+        dst.reflink_or_copy_from(&src)?;
+        chroot.advise_copied(&[&src, &dst]);
+    } else if md.is_symlink() {
+        dst_dir.symlinkat(&src_dir.readlinkat(&name)?, &name)?;
+    } else if md.is_fifo() || md.is_char_device() || md.is_block_device() {
+        let path = crate::LibcString::as_libc(Path::new(name))?;
+        let rc = unsafe {
+            libc::mknodat(dst_dir.fd, path.0, md.as_stat().st_mode, md.rdev())
+        };
+
+        ensure!(rc >= 0, std::io::Error::last_os_error());
+    } else {
+        // sockets can not be recreated
+        return Ok(None);
+    }
+
+    // ownership is kept when it is allowed; chown() must happen before
+    // chmod() because it clears setuid bits
+    match dst_dir.fchownat(&name, Some(md.uid()), Some(md.gid()), false) {
+        Err(ref e) if e.errno() == Some(libc::EPERM) => {},
+        r => r?,
+    }
+
+    if !md.is_symlink() && !md.is_dir() {
+        dst_dir.open_path_at(&name)?.fchmod(mode)?;
+    }
+
+    Ok(res)
+}
+
+/// Recreates the entry `name` of `src_dir` in `dst_dir` for
+/// `Chroot::mirror()`; everything but directories is hardlinked.
+fn mirror_entry(_: &Chroot, src_dir: &Fd, dst_dir: &Fd, name: &std::ffi::OsStr,
+                md: &Metadata) -> Result<Option<(Fd, Fd)>>
+{
+    let dir_flags = libc::O_DIRECTORY | libc::O_RDONLY | libc::O_CLOEXEC |
+        libc::O_NOFOLLOW;
+
+    if !md.is_dir() {
+        return match src_dir.linkat(&name, dst_dir, &name, 0) {
+            Err(ref e) if e.errno() == Some(libc::EXDEV) =>
+                bail!(ErrorKind::CrossesFilesystem(src_dir.mount_id()?,
+                                                   dst_dir.mount_id()?)),
+            r => r.map(|_| None),
+        };
+    }
+
+    dst_dir.mkdirat(&name, 0o700)?;
+
+    match dst_dir.fchownat(&name, Some(md.uid()), Some(md.gid()), false) {
+        Err(ref e) if e.errno() == Some(libc::EPERM) => {},
+        r => r?,
+    }
+
+    Ok(Some((src_dir.openat(&name, dir_flags)?, dst_dir.openat(&name, dir_flags)?)))
+}
+
+type EntryFn = fn(&Chroot, &Fd, &Fd, &std::ffi::OsStr, &Metadata)
+                  -> Result<Option<(Fd, Fd)>>;
+
+impl Chroot {
+    /// Copies the directory tree at `src_dir` to the new directory
+    /// `dst_dir`.
+    ///
+    /// Regular files are reflinked where possible so that the copy
+    /// is cheap on filesystems like btrfs or XFS.  Symlinks are copied
+    /// verbatim, modes and (when permitted) ownership are kept.
+    /// Sockets are skipped.  `dst_dir` must not exist; when it lies
+    /// below `src_dir`, it is not copied into itself.
+    pub fn snapshot<S, D>(&self, src_dir: &S, dst_dir: &D) -> Result<()>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+    {
+        self.copy_tree(src_dir.as_ref(), dst_dir.as_ref(), false, snapshot_entry)
+    }
+
+    /// Recreates the directory tree at `src_dir` in the new directory
+    /// `dst_dir` with hardlinks to the original files.
+    ///
+    /// No data is copied so that this is instant; the mirror keeps
+    /// the content of files which are replaced (e.g. by
+    /// `Self::write_atomic()`) or removed in `src_dir` later but
+    /// shares in-place modifications.  Directories get the mode and
+    /// (when permitted) the ownership of the originals.  Both trees
+    /// must be on the same mount; else, `ErrorKind::CrossesFilesystem`
+    /// is returned.
+    ///
+    /// The mirrored files are not made read-only: a hardlink shares
+    /// the inode and so the mode with the original and a `chmod()`
+    /// would make `src_dir` read-only too.  Export the mirror by a
+    /// read-only bind mount to enforce this.
+    ///
+    /// The traversal is the one of `Self::snapshot()` and not
+    /// `crate::visit` because it walks the source and the destination
+    /// in lockstep and applies directory modes after their content
+    /// was created.
+    pub fn mirror<S, D>(&self, src_dir: &S, dst_dir: &D) -> Result<()>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Path>,
+    {
+        self.copy_tree(src_dir.as_ref(), dst_dir.as_ref(), true, mirror_entry)
+    }
+
+    fn copy_tree(&self, src_dir: &Path, dst_dir: &Path, same_mount: bool,
+                 entry_fn: EntryFn) -> Result<()> {
+        let src_fd = self.chdir(&self.canonicalize(&src_dir)?)?;
+        let src_md = src_fd.metadata()?;
+
+        let dst = self.canonicalize(&dst_dir)?;
+        let dst_name = match dst.file_name() {
+            Some(n) => Path::new(n),
+            None => bail!("bad snapshot destination {:?}", dst_dir),
+        };
+
+        if same_mount {
+            let (src_mnt, dst_mnt) = (src_fd.mount_id()?, dst.dir_fd().mount_id()?);
+
+            ensure!(src_mnt == dst_mnt, ErrorKind::CrossesFilesystem(src_mnt, dst_mnt));
+        }
+
+        self.ensure_writable(dst.dir_fd(), dst_dir)?;
+
+        self.keeping_dir_times(&[dst.dir_fd()], || dst.dir_fd().mkdirat(&dst_name, 0o700))?;
+
+        let res = dst.dir_fd()
+            .openat(&dst_name, libc::O_DIRECTORY | libc::O_RDONLY |
+                    libc::O_CLOEXEC | libc::O_NOFOLLOW)
+            .and_then(|dst_fd| self.copy_tree_fd(src_fd, dst_fd, src_md.mode(), !same_mount,
+                                                 entry_fn));
+
+        // misses recorded while the copy was created might be stale
+        self.clear_negative_cache();
+
+        res
+    }
+
+    /// Copies the content of `src_fd` into `dst_fd`; the sizes of
+    /// regular files are reported as progress when `copies_data`.
+    fn copy_tree_fd(&self, src_fd: Fd, dst_fd: Fd, mode: u32, copies_data: bool,
+                    entry_fn: EntryFn) -> Result<()> {
+        let dst_md = dst_fd.metadata()?;
+        let progress = self.progress();
+
+        // directory modes are applied after their content was created
+        let mut dir_modes = vec![(dst_fd.clone(), mode)];
+        let mut todo = vec![(src_fd, dst_fd)];
+        let mut deadline = None;
+
+        while let Some((src, dst)) = todo.pop() {
+            for e in src.read_dir()? {
+                self.check_interrupt(&mut deadline)?;
+
+                let name = e?.d_name;
+                let md = Metadata::from_stat(src.fstatat(&name, false)?);
+
+                if md.is_same_file(&dst_md) {
+                    continue;
+                }
+
+                let sub = entry_fn(self, &src, &dst, &name, &md)?;
+
+                progress.add(1, if copies_data && md.is_file() { md.len() } else { 0 });
+
+                if let Some((sub_src, sub_dst)) = sub {
+                    dir_modes.push((sub_dst.clone(), md.mode()));
+                    todo.push((sub_src, sub_dst));
+                }
+            }
+        }
+
+        for (fd, mode) in dir_modes.into_iter().rev() {
+            fd.fchmod(mode)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[path="tests/snapshot.inc.rs"]
+mod test;
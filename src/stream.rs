@@ -0,0 +1,112 @@
+//! Asynchronous directory iteration
+//!
+//! `ReadDirStream` implements `futures_core::Stream` so that the
+//! entries of a directory can be consumed by stream combinators
+//! without blocking the executor.
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+
+use crate::dir::{DirEntry, ReadDir};
+use crate::errors::*;
+
+/// number of entries read by the worker at once
+const BATCH_SIZE: usize = 64;
+
+type Batch = Vec<Result<DirEntry>>;
+
+/// `ReadDir` whose blocking `readdir()` calls run in a worker thread
+///
+/// The worker reads entries in batches and stops when one batch is
+/// waiting for the consumer, so slow consumers throttle it.
+pub struct ReadDirStream {
+    rx:		Receiver<Batch>,
+    waker:	Arc<Mutex<Option<Waker>>>,
+    current:	std::vec::IntoIter<Result<DirEntry>>,
+}
+
+fn wake(waker: &Mutex<Option<Waker>>) {
+    if let Some(w) = waker.lock().unwrap().take() {
+        w.wake();
+    }
+}
+
+fn worker(mut dir: ReadDir, tx: SyncSender<Batch>, waker: Arc<Mutex<Option<Waker>>>) {
+    loop {
+        let batch: Batch = dir.by_ref().take(BATCH_SIZE).collect();
+
+        // blocks while the previous batch has not been consumed
+        if batch.is_empty() || tx.send(batch).is_err() {
+            break;
+        }
+
+        wake(&waker);
+    }
+
+    drop(tx);
+    wake(&waker);
+}
+
+impl ReadDirStream {
+    /// Moves `dir` into a new worker thread.
+    pub fn new(dir: ReadDir) -> Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let waker = Arc::new(Mutex::new(None));
+        let worker_waker = waker.clone();
+
+        std::thread::Builder::new()
+            .name("unix-fd-readdir".into())
+            .spawn(move || worker(dir, tx, worker_waker))?;
+
+        Ok(ReadDirStream {
+            rx:		rx,
+            waker:	waker,
+            current:	Vec::new().into_iter(),
+        })
+    }
+}
+
+impl Stream for ReadDirStream {
+    type Item = Result<DirEntry>;
+
+    /// Returns the next entry or `Poll::Pending` when the worker has
+    /// not read it yet; `cx` is woken when more entries are
+    /// available.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut registered = false;
+
+        loop {
+            if let Some(e) = this.current.next() {
+                return Poll::Ready(Some(e));
+            }
+
+            match this.rx.try_recv() {
+                Ok(batch) => this.current = batch.into_iter(),
+                Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+                Err(TryRecvError::Empty) if registered => return Poll::Pending,
+                Err(TryRecvError::Empty) => {
+                    *this.waker.lock().unwrap() = Some(cx.waker().clone());
+                    // check again; the worker might have sent a batch
+                    // before the waker was registered
+                    registered = true;
+                },
+            }
+        }
+    }
+}
+
+impl ReadDir {
+    /// Converts the iterator into a `ReadDirStream`
+    pub fn into_stream(self) -> Result<ReadDirStream> {
+        ReadDirStream::new(self)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/stream.inc.rs"]
+mod test;
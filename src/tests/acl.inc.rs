@@ -0,0 +1,62 @@
+use crate::acl::*;
+
+#[test]
+fn test_acl_encoding() {
+    let acl = Acl {
+        entries: vec![
+            AclEntry { tag: AclTag::Other,	perm: ACL_READ },
+            AclEntry { tag: AclTag::User(1000),	perm: ACL_READ | ACL_WRITE },
+            AclEntry { tag: AclTag::UserObj,	perm: ACL_READ | ACL_WRITE },
+            AclEntry { tag: AclTag::GroupObj,	perm: ACL_READ },
+            AclEntry { tag: AclTag::Mask,	perm: ACL_READ | ACL_WRITE },
+        ],
+    };
+
+    let data = acl.to_xattr();
+
+    assert_eq!(data.len(), 4 + 5 * 8);
+    assert_eq!(&data[0..4], &[2, 0, 0, 0]);
+    // USER_OBJ comes first
+    assert_eq!(&data[4..12], &[1, 0, 6, 0, 0xff, 0xff, 0xff, 0xff]);
+
+    let decoded = Acl::from_xattr(&data).unwrap();
+
+    assert_eq!(decoded.entries.len(), 5);
+    assert_eq!(decoded.entries[1],
+               AclEntry { tag: AclTag::User(1000), perm: ACL_READ | ACL_WRITE });
+    assert_eq!(decoded.to_xattr(), data);
+
+    assert!(Acl::from_xattr(&data[..7]).is_err());
+}
+
+#[test]
+fn test_acl_chroot() {
+    use std::path::Path;
+
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::create_dir(tmpdir.path().join("etc")).unwrap();
+    std::fs::write(tmpdir.path().join("etc/f"), "").unwrap();
+    std::os::unix::fs::symlink("/etc/f", tmpdir.path().join("l")).unwrap();
+
+    let chroot = crate::chroot::Chroot::new(&tmpdir.path());
+    let acl = Acl {
+        entries: vec![
+            AclEntry { tag: AclTag::UserObj,	perm: ACL_READ | ACL_WRITE },
+            AclEntry { tag: AclTag::User(4242),	perm: ACL_READ },
+            AclEntry { tag: AclTag::GroupObj,	perm: ACL_READ },
+            AclEntry { tag: AclTag::Mask,	perm: ACL_READ },
+            AclEntry { tag: AclTag::Other,	perm: 0 },
+        ],
+    };
+
+    match chroot.set_acl(&Path::new("/l"), &acl) {
+        // filesystem without ACL support
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => return,
+        r => r.unwrap(),
+    }
+
+    let res = chroot.get_acl(&Path::new("/etc/f")).unwrap().unwrap();
+
+    assert_eq!(res.to_xattr(), acl.to_xattr());
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::os::unix::fs::symlink;
+
+use crate::chroot::Chroot;
+use crate::archive::ArchiveOptions;
+
+#[derive(Debug, Default)]
+struct TarEntry {
+    name:	String,
+    typeflag:	u8,
+    mode:	u32,
+    link:	String,
+    data:	Vec<u8>,
+    pax:	HashMap<String, Vec<u8>>,
+}
+
+fn field(block: &[u8]) -> String {
+    let len = block.iter().position(|c| *c == 0).unwrap_or(block.len());
+
+    String::from_utf8(block[..len].to_vec()).unwrap()
+}
+
+fn octal(block: &[u8]) -> u64 {
+    u64::from_str_radix(field(block).trim(), 8).unwrap()
+}
+
+/// Minimal reader for the pax archives of `Chroot::archive()`
+fn parse_tar(mut data: &[u8]) -> Vec<TarEntry> {
+    let mut res = Vec::new();
+    let mut pax = HashMap::new();
+
+    loop {
+        let (block, rest) = data.split_at(512);
+
+        if block.iter().all(|c| *c == 0) {
+            assert!(rest.len() >= 512 && rest[..512].iter().all(|c| *c == 0));
+            break;
+        }
+
+        let sum: u64 = block.iter().enumerate()
+            .map(|(i, c)| if (148..156).contains(&i) { b' ' as u64 } else { *c as u64 })
+            .sum();
+
+        assert_eq!(octal(&block[148..155]), sum);
+        assert_eq!(&block[257..263], b"ustar\0");
+
+        let size = octal(&block[124..136]) as usize;
+        let content = &rest[..size];
+
+        data = &rest[(size + 511) / 512 * 512..];
+
+        if block[156] == b'x' {
+            let mut records = content;
+
+            while !records.is_empty() {
+                let sp = records.iter().position(|c| *c == b' ').unwrap();
+                let len: usize = std::str::from_utf8(&records[..sp]).unwrap().parse().unwrap();
+                let rec = &records[sp + 1..len - 1];
+                let eq = rec.iter().position(|c| *c == b'=').unwrap();
+
+                assert_eq!(records[len - 1], b'\n');
+                pax.insert(String::from_utf8(rec[..eq].to_vec()).unwrap(), rec[eq + 1..].to_vec());
+                records = &records[len..];
+            }
+
+            continue;
+        }
+
+        let pax = std::mem::take(&mut pax);
+        let string = |key: &str, f: &[u8]| pax.get(key)
+            .map(|v| String::from_utf8(v.clone()).unwrap())
+            .unwrap_or_else(|| field(f));
+
+        res.push(TarEntry {
+            name:	string("path", &block[0..100]),
+            typeflag:	block[156],
+            mode:	octal(&block[100..108]) as u32,
+            link:	string("linkpath", &block[157..257]),
+            data:	content.to_vec(),
+            pax:	pax,
+        });
+    }
+
+    res
+}
+
+#[test]
+fn test_archive() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+    let long = "x".repeat(120);
+
+    std::fs::create_dir_all(root.join("src/d")).unwrap();
+    std::fs::create_dir_all(root.join("outside")).unwrap();
+    std::fs::write(root.join("src/f"), vec![7u8; 1000]).unwrap();
+    std::fs::hard_link(root.join("src/f"), root.join("src/d/hard")).unwrap();
+    std::fs::write(root.join("src/d").join(&long), b"long").unwrap();
+    std::fs::write(root.join("outside/secret"), b"secret").unwrap();
+    symlink("/outside", root.join("src/lout")).unwrap();
+    assert_eq!(unsafe {
+        libc::mkfifo(crate::LibcString::as_libc(root.join("src/fifo").as_path()).unwrap().0,
+                     0o600)
+    }, 0);
+
+    let chroot = Chroot::new(&root);
+    let has_xattrs = chroot.open(&"/src/f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap()
+        .setxattr("user.test", b"v\0w", 0).is_ok();
+
+    let mut out = Vec::new();
+
+    chroot.archive(&"/src", &mut out, &ArchiveOptions { xattrs: true }).unwrap();
+    assert_eq!(out.len() % 512, 0);
+
+    let entries = parse_tar(&out);
+    let entry = |n: &str| entries.iter().find(|e| e.name == n)
+        .unwrap_or_else(|| panic!("missing {} in {:?}", n, entries));
+
+    assert_eq!(entries[0].name, "./");
+    assert_eq!(entries.len(), 7);
+
+    assert_eq!(entry("./d/").typeflag, b'5');
+    assert_eq!(entry("./fifo").typeflag, b'6');
+    assert_eq!(entry("./fifo").mode, 0o600);
+    assert_eq!(entry(&format!("./d/{}", long)).data, b"long");
+
+    // symlinks are stored but not followed
+    assert_eq!(entry("./lout").typeflag, b'2');
+    assert_eq!(entry("./lout").link, "/outside");
+    assert!(!entries.iter().any(|e| e.name.contains("secret")));
+
+    // the first visited name carries the content
+    let pos = |n: &str| entries.iter().position(|e| e.name == n);
+    let (first, second) = if pos("./f") < pos("./d/hard") {
+        ("./f", "./d/hard")
+    } else {
+        ("./d/hard", "./f")
+    };
+
+    assert_eq!(entry(first).typeflag, b'0');
+    assert_eq!(entry(first).data, vec![7u8; 1000]);
+    assert_eq!(entry(second).typeflag, b'1');
+    assert_eq!(entry(second).link, first);
+    assert!(entry(second).data.is_empty());
+
+    if has_xattrs {
+        assert_eq!(entry(first).pax.get("SCHILY.xattr.user.test").map(Vec::as_slice),
+                   Some(&b"v\0w"[..]));
+    }
+
+    let mut plain = Vec::new();
+
+    chroot.archive(&"/src/d", &mut plain, &ArchiveOptions::default()).unwrap();
+
+    let entries = parse_tar(&plain);
+
+    assert_eq!(entries.len(), 3);
+    assert!(entries.iter().all(|e| e.pax.keys().all(|k| !k.starts_with("SCHILY."))));
+}
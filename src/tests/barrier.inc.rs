@@ -0,0 +1,24 @@
+use crate::barrier::Barrier;
+use crate::fd::Fd;
+
+#[test]
+fn test_barrier() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = Fd::open(&tmpdir.path(), libc::O_RDONLY | libc::O_DIRECTORY |
+                       libc::O_CLOEXEC).unwrap();
+    let file = dir.createat(&"f", libc::O_RDWR | libc::O_CLOEXEC, 0o600).unwrap();
+
+    let rc = unsafe { libc::pwrite(file.fd, b"data".as_ptr() as *const _, 4, 0) };
+    assert_eq!(rc, 4);
+
+    file.sync_data_range(0, 0).unwrap();
+    file.sync_data_range(2, 2).unwrap();
+    assert!(file.sync_data_range(u64::MAX, 1).is_err());
+
+    Barrier::new().file(&file).dir(&dir).commit().unwrap();
+
+    // O_PATH descriptors can not be flushed
+    let path_fd = dir.open_path_at(&"f").unwrap();
+
+    assert!(Barrier::new().dir(&path_fd).commit().is_err());
+}
@@ -1,3 +1,6 @@
+#![allow(clippy::single_component_path_imports, clippy::identity_op,
+         clippy::expect_fun_call, clippy::single_match, clippy::needless_borrow)]
+
 use super::testdata::*;
 
 use std;
@@ -10,6 +13,20 @@ use libc;
 use crate::test::FsItem;
 use crate::chroot::Chroot;
 
+/// Creates the TEST_FS_* fixtures in a temporary directory and
+/// returns a `Chroot` on the inner one.
+pub(crate) fn create_test_chroot() -> (tempdir::TempDir, Chroot) {
+    let tmpdir = crate::test::create_tmpdir();
+    let chroot_path = &tmpdir.path().join("chroot");
+
+    crate::test::create_fs(tmpdir.path(), &TEST_FS_OUTSIDE);
+    crate::test::create_fs(chroot_path, &TEST_FS_INSIDE);
+
+    let chroot = Chroot::new(chroot_path);
+
+    (tmpdir, chroot)
+}
+
 struct ChrootedChroot {
     dir: std::path::PathBuf,
     chroot: Chroot,
@@ -231,7 +248,7 @@ fn test0() {
     use crate::chroot::Chroot;
     use env_logger;
 
-    let _ = env_logger::init();
+    let _ = env_logger::try_init();
 
     let tmpdir = crate::test::create_tmpdir();
     let chroot_path = &tmpdir.path().join("chroot");
@@ -265,3 +282,1214 @@ fn test0() {
     fd = root.chroot.chdirat(&fd, &Path::new("./d0")).unwrap();
     assert!(fd.fstat().is_ok());
 }
+
+#[test]
+fn test_search_only() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    let _ = env_logger::try_init();
+
+    let (tmpdir, chroot) = create_test_chroot();
+
+    let search_dir = tmpdir.path().join("chroot/tmp/d0");
+    std::fs::set_permissions(&search_dir,
+                             std::fs::Permissions::from_mode(0o111)).unwrap();
+
+    assert!(chroot.fstatat(&chroot.root_fd().unwrap(),
+                           &Path::new("/tmp/d0/d1/f0")).is_ok());
+    assert!(chroot.is_lnkat(&chroot.root_fd().unwrap(),
+                            &Path::new("/tmp/ld0/d1/lf2")));
+    assert!(chroot.existsat(&chroot.root_fd().unwrap(),
+                            &Path::new("/tmp/d0/d1/lf2")));
+    assert!(!chroot.existsat(&chroot.root_fd().unwrap(),
+                             &Path::new("/tmp/d0/d1/non-existing")));
+    assert_eq!(chroot.readlinkat(&chroot.root_fd().unwrap(),
+                                 &Path::new("/tmp/ld0/d1/lf2")).unwrap(),
+               OsString::from("/tmp/d0/d1/f0"));
+    assert!(chroot.open(&Path::new("/tmp/ld0/d1/f0"),
+                        libc::O_RDONLY | libc::O_CLOEXEC).is_ok());
+    assert!(chroot.chdir(&Path::new("/tmp/d0/d1")).unwrap()
+            .read_dir().is_ok());
+
+    let path_fd = chroot.root_fd().unwrap().open_path_at(&"tmp/ld0").unwrap();
+    assert!(path_fd.is_symlink());
+
+    std::fs::set_permissions(&search_dir,
+                             std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_metadata_many() {
+    let _ = env_logger::try_init();
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let paths = ["/tmp/ld0/d1/f0", "/etc/passwd", "/tmp/d0/d1/f0",
+                 "/tmp/d0/non-existing", "/tmp/ld6/etc/passwd", "/"];
+
+    let res = chroot.metadata_many(&paths).unwrap();
+
+    assert_eq!(res.len(), paths.len());
+
+    for (p, st) in paths.iter().zip(res.iter()) {
+        let exp = chroot.fstatat(&chroot.root_fd().unwrap(), p);
+
+        match (st, exp) {
+            (Ok(a), Ok(b)) => assert!(crate::fd::same_file_by_stat(a, &b)),
+            (Err(_), Err(_)) => {},
+            _ => panic!("metadata_many() mismatch for {:?}", p),
+        }
+    }
+
+    assert!(res[3].is_err());
+}
+
+#[test]
+fn test_exists_many() {
+    use std::time::Duration;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let paths = ["/tmp/ld0/d1/f0", "/etc/lf0", "/etc/group", "/tmp/d0/d1/f0/x",
+                 "/tmp/d0/missing", "/tmp/ld6/etc/passwd", "/"];
+
+    let res: Vec<bool> = chroot.exists_many(&paths).unwrap()
+        .into_iter().map(Result::unwrap).collect();
+
+    assert_eq!(res, [true, true, false, false, false, true, true]);
+
+    for (p, r) in paths.iter().zip(res) {
+        assert_eq!(chroot.existsat(&crate::fd::Fd::cwd(), p), r, "{:?}", p);
+    }
+
+    // following the symlink in /etc/lf0 creates no audit event
+    let events = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let hook_events = events.clone();
+    let audited = Chroot::new(&tmpdir.path().join("chroot"))
+        .with_audit_hook(Some(std::sync::Arc::new(move |_: &crate::chroot::AuditEvent| {
+            hook_events.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        })));
+
+    assert!(audited.exists_many(&["/etc/lf0"]).unwrap()[0].as_ref().unwrap());
+    assert_eq!(events.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+    // misses are recorded in the negative cache
+    let chroot = chroot.with_negative_cache(Some(Duration::from_secs(3600)));
+
+    assert!(!chroot.exists_many(&["/tmp/d0/missing"]).unwrap()[0].as_ref().unwrap());
+    std::fs::write(tmpdir.path().join("chroot/tmp/d0/missing"), b"").unwrap();
+    assert!(!chroot.exists_many(&["/tmp/d0/missing"]).unwrap()[0].as_ref().unwrap());
+    assert!(!chroot.existsat(&crate::fd::Fd::cwd(), &"/tmp/d0/missing"));
+    assert_eq!(chroot.stats().negative_hits, 2);
+
+    chroot.clear_negative_cache();
+    assert!(chroot.exists_many(&["/tmp/d0/missing"]).unwrap()[0].as_ref().unwrap());
+}
+
+#[test]
+fn test_open_many() {
+    let (_tmpdir, chroot) = create_test_chroot();
+    let rd = libc::O_RDONLY | libc::O_CLOEXEC;
+    let requests = [("/tmp/ld0/d1/f0", rd), ("/tmp/d0/d1/lf2", rd),
+                    ("/tmp/d0/non-existing", rd), ("/", rd | libc::O_DIRECTORY),
+                    ("/tmp/d0/d1/new", libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC)];
+
+    let res = chroot.open_many(&requests).unwrap();
+
+    assert_eq!(res.len(), requests.len());
+
+    for ((p, flags), fd) in requests.iter().zip(res.iter()) {
+        let exp = chroot.open(p, *flags);
+
+        match (fd, exp) {
+            (Ok(a), Ok(b)) =>
+                assert!(crate::fd::same_file_by_stat(&a.fstat().unwrap(),
+                                                     &b.fstat().unwrap())),
+            (Err(_), Err(_)) => {},
+            _ => panic!("open_many() mismatch for {:?}", p),
+        }
+    }
+
+    assert!(res[2].is_err());
+    assert!(res[4].is_ok());
+}
+
+#[test]
+fn test_swap() {
+    use std::path::Path;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let root_fd = chroot.root_fd().unwrap();
+    let d1 = chroot.fstatat(&root_fd, &Path::new("/tmp/d0/d1")).unwrap();
+    let d2 = chroot.fstatat(&root_fd, &Path::new("/tmp/d0/d2")).unwrap();
+
+    chroot.swap(&Path::new("/tmp/ld0/d1"), &Path::new("/tmp/d0/d2")).unwrap();
+
+    assert!(crate::fd::same_file_by_stat(
+        &chroot.fstatat(&root_fd, &Path::new("/tmp/d0/d1")).unwrap(), &d2));
+    assert!(crate::fd::same_file_by_stat(
+        &chroot.fstatat(&root_fd, &Path::new("/tmp/d0/d2")).unwrap(), &d1));
+
+    assert!(chroot.swap(&Path::new("/tmp/d0/d1"), &Path::new("/")).is_err());
+}
+
+#[test]
+fn test_write_atomic() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::Path;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let chroot = chroot.with_sync_dirs(true);
+    let is_root = unsafe { libc::geteuid() } == 0;
+    let passwd = tmpdir.path().join("chroot/etc/passwd");
+
+    std::fs::set_permissions(&passwd, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+    if is_root {
+        std::os::unix::fs::chown(&passwd, Some(1234), Some(1235)).unwrap();
+    }
+
+    chroot.write_atomic(&Path::new("/tmp/ld0/new"), b"new-data").unwrap();
+    chroot.write_atomic(&Path::new("/etc/passwd"), b"passwd-data").unwrap();
+
+    let meta = std::fs::metadata(&passwd).unwrap();
+
+    assert_eq!(meta.permissions().mode() & 0o7777, 0o640);
+
+    if is_root {
+        assert_eq!((meta.uid(), meta.gid()), (1234, 1235));
+    }
+
+    assert_eq!(std::fs::read(tmpdir.path().join("chroot/tmp/d0/new")).unwrap(),
+               b"new-data");
+    assert_eq!(std::fs::read(tmpdir.path().join("chroot/etc/passwd")).unwrap(),
+               b"passwd-data");
+    assert_eq!(std::fs::read(tmpdir.path().join("etc/passwd")).unwrap(),
+               b"outer-etc_passwd");
+
+    assert!(chroot.write_atomic(&Path::new("/non-existing/f0"), b"").is_err());
+}
+
+#[test]
+fn test_truncate() {
+    let (tmpdir, chroot) = create_test_chroot();
+    let file = tmpdir.path().join("chroot/tmp/d0/d1/f0");
+
+    chroot.truncate(&"/tmp/ld0/d1/lf2", 5).unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), b"inner");
+
+    chroot.truncate(&"/tmp/d0/d1/f0", 8).unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), b"inner\0\0\0");
+
+    // the file outside of the chroot is not touched
+    assert_eq!(std::fs::read(tmpdir.path().join("tmp/d0/d1/f0")).unwrap(),
+               b"outer-tmp_d0_d1_f0");
+
+    assert!(chroot.truncate(&"/tmp/d0", 0).is_err());
+    assert!(chroot.truncate(&"/tmp/d0/missing", 0).is_err());
+    assert!(!tmpdir.path().join("chroot/tmp/d0/missing").exists());
+}
+
+#[test]
+fn test_truncate_front() {
+    use std::path::Path;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let log = tmpdir.path().join("chroot/tmp/d0/log");
+    let data: Vec<u8> = (0..3 * 4096).map(|i| (i % 251 + 1) as u8).collect();
+
+    std::fs::write(&log, &data).unwrap();
+
+    match chroot.truncate_front(&Path::new("/tmp/ld0/log"), 4096 + 10) {
+        // filesystem without hole punching
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => return,
+        r => r.unwrap(),
+    }
+
+    let content = std::fs::read(&log).unwrap();
+
+    assert_eq!(content.len(), data.len());
+    assert!(content[..4096 + 10].iter().all(|c| *c == 0));
+    assert_eq!(content[4096 + 10..], data[4096 + 10..]);
+
+    chroot.truncate_front(&Path::new("/tmp/ld0/log"), 0).unwrap();
+    assert_eq!(std::fs::read(&log).unwrap(), content);
+
+    assert!(chroot.truncate_front(&Path::new("/tmp/d0"), 1).is_err());
+}
+
+#[test]
+fn test_create_dir_all_with() {
+    use std::path::{Path, PathBuf};
+    use crate::chroot::DirAttrs;
+
+    // needs CAP_CHOWN
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not running as root; skipping test");
+        return;
+    }
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let mut seen = Vec::new();
+
+    let fd = chroot.create_dir_all_with(&"/tmp/ld0/x/y", |p| {
+        seen.push(p.to_path_buf());
+
+        match p.file_name().unwrap().to_str().unwrap() {
+            "x" => DirAttrs { mode: 0o2777, uid: Some(1234), gid: Some(5678) },
+            _ => DirAttrs { mode: 0o700, uid: None, gid: Some(5678) },
+        }
+    }).unwrap();
+
+    // "ld0" exists already
+    assert_eq!(seen, [PathBuf::from("/tmp/ld0/x"), PathBuf::from("/tmp/ld0/x/y")]);
+    assert!(fd.read_dir().is_ok());
+
+    let base = tmpdir.path().join("chroot/tmp/d0");
+    let md = crate::fd::Fd::cwd().fstatat(&base.join("x"), false).unwrap();
+    assert_eq!(md.st_mode & 0o7777, 0o2777);
+    assert_eq!((md.st_uid, md.st_gid), (1234, 5678));
+
+    let md = crate::fd::Fd::cwd().fstatat(&base.join("x/y"), false).unwrap();
+    assert_eq!(md.st_mode & 0o7777, 0o700);
+    assert_eq!(md.st_gid, 5678);
+
+    let base_md = crate::fd::Fd::cwd().fstatat(&base, false).unwrap();
+
+    chroot.create_dir_all_with(&Path::new("/tmp/d0/x/z"), |_| {
+        DirAttrs { mode: 0o711, uid: None, gid: None }
+    }).unwrap();
+
+    // existing directories are not changed
+    let md = crate::fd::Fd::cwd().fstatat(&base.join("x"), false).unwrap();
+    assert_eq!(md.st_mode & 0o7777, 0o2777);
+    assert_eq!(crate::fd::Fd::cwd().fstatat(&base, false).unwrap().st_mode,
+               base_md.st_mode);
+}
+
+#[test]
+fn test_create_with() {
+    use std::io::Write;
+    use std::path::Path;
+    use crate::chroot::CreateOptions;
+
+    let (tmpdir, chroot) = create_test_chroot();
+
+    let options = CreateOptions {
+        create_parents: true,
+        .. CreateOptions::default()
+    };
+
+    let fd = chroot.create_with(&Path::new("/tmp/ld6/tmp/ld0/new/sub/f"), &options)
+        .unwrap();
+
+    fd.into_rawfd().unwrap().into_file().unwrap().write_all(b"data").unwrap();
+
+    assert_eq!(std::fs::read(tmpdir.path().join("chroot/tmp/d0/new/sub/f")).unwrap(),
+               b"data");
+
+    assert!(chroot.create_with(&Path::new("/tmp/other/f"),
+                               &CreateOptions::default()).is_err());
+    assert!(chroot.create_dir_all(&Path::new("/tmp/lD0/sub"), 0o755).is_err());
+    assert!(chroot.create_dir_all(&Path::new("/tmp/d0/d1/ld0/a/b"), 0o755)
+            .unwrap().is_dir());
+    assert!(tmpdir.path().join("chroot/tmp/d0/d1/a/b").is_dir());
+}
+
+#[test]
+fn test_create_modes() {
+    use std::os::unix::fs::PermissionsExt;
+    use crate::chroot::CreateOptions;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let chroot = chroot
+        .with_create_modes(0o664, 0o775)
+        .with_umask(Some(0o027));
+
+    let mode = |p: &str| std::fs::symlink_metadata(tmpdir.path().join(p))
+        .unwrap().permissions().mode() & 0o7777;
+
+    let options = CreateOptions {
+        create_parents: true,
+        .. chroot.create_options()
+    };
+
+    chroot.create_with(&"/tmp/d0/new/f", &options).unwrap();
+    assert_eq!(mode("chroot/tmp/d0/new"), 0o750);
+    assert_eq!(mode("chroot/tmp/d0/new/f"), 0o640);
+
+    let options = CreateOptions {
+        mode: 0o666,
+        .. CreateOptions::default()
+    };
+
+    chroot.create_with(&"/tmp/d0/g", &options).unwrap();
+    assert_eq!(mode("chroot/tmp/d0/g"), 0o640);
+
+    chroot.write_atomic(&"/tmp/d0/atomic", b"data").unwrap();
+    assert_eq!(mode("chroot/tmp/d0/atomic"), 0o640);
+
+    chroot.open(&"/tmp/d0/opened", libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC)
+        .unwrap();
+    assert_eq!(mode("chroot/tmp/d0/opened"), 0o640);
+
+    // existing files are not modified
+    std::fs::set_permissions(tmpdir.path().join("chroot/tmp/d0/opened"),
+                             std::fs::Permissions::from_mode(0o606)).unwrap();
+    chroot.open(&"/tmp/d0/opened", libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC)
+        .unwrap();
+    assert_eq!(mode("chroot/tmp/d0/opened"), 0o606);
+
+    assert!(chroot.open(&"/tmp/d0/opened", libc::O_WRONLY | libc::O_CREAT |
+                        libc::O_EXCL | libc::O_CLOEXEC).is_err());
+}
+
+#[test]
+fn test_open_relative_link() {
+    use std::io::Read;
+    use std::path::Path;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let mut data = String::new();
+
+    chroot.open(&Path::new("/tmp/d0/d1/lf0"), libc::O_RDONLY | libc::O_CLOEXEC)
+        .unwrap()
+        .into_rawfd().unwrap()
+        .into_file().unwrap()
+        .read_to_string(&mut data).unwrap();
+
+    assert_eq!(data, "inner-tmp_d0_d1_f0");
+}
+
+#[test]
+fn test_final_relative_link() {
+    use std::os::unix::fs::symlink;
+    use crate::chroot::CreateOptions;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::write(root.join("f"), b"top").unwrap();
+    std::fs::write(root.join("a/f"), b"a").unwrap();
+    symlink("../f", root.join("a/b/l")).unwrap();
+    symlink("l", root.join("a/b/l2")).unwrap();
+    symlink("new", root.join("a/b/dangling")).unwrap();
+
+    let chroot = Chroot::new(&root);
+
+    // targets are relative to the directory of the link and not to
+    // the one where the lookup started
+    assert_eq!(chroot.read(&"/a/b/l").unwrap(), b"a");
+    assert_eq!(chroot.read(&"/a/b/l2").unwrap(), b"a");
+
+    let a = chroot.chdir(&"/a").unwrap();
+    let fd = chroot.openat(&a, &"b/l", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    let st = crate::fd::Fd::cwd().fstatat(&root.join("a/f"), false).unwrap();
+    assert!(crate::fd::same_file_by_stat(&fd.fstat().unwrap(), &st));
+
+    chroot.create_with(&"/a/b/dangling", &CreateOptions::default()).unwrap();
+    assert!(root.join("a/b/new").exists());
+    assert!(!root.join("new").exists());
+}
+
+#[test]
+fn test_buf_io() {
+    use std::io::{BufRead, Write};
+    use std::path::Path;
+    use crate::chroot::CreateOptions;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+
+    let options = CreateOptions {
+        flags: libc::O_WRONLY | libc::O_CLOEXEC | libc::O_TRUNC,
+        .. CreateOptions::default()
+    };
+
+    let mut w = chroot.buf_writer(&Path::new("/tmp/lf3"), &options).unwrap();
+    w.write_all(b"line0\nline1\n").unwrap();
+    drop(w);
+
+    let lines: Vec<String> = chroot.buf_reader(&Path::new("/etc/passwd")).unwrap()
+        .lines()
+        .map(|l| l.unwrap())
+        .collect();
+
+    assert_eq!(lines, ["line0", "line1"]);
+}
+
+#[cfg(feature = "users")]
+#[test]
+fn test_chown_by_name() {
+    use std::path::Path;
+    use crate::users::NameLookup;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+
+    chroot.write_atomic(&Path::new("/etc/passwd"),
+                        b"root:x:0:0::/root:/bin/sh\ntest:x:4242:4343::/:/bin/false\n")
+        .unwrap();
+    chroot.write_atomic(&Path::new("/etc/group"),
+                        b"root:x:0:\ntest:x:4343:\nother:x:4444:test\n")
+        .unwrap();
+
+    let root_fd = chroot.root_fd().unwrap();
+
+    assert!(chroot.chown_by_name(&Path::new("/tmp/lf2"), Some("unknown"), None,
+                                 NameLookup::Chroot).is_err());
+
+    // needs CAP_CHOWN
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+
+    chroot.chown_by_name(&Path::new("/tmp/lf2"), Some("test"), Some("other"),
+                         NameLookup::Chroot).unwrap();
+
+    let st = chroot.fstatat(&root_fd, &Path::new("/tmp/passwd")).unwrap();
+
+    assert_eq!((st.st_uid, st.st_gid), (4242, 4444));
+}
+
+#[test]
+fn test_audit() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use crate::audit::{AuditFinding, AuditIssue};
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let dir = tmpdir.path().join("chroot");
+
+    std::fs::set_permissions(dir.join("tmp/d0/d2"),
+                             std::fs::Permissions::from_mode(0o777)).unwrap();
+    std::fs::set_permissions(dir.join("tmp/d0"),
+                             std::fs::Permissions::from_mode(0o1777)).unwrap();
+    std::fs::set_permissions(dir.join("f0"),
+                             std::fs::Permissions::from_mode(0o4755)).unwrap();
+
+    let res = chroot.audit().unwrap();
+    let finding = |p: &str, issue| AuditFinding {
+        path: PathBuf::from(p),
+        issue: issue,
+    };
+
+    assert!(res.contains(&finding("/tmp/d0/d2", AuditIssue::WorldWritableDir)));
+    assert!(res.contains(&finding("/f0", AuditIssue::SetUid)));
+    assert!(res.contains(&finding("/etc/lf0", AuditIssue::EscapingSymlink(
+        OsString::from("../../etc/shadow")))));
+    assert!(res.contains(&finding("/tmp/ld6", AuditIssue::EscapingSymlink(
+        OsString::from("/")))));
+
+    assert!(!res.iter().any(|f| f.path == Path::new("/tmp/d0")));
+    assert!(!res.iter().any(|f| f.path == Path::new("/tmp/ld3")));
+}
+
+#[test]
+fn test_read_dir_fd() {
+    use std::path::Path;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let (fd, entries) = chroot.read_dir_fd(&Path::new("/tmp/ld0/d1")).unwrap();
+
+    let mut names: Vec<OsString> = entries
+        .map(|e| e.unwrap().d_name)
+        .filter(|n| fd.is_regat(n))
+        .collect();
+
+    names.sort();
+
+    assert_eq!(names, [OsString::from("f0")]);
+}
+
+#[test]
+fn test_stats() {
+    use std::path::Path;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    assert_eq!(chroot.stats(), Default::default());
+
+    // ld4 -> ../.././../../tmp/d0
+    chroot.open(&Path::new("/tmp/ld4/d1/f0"), flags).unwrap();
+    // lf2 -> /tmp/d0/d1/f0
+    chroot.open(&Path::new("/tmp/d0/d1/lf2"), flags).unwrap();
+    chroot.open(&Path::new("/tmp/lD2"), flags).unwrap_err();
+
+    let stats = chroot.stats();
+
+    assert_eq!(stats.root_clamps, 3);
+    assert_eq!(stats.absolute_links, 1);
+    assert_eq!(stats.loop_limits, 0);
+    assert_eq!(stats.link_cycles, 1);
+}
+
+#[test]
+fn test_symlink_limit() {
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    std::fs::create_dir(tmpdir.path().join("d")).unwrap();
+    std::fs::write(tmpdir.path().join("d/f"), b"").unwrap();
+
+    // acyclic chains of 300 links to a file and to a directory
+    for i in 0..300 {
+        symlink(format!("f{}", i + 1), tmpdir.path().join(format!("f{}", i))).unwrap();
+        symlink(format!("d{}", i + 1), tmpdir.path().join(format!("d{}", i))).unwrap();
+    }
+
+    symlink("d/f", tmpdir.path().join("f300")).unwrap();
+    symlink("/d", tmpdir.path().join("d300")).unwrap();
+
+    symlink("c1", tmpdir.path().join("c0")).unwrap();
+    symlink("../c0", tmpdir.path().join("d/c1")).unwrap();
+    symlink("d/c1", tmpdir.path().join("c1")).unwrap();
+
+    let chroot = Chroot::new(&tmpdir.path());
+
+    assert!(chroot.open(&Path::new("/f0"), flags).is_err());
+    assert!(chroot.open(&Path::new("/d0/f"), flags).is_err());
+    assert_eq!(chroot.stats().loop_limits, 2);
+
+    let chroot = Chroot::new(&tmpdir.path()).with_symlink_limit(400);
+
+    chroot.open(&Path::new("/f0"), flags).unwrap();
+    chroot.open(&Path::new("/d0/f"), flags).unwrap();
+
+    let err = chroot.open(&Path::new("/c0"), flags).unwrap_err();
+    assert!(err.to_string().contains("cycle"));
+
+    let err = chroot.open(&Path::new("/c0/f"), flags).unwrap_err();
+    assert!(err.to_string().contains("cycle"));
+
+    let stats = chroot.stats();
+
+    assert_eq!(stats.loop_limits, 0);
+    assert_eq!(stats.link_cycles, 2);
+}
+
+#[test]
+fn test_component_budget() {
+    use std::os::unix::fs::symlink;
+    use crate::errors::{Error, ErrorKind};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    std::fs::create_dir_all(tmpdir.path().join("a/b")).unwrap();
+    std::fs::write(tmpdir.path().join("a/b/f"), b"").unwrap();
+
+    // every link expands to four copies of the previous one; l10
+    // resolves to the top directory after 4^10 components
+    symlink(".", tmpdir.path().join("l0")).unwrap();
+
+    for i in 1..=10 {
+        let prev = format!("l{}", i - 1);
+
+        symlink([prev.as_str(); 4].join("/"), tmpdir.path().join(format!("l{}", i)))
+            .unwrap();
+    }
+
+    let chroot = Chroot::new(&tmpdir.path());
+
+    match chroot.chdir(&"/l10/a") {
+        Err(Error(ErrorKind::BudgetExceeded("components", 65536), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    assert!(chroot.chdir(&"/l5/a").unwrap().is_dir());
+    assert_eq!(chroot.stats().loop_limits, 1);
+
+    let chroot = Chroot::new(&tmpdir.path()).with_component_budget(3);
+
+    // "/", "a" and "b"; the final component is not counted
+    chroot.open(&"/a/b/f", flags).unwrap();
+
+    match chroot.open(&"/a/b/../b/f", flags) {
+        Err(Error(ErrorKind::BudgetExceeded("components", 3), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // the budget applies to every path of a batch separately
+    let res = chroot.metadata_many(&["/a/b/f", "/a/f", "/a/b"]).unwrap();
+
+    assert!(res[0].is_ok());
+    assert_eq!(res[1].as_ref().unwrap_err().errno(), Some(libc::ENOENT));
+    assert!(res[2].is_ok());
+}
+
+#[test]
+fn test_full_path_removed() {
+    use crate::errors::{Error, ErrorKind};
+
+    let (tmpdir, chroot) = create_test_chroot();
+
+    std::fs::create_dir(tmpdir.path().join("chroot/tmp/d0/x")).unwrap();
+
+    let fd = chroot.chdir(&"/tmp/d0/x").unwrap();
+
+    std::fs::remove_dir(tmpdir.path().join("chroot/tmp/d0/x")).unwrap();
+
+    match chroot.full_path(&fd, Some(&"f")) {
+        Err(Error(ErrorKind::PartialPath(suffix, _), _)) =>
+            assert_eq!(suffix, std::path::PathBuf::from("f")),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_link_chain() {
+    use std::path::{Path, PathBuf};
+    use crate::chroot::LinkHop;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+
+    let hop = |l: &str, t: &str| LinkHop {
+        link:	PathBuf::from(l),
+        target:	PathBuf::from(t),
+    };
+
+    assert_eq!(chroot.link_chain(&Path::new("/tmp/ld0/d1/lf2")).unwrap(),
+               vec![hop("/tmp/ld0", "d0"),
+                    hop("/tmp/d0/d1/lf2", "/tmp/d0/d1/f0")]);
+    assert_eq!(chroot.link_chain(&Path::new("/tmp/d0/d1/f0")).unwrap(), vec![]);
+    assert!(chroot.link_chain(&Path::new("/tmp/lD2")).is_err());
+}
+
+#[test]
+fn test_interrupt() {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use crate::errors::ErrorKind;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let flag = Arc::new(AtomicBool::new(false));
+    let chroot = chroot.with_cancel_flag(Some(flag.clone()));
+    let path = Path::new("/tmp/ld0/d1/lf2");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    assert!(chroot.open(&path, flags).is_ok());
+
+    flag.store(true, Ordering::Relaxed);
+
+    match chroot.open(&path, flags).map_err(|e| e.0) {
+        Err(ErrorKind::Cancelled) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    match chroot.audit().map_err(|e| e.0) {
+        Err(ErrorKind::Cancelled) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let chroot = chroot
+        .with_cancel_flag(None)
+        .with_timeout(Some(Duration::from_secs(0)));
+
+    match chroot.open(&path, flags).map_err(|e| e.0) {
+        Err(ErrorKind::TimedOut) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let chroot = chroot.with_timeout(Some(Duration::from_secs(60)));
+
+    assert!(chroot.open(&path, flags).is_ok());
+}
+
+#[test]
+fn test_verify() {
+    let (tmpdir, chroot) = create_test_chroot();
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+    let fd = chroot.open(&"/tmp/d0/d1/f0", flags).unwrap();
+    let dir = tmpdir.path().join("chroot/tmp/d0/d1");
+
+    assert!(chroot.verify(&"/tmp/d0/d1/f0", &fd).unwrap());
+    assert!(chroot.verify(&"/tmp/ld0/d1/lf2", &fd).unwrap());
+    assert!(!chroot.verify(&"/etc/passwd", &fd).unwrap());
+
+    std::fs::rename(dir.join("f0"), dir.join("f0.old")).unwrap();
+    assert!(!chroot.verify(&"/tmp/d0/d1/f0", &fd).unwrap());
+
+    std::fs::write(dir.join("f0"), b"").unwrap();
+    assert!(!chroot.verify(&"/tmp/d0/d1/f0", &fd).unwrap());
+    assert!(chroot.verify(&"/tmp/d0/d1/f0.old", &fd).unwrap());
+}
+
+#[test]
+fn test_identity() {
+    let (tmpdir, chroot) = create_test_chroot();
+    let dir = tmpdir.path().join("chroot");
+    let identity = chroot.identity().unwrap();
+
+    let reattached = Chroot::reattach(&identity).unwrap();
+
+    std::fs::rename(&dir, tmpdir.path().join("old")).unwrap();
+    std::fs::create_dir(&dir).unwrap();
+
+    assert!(Chroot::reattach(&identity).is_err());
+
+    // the checked directory is kept
+    assert!(reattached.existsat(&reattached.root_fd().unwrap(), &"/etc/passwd"));
+    assert_eq!(reattached.identity().unwrap(), identity);
+}
+
+#[test]
+fn test_fallback() {
+    let (tmpdir, chroot) = create_test_chroot();
+    let image = tmpdir.path().join("image");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    std::fs::create_dir_all(image.join("tmp/d0/d1")).unwrap();
+    std::fs::write(image.join("tmp/d0/only-image"), b"image").unwrap();
+    std::fs::write(image.join("tmp/d0/d1/f0"), b"image").unwrap();
+
+    let chroot = chroot.with_fallback(Some(&image));
+    let cwd = crate::fd::Fd::cwd();
+
+    // reads of missing entries go to the image
+    assert!(chroot.open(&"/tmp/d0/only-image", flags).is_ok());
+    assert!(chroot.is_regat(&cwd, &"/tmp/d0/only-image"));
+    assert!(chroot.fstatat(&cwd, &"/tmp/d0/only-image").is_ok());
+    assert_eq!(chroot.stats().fallback_reads, 3);
+
+    // existing entries are taken from the primary root
+    let mut content = String::new();
+    chroot.buf_reader(&"/tmp/d0/d1/f0").unwrap().read_to_string(&mut content).unwrap();
+    assert_ne!(content, "image");
+
+    // lookups relative to a directory are not retried
+    let dir_fd = chroot.chdir(&"/tmp/d0").unwrap();
+    assert!(chroot.openat(&dir_fd, &"only-image", flags).is_err());
+
+    // writes go to the primary root
+    assert!(chroot.open(&"/tmp/d0/only-image",
+                        libc::O_WRONLY | libc::O_CLOEXEC).is_err());
+    chroot.create_with(&"/tmp/d0/only-image", &Default::default()).unwrap();
+    assert!(tmpdir.path().join("chroot/tmp/d0/only-image").exists());
+    assert_eq!(std::fs::read(image.join("tmp/d0/only-image")).unwrap(), b"image");
+}
+
+#[test]
+fn test_negative_cache() {
+    use std::time::Duration;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let dir = tmpdir.path().join("chroot/tmp/d0");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let chroot = chroot.with_negative_cache(Some(Duration::from_secs(3600)));
+
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_err());
+    assert_eq!(chroot.stats().negative_hits, 0);
+
+    // changes done outside are not seen...
+    std::fs::write(dir.join("missing"), b"").unwrap();
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_err());
+    assert!(!chroot.existsat(&crate::fd::Fd::cwd(), &"/tmp/d0/missing"));
+    assert_eq!(chroot.stats().negative_hits, 2);
+
+    // ...until the cache is invalidated by an own modification
+    chroot.write_atomic(&"/tmp/d0/other", b"").unwrap();
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_ok());
+
+    std::fs::remove_file(dir.join("missing")).unwrap();
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_err());
+    std::fs::write(dir.join("missing"), b"").unwrap();
+    chroot.clear_negative_cache();
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_ok());
+
+    // entries expire
+    let chroot = chroot.with_negative_cache(Some(Duration::from_millis(10)));
+
+    assert!(chroot.open(&"/tmp/d0/missing2", flags).is_err());
+    std::fs::write(dir.join("missing2"), b"").unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(chroot.open(&"/tmp/d0/missing2", flags).is_ok());
+}
+
+#[test]
+fn test_cache_watch() {
+    use std::time::Duration;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let dir = tmpdir.path().join("chroot/tmp/d0");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let chroot = chroot
+        .with_cache_watch(true)
+        .with_negative_cache(Some(Duration::from_secs(3600)));
+
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_err());
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_err());
+    assert_eq!(chroot.stats().negative_hits, 1);
+
+    // changes done outside are seen
+    std::fs::write(dir.join("missing"), b"").unwrap();
+    assert!(chroot.open(&"/tmp/d0/missing", flags).is_ok());
+
+    // missing parent directories are watched in their parent
+    assert!(chroot.open(&"/tmp/d0/sub/f", flags).is_err());
+    assert!(chroot.open(&"/tmp/d0/sub/f", flags).is_err());
+    assert_eq!(chroot.stats().negative_hits, 2);
+
+    std::fs::create_dir(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/f"), b"").unwrap();
+    assert!(chroot.open(&"/tmp/d0/sub/f", flags).is_ok());
+    assert_eq!(chroot.stats().negative_hits, 2);
+}
+
+#[test]
+fn test_open_root() {
+    use crate::errors::{Error, ErrorKind};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().join("root");
+
+    std::fs::create_dir(&root).unwrap();
+    std::fs::write(root.join("f"), b"f").unwrap();
+
+    match Chroot::open_root(&tmpdir.path().join("missing")) {
+        Err(Error(ErrorKind::InvalidRoot(_, libc::ENOENT), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    match Chroot::open_root(&root.join("f")) {
+        Err(Error(ErrorKind::InvalidRoot(_, libc::ENOTDIR), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    match Chroot::open_root(&root.join("f/x")) {
+        Err(Error(ErrorKind::InvalidRoot(_, libc::ENOTDIR), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    let chroot = Chroot::open_root(&root).unwrap();
+
+    // the kept descriptor is used after the directory was moved
+    std::fs::rename(&root, tmpdir.path().join("moved")).unwrap();
+
+    assert_eq!(chroot.read(&"/f").unwrap(), b"f");
+    assert!(chroot.root_fd().unwrap().is_regat(&"f"));
+    assert!(chroot.unmasked().open(&"/../f", libc::O_RDONLY | libc::O_CLOEXEC).is_ok());
+}
+
+#[test]
+fn test_temp_naming() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use crate::chroot::TempNaming;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let cnt = Arc::new(AtomicU64::new(0));
+    let random_cnt = cnt.clone();
+
+    // the first name is taken already
+    std::fs::write(tmpdir.path().join("svc-f.new0000000000000000"), b"other").unwrap();
+
+    let chroot = Chroot::new(&tmpdir.path())
+        .with_temp_naming(TempNaming {
+            prefix:	"svc-".into(),
+            suffix:	".new".into(),
+            random:	Some(Arc::new(move || random_cnt.fetch_add(1, Ordering::SeqCst))),
+        });
+
+    chroot.write_atomic(&"/f", b"data").unwrap();
+
+    assert_eq!(cnt.load(Ordering::SeqCst), 2);
+    assert_eq!(std::fs::read(tmpdir.path().join("f")).unwrap(), b"data");
+    assert_eq!(std::fs::read(tmpdir.path().join("svc-f.new0000000000000000")).unwrap(),
+               b"other");
+    assert!(!tmpdir.path().join("svc-f.new0000000000000001").exists());
+
+    // a source which never changes fails eventually
+    let chroot = chroot.with_temp_naming(TempNaming {
+        prefix:	"svc-".into(),
+        suffix:	".new".into(),
+        random:	Some(Arc::new(|| 0)),
+    });
+
+    assert_eq!(chroot.write_atomic(&"/f", b"").unwrap_err().errno(), Some(libc::EEXIST));
+
+    // names must be valid
+    let chroot = chroot.with_temp_naming(TempNaming {
+        prefix:	"a/".into(),
+        .. TempNaming::default()
+    });
+
+    assert!(chroot.write_atomic(&"/f", b"").is_err());
+}
+
+#[test]
+fn test_type_guard() {
+    use crate::errors::ErrorKind;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let dir = tmpdir.path().join("chroot/tmp/d0");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let fifo = crate::LibcString::as_libc(dir.join("fifo").as_path()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.0, 0o600) }, 0);
+
+    let mut special = vec!["/tmp/d0/fifo"];
+
+    // creating devices needs CAP_MKNOD
+    let is_root = unsafe { libc::geteuid() } == 0;
+
+    if is_root {
+        let null = crate::LibcString::as_libc(dir.join("null").as_path()).unwrap();
+        assert_eq!(unsafe { libc::mknod(null.0, libc::S_IFCHR | 0o666,
+                                        libc::makedev(1, 3)) }, 0);
+        special.push("/tmp/d0/null");
+    }
+
+    for p in &special {
+        match chroot.open(p, flags) {
+            Err(crate::errors::Error(ErrorKind::SpecialFile(path), _)) =>
+                assert_eq!(path, std::path::Path::new(p)),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(chroot.open(p, libc::O_PATH | libc::O_CLOEXEC).is_ok());
+    }
+
+    assert_eq!(chroot.stats().policy_rejections, special.len() as u64);
+
+    // O_NONBLOCK is not leaked into regular opens
+    let fd = chroot.open(&"/tmp/d0/d1/f0", flags).unwrap();
+    let fl = unsafe { libc::fcntl(fd.fd, libc::F_GETFL) };
+    assert_eq!(fl & libc::O_NONBLOCK, 0);
+
+    let chroot = chroot.with_type_guard(false);
+
+    assert!(chroot.open(&"/tmp/d0/fifo", flags | libc::O_NONBLOCK).is_ok());
+
+    if is_root {
+        assert!(chroot.open(&"/tmp/d0/null", flags).is_ok());
+    }
+}
+
+#[test]
+fn test_max_file_size() {
+    use crate::errors::ErrorKind;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let dir = tmpdir.path().join("chroot/tmp/d0");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    std::fs::write(dir.join("small"), b"0123").unwrap();
+    std::fs::write(dir.join("large"), b"0123456789").unwrap();
+
+    let chroot = chroot.with_max_file_size(Some(4));
+
+    assert_eq!(chroot.read_to_string(&"/tmp/d0/small").unwrap(), "0123");
+
+    match chroot.read(&"/tmp/d0/large") {
+        Err(crate::errors::Error(ErrorKind::FileTooLarge(_, 4), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    assert!(chroot.open(&"/tmp/d0/large", flags).is_err());
+    assert!(chroot.open(&"/tmp/d0/large", libc::O_PATH | libc::O_CLOEXEC).is_ok());
+    assert!(chroot.open(&"/tmp/d0/large", libc::O_WRONLY | libc::O_CLOEXEC).is_ok());
+    assert_eq!(chroot.stats().policy_rejections, 2);
+
+    // directories are larger than the limit but not restricted
+    assert!(std::fs::metadata(&dir).unwrap().len() > 4);
+    assert!(chroot.open(&"/tmp/d0", libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .is_ok());
+    assert_eq!(chroot.stats().policy_rejections, 2);
+
+    let chroot = chroot.with_max_file_size(None);
+    std::fs::write(dir.join("invalid"), b"\xff").unwrap();
+
+    assert_eq!(chroot.read(&"/tmp/d0/large").unwrap(), b"0123456789");
+    assert!(chroot.read_to_string(&"/tmp/d0/invalid").is_err());
+}
+
+#[test]
+fn test_open_nonblocking() {
+    use crate::errors::ErrorKind;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let fifo = tmpdir.path().join("chroot/tmp/d0/fifo");
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let fifo = crate::LibcString::as_libc(fifo.as_path()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.0, 0o600) }, 0);
+
+    let fd = chroot.open_nonblocking(&"/tmp/d0/d1/f0", flags).unwrap();
+    let fl = unsafe { libc::fcntl(fd.fd, libc::F_GETFL) };
+    assert_ne!(fl & libc::O_NONBLOCK, 0);
+
+    match chroot.open_nonblocking(&"/tmp/d0/fifo", flags) {
+        Err(crate::errors::Error(ErrorKind::SpecialFile(_), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // would block without a writer
+    let chroot = chroot.with_type_guard(false);
+    let fd = chroot.open_nonblocking(&"/tmp/d0/fifo", flags).unwrap();
+    let fl = unsafe { libc::fcntl(fd.fd, libc::F_GETFL) };
+    assert_ne!(fl & libc::O_NONBLOCK, 0);
+}
+
+#[cfg(feature = "unstable-resolver")]
+#[test]
+fn test_resolve_component() {
+    use std::path::{Component, Path};
+    use crate::chroot::ComponentStep;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let root_fd = chroot.root_fd().unwrap();
+    let root_ino = root_fd.fstat().unwrap().st_ino;
+    let ino = |fd: &crate::fd::Fd| fd.fstat().unwrap().st_ino;
+
+    let (fd, step) = chroot.resolve_component(&root_fd, Component::ParentDir).unwrap();
+    assert_eq!(step, ComponentStep::RootClamp);
+    assert_eq!(ino(&fd), root_ino);
+
+    let (tmp, step) = chroot.resolve_component(&root_fd, Component::Normal("tmp".as_ref()))
+        .unwrap();
+    assert_eq!(step, ComponentStep::Normal);
+
+    let (fd, step) = chroot.resolve_component(&tmp, Component::Normal("ld0".as_ref()))
+        .unwrap();
+    assert!(matches!(step, ComponentStep::Symlink(_)));
+    assert_eq!(ino(&fd), ino(&chroot.chdir(&"/tmp/d0").unwrap()));
+
+    let (fd, step) = chroot.resolve_component(&fd, Component::ParentDir).unwrap();
+    assert_eq!(step, ComponentStep::Parent);
+    assert_eq!(ino(&fd), ino(&tmp));
+
+    let (fd, step) = chroot.resolve_component(&fd, Component::RootDir).unwrap();
+    assert_eq!(step, ComponentStep::Root);
+    assert_eq!(ino(&fd), root_ino);
+
+    assert!(chroot.resolve_component(&tmp, Component::Normal(Path::new("missing").as_os_str()))
+            .is_err());
+}
+
+#[test]
+fn test_stale_retries() {
+    use std::cell::Cell;
+
+    let (_tmpdir, chroot) = create_test_chroot();
+    let calls = Cell::new(0);
+    let stale_twice = || {
+        calls.set(calls.get() + 1);
+
+        if calls.get() <= 2 {
+            Err(std::io::Error::from_raw_os_error(libc::ESTALE).into())
+        } else {
+            Ok(calls.get())
+        }
+    };
+
+    assert!(chroot.retry_stale(stale_twice).is_err());
+    assert_eq!(calls.get(), 1);
+
+    let chroot = chroot.with_stale_retries(1);
+    calls.set(0);
+    assert_eq!(chroot.retry_stale(stale_twice).unwrap_err().errno(), Some(libc::ESTALE));
+
+    let chroot = chroot.with_stale_retries(5);
+    calls.set(0);
+    assert_eq!(chroot.retry_stale(stale_twice).unwrap(), 3);
+    // one retry of the previous attempt is counted too
+    assert_eq!(chroot.stats().stale_retries, 3);
+}
+
+#[test]
+fn test_link_cache() {
+    let (_tmpdir, chroot) = create_test_chroot();
+    let path = "/tmp/ld0/../ld0/../ld0/d1/f0";
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let fd = chroot.open(&path, flags).unwrap();
+    assert_eq!(chroot.stats().link_cache_hits, 0);
+
+    let chroot = chroot.with_link_cache(true);
+    let cached_fd = chroot.open(&path, flags).unwrap();
+
+    assert!(crate::fd::same_file_by_stat(&fd.fstat().unwrap(), &cached_fd.fstat().unwrap()));
+    assert_eq!(chroot.stats().link_cache_hits, 2);
+
+    // the cache does not survive the operation
+    chroot.open(&"/tmp/ld0/d1/f0", flags).unwrap();
+    assert_eq!(chroot.stats().link_cache_hits, 2);
+}
+
+#[test]
+fn test_create_invalid_name() {
+    use std::os::unix::ffi::OsStrExt;
+    use crate::errors::ErrorKind;
+
+    let (tmpdir, chroot) = create_test_chroot();
+    let path = |p: &[u8]| std::path::PathBuf::from(std::ffi::OsStr::from_bytes(p));
+
+    for p in &[path(b"/tmp/d0/a\0b"), path(b"/tmp/d0/x\0y/z")] {
+        let res = if p.ends_with("z") {
+            chroot.create_dir_all(p, 0o755).map(|_| ())
+        } else {
+            chroot.create_with(p, &Default::default()).map(|_| ())
+        };
+
+        match res {
+            Err(crate::errors::Error(ErrorKind::InvalidName(..), _)) => {},
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    assert!(chroot.write_atomic(&"/tmp/d0/..", b"").is_err());
+    assert!(!tmpdir.path().join("chroot/tmp/d0/a").exists());
+    assert!(!tmpdir.path().join("chroot/tmp/d0/x").exists());
+}
+
+#[test]
+fn test_preserve_dir_times() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC;
+
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::create_dir(root.join("c")).unwrap();
+    std::fs::write(root.join("a/f"), b"").unwrap();
+
+    let times = |p: &str| {
+        let md = std::fs::metadata(root.join(p)).unwrap();
+
+        (md.atime(), md.atime_nsec(), md.mtime(), md.mtime_nsec())
+    };
+    let reset = || for d in &["a", "a/b", "c"] {
+        let fd = crate::fd::Fd::open(&root.join(d), libc::O_PATH | libc::O_CLOEXEC).unwrap();
+
+        fd.futimens(libc::timespec { tv_sec: 1000, tv_nsec: 1 },
+                    libc::timespec { tv_sec: 2000, tv_nsec: 2 }).unwrap();
+    };
+    let unchanged = || ["a", "a/b", "c"].iter().all(|d| times(d) == (1000, 1, 2000, 2));
+
+    reset();
+
+    let chroot = Chroot::new(&root).with_preserve_dir_times(true);
+
+    chroot.open(&"/a/new", flags).unwrap();
+    chroot.write_atomic(&"/a/b/g", b"data").unwrap();
+    chroot.rename(&"/a/f", &"/c/f").unwrap();
+    chroot.swap(&"/a/b/g", &"/c/f").unwrap();
+    chroot.create_dir_all(&"/a/b/d/e", 0o755).unwrap();
+    chroot.remove_file(&"/a/new").unwrap();
+    chroot.canonicalize(&"/c/f").unwrap().remove_file().unwrap();
+    chroot.remove_dir_all(&"/a/b/d").unwrap();
+    assert!(unchanged());
+
+    let chroot = Chroot::new(&root);
+
+    chroot.open(&"/a/new", flags).unwrap();
+    assert_ne!(times("a"), (1000, 1, 2000, 2));
+}
@@ -0,0 +1,86 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::chroot::Chroot;
+use crate::control::Control;
+use crate::visit::VisitAction;
+use crate::errors::*;
+
+/// Records the reports and stops after `limit` entries
+#[derive(Default)]
+struct Recorder {
+    reports:	Mutex<Vec<(u64, u64)>>,
+    limit:	Option<u64>,
+}
+
+impl Control for Recorder {
+    fn should_stop(&self) -> bool {
+        let last = self.reports.lock().unwrap().last().map_or(0, |r| r.0);
+
+        self.limit.map_or(false, |l| last >= l)
+    }
+
+    fn on_progress(&self, entries: u64, bytes: u64) {
+        self.reports.lock().unwrap().push((entries, bytes));
+    }
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<(u64, u64)> {
+        std::mem::take(&mut self.reports.lock().unwrap())
+    }
+}
+
+#[test]
+fn test_control() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("src/d")).unwrap();
+    std::fs::write(root.join("src/a"), vec![0u8; 100]).unwrap();
+    std::fs::write(root.join("src/d/b"), vec![0u8; 20]).unwrap();
+
+    let recorder = Arc::new(Recorder::default());
+    let chroot = Chroot::new(&root).with_control(Some(recorder.clone()));
+
+    chroot.visit(&"/src", |_| Ok(VisitAction::Descend)).unwrap();
+    assert_eq!(recorder.take(), [(1, 0), (2, 0), (3, 0)]);
+
+    chroot.snapshot(&"/src", &"/copy").unwrap();
+
+    let reports = recorder.take();
+
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports.last(), Some(&(3, 120)));
+
+    let mut archive = Vec::new();
+
+    chroot.archive(&"/src", &mut archive, &Default::default()).unwrap();
+    assert_eq!(recorder.take().last(), Some(&(3, 120)));
+
+    chroot.remove_dir_all(&"/copy").unwrap();
+    assert_eq!(recorder.take(), [(1, 0), (2, 0), (3, 0)]);
+    assert!(!root.join("copy").exists());
+
+    // operations are aborted once the control asks for it
+    let recorder = Arc::new(Recorder { limit: Some(1), ..Default::default() });
+    let chroot = Chroot::new(&root).with_control(Some(recorder.clone()));
+
+    match chroot.visit(&"/src", |_| Ok(VisitAction::Descend)) {
+        Err(Error(ErrorKind::Cancelled, _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    assert_eq!(recorder.take(), [(1, 0)]);
+
+    let flag = Arc::new(AtomicBool::new(false));
+    let chroot = Chroot::new(&root).with_control(Some(flag.clone()));
+
+    assert!(chroot.open(&"/src/a", libc::O_RDONLY | libc::O_CLOEXEC).is_ok());
+    flag.store(true, Ordering::Relaxed);
+
+    match chroot.open(&"/src/a", libc::O_RDONLY | libc::O_CLOEXEC) {
+        Err(Error(ErrorKind::Cancelled, _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+}
@@ -1,3 +1,6 @@
+#![allow(clippy::single_component_path_imports, clippy::identity_op,
+         clippy::expect_fun_call, clippy::useless_format, clippy::let_and_return)]
+
 use std;
 use libc;
 
@@ -86,3 +89,826 @@ pub fn create_tmpdir() -> TempDir {
 
     res
 }
+
+/// Flushes `path` and reads it so that its pages are in the page
+/// cache; returns the number of them.
+pub fn load_page_cache(path: &std::path::Path) -> usize {
+    std::fs::File::open(path).and_then(|f| f.sync_all())
+        .expect("failed to sync file");
+    std::fs::read(path).expect("failed to read file");
+
+    cached_pages(path)
+}
+
+/// Returns the number of pages of `path` in the page cache
+/// (`mincore()`)
+pub fn cached_pages(path: &std::path::Path) -> usize {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::File::open(path).expect("failed to open file");
+    let len = file.metadata().unwrap().len() as usize;
+
+    if len == 0 {
+        return 0;
+    }
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let mut vec = vec![0u8; (len + page_size - 1) / page_size];
+
+    unsafe {
+        let addr = libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ,
+                              libc::MAP_SHARED, file.as_raw_fd(), 0);
+
+        assert_ne!(addr, libc::MAP_FAILED);
+        assert_eq!(libc::mincore(addr, len, vec.as_mut_ptr()), 0);
+        libc::munmap(addr, len);
+    }
+
+    vec.iter().filter(|v| *v & 1 != 0).count()
+}
+
+/// Returns whether clean pages of files in `dir` can be dropped from
+/// the page cache; e.g. not on tmpfs where the cache is the storage.
+pub fn can_drop_page_cache(dir: &std::path::Path) -> bool {
+    let probe = dir.join(".page-cache-probe");
+
+    std::fs::write(&probe, vec![0u8; 1 << 16]).unwrap();
+    load_page_cache(&probe);
+
+    crate::fd::FdRaw::open(&probe, libc::O_RDONLY | libc::O_CLOEXEC).unwrap()
+        .drop_page_cache().unwrap();
+
+    let res = cached_pages(&probe) == 0;
+
+    std::fs::remove_file(&probe).unwrap();
+    res
+}
+
+#[test]
+fn test_fd_metadata() {
+    let tmpdir = create_tmpdir();
+    let dir = tmpdir.path();
+
+    std::fs::write(dir.join("f"), "data").unwrap();
+    std::os::unix::fs::symlink("f", dir.join("l")).unwrap();
+
+    let dir_fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                     libc::O_CLOEXEC).unwrap();
+    let file_fd = dir_fd.openat(&"f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    let link_fd = dir_fd.openat(&"l", libc::O_PATH | libc::O_NOFOLLOW |
+                                libc::O_CLOEXEC).unwrap();
+
+    assert!(dir_fd.is_dir());
+    assert!(file_fd.is_file());
+    assert!(link_fd.is_symlink());
+    assert!(!link_fd.is_file());
+
+    assert_eq!(file_fd.metadata().unwrap().len(), 4);
+
+    // AT_EMPTY_PATH operations on the fd itself
+    assert!(link_fd.is_lnkat(&""));
+    assert!(file_fd.is_regat(&""));
+    assert_eq!(link_fd.readlinkat(&"").unwrap(), OsString::from("f"));
+}
+
+#[test]
+fn test_fd_reopen() {
+    use std::io::{Read, Write};
+
+    let tmpdir = create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), "data").unwrap();
+
+    let fd = crate::fd::Fd::open(&tmpdir.path().join("f"),
+                                 libc::O_PATH | libc::O_CLOEXEC).unwrap();
+    let rw_fd = fd.reopen(libc::O_RDWR | libc::O_CLOEXEC).unwrap();
+
+    let mut file = rw_fd.into_rawfd().unwrap().into_file().unwrap();
+    let mut data = String::new();
+
+    file.read_to_string(&mut data).unwrap();
+    assert_eq!(data, "data");
+
+    file.write_all(b"-more").unwrap();
+
+    assert_eq!(std::fs::read(tmpdir.path().join("f")).unwrap(), b"data-more");
+}
+
+#[test]
+fn test_fd_reader() {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let tmpdir = create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), "line0\nline1\n").unwrap();
+
+    let fd = crate::fd::Fd::open(&tmpdir.path().join("f"),
+                                 libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    let mut r0 = fd.reader();
+    let mut r1 = fd.reader();
+    let mut buf = [0u8; 6];
+
+    r0.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"line0\n");
+
+    r1.read_exact(&mut buf[..4]).unwrap();
+    assert_eq!(&buf[..4], b"line");
+
+    // offsets which pread() can not take are rejected
+    assert_eq!(r0.seek(SeekFrom::Start(u64::MAX)).unwrap_err().raw_os_error(),
+               Some(libc::EINVAL));
+    assert_eq!(r0.seek(SeekFrom::Start(1 << 63)).unwrap_err().raw_os_error(),
+               Some(libc::EINVAL));
+    assert_eq!(r0.position(), 6);
+    assert_eq!(r0.seek(SeekFrom::End(-6)).unwrap(), 6);
+    r0.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"line1\n");
+
+    let lines: Vec<String> = fd.lines().map(|l| l.unwrap()).collect();
+    assert_eq!(lines, ["line0", "line1"]);
+}
+
+#[test]
+fn test_fd_async() {
+    let mut fds = [0; 2];
+
+    assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+
+    let rd = unsafe { crate::fd::FdRaw::new(fds[0]) };
+    let _wr = unsafe { crate::fd::FdRaw::new(fds[1]) };
+
+    rd.set_owner(unsafe { libc::getpid() }).unwrap();
+    rd.set_signal(libc::SIGRTMIN()).unwrap();
+    rd.set_async(true).unwrap();
+
+    let flags = unsafe { libc::fcntl(fds[0], libc::F_GETFL) };
+    assert!(flags & libc::O_ASYNC != 0);
+    assert_eq!(unsafe { libc::fcntl(fds[0], libc::F_GETOWN) },
+               unsafe { libc::getpid() });
+
+    rd.set_async(false).unwrap();
+
+    let flags = unsafe { libc::fcntl(fds[0], libc::F_GETFL) };
+    assert!(flags & libc::O_ASYNC == 0);
+}
+
+#[test]
+fn test_fd_leak() {
+    use crate::fd::FdRaw;
+    use std::os::unix::io::AsRawFd;
+
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    let fd = FdRaw::open(&"/", flags).unwrap();
+    let raw = fd.leak();
+
+    // still open
+    assert!(unsafe { libc::fcntl(raw, libc::F_GETFD) } >= 0);
+    assert_eq!(unsafe { libc::close(raw) }, 0);
+
+    let fd = FdRaw::open(&"/", flags).unwrap();
+    let raw = fd.fd;
+    let owned = fd.into_owned_fd().unwrap();
+
+    assert_eq!(owned.as_raw_fd(), raw);
+
+    assert!(FdRaw::cwd().into_owned_fd().is_err());
+    let fd = unsafe { FdRaw::new(std::os::unix::io::IntoRawFd::into_raw_fd(owned)) };
+    let borrowed = fd.borrow();
+
+    assert_eq!(borrowed.clone().fd, raw);
+    assert!(borrowed.fstat().is_ok());
+
+    drop(borrowed);
+
+    // the borrowed reference did not close it
+    assert!(fd.fstat().is_ok());
+}
+
+#[test]
+fn test_metadata_roundtrip() {
+    use std::os::unix::fs::MetadataExt;
+    use crate::metadata::Metadata;
+
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not running as root; skipping test");
+        return;
+    }
+
+    let tmpdir = create_tmpdir();
+    let dir = tmpdir.path();
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+    std::fs::write(dir.join("src"), "data").unwrap();
+    std::fs::write(dir.join("dst"), "data").unwrap();
+
+    let src = crate::fd::Fd::open(&dir.join("src"), flags).unwrap();
+    let dst = crate::fd::Fd::open(&dir.join("dst"), flags).unwrap();
+    let times = [libc::timespec { tv_sec: 1000, tv_nsec: 1 },
+                 libc::timespec { tv_sec: 2000, tv_nsec: 2 }];
+
+    src.fchownat(&"", Some(1234), Some(2345), false).unwrap();
+    src.fchmod(0o2755).unwrap();
+    assert_eq!(unsafe { libc::futimens(src.fd, times.as_ptr()) }, 0);
+
+    // xattrs are not supported by every filesystem
+    let has_xattrs = match src.setxattr("user.test", b"value", 0) {
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => false,
+        r => { r.unwrap(); true },
+    };
+
+    let md = Metadata::capture(&src).unwrap();
+    let xattrs = Metadata::capture_xattrs(&src).unwrap();
+
+    md.apply(&dst, Some(&xattrs)).unwrap();
+
+    let res = std::fs::metadata(dir.join("dst")).unwrap();
+
+    assert_eq!((res.uid(), res.gid(), res.mode() & 0o7777), (1234, 2345, 0o2755));
+    assert_eq!((res.atime(), res.atime_nsec()), (1000, 1));
+    assert_eq!((res.mtime(), res.mtime_nsec()), (2000, 2));
+
+    if has_xattrs {
+        assert_eq!(dst.getxattr("user.test").unwrap().unwrap(), b"value");
+        assert_eq!(dst.listxattr().unwrap(), xattrs.iter().map(|x| x.0.clone())
+                   .collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn test_apply_readonly() {
+    use std::os::unix::fs::MetadataExt;
+    use crate::metadata::Metadata;
+
+    let tmpdir = create_tmpdir();
+    let path = tmpdir.path().join("f");
+
+    std::fs::write(&path, "data").unwrap();
+
+    let fd = crate::fd::Fd::open(&path, libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    let mut st = fd.fstat().unwrap();
+    let xattrs = vec![("user.test".to_string(), b"value".to_vec())];
+
+    // xattrs are not supported by every filesystem
+    match fd.setxattr("user.probe", b"", 0) {
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => {
+            eprintln!("no user xattrs; skipping test");
+            return;
+        },
+        r => r.unwrap(),
+    }
+
+    // a read-only mode must not prevent the owner from setting the
+    // xattrs
+    st.st_mode = libc::S_IFREG | 0o444;
+
+    Metadata::from_stat(st).apply(&fd, Some(&xattrs)).unwrap();
+
+    assert_eq!(std::fs::metadata(&path).unwrap().mode() & 0o7777, 0o444);
+    assert_eq!(fd.getxattr("user.test").unwrap().unwrap(), b"value");
+}
+
+#[test]
+fn test_nul_in_path() {
+    use std::os::unix::ffi::OsStrExt;
+    use crate::errors::ErrorKind;
+
+    let tmpdir = create_tmpdir();
+    let dir_fd = crate::fd::Fd::open(&tmpdir.path(), libc::O_RDONLY | libc::O_DIRECTORY |
+                                     libc::O_CLOEXEC).unwrap();
+    let path = std::path::Path::new(std::ffi::OsStr::from_bytes(b"etc\0/passwd"));
+
+    std::fs::create_dir(tmpdir.path().join("etc")).unwrap();
+
+    // must not be truncated to "etc"
+    match dir_fd.openat(&path, libc::O_PATH | libc::O_CLOEXEC) {
+        Err(crate::errors::Error(ErrorKind::InvalidPath(p), _)) => assert_eq!(p, path),
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    assert!(dir_fd.fstatat(&path, false).is_err());
+    assert!(dir_fd.mkdirat(&path, 0o755).is_err());
+
+    let chroot = crate::chroot::Chroot::new(&tmpdir.path());
+
+    assert!(chroot.open(&std::path::Path::new("/").join(path), libc::O_PATH | libc::O_CLOEXEC)
+            .is_err());
+}
+
+#[test]
+fn test_current_dir() {
+    use crate::fd::{current_dir, set_current_dir};
+
+    let tmpdir = create_tmpdir();
+    let orig = current_dir().unwrap();
+    let orig_path = std::env::current_dir().unwrap();
+    let tmp_fd = crate::fd::Fd::open(&tmpdir.path(), libc::O_PATH | libc::O_DIRECTORY |
+                                     libc::O_CLOEXEC).unwrap();
+
+    assert!(orig.is_dir());
+
+    // the working directory is shared by all threads of the test
+    // process; change it in a child only
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork: {}", std::io::Error::last_os_error()),
+
+        0 => {
+            let ok = std::panic::catch_unwind(|| {
+                set_current_dir(&tmp_fd).unwrap();
+                assert_eq!(std::env::current_dir().unwrap(),
+                           tmpdir.path().canonicalize().unwrap());
+
+                set_current_dir(&orig).unwrap();
+                assert_eq!(std::env::current_dir().unwrap(), orig_path);
+            }).is_ok();
+
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        },
+
+        pid => {
+            let mut status = 0;
+
+            assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+            assert!(libc::WIFEXITED(status));
+            assert_eq!(libc::WEXITSTATUS(status), 0);
+        },
+    }
+
+    assert_eq!(std::env::current_dir().unwrap(), orig_path);
+}
+
+#[test]
+fn test_pread_pwrite() {
+    let tmpdir = create_tmpdir();
+    let fd = crate::fd::Fd::cwd().createat(&tmpdir.path().join("f"),
+                                           libc::O_RDWR | libc::O_CLOEXEC, 0o600).unwrap();
+    let mut buf = [0u8; 4];
+
+    assert_eq!(fd.pwrite(b"0123456789", 0).unwrap(), 10);
+    assert_eq!(fd.pwrite(b"ab", 4).unwrap(), 2);
+
+    assert_eq!(fd.pread(&mut buf, 3).unwrap(), 4);
+    assert_eq!(&buf, b"3ab6");
+    assert_eq!(fd.pread(&mut buf, 8).unwrap(), 2);
+    assert_eq!(&buf[..2], b"89");
+    assert_eq!(fd.pread(&mut buf, 20).unwrap(), 0);
+
+    // the file offset is not changed
+    assert_eq!(fd.read_uninit(&mut [std::mem::MaybeUninit::uninit(); 3]).unwrap(), b"012");
+    assert!(fd.pwrite(b"x", u64::MAX).is_err());
+}
+
+#[test]
+fn test_readv_writev() {
+    use std::io::{IoSlice, IoSliceMut};
+
+    let tmpdir = create_tmpdir();
+    let fd = crate::fd::Fd::cwd().createat(&tmpdir.path().join("f"),
+                                           libc::O_RDWR | libc::O_CLOEXEC, 0o600).unwrap();
+
+    assert_eq!(fd.writev(&[IoSlice::new(b"head"), IoSlice::new(b""),
+                           IoSlice::new(b"-body")]).unwrap(), 9);
+    assert_eq!(std::fs::read(tmpdir.path().join("f")).unwrap(), b"head-body");
+
+    let (mut a, mut b) = ([0u8; 5], [0u8; 8]);
+    let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+
+    // the file offset is behind the written data
+    assert_eq!(fd.readv(&mut bufs).unwrap(), 0);
+
+    let fd = crate::fd::Fd::open(&tmpdir.path().join("f"), libc::O_RDONLY | libc::O_CLOEXEC)
+        .unwrap();
+    let mut bufs = [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)];
+
+    assert_eq!(fd.readv(&mut bufs).unwrap(), 9);
+    assert_eq!(&a, b"head-");
+    assert_eq!(&b[..4], b"body");
+}
+
+#[test]
+fn test_preadv2_pwritev2() {
+    use std::io::{IoSlice, IoSliceMut};
+
+    let tmpdir = create_tmpdir();
+    let fd = crate::fd::Fd::cwd().createat(&tmpdir.path().join("f"),
+                                           libc::O_RDWR | libc::O_CLOEXEC, 0o600).unwrap();
+
+    assert_eq!(fd.pwritev2(&[IoSlice::new(b"0123"), IoSlice::new(b"4567")], Some(2),
+                           libc::RWF_DSYNC).unwrap(), 8);
+    assert_eq!(fd.pwritev2(&[IoSlice::new(b"ab")], None, 0).unwrap(), 2);
+    assert_eq!(std::fs::read(tmpdir.path().join("f")).unwrap(), b"ab01234567");
+
+    let (mut a, mut b) = ([0u8; 3], [0u8; 3]);
+
+    assert_eq!(fd.preadv2(&mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)], Some(1),
+                          0).unwrap(), 6);
+    assert_eq!((&a, &b), (b"b01", b"234"));
+
+    // the file offset was advanced by the write without offset only
+    assert_eq!(fd.preadv2(&mut [IoSliceMut::new(&mut a)], None, 0).unwrap(), 3);
+    assert_eq!(&a, b"012");
+
+    // data was just written and is in the page cache; filesystems
+    // without support fail with EOPNOTSUPP
+    match fd.preadv2(&mut [IoSliceMut::new(&mut a)], Some(7), libc::RWF_NOWAIT) {
+        Ok(n) => assert_eq!(&a[..n], b"567"),
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => {},
+        Err(e) => panic!("unexpected error {:?}", e),
+    }
+
+    assert!(fd.preadv2(&mut [IoSliceMut::new(&mut a)], Some(u64::MAX), 0).is_err());
+}
+
+#[test]
+fn test_ftruncate() {
+    let tmpdir = create_tmpdir();
+    let path = tmpdir.path().join("f");
+
+    std::fs::write(&path, b"0123456789").unwrap();
+
+    let fd = crate::fd::Fd::open(&path, libc::O_WRONLY | libc::O_CLOEXEC).unwrap();
+
+    fd.ftruncate(4).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"0123");
+    fd.ftruncate(6).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"0123\0\0");
+    assert_eq!(fd.ftruncate(u64::MAX).unwrap_err().errno(), Some(libc::EINVAL));
+
+    let fd = crate::fd::Fd::open(&path, libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+
+    assert!(fd.ftruncate(0).is_err());
+}
+
+#[test]
+fn test_fallocate() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmpdir = create_tmpdir();
+    let path = tmpdir.path().join("f");
+
+    std::fs::write(&path, vec![1u8; 3 * 65536]).unwrap();
+
+    let fd = crate::fd::Fd::open(&path, libc::O_RDWR | libc::O_CLOEXEC).unwrap();
+    let blocks = std::fs::metadata(&path).unwrap().blocks();
+
+    match fd.fallocate(libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE, 65536, 65536) {
+        Err(ref e) if e.errno() == Some(libc::EOPNOTSUPP) => {
+            eprintln!("no hole punching; skipping test");
+            return;
+        },
+        r => r.unwrap(),
+    }
+
+    let data = std::fs::read(&path).unwrap();
+    let md = std::fs::metadata(&path).unwrap();
+
+    assert_eq!(md.len(), 3 * 65536);
+    assert!(md.blocks() < blocks);
+    assert!(data[..65536].iter().all(|c| *c == 1));
+    assert!(data[65536..2 * 65536].iter().all(|c| *c == 0));
+    assert!(data[2 * 65536..].iter().all(|c| *c == 1));
+
+    // preallocation beyond the end keeps the size with KEEP_SIZE only
+    fd.fallocate(libc::FALLOC_FL_KEEP_SIZE, 3 * 65536, 65536).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 3 * 65536);
+
+    fd.fallocate(0, 3 * 65536, 100).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 3 * 65536 + 100);
+
+    assert_eq!(fd.fallocate(0, u64::MAX, 1).unwrap_err().errno(), Some(libc::EINVAL));
+    assert_eq!(fd.fallocate(0, 0, u64::MAX).unwrap_err().errno(), Some(libc::EINVAL));
+    assert!(fd.fallocate(0, 0, 0).is_err());
+}
+
+#[test]
+fn test_proc_open() {
+    use crate::fd::proc_open;
+    use std::io::Read;
+
+    let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+    let mut status = String::new();
+
+    proc_open(None, &"status", flags).unwrap().reader()
+        .read_to_string(&mut status).unwrap();
+    assert!(status.contains(&format!("\nPid:\t{}\n", std::process::id())));
+
+    assert!(proc_open(Some(std::process::id() as libc::pid_t), &"/task/../maps", flags)
+            .unwrap().metadata().unwrap().is_file());
+
+    // magic links are never followed
+    assert!(proc_open(None, &"cwd", libc::O_PATH | libc::O_CLOEXEC).is_err());
+    assert!(proc_open(None, &"root/etc", libc::O_PATH | libc::O_CLOEXEC).is_err());
+    assert!(proc_open(None, &"fd/0", flags).is_err());
+
+    // ".." stays within the process directory
+    assert!(proc_open(None, &"../self/status", flags).is_err());
+    assert!(proc_open(None, &"../../../../status", flags).is_ok());
+}
+
+#[test]
+fn test_read_uninit() {
+    use std::mem::MaybeUninit;
+
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), b"0123456789").unwrap();
+
+    let fd = crate::fd::Fd::open(&tmpdir.path().join("f"),
+                                 libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    let mut buf = [MaybeUninit::<u8>::uninit(); 4];
+
+    assert_eq!(fd.read_uninit(&mut buf).unwrap(), b"0123");
+    assert_eq!(fd.read_uninit(&mut buf).unwrap(), b"4567");
+    assert_eq!(fd.pread_uninit(&mut buf, 1).unwrap(), b"1234");
+    assert_eq!(fd.pread_uninit(&mut buf, 8).unwrap(), b"89");
+    assert_eq!(fd.read_uninit(&mut buf).unwrap(), b"89");
+    assert_eq!(fd.read_uninit(&mut buf).unwrap(), b"");
+    assert!(fd.pread_uninit(&mut buf, u64::MAX).is_err());
+}
+
+#[test]
+fn test_cloexec() {
+    use crate::chroot::Chroot;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let cloexec = |fd: &crate::fd::FdRaw| {
+        let flags = unsafe { libc::fcntl(fd.fd, libc::F_GETFD) };
+
+        flags & libc::FD_CLOEXEC != 0
+    };
+
+    std::fs::create_dir(tmpdir.path().join("d")).unwrap();
+    std::fs::write(tmpdir.path().join("d/f"), b"").unwrap();
+
+    let chroot = Chroot::new(&tmpdir.path());
+    let inherit = crate::fd::OpenFlags::INHERIT;
+
+    assert!(cloexec(&chroot.open(&"/d/f", libc::O_RDONLY).unwrap()));
+    assert!(cloexec(&chroot.open(&"/d/f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap()));
+    assert!(!cloexec(&chroot.open(&"/d/f", libc::O_RDONLY | inherit).unwrap()));
+    assert!(!cloexec(&chroot.open(&"/d/f", libc::O_RDONLY | libc::O_CLOEXEC | inherit).unwrap()));
+
+    let opts = crate::chroot::CreateOptions {
+        flags:	libc::O_WRONLY | inherit,
+        ..Default::default()
+    };
+
+    assert!(!cloexec(&chroot.create_with(&"/d/g", &opts).unwrap()));
+
+    let dir = chroot.chdir(&"/d").unwrap();
+
+    assert!(!cloexec(&dir.openat(&"f", libc::O_RDONLY | inherit).unwrap()));
+    assert!(cloexec(&dir.openat(&"f", libc::O_RDONLY).unwrap()));
+    assert!(!cloexec(&dir.createat(&"h", libc::O_WRONLY | inherit, 0o600).unwrap()));
+    assert!(cloexec(&crate::fd::Fd::open(&tmpdir.path(), libc::O_RDONLY).unwrap()));
+
+    assert!(cloexec(&dir));
+    dir.set_cloexec(false).unwrap();
+    assert!(!cloexec(&dir));
+    dir.set_cloexec(true).unwrap();
+    assert!(cloexec(&dir));
+}
+
+#[test]
+fn test_above_stdio() {
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), b"").unwrap();
+
+    let path = tmpdir.path().join("f");
+
+    // the thread gets a private descriptor table so that closing stdin
+    // does not affect other tests
+    std::thread::spawn(move || {
+        assert_eq!(unsafe { libc::unshare(libc::CLONE_FILES) }, 0);
+        unsafe { libc::close(0) };
+
+        let fd = crate::fd::FdRaw::open(&path, libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+        let flags = unsafe { libc::fcntl(fd.fd, libc::F_GETFD) };
+
+        assert!(fd.fd >= 3);
+        assert_ne!(flags & libc::FD_CLOEXEC, 0);
+        assert!(unsafe { libc::fcntl(0, libc::F_GETFD) } < 0);
+
+        let fd = crate::fd::FdRaw::open(&path, libc::O_RDONLY |
+                                        crate::fd::OpenFlags::INHERIT).unwrap();
+        let flags = unsafe { libc::fcntl(fd.fd, libc::F_GETFD) };
+
+        assert!(fd.fd >= 3);
+        assert_eq!(flags & libc::FD_CLOEXEC, 0);
+    }).join().unwrap();
+}
+
+#[test]
+fn test_drop_page_cache() {
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), vec![1u8; 1 << 20]).unwrap();
+
+    let fd = crate::fd::FdRaw::open(&tmpdir.path().join("f"),
+                                    libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+
+    assert_ne!(load_page_cache(&tmpdir.path().join("f")), 0);
+
+    fd.drop_page_cache().unwrap();
+
+    if can_drop_page_cache(tmpdir.path()) {
+        assert_eq!(cached_pages(&tmpdir.path().join("f")), 0);
+    }
+
+    assert_eq!(std::fs::read(tmpdir.path().join("f")).unwrap(), vec![1u8; 1 << 20]);
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+
+    let (rd, _wr) = unsafe { (crate::fd::FdRaw::new(fds[0]), crate::fd::FdRaw::new(fds[1])) };
+    assert_eq!(rd.drop_page_cache().unwrap_err().errno(), Some(libc::ESPIPE));
+}
+
+#[test]
+fn test_renameat() {
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::create_dir(tmpdir.path().join("d")).unwrap();
+    std::fs::write(tmpdir.path().join("a"), b"a").unwrap();
+    std::fs::write(tmpdir.path().join("b"), b"b").unwrap();
+
+    let top = crate::fd::FdRaw::open(&tmpdir.path(), libc::O_PATH | libc::O_CLOEXEC |
+                                     libc::O_DIRECTORY).unwrap();
+    let dir = top.openat(&"d", libc::O_PATH | libc::O_CLOEXEC | libc::O_DIRECTORY)
+        .unwrap();
+    let read = |p: &str| std::fs::read(tmpdir.path().join(p)).unwrap();
+
+    top.renameat(&"a", &dir, &"a").unwrap();
+    assert_eq!(read("d/a"), b"a");
+
+    assert_eq!(dir.renameat2(&"a", &top, &"b", libc::RENAME_NOREPLACE)
+               .unwrap_err().errno(), Some(libc::EEXIST));
+
+    dir.renameat2(&"a", &top, &"b", libc::RENAME_EXCHANGE).unwrap();
+    assert_eq!(read("d/a"), b"b");
+    assert_eq!(read("b"), b"a");
+
+    top.renameat2(&"b", &top, &"c", libc::RENAME_NOREPLACE).unwrap();
+    assert_eq!(read("c"), b"a");
+
+    top.renameat(&"c", &dir, &"a").unwrap();
+    assert_eq!(read("d/a"), b"a");
+    assert!(!tmpdir.path().join("c").exists());
+}
+
+#[test]
+fn test_linkat() {
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::create_dir(tmpdir.path().join("d")).unwrap();
+    std::fs::write(tmpdir.path().join("f"), b"f").unwrap();
+    symlink("f", tmpdir.path().join("l")).unwrap();
+
+    let top = crate::fd::FdRaw::open(&tmpdir.path(), libc::O_PATH | libc::O_CLOEXEC |
+                                     libc::O_DIRECTORY).unwrap();
+    let dir = top.openat(&"d", libc::O_PATH | libc::O_CLOEXEC | libc::O_DIRECTORY)
+        .unwrap();
+    let ino = |p: &str| std::fs::symlink_metadata(tmpdir.path().join(p)).unwrap().ino();
+
+    top.linkat(&"f", &dir, &"f", 0).unwrap();
+    assert_eq!(ino("d/f"), ino("f"));
+    assert_eq!(top.linkat(&"f", &dir, &"f", 0).unwrap_err().errno(), Some(libc::EEXIST));
+
+    top.linkat(&"l", &dir, &"l", 0).unwrap();
+    assert_eq!(ino("d/l"), ino("l"));
+
+    top.linkat(&"l", &dir, &"lf", libc::AT_SYMLINK_FOLLOW).unwrap();
+    assert_eq!(ino("d/lf"), ino("f"));
+
+    let fd = top.openat(&"f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+
+    match fd.linkat(&"", &dir, &"empty", libc::AT_EMPTY_PATH) {
+        Ok(_) => assert_eq!(ino("d/empty"), ino("f")),
+        // unprivileged
+        Err(ref e) if e.errno() == Some(libc::ENOENT) => {},
+        Err(e) => panic!("linkat() failed: {:?}", e),
+    }
+}
+
+#[test]
+fn test_fchmodat() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), b"").unwrap();
+    symlink("f", tmpdir.path().join("l")).unwrap();
+
+    let dir = crate::fd::Fd::open(&tmpdir.path(), libc::O_PATH | libc::O_CLOEXEC |
+                                  libc::O_DIRECTORY).unwrap();
+    let mode = |p: &str| std::fs::metadata(tmpdir.path().join(p)).unwrap()
+        .permissions().mode() & 0o7777;
+
+    dir.fchmodat(&"f", 0o640, false).unwrap();
+    assert_eq!(mode("f"), 0o640);
+
+    dir.fchmodat(&"l", 0o604, true).unwrap();
+    assert_eq!(mode("f"), 0o604);
+
+    assert!(dir.fchmodat(&"l", 0o600, false).is_err());
+    assert_eq!(mode("f"), 0o604);
+
+    let fd = dir.open_path_at(&"f").unwrap();
+
+    fd.fchmodat(&"", 0o600, false).unwrap();
+    assert_eq!(mode("f"), 0o600);
+
+    fd.fchmod(0o644).unwrap();
+    assert_eq!(mode("f"), 0o644);
+}
+
+#[test]
+fn test_fchown() {
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not running as root; skipping test");
+        return;
+    }
+
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), b"").unwrap();
+    symlink("f", tmpdir.path().join("l")).unwrap();
+
+    let dir = crate::fd::Fd::open(&tmpdir.path(), libc::O_PATH | libc::O_CLOEXEC |
+                                  libc::O_DIRECTORY).unwrap();
+    let owner = |p: &str| {
+        let md = std::fs::symlink_metadata(tmpdir.path().join(p)).unwrap();
+
+        (md.uid(), md.gid())
+    };
+
+    dir.fchownat(&"l", Some(1000), None, false).unwrap();
+    assert_eq!(owner("l"), (1000, 0));
+    assert_eq!(owner("f"), (0, 0));
+
+    dir.fchownat(&"l", None, Some(1001), true).unwrap();
+    assert_eq!(owner("f"), (0, 1001));
+
+    let fd = dir.open_path_at(&"f").unwrap();
+
+    fd.fchown(Some(1002), None).unwrap();
+    assert_eq!(owner("f"), (1002, 1001));
+
+    let fd = dir.openat(&"f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+
+    fd.fchown(None, Some(1003)).unwrap();
+    assert_eq!(owner("f"), (1002, 1003));
+}
+
+#[test]
+fn test_utimensat() {
+    use std::os::unix::fs::{symlink, MetadataExt};
+    use std::time::{Duration, UNIX_EPOCH};
+    use crate::fd::{timespec_of, UTIME_NOW, UTIME_OMIT};
+
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::write(tmpdir.path().join("f"), b"").unwrap();
+    symlink("f", tmpdir.path().join("l")).unwrap();
+
+    let dir = crate::fd::Fd::open(&tmpdir.path(), libc::O_PATH | libc::O_CLOEXEC |
+                                  libc::O_DIRECTORY).unwrap();
+    let times = |p: &str| {
+        let md = std::fs::symlink_metadata(tmpdir.path().join(p)).unwrap();
+
+        ((md.atime(), md.atime_nsec()), (md.mtime(), md.mtime_nsec()))
+    };
+    let ts = |sec, nsec| libc::timespec { tv_sec: sec, tv_nsec: nsec };
+
+    dir.utimensat(&"l", ts(1000, 1), ts(2000, 2), true).unwrap();
+    assert_eq!(times("f"), ((1000, 1), (2000, 2)));
+
+    dir.utimensat(&"l", ts(3000, 3), ts(4000, 4), false).unwrap();
+    assert_eq!(times("l"), ((3000, 3), (4000, 4)));
+    assert_eq!(times("f"), ((1000, 1), (2000, 2)));
+
+    dir.utimensat(&"f", UTIME_OMIT, UTIME_NOW, false).unwrap();
+    assert_eq!(times("f").0, (1000, 1));
+    assert!(times("f").1 .0 > 4000);
+
+    let fd = dir.open_path_at(&"f").unwrap();
+
+    fd.utimensat(&"", ts(5000, 5), UTIME_OMIT, false).unwrap();
+    assert_eq!(times("f").0, (5000, 5));
+
+    fd.set_times(Some(UNIX_EPOCH + Duration::new(6000, 6)), None).unwrap();
+    assert_eq!(times("f").0, (6000, 6));
+
+    dir.set_times_at(&"f", None, Some(UNIX_EPOCH - Duration::new(1, 250_000_000)), false)
+        .unwrap();
+    assert_eq!(times("f"), ((6000, 6), (-2, 750_000_000)));
+
+    let before = timespec_of(UNIX_EPOCH - Duration::from_secs(10));
+
+    assert_eq!((before.tv_sec, before.tv_nsec), (-10, 0));
+}
@@ -0,0 +1,289 @@
+use std::ffi::OsString;
+
+use crate::dir::DirSnapshot;
+
+#[test]
+fn test_snapshot() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    std::fs::write(dir.join("a"), "a").unwrap();
+    std::fs::write(dir.join("b"), "b").unwrap();
+    std::fs::write(dir.join("c"), "c").unwrap();
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+    let snapshot = DirSnapshot::capture(&fd).unwrap();
+
+    assert!(snapshot.changes_since(&fd).unwrap().is_empty());
+
+    std::fs::remove_file(dir.join("a")).unwrap();
+    std::fs::write(dir.join("b"), "bbb").unwrap();
+    std::fs::write(dir.join("d"), "d").unwrap();
+
+    let changes = snapshot.changes_since(&fd).unwrap();
+
+    assert_eq!(changes.added, vec![OsString::from("d")]);
+    assert_eq!(changes.removed, vec![OsString::from("a")]);
+    assert_eq!(changes.modified, vec![OsString::from("b")]);
+}
+
+#[test]
+fn test_error_policy() {
+    use crate::dir::{Dir, ErrorPolicy};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    std::fs::write(dir.join("a"), "a").unwrap();
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+    let file = crate::fd::Fd::open(&dir.join("a"), libc::O_RDONLY |
+                                   libc::O_CLOEXEC).unwrap();
+
+    // replaces the descriptor of the stream by a regular file so that
+    // every getdents() fails with ENOTDIR
+    let broken_dir = || {
+        let d = Dir::fdopendir(&fd).unwrap();
+
+        assert!(unsafe { libc::dup2(file.fd, libc::dirfd(d.dirp)) } >= 0);
+        d
+    };
+
+    let mut it = broken_dir().readdir();
+    assert!(it.next().unwrap().is_err());
+    assert!(it.next().is_none());
+
+    let mut it = broken_dir().readdir().with_error_policy(ErrorPolicy::Skip);
+    assert!(it.next().is_none());
+    assert!(it.errors().is_empty());
+
+    let mut it = broken_dir().readdir().with_error_policy(ErrorPolicy::Collect);
+    assert!(it.next().is_none());
+    assert_eq!(it.errors().len(), 1);
+    assert_eq!(it.take_errors().len(), 1);
+    assert!(it.errors().is_empty());
+
+    let names: Vec<_> = Dir::fdopendir(&fd).unwrap().readdir()
+        .with_error_policy(ErrorPolicy::Collect)
+        .map(|e| e.unwrap().d_name)
+        .collect();
+    assert_eq!(names, vec![OsString::from("a")]);
+}
+
+#[test]
+fn test_next_from() {
+    use crate::dir::{Dir, DirOffset};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    for i in 0..20 {
+        std::fs::write(dir.join(format!("f{}", i)), "").unwrap();
+    }
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+    let all: Vec<_> = Dir::fdopendir(&fd).unwrap().readdir()
+        .map(|e| e.unwrap())
+        .collect();
+
+    assert_eq!(all.len(), 20);
+
+    // resume with a fresh stream like a stateless server would
+    let cookie = all[4].offset().as_raw();
+    let mut it = Dir::fdopendir(&fd).unwrap().readdir();
+
+    let first = it.next_from(DirOffset::from_raw(cookie)).unwrap().unwrap();
+    let rest: Vec<_> = std::iter::once(first).chain(it.map(|e| e.unwrap()))
+        .map(|e| e.d_name)
+        .collect();
+    let expected: Vec<_> = all[5..].iter().map(|e| e.d_name.clone()).collect();
+
+    assert_eq!(rest, expected);
+
+    let mut it = Dir::fdopendir(&fd).unwrap().readdir();
+    it.by_ref().count();
+
+    assert_eq!(it.next_from(DirOffset::START).map(|e| e.is_ok()), Some(true));
+    assert_eq!(it.count(), 19);
+}
+
+#[test]
+fn test_from_fd_owned() {
+    use crate::dir::Dir;
+    use crate::fd::FdRaw;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    std::fs::write(dir.join("a"), "a").unwrap();
+
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    let fd = FdRaw::open(&dir, flags).unwrap();
+    let names: Vec<_> = Dir::from_fd_owned(fd).unwrap().readdir()
+        .map(|e| e.unwrap().d_name)
+        .collect();
+
+    assert_eq!(names, vec![OsString::from("a")]);
+
+    let fd = FdRaw::open(&dir, libc::O_PATH | libc::O_CLOEXEC).unwrap();
+    assert!(Dir::from_fd_owned(fd).unwrap().readdir().next().unwrap().is_err());
+
+    let names: Vec<_> = crate::fd::Fd::cwd().read_dir_at(&dir).unwrap()
+        .map(|e| e.unwrap().d_name)
+        .collect();
+
+    assert_eq!(names, vec![OsString::from("a")]);
+}
+
+#[test]
+fn test_prefetch_stats() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    for i in 0..10 {
+        std::fs::write(dir.join(format!("f{}", i)), "x".repeat(i)).unwrap();
+    }
+
+    std::os::unix::fs::symlink("f3", dir.join("l")).unwrap();
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+    let mut names = Vec::new();
+
+    for e in fd.read_dir().unwrap().prefetch_stats(4) {
+        let (e, md) = e.unwrap();
+        let md = md.unwrap();
+        let name = e.name().to_str().unwrap().to_string();
+
+        if name == "l" {
+            assert!(md.is_symlink());
+        } else {
+            assert_eq!(md.len(), name[1..].parse::<u64>().unwrap());
+        }
+
+        names.push(name);
+    }
+
+    names.sort();
+    assert_eq!(names.len(), 11);
+    assert_eq!(names[0], "f0");
+    assert_eq!(names[10], "l");
+
+    // entries which were removed before their batch was looked up
+    // fail individually; readdir() has buffered them already
+    let mut iter = fd.read_dir().unwrap().prefetch_stats(1);
+    let (first, md) = iter.next().unwrap().unwrap();
+
+    assert!(md.is_ok());
+    std::fs::remove_file(dir.join(first.name())).unwrap();
+    std::fs::remove_file(dir.join(if first.name() == "f0" { "f1" } else { "f0" })).unwrap();
+
+    let errors = iter.filter(|e| e.as_ref().unwrap().1.is_err()).count();
+
+    assert_eq!(errors, 1);
+}
+
+#[test]
+fn test_stable_entries() {
+    use crate::dir::{Dir, DirEntry, StableEntries};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    std::fs::write(dir.join("a"), "a").unwrap();
+    std::fs::create_dir(dir.join("b")).unwrap();
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+    let mut d = Dir::fdopendir(&fd).unwrap();
+    let res = d.stable_entries().unwrap();
+    let names: Vec<_> = res.entries.iter().map(|e| e.d_name.clone()).collect();
+
+    assert_eq!(names, vec![OsString::from("a"), OsString::from("b")]);
+    assert!(res.appeared.is_empty() && res.disappeared.is_empty());
+
+    // the stream can be reused
+    std::fs::remove_file(dir.join("a")).unwrap();
+    assert_eq!(d.stable_entries().unwrap().entries.len(), 1);
+
+    let entry = |name: &str, ino| DirEntry {
+        d_name:	name.into(),
+        d_ino:	ino,
+        d_off:	0,
+        d_type:	libc::DT_REG,
+    };
+
+    let res = StableEntries::reconcile(
+        vec![entry("x", 1), entry("gone", 2), entry("moved", 3)],
+        vec![entry("moved", 4), entry("x", 1), entry("new", 5), entry("x", 1)]);
+
+    let names = |v: &[DirEntry]| v.iter()
+        .map(|e| (e.d_name.to_str().unwrap().to_string(), e.d_ino))
+        .collect::<Vec<_>>();
+
+    assert_eq!(names(&res.entries), vec![("x".to_string(), 1)]);
+    assert_eq!(names(&res.appeared), vec![("moved".to_string(), 4), ("new".to_string(), 5)]);
+    assert_eq!(names(&res.disappeared), vec![("gone".to_string(), 2), ("moved".to_string(), 3)]);
+}
+
+#[test]
+fn test_from_raw_bytes() {
+    use crate::dir::DirEntry;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    std::fs::write(dir.join("file"), "").unwrap();
+    std::fs::create_dir(dir.join("sub")).unwrap();
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+    let mut buf = vec![0u8; 4096];
+    let len = unsafe {
+        libc::syscall(libc::SYS_getdents64, fd.fd, buf.as_mut_ptr(), buf.len())
+    };
+
+    assert!(len > 0);
+
+    let mut records = &buf[..len as usize];
+    let mut entries = Vec::new();
+
+    while !records.is_empty() {
+        let (e, reclen) = DirEntry::from_raw_bytes(records).unwrap();
+
+        records = &records[reclen..];
+        entries.push(e);
+    }
+
+    entries.sort_by(|a, b| a.d_name.cmp(&b.d_name));
+
+    let names: Vec<_> = entries.iter().map(|e| e.name().to_str().unwrap()).collect();
+
+    assert_eq!(names, [".", "..", "file", "sub"]);
+    assert_eq!(entries[2].d_type, libc::DT_REG);
+    assert_eq!(entries[3].d_type, libc::DT_DIR);
+    assert_eq!(entries[3].d_ino,
+               std::os::unix::fs::MetadataExt::ino(&std::fs::metadata(dir.join("sub")).unwrap()));
+
+    // a record of 24 bytes with the name "ab"
+    let mut raw = vec![0u8; 24];
+
+    raw[0] = 7;
+    raw[8] = 9;
+    raw[16] = 24;
+    raw[18] = libc::DT_LNK;
+    raw[19..21].copy_from_slice(b"ab");
+
+    let (e, reclen) = DirEntry::from_raw_bytes(&raw).unwrap();
+
+    assert_eq!((e.d_ino, e.d_off, e.d_type, reclen), (7, 9, libc::DT_LNK, 24));
+    assert_eq!(e.name(), "ab");
+
+    assert!(DirEntry::from_raw_bytes(&raw[..20]).is_err());
+
+    raw[19..24].copy_from_slice(b"abcde");
+    assert!(DirEntry::from_raw_bytes(&raw).is_err());
+}
@@ -0,0 +1,90 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::chroot::{AuditEvent, Chroot};
+
+/// `Write` implementation whose data can be inspected while the hook
+/// owns it
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_format_json() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let err: crate::errors::Error =
+        std::io::Error::from_raw_os_error(libc::ENOENT).into();
+
+    assert_eq!(super::format_json(&AuditEvent {
+        op:		"openat",
+        path:		Path::new("/a\"b\\c\n\x01"),
+        canonical:	None,
+        error:		Some(&err),
+        latency:	Duration::from_micros(17),
+    }), format!("{{\"op\":\"openat\",\"path\":\"/a\\\"b\\\\c\\n\\u0001\",\
+                 \"canonical\":null,\"result\":\"error\",\"errno\":{},\
+                 \"latency_us\":17}}", libc::ENOENT));
+
+    assert_eq!(super::format_json(&AuditEvent {
+        op:		"apply_metadata",
+        path:		Path::new("/la/f"),
+        canonical:	Some(Path::new("/a/f")),
+        error:		None,
+        latency:	Duration::from_micros(3),
+    }), "{\"op\":\"apply_metadata\",\"path\":\"/la/f\",\"canonical\":\"/a/f\",\
+         \"result\":\"ok\",\"errno\":null,\"latency_us\":3}");
+
+    // invalid UTF-8 is kept as lone surrogates
+    assert_eq!(super::format_json(&AuditEvent {
+        op:		"chdir",
+        path:		Path::new(OsStr::from_bytes(b"/\xff\xc3\xa4")),
+        canonical:	None,
+        error:		None,
+        latency:	Duration::from_micros(1),
+    }), "{\"op\":\"chdir\",\"path\":\"/\\udcff\u{e4}\",\"canonical\":null,\
+         \"result\":\"ok\",\"errno\":null,\"latency_us\":1}");
+}
+
+#[test]
+fn test_json_lines_hook() {
+    let tmpdir = crate::test::create_tmpdir();
+    let buf = SharedBuf::default();
+
+    std::fs::write(tmpdir.path().join("f"), b"").unwrap();
+    std::fs::create_dir(tmpdir.path().join("a")).unwrap();
+    std::fs::write(tmpdir.path().join("a/f"), b"").unwrap();
+    std::os::unix::fs::symlink("/a", tmpdir.path().join("l")).unwrap();
+
+    let chroot = Chroot::new(&tmpdir.path())
+        .with_audit_hook(Some(super::json_lines_hook(buf.clone())));
+
+    chroot.open(&"/f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    assert!(chroot.open(&"/missing", libc::O_RDONLY | libc::O_CLOEXEC).is_err());
+    chroot.open(&"/l/f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    chroot.chdir(&"/l").unwrap();
+
+    let log = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("{\"op\":\"openat\",\"path\":\"/f\",\"canonical\":\"/f\",\
+                                  \"result\":\"ok\",\"errno\":null,\"latency_us\":"));
+    assert!(lines[1].starts_with(&format!("{{\"op\":\"openat\",\"path\":\"/missing\",\
+                                           \"canonical\":null,\"result\":\"error\",\
+                                           \"errno\":{},", libc::ENOENT)));
+    assert!(lines[2].starts_with("{\"op\":\"openat\",\"path\":\"/l/f\",\"canonical\":\"/a/f\","));
+    assert!(lines[3].starts_with("{\"op\":\"chdir\",\"path\":\"/l\",\"canonical\":\"/a\","));
+}
@@ -0,0 +1,155 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::chroot::Chroot;
+use crate::errors::{Error, ErrorKind};
+use super::{expand_braces, GlobOptions, Pattern};
+
+fn matches(pattern: &str, name: &str) -> bool {
+    Pattern::new(&pattern, &GlobOptions::default()).unwrap().matches(&name)
+}
+
+#[test]
+fn test_pattern() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    assert!(matches("*.rs", "lib.rs"));
+    assert!(!matches("*.rs", "lib.rsx"));
+    assert!(matches("a*b*c", "abbbc"));
+    assert!(matches("a*b*c", "abc"));
+    assert!(!matches("a*b*c", "acb"));
+    assert!(matches("?", "x"));
+    assert!(!matches("?", ""));
+    assert!(matches("[a-c]x", "bx"));
+    assert!(!matches("[!a-c]x", "bx"));
+    assert!(matches("[^a-c]x", "dx"));
+    assert!(matches("[]]", "]"));
+    assert!(matches("[[:digit:][:upper:]]", "7"));
+    assert!(matches("[[:digit:][:upper:]]", "Q"));
+    assert!(!matches("[[:digit:][:upper:]]", "q"));
+    assert!(matches("\\*", "*"));
+    assert!(!matches("\\*", "a"));
+
+    // malformed expressions are literal unless strict
+    assert!(matches("[a", "[a"));
+    assert!(matches("a\\", "a\\"));
+
+    // hidden entries
+    assert!(!matches("*", ".git"));
+    assert!(!matches("[.]git", ".git"));
+    assert!(matches(".g*", ".git"));
+
+    let opts = GlobOptions {
+        match_hidden:	true,
+        .. GlobOptions::default()
+    };
+    assert!(Pattern::new(&"*", &opts).unwrap().matches(&".git"));
+
+    // raw bytes
+    let name = OsStr::from_bytes(b"a\xffb");
+    assert!(Pattern::new(&"a?b", &GlobOptions::default()).unwrap().matches(&name));
+    assert!(Pattern::new(&OsStr::from_bytes(b"[\x80-\xff]*"), &GlobOptions::default())
+            .unwrap().matches(&OsStr::from_bytes(b"\xffb")));
+
+    assert_eq!(Pattern::new(&"a\\*b", &GlobOptions::default()).unwrap().literal(),
+               Some(OsString::from("a*b")));
+    assert_eq!(Pattern::new(&"a*b", &GlobOptions::default()).unwrap().literal(), None);
+}
+
+#[test]
+fn test_strict() {
+    let strict = GlobOptions {
+        strict:		true,
+        .. GlobOptions::default()
+    };
+
+    for p in &["[a", "[z-a]", "[[:foo:]]", "a\\", "\\a"] {
+        match Pattern::new(p, &strict) {
+            Err(Error(ErrorKind::InvalidPattern(..), _)) => {},
+            r => panic!("unexpected result for {:?}: {:?}", p, r),
+        }
+    }
+
+    assert!(Pattern::new(&"\\[\\]", &strict).unwrap().matches(&"[]"));
+
+    match expand_braces(&"{a,b", true) {
+        Err(Error(ErrorKind::InvalidPattern(..), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    assert_eq!(expand_braces(&"{a,b", false).unwrap(), vec![OsString::from("{a,b")]);
+}
+
+#[test]
+fn test_expand_braces() {
+    let expand = |p: &str| -> Vec<String> {
+        expand_braces(&p, false).unwrap().into_iter()
+            .map(|s| s.into_string().unwrap())
+            .collect()
+    };
+
+    assert_eq!(expand("{a,b{c,d}}e"), vec!["ae", "bce", "bde"]);
+    assert_eq!(expand("x{,y}"), vec!["x", "xy"]);
+    assert_eq!(expand("{a}"), vec!["{a}"]);
+    assert_eq!(expand("\\{a,b}"), vec!["\\{a,b}"]);
+    assert_eq!(expand("[{]a,b}"), vec!["[{]a,b}"]);
+    assert_eq!(expand("{a/b,c}/d"), vec!["a/b/d", "c/d"]);
+
+    let bomb = "{a,b}".repeat(13);
+
+    match expand_braces(&bomb, false) {
+        Err(Error(ErrorKind::BudgetExceeded("brace expansions", _), _)) => {},
+        r => panic!("unexpected result {:?}", r.map(|v| v.len())),
+    }
+}
+
+#[test]
+fn test_glob() {
+    let tmpdir = crate::test::create_tmpdir();
+    let top = tmpdir.path();
+
+    for d in &["src/bin", "src/tests", "doc", ".git"] {
+        std::fs::create_dir_all(top.join(d)).unwrap();
+    }
+
+    for f in &["src/lib.rs", "src/fd.rs", "src/bin/main.rs", "src/tests/a.inc.rs",
+               "doc/README", ".git/HEAD", "f*"] {
+        std::fs::write(top.join(f), b"").unwrap();
+    }
+
+    std::os::unix::fs::symlink("/src", top.join("lsrc")).unwrap();
+
+    let paths = |v: &[&str]| v.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    let fd = crate::fd::Fd::open(&top, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+
+    assert_eq!(fd.glob_at(&"src/*.rs").unwrap(), paths(&["src/fd.rs", "src/lib.rs"]));
+    assert_eq!(fd.glob_at(&"src/{bin,tests}/*.rs").unwrap(),
+               paths(&["src/bin/main.rs", "src/tests/a.inc.rs"]));
+    assert_eq!(fd.glob_at(&"*/").unwrap(), paths(&["doc", "src"]));
+    assert_eq!(fd.glob_at(&"*/*/*.rs").unwrap(),
+               paths(&["src/bin/main.rs", "src/tests/a.inc.rs"]));
+    assert_eq!(fd.glob_at(&"f\\*").unwrap(), paths(&["f*"]));
+    assert_eq!(fd.glob_at(&"src/missing").unwrap(), paths(&[]));
+    assert_eq!(fd.glob_at(&".git/*").unwrap(), paths(&[".git/HEAD"]));
+    // symlinks to directories are not followed
+    assert_eq!(fd.glob_at(&"lsrc/*.rs").unwrap(), paths(&[]));
+    assert!(fd.glob_at(&"/src").is_err());
+
+    let chroot = Chroot::new(&top);
+
+    // the absolute symlink is resolved within the chroot
+    assert_eq!(chroot.glob(&"/lsrc/[a-k]*.rs").unwrap(), paths(&["/lsrc/fd.rs"]));
+    assert_eq!(chroot.glob(&"/../../*/README").unwrap(), paths(&["/../../doc/README"]));
+    assert_eq!(chroot.glob(&"/doc/README").unwrap(), paths(&["/doc/README"]));
+
+    let opts = GlobOptions {
+        match_hidden:	true,
+        .. GlobOptions::default()
+    };
+
+    assert_eq!(chroot.glob_with(&"/*/HEAD", &opts).unwrap(), paths(&["/.git/HEAD"]));
+    assert!(chroot.glob(&"src/*").is_err());
+}
@@ -0,0 +1,46 @@
+use crate::fd::Fd;
+use crate::handles::{Handle, HandleId, HandleTable};
+
+#[test]
+fn test_handle_table() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = Fd::open(&tmpdir.path(), libc::O_RDONLY | libc::O_DIRECTORY |
+                       libc::O_CLOEXEC).unwrap();
+    let file = dir.createat(&"f", libc::O_RDWR | libc::O_CLOEXEC, 0o600).unwrap();
+    let errno = |r: crate::errors::Result<()>| r.err().and_then(|e| e.errno());
+
+    let mut table = HandleTable::new(2);
+
+    let h_file = table.insert(file.clone()).unwrap();
+    let h_dir = table.insert(dir.read_dir().unwrap()).unwrap();
+
+    assert_eq!(table.len(), 2);
+    assert_eq!(errno(table.insert(file.clone()).map(|_| ())), Some(libc::EMFILE));
+
+    assert_eq!(table.fd(h_file).unwrap().fd, file.fd);
+    assert!(table.dir(h_dir).unwrap().any(|e| e.unwrap().d_name == "f"));
+    assert_eq!(errno(table.fd(h_dir).map(|_| ())), Some(libc::EBADF));
+    assert_eq!(errno(table.dir(h_file).map(|_| ())), Some(libc::EBADF));
+
+    assert_eq!(HandleId::from_u64(h_dir.to_u64()), h_dir);
+
+    match table.close(h_file).unwrap() {
+        Handle::File(fd) => assert_eq!(fd.fd, file.fd),
+        Handle::Dir(_) => panic!("bad handle type"),
+    }
+
+    // the slot is reused but the old handle stays invalid
+    let h_new = table.insert(file.clone()).unwrap();
+
+    assert_ne!(h_new, h_file);
+    assert_eq!(errno(table.fd(h_file).map(|_| ())), Some(libc::EBADF));
+    assert_eq!(errno(table.close(h_file).map(|_| ())), Some(libc::EBADF));
+    assert!(table.fd(h_new).is_ok());
+
+    // descriptors without O_CLOEXEC are refused
+    let inherited = dir.openat(&"f", libc::O_RDONLY | crate::fd::OpenFlags::INHERIT).unwrap();
+
+    table.close(h_new).unwrap();
+    assert_eq!(errno(table.insert(inherited).map(|_| ())), Some(libc::EINVAL));
+    assert_eq!(table.len(), 1);
+}
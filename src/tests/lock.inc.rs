@@ -0,0 +1,56 @@
+use crate::chroot::test::create_test_chroot;
+
+#[test]
+fn test_lock_file() {
+    let (_tmpdir, chroot) = create_test_chroot();
+
+    let lock = chroot.lock_file(&"/lock", true, true).unwrap();
+
+    // flock() locks are per open file description
+    let other = chroot.open(&"/lock", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    assert_eq!(other.flock(libc::LOCK_SH | libc::LOCK_NB).unwrap_err().errno(),
+               Some(libc::EWOULDBLOCK));
+
+    drop(lock);
+
+    other.flock(libc::LOCK_SH | libc::LOCK_NB).unwrap();
+    other.flock(libc::LOCK_UN).unwrap();
+
+    let _shared_a = chroot.lock_file(&"/lock", false, false).unwrap();
+    let _shared_b = chroot.lock_file(&"/lock", false, false).unwrap();
+
+    assert!(chroot.lock_file(&"/missing", false, false).is_err());
+}
+
+#[test]
+fn test_pid_file() {
+    let (_tmpdir, chroot) = create_test_chroot();
+
+    let pid = format!("{}\n", std::process::id());
+
+    {
+        let _pid_file = chroot.pid_file(&"/run.pid").unwrap();
+
+        let content = std::fs::read_to_string(_tmpdir.path().join("chroot/run.pid"))
+            .unwrap();
+        assert_eq!(content, pid);
+
+        let err = chroot.pid_file(&"/run.pid").unwrap_err();
+        assert!(err.to_string().contains(pid.trim()));
+    }
+
+    assert!(!chroot.existsat(&chroot.root_fd().unwrap(), &"/run.pid"));
+
+    // a stale pid file is overwritten
+    chroot.write_atomic(&"/run.pid", b"999999\n").unwrap();
+
+    let _pid_file = chroot.pid_file(&"/run.pid").unwrap();
+    let mut content = String::new();
+    std::io::Read::read_to_string(&mut chroot.buf_reader(&"/run.pid").unwrap(),
+                                  &mut content).unwrap();
+    assert_eq!(content, pid);
+    drop(_pid_file);
+
+    assert!(chroot.pid_file(&"run.pid").unwrap_err().to_string().contains("absolute"));
+    assert!(!chroot.existsat(&chroot.root_fd().unwrap(), &"/run.pid"));
+}
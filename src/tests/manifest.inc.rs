@@ -0,0 +1,78 @@
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::chroot::Chroot;
+use crate::manifest::ManifestOptions;
+
+#[test]
+fn test_manifest() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("src/a/ro")).unwrap();
+    std::fs::write(root.join("src/f"), b"file").unwrap();
+    std::fs::write(root.join("src/a/ro/g"), b"g").unwrap();
+    symlink("../f", root.join("src/a/lf")).unwrap();
+    std::fs::set_permissions(root.join("src/f"),
+                             std::fs::Permissions::from_mode(0o640)).unwrap();
+    std::fs::set_permissions(root.join("src/a/ro"),
+                             std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    let chroot = Chroot::new(&root);
+    let has_xattrs = chroot.open(&"/src/f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap()
+        .setxattr("user.test", b"v", 0).is_ok();
+
+    let manifest = chroot.export_manifest(&"/src", &ManifestOptions {
+        xattrs:	true,
+        digest:	Some(Arc::new(|r| {
+            let mut buf = Vec::new();
+
+            r.read_to_end(&mut buf)?;
+            Ok(buf.iter().rev().cloned().collect())
+        })),
+    }).unwrap();
+
+    let mut paths: Vec<&Path> = manifest.iter().map(|e| e.path.as_path()).collect();
+
+    assert_eq!(paths[0], Path::new("."));
+    paths.sort();
+    assert_eq!(paths, [".", "a", "a/lf", "a/ro", "a/ro/g", "f"].iter()
+               .map(Path::new).collect::<Vec<_>>());
+
+    let entry = |p: &str| manifest.iter().find(|e| e.path == Path::new(p)).unwrap();
+
+    assert_eq!(entry("f").digest.as_deref(), Some(&b"elif"[..]));
+    assert_eq!(entry("a").digest, None);
+    assert_eq!(entry("a/lf").link_target.as_deref(), Some("../f".as_ref()));
+    assert_eq!(entry("a/ro").metadata.mode() & 0o7777, 0o555);
+
+    if has_xattrs {
+        assert_eq!(entry("f").xattrs, vec![("user.test".to_string(), b"v".to_vec())]);
+        assert_eq!(chroot.list_xattrs_recursive(&"/src").unwrap(),
+                   vec![(PathBuf::from("f"), vec![("user.test".to_string(), b"v".to_vec())])]);
+    }
+
+    // restore the metadata on a modified copy
+    chroot.mirror(&"/src", &"/dst").unwrap();
+    std::fs::set_permissions(root.join("dst/a/ro"),
+                             std::fs::Permissions::from_mode(0o700)).unwrap();
+    // files are shared with the mirror
+    std::fs::set_permissions(root.join("src/f"),
+                             std::fs::Permissions::from_mode(0o600)).unwrap();
+
+    chroot.apply_manifest(&"/dst", &manifest).unwrap();
+
+    assert_eq!(std::fs::metadata(root.join("dst/a/ro")).unwrap().mode() & 0o7777, 0o555);
+    assert_eq!(std::fs::metadata(root.join("src/f")).unwrap().mode() & 0o7777, 0o640);
+    assert_eq!(std::fs::metadata(root.join("dst/a/ro")).unwrap().mtime(),
+               entry("a/ro").metadata.mtime().tv_sec);
+
+    // types must match
+    std::fs::remove_file(root.join("dst/a/lf")).unwrap();
+    std::fs::create_dir(root.join("dst/a/lf")).unwrap();
+    assert!(chroot.apply_manifest(&"/dst", &manifest).is_err());
+
+    std::fs::remove_dir(root.join("dst/a/lf")).unwrap();
+    assert!(chroot.apply_manifest(&"/dst", &manifest).is_err());
+}
@@ -0,0 +1,351 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::chroot::Chroot;
+
+#[test]
+fn test_unescape() {
+    assert_eq!(super::unescape(b"/a\\040b\\134"), OsString::from("/a b\\"));
+    assert_eq!(super::unescape(b"/a\\09"), OsString::from("/a\\09"));
+
+    let line = b"64 44 0:39 / /tmp/m\\040t rw,relatime shared:1 - tmpfs none rw";
+    assert_eq!(super::parse_mountinfo_line(line),
+               Some((64, "/tmp/m t".into(), "tmpfs".into(), "none".into())));
+}
+
+fn mount_tmpfs(dir: &Path) -> bool {
+    use crate::LibcString;
+
+    let none = Path::new("none");
+    let tmpfs = Path::new("tmpfs");
+
+    unsafe {
+        libc::mount(none.as_libc().unwrap().0, dir.as_libc().unwrap().0,
+                    tmpfs.as_libc().unwrap().0,
+                    0, std::ptr::null()) == 0
+    }
+}
+
+fn umount(dir: &Path) {
+    use crate::LibcString;
+
+    unsafe { libc::umount(dir.as_libc().unwrap().0) };
+}
+
+/// Runs `f` in a thread with a private mount namespace; mounts made
+/// there are not visible to other threads.  Skips `f` when the
+/// namespace can not be created (e.g. without `CAP_SYS_ADMIN`).
+fn with_private_mount_ns<F: FnOnce() + Send + 'static>(f: F) {
+    use crate::LibcString;
+
+    std::thread::spawn(move || {
+        if unsafe { libc::unshare(libc::CLONE_NEWNS) } < 0 {
+            eprintln!("no mount namespace; skipping test: {:?}",
+                      std::io::Error::last_os_error());
+            return;
+        }
+
+        // do not propagate the mounts of the test into the parent
+        // namespace
+        assert_eq!(unsafe {
+            libc::mount(std::ptr::null(), Path::new("/").as_libc().unwrap().0,
+                        std::ptr::null(), libc::MS_REC | libc::MS_PRIVATE,
+                        std::ptr::null())
+        }, 0);
+
+        f();
+    }).join().unwrap();
+}
+
+#[test]
+fn test_foreign_mounts() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir(root.join("proc")).unwrap();
+        std::fs::create_dir(root.join("d")).unwrap();
+        std::os::unix::fs::symlink("/proc", root.join("d/lproc")).unwrap();
+
+        assert!(mount_tmpfs(&root.join("proc")));
+        std::fs::write(root.join("proc/f"), b"").unwrap();
+
+        let chroot = Chroot::new(&root);
+        let mounts = chroot.foreign_mounts().unwrap();
+
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].path, PathBuf::from("/proc"));
+        assert_eq!(mounts[0].fs_type, OsString::from("tmpfs"));
+
+        let flags = libc::O_RDONLY | libc::O_CLOEXEC;
+
+        assert!(chroot.open(&"/proc/f", flags).is_ok());
+
+        let chroot = Chroot::new(&root).with_mask_foreign_mounts(true);
+
+        assert_eq!(chroot.open(&"/proc/f", flags).unwrap_err().errno(),
+                   Some(libc::ENOENT));
+        assert_eq!(chroot.open(&"/d/lproc/f", flags).unwrap_err().errno(),
+                   Some(libc::ENOENT));
+        assert_eq!(chroot.open(&"/proc", flags).unwrap_err().errno(),
+                   Some(libc::ENOENT));
+        assert!(chroot.open(&"/d", flags).is_ok());
+        assert_eq!(chroot.foreign_mounts().unwrap().len(), 1);
+        assert_eq!(chroot.stats().masked_mounts, 3);
+
+        umount(&root.join("proc"));
+    });
+}
+
+#[test]
+fn test_rename_across_mounts() {
+    use std::os::unix::fs::MetadataExt;
+    use crate::errors::{Error, ErrorKind};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir(root.join("mnt")).unwrap();
+        assert!(mount_tmpfs(&root.join("mnt")));
+
+        std::fs::write(root.join("f"), b"data").unwrap();
+        std::fs::set_permissions(root.join("f"),
+                                 std::os::unix::fs::PermissionsExt::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink("f", root.join("l")).unwrap();
+
+        let chroot = Chroot::new(&root);
+
+        match chroot.rename(&"/f", &"/mnt/f") {
+            Err(Error(ErrorKind::CrossesFilesystem(a, b), _)) => assert_ne!(a, b),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mtime = std::fs::metadata(root.join("f")).unwrap().mtime();
+
+        chroot.rename_or_copy(&"/f", &"/mnt/g").unwrap();
+
+        let md = std::fs::metadata(root.join("mnt/g")).unwrap();
+
+        assert!(!root.join("f").exists());
+        assert_eq!(std::fs::read(root.join("mnt/g")).unwrap(), b"data");
+        assert_eq!(md.mode() & 0o7777, 0o640);
+        assert_eq!(md.mtime(), mtime);
+
+        // only regular files are copied
+        assert!(chroot.rename_or_copy(&"/l", &"/mnt/l").is_err());
+        assert!(root.join("l").symlink_metadata().is_ok());
+
+        // same filesystem
+        chroot.rename(&"/mnt/g", &"/mnt/h").unwrap();
+        assert!(root.join("mnt/h").exists());
+
+        umount(&root.join("mnt"));
+    });
+}
+
+#[test]
+fn test_mirror_across_mounts() {
+    use crate::errors::{Error, ErrorKind};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir_all(root.join("src/mnt")).unwrap();
+        std::fs::write(root.join("src/f"), b"").unwrap();
+        assert!(mount_tmpfs(&root.join("src/mnt")));
+        std::fs::write(root.join("src/mnt/g"), b"").unwrap();
+
+        let chroot = Chroot::new(&root);
+
+        // destination on another mount
+        match chroot.mirror(&"/src", &"/src/mnt/dst") {
+            Err(Error(ErrorKind::CrossesFilesystem(a, b), _)) => assert_ne!(a, b),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(!root.join("src/mnt/dst").exists());
+
+        // mount below the source
+        match chroot.mirror(&"/src", &"/dst") {
+            Err(Error(ErrorKind::CrossesFilesystem(a, b), _)) => assert_ne!(a, b),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        umount(&root.join("src/mnt"));
+    });
+}
+
+#[test]
+fn test_full_path_mount_root() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir_all(root.join("a/mnt")).unwrap();
+        assert!(mount_tmpfs(&root.join("a/mnt")));
+        std::fs::create_dir(root.join("a/mnt/b")).unwrap();
+
+        let chroot = Chroot::new(&root);
+
+        // readdir() of "a" reports the inode of the covered "mnt"
+        let fd = chroot.chdir(&"/a/mnt").unwrap();
+        assert_eq!(chroot.full_path(&fd, Some(&"f")).unwrap(), OsString::from("/a/mnt/f"));
+
+        let fd = chroot.chdir(&"/a/mnt/b").unwrap();
+        assert_eq!(chroot.full_path::<&Path>(&fd, None).unwrap(),
+                   OsString::from("/a/mnt/b"));
+        assert_eq!(chroot.canonicalize(&"/a/mnt/b/../c").unwrap().as_path(),
+                   Path::new("/a/mnt/c"));
+
+        umount(&root.join("a/mnt"));
+    });
+}
+
+#[test]
+fn test_full_path_hidden() {
+    use crate::errors::{Error, ErrorKind};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+
+        let chroot = Chroot::new(&root);
+        let fd = chroot.chdir(&"/a/b/c").unwrap();
+
+        assert_eq!(chroot.full_path(&fd, Some(&"f")).unwrap(), OsString::from("/a/b/c/f"));
+
+        // the lookup of ".." from "b" ends on the mount which hides "a"
+        assert!(mount_tmpfs(&root.join("a")));
+
+        match chroot.full_path(&fd, Some(&"f")) {
+            Err(Error(ErrorKind::PartialPath(suffix, None), _)) =>
+                assert_eq!(suffix, PathBuf::from("c/f")),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        umount(&root.join("a"));
+    });
+}
+
+#[test]
+fn test_readonly_mount() {
+    use crate::errors::{Error, ErrorKind};
+    use crate::LibcString;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir_all(root.join("ro/d")).unwrap();
+        std::fs::write(root.join("ro/f"), b"data").unwrap();
+        std::fs::write(root.join("ro/d/h"), b"").unwrap();
+        std::fs::write(root.join("g"), b"").unwrap();
+
+        let ro = root.join("ro");
+
+        // read-only bind mount of the directory onto itself
+        assert_eq!(unsafe {
+            libc::mount(ro.as_libc().unwrap().0, ro.as_libc().unwrap().0,
+                        std::ptr::null(), libc::MS_BIND, std::ptr::null())
+        }, 0);
+        assert_eq!(unsafe {
+            libc::mount(std::ptr::null(), ro.as_libc().unwrap().0, std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                        std::ptr::null())
+        }, 0);
+
+        let chroot = Chroot::new(&root);
+
+        assert!(chroot.chdir(&"/ro").unwrap().is_on_readonly_mount().unwrap());
+        assert!(!chroot.chdir(&"/").unwrap().is_on_readonly_mount().unwrap());
+
+        match chroot.write_atomic(&"/ro/f", b"new") {
+            Err(e @ Error(ErrorKind::ReadOnlyMount(_), _)) => {
+                assert_eq!(e.errno(), Some(libc::EROFS));
+                assert!(matches!(e.kind(), ErrorKind::ReadOnlyMount(p) if p == Path::new("/ro/f")));
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        match chroot.rename(&"/g", &"/ro/g") {
+            Err(Error(ErrorKind::ReadOnlyMount(p), _)) => assert_eq!(p, PathBuf::from("/ro/g")),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(matches!(chroot.canonicalize(&"/ro/f").unwrap().write_atomic(b"new"),
+                         Err(Error(ErrorKind::ReadOnlyMount(_), _))));
+        assert!(root.join("g").exists());
+        assert_eq!(std::fs::read(root.join("ro/f")).unwrap(), b"data");
+
+        // multi-step operations fail before they changed anything
+        let is_ro = |r: Result<(), Error>, path: &str| match r {
+            Err(Error(ErrorKind::ReadOnlyMount(p), _)) => assert_eq!(p, Path::new(path)),
+            r => panic!("unexpected result {:?} for {}", r, path),
+        };
+        let options = crate::chroot::CreateOptions {
+            create_parents:	true,
+            .. Default::default()
+        };
+
+        is_ro(chroot.create_dir_all(&"/ro/x/y", 0o755).map(|_| ()), "/ro/x");
+        is_ro(chroot.create_with(&"/ro/x/f", &options).map(|_| ()), "/ro/x");
+        is_ro(chroot.remove_file(&"/ro/f"), "/ro/f");
+        is_ro(chroot.remove_dir_all(&"/ro/d"), "/ro/d");
+        is_ro(chroot.clear_dir(&"/ro/d"), "/ro/d");
+        is_ro(chroot.truncate(&"/ro/f", 0), "/ro/f");
+        is_ro(chroot.truncate_front(&"/ro/f", 4096), "/ro/f");
+        is_ro(chroot.snapshot(&"/ro/d", &"/ro/s"), "/ro/s");
+        is_ro(chroot.mirror(&"/ro/d", &"/ro/m"), "/ro/m");
+        is_ro(chroot.pid_file(&"/ro/pid").map(|_| ()), "/ro/pid");
+
+        assert!(root.join("ro/d/h").exists());
+        assert_eq!(std::fs::read(root.join("ro/f")).unwrap(), b"data");
+
+        chroot.write_atomic(&"/g", b"rw").unwrap();
+
+        umount(&ro);
+    });
+}
+
+#[test]
+fn test_audit_bind_mounts() {
+    use crate::LibcString;
+    use crate::audit::AuditIssue;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    with_private_mount_ns(move || {
+        std::fs::create_dir_all(root.join("d/sub")).unwrap();
+        std::fs::create_dir_all(root.join("host")).unwrap();
+        std::fs::write(root.join("host/f"), b"").unwrap();
+        std::fs::write(root.join("f"), b"").unwrap();
+
+        // bind mounts of the same filesystem share st_dev
+        for (src, dst) in &[("host", "d"), ("host/f", "f")] {
+            assert_eq!(unsafe {
+                libc::mount(root.join(src).as_libc().unwrap().0,
+                            root.join(dst).as_libc().unwrap().0,
+                            std::ptr::null(), libc::MS_BIND, std::ptr::null())
+            }, 0);
+        }
+
+        let mut foreign: Vec<PathBuf> = Chroot::new(&root).audit().unwrap()
+            .into_iter()
+            .filter(|f| f.issue == AuditIssue::ForeignMount)
+            .map(|f| f.path)
+            .collect();
+
+        foreign.sort();
+
+        // the content of the mounted directory is not reported
+        assert_eq!(foreign, vec![PathBuf::from("/d"), PathBuf::from("/f")]);
+
+        umount(&root.join("f"));
+        umount(&root.join("d"));
+    });
+}
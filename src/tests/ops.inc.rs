@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::prelude::*;
+use crate::errors::*;
+
+/// in-memory xattr store
+#[derive(Default)]
+struct MockXattrs(RefCell<BTreeMap<String, Vec<u8>>>);
+
+impl XattrExt for MockXattrs {
+    fn getxattr(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.borrow().get(name).cloned())
+    }
+
+    fn setxattr(&self, name: &str, value: &[u8], _flags: libc::c_int) -> Result<()> {
+        self.0.borrow_mut().insert(name.into(), value.to_vec());
+        Ok(())
+    }
+
+    fn removexattr(&self, name: &str) -> Result<()> {
+        match self.0.borrow_mut().remove(name) {
+            Some(_)	=> Ok(()),
+            None	=> Err(std::io::Error::from_raw_os_error(libc::ENODATA).into()),
+        }
+    }
+
+    fn listxattr(&self) -> Result<Vec<String>> {
+        Ok(self.0.borrow().keys().cloned().collect())
+    }
+}
+
+fn copy_xattr<S: XattrExt, D: XattrExt>(src: &S, dst: &D, name: &str) -> Result<bool> {
+    match src.getxattr(name)? {
+        Some(v)	=> dst.setxattr(name, &v, 0).map(|_| true),
+        None	=> Ok(false),
+    }
+}
+
+fn create_file<D: DirExt + FileExt>(dir: &D, name: &str) -> Result<D> {
+    let fd = dir.createat(Path::new(name), libc::O_WRONLY | libc::O_CLOEXEC, 0o600)?;
+
+    fd.fchmod(0o640)?;
+    Ok(fd)
+}
+
+#[test]
+fn test_mock() {
+    let src = MockXattrs::default();
+    let dst = MockXattrs::default();
+
+    src.setxattr("user.a", b"1", 0).unwrap();
+
+    assert!(copy_xattr(&src, &dst, "user.a").unwrap());
+    assert!(!copy_xattr(&src, &dst, "user.b").unwrap());
+    assert_eq!(dst.listxattr().unwrap(), vec!["user.a".to_string()]);
+    assert_eq!(dst.removexattr("user.b").unwrap_err().errno(), Some(libc::ENODATA));
+}
+
+#[test]
+fn test_fd_impls() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = crate::fd::Fd::open(&tmpdir.path(), libc::O_DIRECTORY | libc::O_RDONLY |
+                                  libc::O_CLOEXEC).unwrap();
+
+    let fd = create_file(&dir, "f").unwrap();
+    assert_eq!(FileExt::metadata(&fd).unwrap().mode() & 0o777, 0o640);
+    LockExt::flock(&fd, libc::LOCK_EX).unwrap();
+
+    let raw = create_file(dir.to_fdraw(), "g").unwrap();
+    assert!(FileExt::fstat(&raw).unwrap().st_mode & 0o777 == 0o640);
+
+    DirExt::exchangeat(&dir, Path::new("f"), &dir, Path::new("g")).unwrap();
+    DirExt::sync_dir(&dir).unwrap();
+
+    FileExt::pwrite(&fd, &[1u8; 4096], 0).unwrap();
+    FileExt::punch_front(&fd, 4096).unwrap();
+    assert_eq!(FileExt::fstat(&fd).unwrap().st_size, 4096);
+    FileExt::drop_page_cache(&raw).unwrap();
+    FileExt::drop_page_cache(&fd).unwrap();
+
+    drop(create_file(&dir, "u").unwrap());
+    DirExt::unlinkat(&dir, Path::new("u"), 0).unwrap();
+    assert!(DirExt::fstatat(&dir, Path::new("u"), false).is_err());
+
+    DirExt::renameat(&dir, Path::new("g"), &dir, Path::new("r")).unwrap();
+    assert_eq!(DirExt::renameat2(&dir, Path::new("r"), &dir, Path::new("f"),
+                                 libc::RENAME_NOREPLACE).unwrap_err().errno(),
+               Some(libc::EEXIST));
+
+    DirExt::linkat(&dir, Path::new("f"), &dir, Path::new("l"), 0).unwrap();
+    assert_eq!(DirExt::fstatat(&dir, Path::new("l"), false).unwrap().st_nlink, 2);
+
+    FileExt::ftruncate(&fd, 10).unwrap();
+    assert_eq!(FileExt::fstat(&fd).unwrap().st_size, 10);
+    FileExt::fallocate(&fd, 0, 0, 8192).unwrap();
+    assert_eq!(FileExt::fstat(&fd).unwrap().st_size, 8192);
+
+    let mock = MockXattrs::default();
+    mock.setxattr("user.x", b"v", 0).unwrap();
+
+    // tmpfs might not support user xattrs
+    match copy_xattr(&mock, &fd, "user.x") {
+        Ok(true)	=> assert_eq!(fd.getxattr("user.x").unwrap().unwrap(), b"v"),
+        Ok(false)	=> unreachable!(),
+        Err(e)		=> assert_eq!(e.errno(), Some(libc::EOPNOTSUPP)),
+    }
+}
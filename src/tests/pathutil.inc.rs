@@ -0,0 +1,114 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::errors::ErrorKind;
+use crate::pathutil::validate_component;
+
+#[test]
+fn test_validate_component() {
+    let long = "x".repeat(256);
+
+    for name in &["f", ".f", "..f", "f g", &long[..255]] {
+        assert!(validate_component(name).is_ok(), "{:?}", name);
+    }
+
+    for name in [&b""[..], b".", b"..", b"a/b", b"etc\0passwd", long.as_bytes()].iter() {
+        match validate_component(&OsStr::from_bytes(name)) {
+            Err(crate::errors::Error(ErrorKind::InvalidName(n, _), _)) =>
+                assert_eq!(n.as_bytes(), *name),
+            r => panic!("unexpected result {:?} for {:?}", r, name),
+        }
+    }
+}
+
+#[test]
+fn test_path_bytes() {
+    use crate::chroot::Chroot;
+    use crate::pathutil::PathBytes;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let name = b"\xffname";
+
+    std::fs::write(tmpdir.path().join(OsStr::from_bytes(name)), b"data").unwrap();
+
+    let chroot = Chroot::new(&tmpdir.path());
+    let path = [&b"/"[..], name].concat();
+
+    assert_eq!(chroot.read(&PathBytes::from(&path)).unwrap(), b"data");
+    assert_eq!(chroot.read(&PathBytes::from(&path[..])).unwrap(), b"data");
+    assert_eq!(PathBytes::from(path.clone()).into_path_buf().as_os_str().as_bytes(),
+               &path[..]);
+
+    match chroot.read(&PathBytes::from(&b"/f\0g"[..])) {
+        Err(crate::errors::Error(ErrorKind::InvalidPath(_), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_name_encoding() {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    use crate::pathutil::*;
+
+    let names: Vec<OsString> = [&b"plain"[..], b"50%", "\u{e4}\u{1f600}".as_bytes(),
+                                b"\xff\xfe", b"a\xc3", b"\xed\xb2\x80", b""]
+        .iter().map(|n| OsString::from_vec(n.to_vec())).collect();
+
+    for name in &names {
+        assert_eq!(&percent_decode(&percent_encode(name)).unwrap(), name);
+        assert_eq!(&surrogate_unescape(&surrogate_escape(name)).unwrap(), name);
+    }
+
+    assert_eq!(percent_encode(&names[1]), "50%25");
+    assert_eq!(percent_encode(&names[2]), "\u{e4}\u{1f600}");
+    assert_eq!(percent_encode(&names[3]), "%FF%FE");
+    assert_eq!(surrogate_escape(&names[4]), vec![0x61, 0xdcc3]);
+
+    assert!(percent_decode("%").is_err());
+    assert!(percent_decode("%4").is_err());
+    assert!(percent_decode("%+1").is_err());
+    assert_eq!(percent_decode("%41%c3%A4").unwrap(), OsString::from("A\u{e4}"));
+    assert!(surrogate_unescape(&[0xdc10]).is_err());
+    assert!(surrogate_unescape(&[0xd800]).is_err());
+}
+
+#[test]
+fn test_matches_prefix() {
+    use std::path::PathBuf;
+    use crate::chroot::Chroot;
+    use crate::pathutil::{matches_prefix, normalize_path};
+
+    assert_eq!(normalize_path(&"//a/./b//").unwrap(), PathBuf::from("/a/b"));
+    assert_eq!(normalize_path(&"./a/b/.").unwrap(), PathBuf::from("a/b"));
+    assert_eq!(normalize_path(&"/./").unwrap(), PathBuf::from("/"));
+    assert!(normalize_path(&"/a/../b").is_err());
+
+    for (path, prefix) in &[("/etc", "/etc"), ("/etc/passwd", "/etc"), ("/etc/passwd", "/etc/"),
+                            ("//etc/./passwd", "/etc"), ("/etc", "/"), ("/", "/"),
+                            ("a/b", "a"), ("a", "")] {
+        assert!(matches_prefix(path, prefix), "{:?} {:?}", path, prefix);
+    }
+
+    for (path, prefix) in &[("/etc2", "/etc"), ("/et", "/etc"), ("/etc", "/etc/passwd"),
+                            ("etc/passwd", "/etc"), ("/etc/passwd", "etc"), ("/a", ""),
+                            ("/etc/../root", "/etc"), ("/etc/x", "/etc/..")] {
+        assert!(!matches_prefix(path, prefix), "{:?} {:?}", path, prefix);
+    }
+
+    assert!(!matches_prefix(&OsStr::from_bytes(b"/etc\xff"), &"/etc"));
+    assert!(matches_prefix(&OsStr::from_bytes(b"/etc/\xff"), &"/etc"));
+
+    // symlinks are resolved before the check
+    let tmpdir = crate::test::create_tmpdir();
+
+    std::fs::create_dir_all(tmpdir.path().join("srv/www")).unwrap();
+    std::fs::create_dir(tmpdir.path().join("etc")).unwrap();
+    std::os::unix::fs::symlink("/etc", tmpdir.path().join("srv/www/letc")).unwrap();
+
+    let chroot = Chroot::new(&tmpdir.path());
+    let canon = chroot.canonicalize(&"/srv/www/letc/passwd").unwrap();
+
+    assert!(!matches_prefix(&canon, &"/srv/www"));
+    assert!(matches_prefix(&canon, &"/etc"));
+}
@@ -0,0 +1,26 @@
+use crate::errors::ErrorKind;
+use crate::fd::Fd;
+
+#[test]
+fn test_open_unlinked_link() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir_fd = Fd::open(&tmpdir.path(), libc::O_RDONLY | libc::O_DIRECTORY |
+                          libc::O_CLOEXEC).unwrap();
+
+    let tmp = Fd::open(&tmpdir.path(), libc::O_TMPFILE | libc::O_RDWR |
+                       libc::O_CLOEXEC).unwrap();
+
+    tmp.open_unlinked_link(&dir_fd, &"restored").unwrap();
+    assert!(tmpdir.path().join("restored").exists());
+
+    std::fs::write(tmpdir.path().join("f"), b"f").unwrap();
+
+    let fd = dir_fd.openat(&"f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+
+    std::fs::remove_file(tmpdir.path().join("f")).unwrap();
+
+    match fd.open_unlinked_link(&dir_fd, &"f") {
+        Err(crate::errors::Error(ErrorKind::NotLinkable(_), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+}
@@ -0,0 +1,79 @@
+use std::os::unix::fs::PermissionsExt;
+
+use crate::chroot::Chroot;
+
+#[test]
+fn test_clear_dir() {
+    use std::os::unix::fs::symlink;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().join("root");
+
+    std::fs::create_dir_all(root.join("cache/a/b/c")).unwrap();
+    std::fs::create_dir_all(root.join("keep")).unwrap();
+    std::fs::write(root.join("cache/f"), b"f").unwrap();
+    std::fs::write(root.join("cache/a/b/f"), b"f").unwrap();
+    std::fs::write(root.join("keep/f"), b"f").unwrap();
+    std::fs::write(tmpdir.path().join("outside"), b"f").unwrap();
+    symlink("/keep", root.join("cache/a/lkeep")).unwrap();
+    symlink("../../outside", root.join("cache/lout")).unwrap();
+    std::fs::set_permissions(root.join("cache"),
+                             std::fs::Permissions::from_mode(0o1750)).unwrap();
+
+    let chroot = Chroot::new(&root);
+
+    chroot.clear_dir(&"/cache").unwrap();
+
+    assert_eq!(std::fs::read_dir(root.join("cache")).unwrap().count(), 0);
+    assert_eq!(std::fs::metadata(root.join("cache")).unwrap().permissions().mode() & 0o7777,
+               0o1750);
+    assert!(root.join("keep/f").exists());
+    assert!(tmpdir.path().join("outside").exists());
+
+    // clearing an empty directory succeeds
+    chroot.clear_dir(&"/cache").unwrap();
+
+    std::fs::create_dir_all(root.join("cache/x/y")).unwrap();
+    symlink("/keep", root.join("lkeep")).unwrap();
+
+    assert!(chroot.remove_dir_all(&"/lkeep").is_err());
+    assert!(chroot.remove_dir_all(&"/").is_err());
+    chroot.remove_dir_all(&"/cache").unwrap();
+
+    assert!(!root.join("cache").exists());
+    assert!(root.join("keep/f").exists());
+}
+
+#[test]
+fn test_remove_file() {
+    use std::os::unix::fs::symlink;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().join("root");
+
+    std::fs::create_dir_all(root.join("a/d")).unwrap();
+    std::fs::write(root.join("a/f"), b"f").unwrap();
+    std::fs::write(tmpdir.path().join("outside"), b"f").unwrap();
+    symlink("/a", root.join("la")).unwrap();
+    symlink("../outside", root.join("lout")).unwrap();
+
+    let chroot = Chroot::new(&root);
+
+    chroot.remove_file(&"/la/f").unwrap();
+    assert!(!root.join("a/f").exists());
+
+    // the link itself is removed
+    chroot.remove_file(&"/lout").unwrap();
+    assert!(root.join("lout").symlink_metadata().is_err());
+    assert!(tmpdir.path().join("outside").exists());
+
+    assert_eq!(chroot.remove_file(&"/a/d").unwrap_err().errno(), Some(libc::EISDIR));
+    assert_eq!(chroot.remove_file(&"/a/f").unwrap_err().errno(), Some(libc::ENOENT));
+    assert!(chroot.remove_file(&"/a/..").is_err());
+
+    let dir = chroot.chdir(&"/a").unwrap();
+
+    assert!(dir.unlinkat(&"d", 0).is_err());
+    dir.unlinkat(&"d", libc::AT_REMOVEDIR).unwrap();
+    assert!(!root.join("a/d").exists());
+}
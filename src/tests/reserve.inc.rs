@@ -0,0 +1,34 @@
+use crate::chroot::Chroot;
+
+#[test]
+fn test_reserve() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir(root.join("d")).unwrap();
+
+    let chroot = Chroot::new(&root);
+    let mut reservation = chroot.reserve(&"/d", 1 << 20).unwrap();
+    let md = reservation.fd.metadata().unwrap();
+
+    assert_eq!(reservation.len(), 1 << 20);
+    assert_eq!(md.len(), 1 << 20);
+    assert!(md.as_stat().st_blocks * 512 >= 1 << 20);
+    assert_eq!(md.nlink(), 0);
+
+    // the file is not visible
+    assert_eq!(std::fs::read_dir(root.join("d")).unwrap().count(), 0);
+
+    reservation.shrink_to(4096).unwrap();
+    assert_eq!(reservation.len(), 4096);
+    assert_eq!(reservation.fd.metadata().unwrap().len(), 4096);
+
+    // growing is not possible
+    reservation.shrink_to(1 << 20).unwrap();
+    assert_eq!(reservation.len(), 4096);
+
+    assert!(chroot.reserve(&"/missing", 1).is_err());
+    assert_eq!(chroot.reserve(&"/d", u64::MAX).unwrap_err().errno(), Some(libc::EINVAL));
+    assert!(chroot.reserve(&"/", 1 << 60).is_err());
+    assert_eq!(std::fs::read_dir(root).unwrap().count(), 1);
+}
@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::chroot::Chroot;
+use crate::metadata::Metadata;
+
+#[test]
+fn test_apply_metadata() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().join("root");
+    let events = Arc::new(Mutex::new(Vec::<(PathBuf, bool)>::new()));
+    let hook_events = events.clone();
+
+    std::fs::create_dir_all(root.join("a")).unwrap();
+    std::fs::write(root.join("a/f"), b"f").unwrap();
+    std::fs::write(tmpdir.path().join("outside"), b"").unwrap();
+    std::os::unix::fs::symlink("/a", root.join("la")).unwrap();
+
+    let chroot = Chroot::new(&root)
+        .with_audit_hook(Some(Arc::new(move |ev: &crate::chroot::AuditEvent| {
+            if ev.op == "apply_metadata" {
+                hook_events.lock().unwrap()
+                    .push((ev.path.to_path_buf(), ev.error.is_none()));
+            }
+        })));
+
+    let fd = chroot.open(&"/la/f", libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+    let mut st = fd.fstat().unwrap();
+
+    // changing the owner needs CAP_CHOWN; else, keep our own ids
+    let (uid, gid) = match unsafe { libc::geteuid() } {
+        0 => (1234, 2345),
+        _ => unsafe { (libc::geteuid(), libc::getegid()) },
+    };
+
+    st.st_uid = uid;
+    st.st_gid = gid;
+    st.st_mode = libc::S_IFREG | 0o4751;
+    st.st_mtime = 1_000_000;
+    st.st_mtime_nsec = 42;
+
+    chroot.apply_metadata(&fd, &Metadata::from_stat(st)).unwrap();
+
+    let md = std::fs::metadata(root.join("a/f")).unwrap();
+
+    assert_eq!((md.uid(), md.gid()), (uid, gid));
+    assert_eq!(md.mode() & 0o7777, 0o4751);
+    assert_eq!((md.mtime(), md.mtime_nsec()), (1_000_000, 42));
+
+    assert_eq!(*events.lock().unwrap(), vec![(Path::new("/a/f").to_path_buf(), true)]);
+
+    // descriptors outside of the chroot are rejected; renamed files
+    // are found at their new location, removed ones are rejected
+    let outside = crate::fd::Fd::open(&tmpdir.path().join("outside"),
+                                      libc::O_RDONLY | libc::O_CLOEXEC).unwrap();
+
+    assert!(chroot.apply_metadata(&outside, &Metadata::from_stat(st)).is_err());
+
+    std::fs::rename(root.join("a/f"), root.join("a/g")).unwrap();
+    chroot.apply_metadata(&fd, &Metadata::from_stat(st)).unwrap();
+    assert_eq!(events.lock().unwrap().last().unwrap().0, Path::new("/a/g"));
+
+    std::fs::remove_file(root.join("a/g")).unwrap();
+    assert!(chroot.apply_metadata(&fd, &Metadata::from_stat(st)).is_err());
+}
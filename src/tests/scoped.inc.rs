@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use crate::chroot::Chroot;
+
+#[test]
+fn test_canonicalize() {
+    use std::os::unix::fs::symlink;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::write(root.join("a/b/f"), b"f").unwrap();
+    symlink("/a/b", root.join("lb")).unwrap();
+    symlink("../../lb/f", root.join("a/b/lf")).unwrap();
+
+    let chroot = Chroot::new(&root);
+
+    assert_eq!(chroot.canonicalize(&"/lb/lf").unwrap().as_path(), Path::new("/a/b/f"));
+    assert_eq!(chroot.canonicalize(&"/../lb/new").unwrap().as_path(),
+               Path::new("/a/b/new"));
+    assert_eq!(chroot.canonicalize(&"/").unwrap().as_path(), Path::new("/"));
+    assert_eq!(chroot.canonicalize(&"/lb/..").unwrap().as_path(), Path::new("/a"));
+    assert!(chroot.canonicalize(&"/missing/x").is_err());
+
+    let p = chroot.canonicalize(&"/lb/new").unwrap();
+
+    p.write_atomic(b"new").unwrap();
+    assert_eq!(std::fs::read(root.join("a/b/new")).unwrap(), b"new");
+
+    let mut buf = String::new();
+    std::io::Read::read_to_string(&mut p.open(libc::O_RDONLY | libc::O_CLOEXEC)
+                                  .unwrap().into_rawfd().unwrap().into_file().unwrap(),
+                                  &mut buf).unwrap();
+    assert_eq!(buf, "new");
+
+    p.remove_file().unwrap();
+    assert!(!root.join("a/b/new").exists());
+
+    let p = chroot.canonicalize(&"/a/b/d").unwrap();
+    p.dir_fd().mkdirat(&"d", 0o755).unwrap();
+    assert!(p.remove_file().is_err());
+    p.remove_dir().unwrap();
+
+    let p = chroot.canonicalize(&"/").unwrap();
+    assert!(p.remove_dir().is_err());
+    assert!(p.write_atomic(b"").is_err());
+    assert!(p.open(libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC).is_ok());
+
+    // a scoped path can be passed to the regular API too
+    assert!(chroot.existsat(&chroot.root_fd().unwrap(),
+                            &chroot.canonicalize(&"/lb/lf").unwrap()));
+}
+
+#[test]
+fn test_canonicalize_parent() {
+    use std::os::unix::fs::symlink;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::write(root.join("a/f"), b"f").unwrap();
+    symlink("/a/b", root.join("lb")).unwrap();
+
+    let chroot = Chroot::new(&root);
+
+    assert_eq!(chroot.canonicalize_parent(&"/lb/x/y").unwrap(), Path::new("/a/b/x/y"));
+    assert_eq!(chroot.canonicalize_parent(&"/lb/new").unwrap(), Path::new("/a/b/new"));
+    assert_eq!(chroot.canonicalize_parent(&"/lb/x/../y").unwrap(), Path::new("/a/b/y"));
+    assert_eq!(chroot.canonicalize_parent(&"/x/../../y").unwrap(), Path::new("/y"));
+    assert_eq!(chroot.canonicalize_parent(&"x/y").unwrap(), Path::new("/x/y"));
+    assert_eq!(chroot.canonicalize_parent(&"/lb").unwrap(), Path::new("/a/b"));
+    assert!(chroot.canonicalize_parent(&"/a/f/x").is_err());
+}
@@ -0,0 +1,49 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+use crate::chroot::Chroot;
+use crate::signalfd::SignalFd;
+
+#[test]
+fn test_signalfd() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    assert_eq!(unsafe {
+        libc::mkfifo(crate::LibcString::as_libc(root.join("fifo").as_path()).unwrap().0,
+                     0o600)
+    }, 0);
+
+    // signals are routed to this thread; it is the only one which
+    // blocks them
+    std::thread::spawn(move || {
+        let sig = libc::SIGRTMIN() + 1;
+        let sfd = SignalFd::new(&[sig]).unwrap();
+        let chroot = Chroot::new(&root).with_type_guard(false);
+
+        assert!(sfd.contains(sig));
+        assert!(!sfd.contains(libc::SIGIO));
+        assert!(sfd.read().unwrap().is_none());
+
+        let fd = chroot.open_signal_driven(&"/fifo", libc::O_RDONLY, &sfd, sig).unwrap();
+
+        assert!(fd.route_signals(&sfd, libc::SIGIO).is_err());
+
+        let mut wr = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(root.join("fifo"))
+            .unwrap();
+
+        wr.write_all(b"x").unwrap();
+
+        let info = sfd.read().unwrap().expect("no signal");
+
+        assert_eq!(info.ssi_signo, sig as u32);
+        assert_eq!(info.ssi_fd, fd.fd);
+        assert_ne!(info.ssi_band as libc::c_short & libc::POLLIN, 0);
+
+        drop(wr);
+        drop(fd);
+    }).join().unwrap();
+}
@@ -0,0 +1,92 @@
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+use crate::chroot::Chroot;
+
+#[test]
+fn test_snapshot() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+    // changing the owner needs CAP_CHOWN
+    let is_root = unsafe { libc::geteuid() } == 0;
+
+    std::fs::create_dir_all(root.join("src/a/ro")).unwrap();
+    std::fs::write(root.join("src/f"), b"file").unwrap();
+    std::fs::write(root.join("src/a/ro/g"), vec![7u8; 100000]).unwrap();
+
+    if is_root {
+        std::os::unix::fs::chown(root.join("src/f"), Some(1234), Some(5678)).unwrap();
+    }
+    std::fs::set_permissions(root.join("src/f"),
+                             std::fs::Permissions::from_mode(0o4751)).unwrap();
+    symlink("../f", root.join("src/a/lf")).unwrap();
+    std::fs::set_permissions(root.join("src/a/ro"),
+                             std::fs::Permissions::from_mode(0o555)).unwrap();
+    symlink("/src", root.join("lsrc")).unwrap();
+
+    let chroot = Chroot::new(&root).with_drop_page_cache(true);
+    let can_drop = crate::test::can_drop_page_cache(root);
+
+    assert_ne!(crate::test::load_page_cache(&root.join("src/a/ro/g")), 0);
+
+    chroot.snapshot(&"/lsrc", &"/dst").unwrap();
+
+    // the copied source pages are clean and were dropped
+    if can_drop {
+        assert_eq!(crate::test::cached_pages(&root.join("src/a/ro/g")), 0);
+    }
+
+    let md = std::fs::symlink_metadata(root.join("dst/f")).unwrap();
+    assert_eq!(md.mode() & 0o7777, 0o4751);
+    if is_root {
+        assert_eq!((md.uid(), md.gid()), (1234, 5678));
+    }
+    assert_eq!(std::fs::read(root.join("dst/f")).unwrap(), b"file");
+    assert_eq!(std::fs::read(root.join("dst/a/ro/g")).unwrap(), vec![7u8; 100000]);
+    assert_eq!(std::fs::read_link(root.join("dst/a/lf")).unwrap(),
+               std::path::PathBuf::from("../f"));
+    assert_eq!(std::fs::metadata(root.join("dst/a/ro")).unwrap().mode() & 0o7777,
+               0o555);
+
+    // copies are independent
+    std::fs::write(root.join("dst/f"), b"changed").unwrap();
+    assert_eq!(std::fs::read(root.join("src/f")).unwrap(), b"file");
+
+    assert!(chroot.snapshot(&"/src", &"/dst").is_err());
+
+    // destination below the source
+    chroot.snapshot(&"/src", &"/src/snap").unwrap();
+    assert!(root.join("src/snap/a/ro/g").exists());
+    assert!(!root.join("src/snap/snap").exists());
+}
+
+#[test]
+fn test_mirror() {
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("src/a/ro")).unwrap();
+    std::fs::write(root.join("src/f"), b"file").unwrap();
+    std::fs::write(root.join("src/a/ro/g"), b"g").unwrap();
+    symlink("../f", root.join("src/a/lf")).unwrap();
+    std::fs::set_permissions(root.join("src/a/ro"),
+                             std::fs::Permissions::from_mode(0o555)).unwrap();
+
+    let chroot = Chroot::new(&root);
+
+    chroot.mirror(&"/src", &"/dst").unwrap();
+
+    let md = std::fs::metadata(root.join("dst/f")).unwrap();
+    assert_eq!(md.ino(), std::fs::metadata(root.join("src/f")).unwrap().ino());
+    assert_eq!(md.nlink(), 2);
+    assert_eq!(std::fs::read(root.join("dst/a/ro/g")).unwrap(), b"g");
+    assert_eq!(std::fs::read_link(root.join("dst/a/lf")).unwrap(),
+               std::path::PathBuf::from("../f"));
+    assert_eq!(std::fs::metadata(root.join("dst/a/ro")).unwrap().mode() & 0o7777,
+               0o555);
+
+    // replacing a file in the source keeps the mirrored content
+    chroot.write_atomic(&"/src/f", b"new").unwrap();
+    assert_eq!(std::fs::read(root.join("dst/f")).unwrap(), b"file");
+
+    assert!(chroot.mirror(&"/src", &"/dst").is_err());
+}
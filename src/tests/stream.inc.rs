@@ -0,0 +1,58 @@
+use std::ffi::OsString;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+
+use futures_core::Stream;
+
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Collects all entries of `stream` by polling it from this thread.
+fn collect(mut stream: super::ReadDirStream) -> Vec<OsString> {
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut res = Vec::new();
+
+    loop {
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(e)) => res.push(e.unwrap().d_name),
+            Poll::Ready(None) => break,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+
+    res
+}
+
+#[test]
+fn test_stream() {
+    let tmpdir = crate::test::create_tmpdir();
+    let dir = tmpdir.path();
+
+    for i in 0..200 {
+        std::fs::write(dir.join(format!("f{}", i)), "").unwrap();
+    }
+
+    let fd = crate::fd::Fd::open(&dir, libc::O_RDONLY | libc::O_DIRECTORY |
+                                 libc::O_CLOEXEC).unwrap();
+
+    let mut names = collect(fd.read_dir().unwrap().into_stream().unwrap());
+    let mut expected: Vec<_> = fd.read_dir().unwrap()
+        .map(|e| e.unwrap().d_name)
+        .collect();
+
+    names.sort();
+    expected.sort();
+
+    assert_eq!(names.len(), 200);
+    assert_eq!(names, expected);
+
+    // dropping an unfinished stream terminates the worker
+    drop(fd.read_dir().unwrap().into_stream().unwrap());
+}
@@ -0,0 +1,291 @@
+use std::path::{Path, PathBuf};
+
+use crate::chroot::Chroot;
+use crate::visit::VisitAction;
+
+#[test]
+fn test_visit() {
+    use std::os::unix::fs::symlink;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+    std::fs::create_dir_all(root.join("a/skip/x")).unwrap();
+    std::fs::write(root.join("a/b/f"), b"f").unwrap();
+    symlink("/a/b", root.join("la")).unwrap();
+    symlink("/", root.join("a/b/up")).unwrap();
+
+    let chroot = Chroot::new(&root);
+    let mut seen = Vec::new();
+
+    chroot.visit(&"/la/..", |e| {
+        assert_eq!(e.parent.fstatat(&e.entry.d_name, false).unwrap().st_ino,
+                   e.metadata.ino());
+
+        seen.push((e.depth, e.path.to_path_buf()));
+
+        Ok(if e.entry.d_name == "skip" {
+            VisitAction::Skip
+        } else {
+            VisitAction::Descend
+        })
+    }).unwrap();
+
+    seen.sort();
+
+    let expected: Vec<(usize, PathBuf)> = [
+        (1, "/a/b"), (1, "/a/skip"),
+        (2, "/a/b/c"), (2, "/a/b/f"), (2, "/a/b/up"),
+    ].iter().map(|(d, p)| (*d, Path::new(p).to_path_buf())).collect();
+
+    assert_eq!(seen, expected);
+
+    // entries are reported before their content
+    let mut cnt = 0;
+
+    chroot.visit(&"/", |e| {
+        cnt += 1;
+
+        Ok(if e.path == Path::new("/a") {
+            VisitAction::Stop
+        } else {
+            VisitAction::Skip
+        })
+    }).unwrap();
+
+    assert!(cnt <= 2);
+
+    assert!(chroot.visit(&"/a", |_| bail!("failed")).is_err());
+}
+
+#[test]
+fn test_visit_budget() {
+    use crate::errors::{Error, ErrorKind};
+    use crate::visit::VisitOptions;
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("a/b")).unwrap();
+    std::fs::write(root.join("a/f"), b"0123456789").unwrap();
+    std::fs::write(root.join("a/b/f"), b"0123456789").unwrap();
+
+    let chroot = Chroot::new(&root);
+    let descend = |_: &crate::visit::VisitEntry| Ok(VisitAction::Descend);
+
+    chroot.visit_with(&"/", &VisitOptions {
+        max_entries:		Some(4),
+        max_total_bytes:	Some(20),
+        .. VisitOptions::default()
+    }, descend).unwrap();
+
+    match chroot.visit_with(&"/", &VisitOptions {
+        max_entries:	Some(3),
+        .. VisitOptions::default()
+    }, descend) {
+        Err(Error(ErrorKind::BudgetExceeded("entries", 3), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    match chroot.visit_with(&"/", &VisitOptions {
+        max_total_bytes:	Some(19),
+        .. VisitOptions::default()
+    }, descend) {
+        Err(Error(ErrorKind::BudgetExceeded("bytes", 19), _)) => {},
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // skipped directories do not count
+    chroot.visit_with(&"/", &VisitOptions {
+        max_entries:	Some(1),
+        .. VisitOptions::default()
+    }, |_| Ok(VisitAction::Skip)).unwrap();
+
+    let mut files = 0;
+    let can_drop = crate::test::can_drop_page_cache(root);
+
+    crate::test::load_page_cache(&root.join("a/f"));
+    crate::test::load_page_cache(&root.join("a/b/f"));
+
+    chroot.visit_with(&"/", &VisitOptions {
+        drop_page_cache:	true,
+        .. VisitOptions::default()
+    }, |e| {
+        files += e.metadata.is_file() as usize;
+        Ok(VisitAction::Descend)
+    }).unwrap();
+
+    assert_eq!(files, 2);
+
+    if can_drop {
+        assert_eq!(crate::test::cached_pages(&root.join("a/f")), 0);
+        assert_eq!(crate::test::cached_pages(&root.join("a/b/f")), 0);
+    }
+}
+
+#[test]
+fn test_visit_order() {
+    use crate::visit::{VisitOptions, VisitOrder};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path();
+
+    std::fs::create_dir_all(root.join("a/b/c")).unwrap();
+    std::fs::create_dir_all(root.join("d")).unwrap();
+
+    let chroot = Chroot::new(&root);
+    let walk = |order| {
+        let mut seen = Vec::new();
+
+        chroot.visit_with(&"/", &VisitOptions {
+            order:	order,
+            .. VisitOptions::default()
+        }, |e| {
+            assert_eq!(e.parent.fstatat(&e.entry.d_name, false).unwrap().st_ino,
+                       e.metadata.ino());
+
+            seen.push((e.depth, e.path.to_str().unwrap().to_string()));
+            Ok(VisitAction::Descend)
+        }).unwrap();
+
+        seen
+    };
+
+    let pos = |seen: &[(usize, String)], p: &str| {
+        seen.iter().position(|(_, s)| s == p).unwrap()
+    };
+
+    let pre = walk(VisitOrder::PreOrder);
+
+    assert_eq!(pre.len(), 4);
+    assert!(pos(&pre, "/a") < pos(&pre, "/a/b"));
+    assert!(pos(&pre, "/a/b") < pos(&pre, "/a/b/c"));
+
+    let post = walk(VisitOrder::PostOrder);
+
+    assert_eq!(post.len(), 4);
+    assert!(pos(&post, "/a/b/c") < pos(&post, "/a/b"));
+    assert!(pos(&post, "/a/b") < pos(&post, "/a"));
+    assert!(post.contains(&(3, "/a/b/c".to_string())));
+    assert!(post.contains(&(1, "/a".to_string())));
+
+    let bfs = walk(VisitOrder::BreadthFirst);
+    let depths: Vec<usize> = bfs.iter().map(|(d, _)| *d).collect();
+
+    assert_eq!(depths, vec![1, 1, 2, 3]);
+}
+
+#[test]
+fn test_visit_vanished() {
+    use crate::dir::ErrorPolicy;
+    use crate::visit::{VisitOptions, VisitOrder};
+
+    let tmpdir = crate::test::create_tmpdir();
+    let root = tmpdir.path().to_path_buf();
+
+    std::fs::create_dir_all(root.join("a/d")).unwrap();
+    std::fs::create_dir_all(root.join("b")).unwrap();
+
+    for i in 0..8 {
+        std::fs::write(root.join(format!("a/f{}", i)), b"").unwrap();
+    }
+
+    let chroot = Chroot::new(&root);
+    let mut seen = Vec::new();
+    let mut removed = false;
+
+    // the first entry of /a removes the directory while it is read
+    chroot.visit(&"/", |e| {
+        if e.depth == 2 && !removed {
+            std::fs::remove_dir_all(root.join("a")).unwrap();
+            removed = true;
+        }
+
+        seen.push(e.path.to_path_buf());
+
+        Ok(VisitAction::Descend)
+    }).unwrap();
+
+    assert!(seen.contains(&PathBuf::from("/b")));
+    assert_eq!(seen.iter().filter(|p| p.parent() == Some(Path::new("/a"))).count(), 1);
+
+    std::fs::create_dir(root.join("c")).unwrap();
+    std::fs::create_dir(root.join("c/d")).unwrap();
+
+    // /c/d is replaced by a file before it is descended into
+    chroot.visit(&"/c", |e| {
+        std::fs::remove_dir(root.join("c/d")).unwrap();
+        std::fs::write(root.join("c/d"), b"").unwrap();
+
+        assert!(e.metadata.is_dir());
+        Ok(VisitAction::Descend)
+    }).unwrap();
+
+    // vanished entries are reported by the error policy
+    let replace = |e: &crate::visit::VisitEntry| {
+        if e.path == Path::new("/c/d/e") {
+            std::fs::remove_dir(root.join("c/d/e")).unwrap();
+        }
+
+        Ok(VisitAction::Descend)
+    };
+
+    std::fs::remove_file(root.join("c/d")).unwrap();
+    std::fs::create_dir(root.join("c/d")).unwrap();
+    std::fs::create_dir(root.join("c/d/e")).unwrap();
+
+    let opts = VisitOptions { vanished: ErrorPolicy::Collect, ..Default::default() };
+    let vanished = chroot.visit_collect(&"/c", &opts, replace).unwrap();
+
+    assert_eq!(vanished.len(), 1);
+    assert_eq!(vanished[0].0, PathBuf::from("/c/d/e"));
+    assert_eq!(vanished[0].1.errno(), Some(libc::ENOENT));
+
+    std::fs::create_dir(root.join("c/d/e")).unwrap();
+
+    let opts = VisitOptions { vanished: ErrorPolicy::Stop, ..Default::default() };
+
+    assert_eq!(chroot.visit_with(&"/c", &opts, replace).unwrap_err().errno(),
+               Some(libc::ENOENT));
+
+    // directories queued in breadth-first order are not kept open;
+    // replacing them is noticed when they are opened later
+    std::fs::create_dir_all(root.join("q/x")).unwrap();
+    std::fs::create_dir_all(root.join("q/y")).unwrap();
+
+    let opts = VisitOptions {
+        order:		VisitOrder::BreadthFirst,
+        vanished:	ErrorPolicy::Collect,
+        ..Default::default()
+    };
+    let mut seen = Vec::new();
+    let vanished = chroot.visit_collect(&"/q", &opts, |e| {
+        if e.depth == 1 {
+            let name = root.join("q").join(&e.entry.d_name);
+
+            let tmp = root.join("q-new");
+
+            // same path, other directory
+            std::fs::create_dir(&tmp).unwrap();
+            std::fs::write(tmp.join("new"), b"").unwrap();
+            std::fs::rename(&tmp, &name).unwrap();
+        }
+
+        seen.push(e.path.to_path_buf());
+        Ok(VisitAction::Descend)
+    }).unwrap();
+
+    let mut paths: Vec<_> = vanished.iter().map(|(p, _)| p.clone()).collect();
+
+    paths.sort();
+    assert_eq!(paths, [PathBuf::from("/q/x"), PathBuf::from("/q/y")]);
+    assert_eq!(seen.len(), 2);
+
+    let sub = chroot.chdir(&"/b").unwrap();
+    std::fs::remove_dir(root.join("b")).unwrap();
+
+    // the removed directory has no parent entry anymore
+    assert!(chroot.full_path::<&Path>(&sub, None).is_err());
+    assert_eq!(chroot.full_path::<&Path>(&chroot.root_fd().unwrap(), None).unwrap(), "/");
+}
@@ -0,0 +1,123 @@
+//! Lookup of user and group names
+//!
+//! Names can be resolved either by the host's NSS functions
+//! (`getpwuid_r()` and friends) or by parsing the `/etc/passwd` and
+//! `/etc/group` files of a chroot.
+extern crate libc;
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::io::BufRead;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::chroot::Chroot;
+use crate::errors::*;
+
+/// Selects the database used to resolve user and group names
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameLookup {
+    /// use the NSS functions of the host
+    Host,
+    /// parse `/etc/passwd` and `/etc/group` within the chroot
+    Chroot,
+}
+
+/// Calls a reentrant NSS function and grows the buffer on `ERANGE`.
+macro_rules! nss_lookup {
+    ($fn:ident, $ent:ty, $key:expr, $map:expr) => {{
+        let mut buf = vec![0u8; 1024];
+
+        loop {
+            let mut ent = std::mem::MaybeUninit::<$ent>::uninit();
+            let mut res: *mut $ent = std::ptr::null_mut();
+
+            let rc = unsafe {
+                libc::$fn($key, ent.as_mut_ptr(), buf.as_mut_ptr() as *mut _,
+                          buf.len(), &mut res)
+            };
+
+            if rc == libc::ERANGE {
+                let len = buf.len();
+                buf.resize(len * 2, 0);
+                continue;
+            }
+
+            if rc != 0 {
+                break Err(std::io::Error::from_raw_os_error(rc).into());
+            }
+
+            if res.is_null() {
+                break Ok(None);
+            }
+
+            break Ok(Some($map(unsafe { &*res })));
+        }
+    }}
+}
+
+fn to_os_string(s: *const libc::c_char) -> OsString {
+    let s = unsafe { CStr::from_ptr(s) };
+
+    OsStr::from_bytes(s.to_bytes()).to_os_string()
+}
+
+/// Returns the name of user `uid` in the host database
+pub fn user_name(uid: libc::uid_t) -> Result<Option<OsString>> {
+    nss_lookup!(getpwuid_r, libc::passwd, uid,
+                |p: &libc::passwd| to_os_string(p.pw_name))
+}
+
+/// Returns the name of group `gid` in the host database
+pub fn group_name(gid: libc::gid_t) -> Result<Option<OsString>> {
+    nss_lookup!(getgrgid_r, libc::group, gid,
+                |g: &libc::group| to_os_string(g.gr_name))
+}
+
+/// Returns the id of user `name` in the host database
+pub fn user_id(name: &str) -> Result<Option<libc::uid_t>> {
+    let name = CString::new(name).chain_err(|| "invalid user name")?;
+
+    nss_lookup!(getpwnam_r, libc::passwd, name.as_ptr(),
+                |p: &libc::passwd| p.pw_uid)
+}
+
+/// Returns the id of group `name` in the host database
+pub fn group_id(name: &str) -> Result<Option<libc::gid_t>> {
+    let name = CString::new(name).chain_err(|| "invalid group name")?;
+
+    nss_lookup!(getgrnam_r, libc::group, name.as_ptr(),
+                |g: &libc::group| g.gr_gid)
+}
+
+/// Looks up `name` in a `passwd(5)` or `group(5)` formatted file; the
+/// id is in the third field for both.
+fn parse_db_id<R: BufRead>(reader: R, name: &str) -> Result<Option<u32>> {
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let mut fields = line.split(|c| *c == b':');
+
+        if fields.next() != Some(name.as_bytes()) {
+            continue;
+        }
+
+        let id = fields.nth(1)
+            .and_then(|id| std::str::from_utf8(id).ok())
+            .and_then(|id| id.parse().ok());
+
+        ensure!(id.is_some(), "bad database entry for '{}'", name);
+
+        return Ok(id);
+    }
+
+    Ok(None)
+}
+
+/// Returns the id of user `name` in the `/etc/passwd` of `chroot`
+pub fn chroot_user_id(chroot: &Chroot, name: &str) -> Result<Option<libc::uid_t>> {
+    parse_db_id(chroot.buf_reader(&Path::new("/etc/passwd"))?, name)
+}
+
+/// Returns the id of group `name` in the `/etc/group` of `chroot`
+pub fn chroot_group_id(chroot: &Chroot, name: &str) -> Result<Option<libc::gid_t>> {
+    parse_db_id(chroot.buf_reader(&Path::new("/etc/group"))?, name)
+}
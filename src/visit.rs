@@ -0,0 +1,433 @@
+//! Recursive traversal of directory trees within a chroot
+extern crate libc;
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::chroot::Chroot;
+use crate::control::Progress;
+use crate::dir::{DirEntry, ErrorPolicy, ReadDir};
+use crate::fd::Fd;
+use crate::metadata::Metadata;
+use crate::errors::*;
+
+/// Decision of a `Chroot::visit()` callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisitAction {
+    /// continue but do not descend into the entry
+    Skip,
+    /// descend into the entry when it is a directory; continue else
+    Descend,
+    /// abort the traversal
+    Stop,
+}
+
+/// Entry passed to a `Chroot::visit()` callback
+#[derive(Debug)]
+pub struct VisitEntry<'a> {
+    /// number of directories between the start and the entry; direct
+    /// children of the start directory have depth 1
+    pub depth:		usize,
+    /// absolute path within the chroot without symlinks
+    pub path:		&'a Path,
+    /// directory containing the entry
+    pub parent:		&'a Fd,
+    pub entry:		&'a DirEntry,
+    /// `lstat()` information of the entry
+    pub metadata:	&'a Metadata,
+}
+
+/// Order in which `Chroot::visit_with()` reports entries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VisitOrder {
+    /// depth-first; directories are reported before their content
+    #[default]
+    PreOrder,
+    /// depth-first; directories are reported after their content so
+    /// that it can be removed safely.  Every directory is descended
+    /// into; `VisitAction::Skip` and `VisitAction::Descend` have no
+    /// effect.
+    PostOrder,
+    /// all entries of a depth are reported before those of the next
+    /// one.  Directories which were descended into are not kept open
+    /// but resolved again by their path when their content is
+    /// reported; they count as vanished when they were replaced
+    /// meanwhile.
+    BreadthFirst,
+}
+
+/// Options for `Chroot::visit_with()`
+///
+/// Budgets are checked before an entry is passed to the callback;
+/// the traversal fails with `ErrorKind::BudgetExceeded` when they are
+/// exceeded.
+#[derive(Clone, Debug)]
+pub struct VisitOptions {
+    /// do not descend into directories on other devices than the
+    /// start directory
+    pub one_file_system:	bool,
+    /// maximum number of reported entries
+    pub max_entries:		Option<u64>,
+    /// maximum sum of the sizes of reported regular files
+    pub max_total_bytes:	Option<u64>,
+    /// order in which entries are reported
+    pub order:			VisitOrder,
+    /// drop the page cache of regular files after `visitor` returned
+    /// for them; see `FdRaw::drop_page_cache()`
+    pub drop_page_cache:	bool,
+    /// handling of entries which disappear while walking; `Stop` fails
+    /// with their error, `Collect` returns them from
+    /// `Chroot::visit_collect()`
+    pub vanished:		ErrorPolicy,
+}
+
+impl Default for VisitOptions {
+    fn default() -> Self {
+        VisitOptions {
+            one_file_system:	false,
+            max_entries:	None,
+            max_total_bytes:	None,
+            order:		VisitOrder::default(),
+            drop_page_cache:	false,
+            vanished:		ErrorPolicy::Skip,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Budget {
+    entries:	u64,
+    bytes:	u64,
+}
+
+impl Budget {
+    fn account(&mut self, opts: &VisitOptions, md: &Metadata) -> Result<()> {
+        self.entries += 1;
+
+        if md.is_file() {
+            self.bytes = self.bytes.saturating_add(md.len());
+        }
+
+        match (opts.max_entries, opts.max_total_bytes) {
+            (Some(max), _) if self.entries > max =>
+                bail!(ErrorKind::BudgetExceeded("entries", max)),
+            (_, Some(max)) if self.bytes > max =>
+                bail!(ErrorKind::BudgetExceeded("bytes", max)),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Level {
+    /// depth of the entries
+    depth:	usize,
+    dir_fd:	Fd,
+    path:	PathBuf,
+    entries:	ReadDir,
+    /// the directory itself; reported after its content in post-order
+    pending:	Option<(DirEntry, Metadata)>,
+}
+
+/// Lists `dir_fd`; errors are checked when the level is done
+fn read_entries(dir_fd: &Fd) -> Result<ReadDir> {
+    Ok(dir_fd.read_dir()?.with_error_policy(ErrorPolicy::Collect))
+}
+
+/// Directory whose content is reported later in breadth-first order;
+/// it is not kept open meanwhile.
+struct Queued {
+    depth:	usize,
+    path:	PathBuf,
+    metadata:	Metadata,
+}
+
+/// Checks whether `e` was caused by a directory which was removed
+/// or replaced by another type of file after it was listed.
+fn is_vanished(e: &Error) -> bool {
+    matches!(e.errno(), Some(libc::ENOENT) | Some(libc::ENOTDIR) | Some(libc::ELOOP))
+}
+
+/// Implements `VisitOptions::vanished`
+struct Vanished {
+    policy:	ErrorPolicy,
+    errors:	Vec<(PathBuf, Error)>,
+}
+
+impl Vanished {
+    fn handle(&mut self, path: &Path, e: Error) -> Result<()> {
+        match self.policy {
+            ErrorPolicy::Stop		=> return Err(e),
+            ErrorPolicy::Skip		=> {},
+            ErrorPolicy::Collect	=> self.errors.push((path.to_path_buf(), e)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Implements `VisitOptions::drop_page_cache`; the file is opened
+/// again because `visitor` might have closed it already.  Errors
+/// are ignored.
+fn drop_page_cache(dir_fd: &Fd, entry: &DirEntry) {
+    let fd = dir_fd.openat(&entry.d_name, libc::O_RDONLY | libc::O_CLOEXEC |
+                           libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_NOCTTY);
+
+    if let Ok(fd) = fd {
+        let _ = fd.drop_page_cache();
+    }
+}
+
+impl Chroot {
+    /// Walks the directory tree at `path` depth-first and calls
+    /// `visitor` for every entry before its content.
+    ///
+    /// `path` is resolved like by `Self::chdir()`; below it, symlinks
+    /// are reported but never followed.  Entries which disappear
+    /// while walking are ignored (see `VisitOptions::vanished`); this
+    /// includes directories which are removed or replaced between
+    /// listing and descending into them or while their content is
+    /// read.  With `Self::with_mask_foreign_mounts()`, foreign mount
+    /// points are reported but not descended into.  The traversal
+    /// stops at the first error of `visitor` and returns it.
+    pub fn visit<T, F>(&self, path: &T, visitor: F) -> Result<()>
+    where
+        T: AsRef<Path>,
+        F: FnMut(&VisitEntry) -> Result<VisitAction>,
+    {
+        self.visit_with(path, &VisitOptions::default(), visitor)
+    }
+
+    /// Like `Self::visit()` but restricts the traversal and selects
+    /// the order by `opts`
+    pub fn visit_with<T, F>(&self, path: &T, opts: &VisitOptions, visitor: F) -> Result<()>
+    where
+        T: AsRef<Path>,
+        F: FnMut(&VisitEntry) -> Result<VisitAction>,
+    {
+        self.visit_internal(path.as_ref(), opts, &self.progress(), visitor)
+            .map(|_| ())
+    }
+
+    /// Like `Self::visit_with()` but returns the paths of the entries
+    /// which disappeared while walking and the errors they caused.
+    /// They are gathered with `ErrorPolicy::Collect` in
+    /// `VisitOptions::vanished` only.
+    pub fn visit_collect<T, F>(&self, path: &T, opts: &VisitOptions, visitor: F)
+                               -> Result<Vec<(PathBuf, Error)>>
+    where
+        T: AsRef<Path>,
+        F: FnMut(&VisitEntry) -> Result<VisitAction>,
+    {
+        self.visit_internal(path.as_ref(), opts, &self.progress(), visitor)
+    }
+
+    /// Opens a directory which was queued in breadth-first order; it
+    /// counts as vanished when it was replaced meanwhile.
+    fn open_queued(&self, queued: Queued) -> Result<Level> {
+        let dir_fd = self.chdir(&queued.path)?;
+
+        if !dir_fd.metadata()?.is_same_file(&queued.metadata) {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOENT).into());
+        }
+
+        Ok(Level {
+            depth:	queued.depth,
+            entries:	read_entries(&dir_fd)?,
+            dir_fd:	dir_fd,
+            path:	queued.path,
+            pending:	None,
+        })
+    }
+
+    /// Implements `Self::visit_collect()`; every entry is accounted
+    /// in `progress` before it is passed to `visitor`.
+    pub(crate) fn visit_internal<F>(&self, path: &Path, opts: &VisitOptions,
+                                    progress: &Progress, mut visitor: F)
+                                    -> Result<Vec<(PathBuf, Error)>>
+    where
+        F: FnMut(&VisitEntry) -> Result<VisitAction>,
+    {
+        let post_order = opts.order == VisitOrder::PostOrder;
+        let breadth_first = opts.order == VisitOrder::BreadthFirst;
+        let start = self.canonicalize(&path)?;
+        let start_fd = self.chdir(&start)?;
+        let start_dev = start_fd.metadata()?.dev();
+        // open directories from the start down to the current one; in
+        // breadth-first order, only the current one
+        let mut todo = Vec::new();
+        let mut queue = VecDeque::new();
+        let mut budget = Budget::default();
+        let mut deadline = None;
+        let mut vanished = Vanished {
+            policy:	opts.vanished,
+            errors:	Vec::new(),
+        };
+
+        todo.push(Level {
+            depth:	1,
+            entries:	read_entries(&start_fd)?,
+            dir_fd:	start_fd,
+            path:	start.as_path().into(),
+            pending:	None,
+        });
+
+        loop {
+            if todo.is_empty() {
+                let queued: Queued = match queue.pop_front() {
+                    Some(q) => q,
+                    None => break,
+                };
+
+                let path = queued.path.clone();
+
+                match self.open_queued(queued) {
+                    Ok(level) => todo.push(level),
+                    Err(e) if is_vanished(&e) => vanished.handle(&path, e)?,
+                    Err(e) => return Err(e),
+                }
+
+                continue;
+            }
+
+            let level = todo.last_mut().unwrap();
+
+            self.check_interrupt(&mut deadline)?;
+
+            let entry = match level.entries.next() {
+                Some(e) => e?,
+                None => {
+                    // a directory which was removed while it was read
+                    // ends early with ENOENT
+                    for e in level.entries.take_errors() {
+                        if !is_vanished(&e) {
+                            return Err(e);
+                        }
+
+                        vanished.handle(&level.path, e)?;
+                    }
+
+                    // 'pending' is set in post-order only where the
+                    // parent is the previous level
+                    let (depth, path, entry, md) = match todo.pop() {
+                        Some(Level { depth, path, pending: Some((entry, md)), .. }) =>
+                            (depth - 1, path, entry, md),
+                        _ => continue,
+                    };
+
+                    let action = visitor(&VisitEntry {
+                        depth:		depth,
+                        path:		&path,
+                        parent:		&todo.last().unwrap().dir_fd,
+                        entry:		&entry,
+                        metadata:	&md,
+                    })?;
+
+                    if action == VisitAction::Stop {
+                        break;
+                    }
+
+                    continue;
+                },
+            };
+
+            let path = level.path.join(&entry.d_name);
+
+            let md = match level.dir_fd.fstatat(&entry.d_name, false) {
+                Ok(st) => Metadata::from_stat(st),
+                // entry was removed after readdir()
+                Err(e) if e.errno() == Some(libc::ENOENT) => {
+                    vanished.handle(&path, e)?;
+                    continue;
+                },
+                Err(e) => return Err(e),
+            };
+
+            budget.account(opts, &md)?;
+            progress.add(1, 0);
+
+            let can_descend = md.is_dir() &&
+                !(opts.one_file_system && md.dev() != start_dev);
+
+            if !(post_order && can_descend) {
+                let action = visitor(&VisitEntry {
+                    depth:		level.depth,
+                    path:		&path,
+                    parent:		&level.dir_fd,
+                    entry:		&entry,
+                    metadata:	&md,
+                })?;
+
+                if opts.drop_page_cache && md.is_file() && action != VisitAction::Stop {
+                    drop_page_cache(&level.dir_fd, &entry);
+                }
+
+                match action {
+                    VisitAction::Stop => break,
+                    VisitAction::Descend if can_descend && !post_order => {},
+                    VisitAction::Descend | VisitAction::Skip => continue,
+                }
+            }
+
+            if breadth_first {
+                queue.push_back(Queued {
+                    depth:	level.depth + 1,
+                    path:	path,
+                    metadata:	md,
+                });
+
+                continue;
+            }
+
+            let sub = level.dir_fd.openat(&entry.d_name, libc::O_DIRECTORY |
+                                          libc::O_RDONLY | libc::O_CLOEXEC |
+                                          libc::O_NOFOLLOW)
+                .and_then(|fd| self.check_opened(fd))
+                .and_then(|fd| Ok((read_entries(&fd)?, fd)));
+
+            match sub {
+                Ok((entries, fd)) => {
+                    let depth = level.depth + 1;
+                    let pending = if post_order { Some((entry, md)) } else { None };
+
+                    todo.push(Level {
+                        depth:		depth,
+                        dir_fd:		fd,
+                        path:		path,
+                        entries:	entries,
+                        pending:	pending,
+                    });
+                },
+
+                // removed or masked directories are reported without
+                // content in post-order
+                Err(e) if is_vanished(&e) => {
+                    vanished.handle(&path, e)?;
+
+                    if !post_order {
+                        continue;
+                    }
+
+                    let action = visitor(&VisitEntry {
+                        depth:		level.depth,
+                        path:		&path,
+                        parent:		&level.dir_fd,
+                        entry:		&entry,
+                        metadata:	&md,
+                    })?;
+
+                    if action == VisitAction::Stop {
+                        break;
+                    }
+                },
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(vanished.errors)
+    }
+}
+
+#[cfg(test)]
+#[path="tests/visit.inc.rs"]
+mod test;
@@ -0,0 +1,64 @@
+//! Invalidation of chroot caches by inotify
+extern crate libc;
+
+use crate::fd::FdRaw;
+use crate::errors::*;
+
+/// events which can make a lookup succeed which failed before
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM |
+    libc::IN_DELETE | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_ONLYDIR;
+
+/// inotify instance which watches directories for new or removed
+/// entries
+#[derive(Debug)]
+pub(crate) struct DirWatch {
+    fd:		FdRaw,
+}
+
+impl DirWatch {
+    pub(crate) fn new() -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+
+        ensure!(fd >= 0, std::io::Error::last_os_error());
+
+        Ok(DirWatch {
+            fd:		FdRaw::_new(fd),
+        })
+    }
+
+    /// Watches the directory `dir`; it can be an `O_PATH` descriptor.
+    pub(crate) fn add(&self, dir: &FdRaw) -> Result<()> {
+        let path = format!("/proc/self/fd/{}\0", dir.fd);
+        let rc = unsafe {
+            libc::inotify_add_watch(self.fd.fd, path.as_ptr() as *const _, WATCH_MASK)
+        };
+
+        ensure!(rc >= 0, std::io::Error::last_os_error());
+
+        Ok(())
+    }
+
+    /// Consumes the pending events and tells whether there were any.
+    /// Errors are reported as events because they might have been
+    /// lost.
+    pub(crate) fn has_events(&self) -> bool {
+        let mut buf = [0u8; 4096];
+        let mut res = false;
+
+        loop {
+            let rc = unsafe {
+                libc::read(self.fd.fd, buf.as_mut_ptr() as *mut _, buf.len())
+            };
+
+            match rc {
+                rc if rc > 0 => res = true,
+                0 => break res,
+                _ => match std::io::Error::last_os_error().raw_os_error() {
+                    Some(libc::EAGAIN) => break res,
+                    Some(libc::EINTR) => continue,
+                    _ => break true,
+                },
+            }
+        }
+    }
+}